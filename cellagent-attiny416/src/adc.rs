@@ -0,0 +1,91 @@
+//! Cell voltage measurement with ADC0.
+
+use cellagent::voltage::{Accumulation, Calibration, Measurement, Millivolts, Reference, Source};
+
+use crate::pac;
+
+/// CTRLA: Enable ADC.
+const CTRLA_ENABLE: u8 = 1 << 0;
+/// CTRLC: Reduced sampling capacitance, recommended for references above 1 V.
+const CTRLC_SAMPCAP: u8 = 1 << 6;
+/// CTRLC: Internal reference.
+const CTRLC_REFSEL_INTERNAL: u8 = 0x0 << 4;
+/// CTRLC: VDD as reference.
+const CTRLC_REFSEL_VDD: u8 = 0x1 << 4;
+/// CTRLC: CLK_ADC = CLK_PER / 4, which is within the 50 kHz to 1.5 MHz range
+/// required for full resolution.
+const CTRLC_PRESC_DIV4: u8 = 0x1;
+/// CTRLD: 32 CLK_ADC cycles of initialization delay for the reference to
+/// settle.
+const CTRLD_INITDLY_DLY32: u8 = 0x2 << 5;
+/// MUXPOS: Internal reference.
+const MUXPOS_INTREF: u8 = 0x1D;
+/// COMMAND: Start conversion.
+const COMMAND_STCONV: u8 = 1 << 0;
+/// INTFLAGS: Result ready.
+const INTFLAGS_RESRDY: u8 = 1 << 0;
+
+/// Measurement of the supply voltage, which is the cell voltage.
+///
+/// The calibration is replaced by the one stored in EEPROM.
+pub const MEASUREMENT: Measurement = Measurement {
+    source: Source::Vdd(Reference::V1_1),
+    accumulation: Accumulation::Acc64,
+    calibration: Calibration::IDENTITY,
+};
+
+/// Cell voltage measurement.
+pub struct CellVoltage {
+    adc: pac::ADC0,
+    measurement: Measurement,
+}
+
+impl CellVoltage {
+    /// Configures ADC0 and the voltage reference for the measurement.
+    ///
+    /// `ain` is the analog input for [`Source::Divider`] and ignored for
+    /// [`Source::Vdd`]. The digital input buffer of the pin should be
+    /// disabled by the caller.
+    pub fn new(adc: pac::ADC0, vref: &pac::VREF, measurement: Measurement, ain: u8) -> Self {
+        let (reference, refsel, muxpos) = match measurement.source {
+            Source::Vdd(reference) => (reference, CTRLC_REFSEL_VDD, MUXPOS_INTREF),
+            Source::Divider(reference, _) => (reference, CTRLC_REFSEL_INTERNAL, ain),
+        };
+        let sampcap = if refsel == CTRLC_REFSEL_INTERNAL && reference == Reference::V0_55 {
+            0
+        } else {
+            CTRLC_SAMPCAP
+        };
+        // SAFETY: All values are valid for the respective registers according to
+        // the datasheet.
+        unsafe {
+            vref.ctrla().write(|w| w.bits(reference.adc0refsel()));
+            adc.ctrlb()
+                .write(|w| w.bits(measurement.accumulation.sampnum()));
+            adc.ctrlc()
+                .write(|w| w.bits(sampcap | refsel | CTRLC_PRESC_DIV4));
+            adc.ctrld().write(|w| w.bits(CTRLD_INITDLY_DLY32));
+            adc.muxpos().write(|w| w.bits(muxpos));
+            adc.ctrla().write(|w| w.bits(CTRLA_ENABLE));
+        }
+        Self { adc, measurement }
+    }
+
+    /// Changes the per-unit calibration.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.measurement.calibration = calibration;
+    }
+
+    /// Measures the cell voltage.
+    ///
+    /// Blocks until all samples are accumulated, which takes about 1 ms with
+    /// 64 samples.
+    pub fn measure(&mut self) -> Option<Millivolts> {
+        // SAFETY: STCONV is the only bit in the register.
+        unsafe { self.adc.command().write(|w| w.bits(COMMAND_STCONV)) };
+        while self.adc.intflags().read().bits() & INTFLAGS_RESRDY == 0 {}
+        // Reading the result clears RESRDY.
+        let result = self.adc.res().read().bits();
+        self.measurement.convert(result)
+    }
+}
@@ -0,0 +1,180 @@
+//! Requests of the cellcore on the cell bus.
+
+use avr_usart::Serial;
+use cellagent::addressing::Addressing;
+use cellagent::balancing::Controller;
+use cellagent::bus;
+use cellagent::config::ConfigStore;
+use cellagent::voltage::Millivolts;
+use cellguard_protocol::{Decoder, MAX_FRAME_LEN, Status, StatusFlags, Version};
+use embedded_io::{Read, ReadReady, Write};
+use p3t1755::{P3t1755, Temperature};
+
+use crate::bleed::Bleed;
+use crate::chain::Chain;
+use crate::eeprom::Eeprom;
+use crate::{adc, clock, twi, usart};
+
+/// Firmware version reported to the cellcore.
+const FIRMWARE: Version = Version::new(
+    version(env!("CARGO_PKG_VERSION_MAJOR")),
+    version(env!("CARGO_PKG_VERSION_MINOR")),
+    version(env!("CARGO_PKG_VERSION_PATCH")),
+);
+
+const fn version(part: &str) -> u8 {
+    match u8::from_str_radix(part, 10) {
+        Ok(part) => part,
+        Err(_) => panic!("version parts must fit into a byte"),
+    }
+}
+
+/// Measurements of the cell.
+pub struct Sensors {
+    /// Cell voltage measurement.
+    pub cell: adc::CellVoltage,
+    /// Temperature sensor next to the cell.
+    pub sensor: P3t1755<twi::I2c>,
+}
+
+/// Firmware state the cellcore operates on.
+pub struct State {
+    /// Measurements, `None` in safe mode.
+    sensors: Option<Sensors>,
+    /// Balancing, `None` in safe mode.
+    balancing: Option<Controller>,
+    bleed: Bleed,
+    /// Flags reporting a crash before the last reset.
+    crash_flags: StatusFlags,
+    /// Latest cell voltage.
+    voltage: Option<Millivolts>,
+    /// Latest temperature, `None` after a failed read.
+    temperature: Option<Temperature>,
+}
+
+impl State {
+    /// Creates the state, without measurements and balancing in safe mode.
+    pub const fn new(
+        sensors: Option<Sensors>,
+        balancing: Option<Controller>,
+        bleed: Bleed,
+        crash_flags: StatusFlags,
+    ) -> Self {
+        Self {
+            sensors,
+            balancing,
+            bleed,
+            crash_flags,
+            voltage: None,
+            temperature: None,
+        }
+    }
+
+    /// Measures the cell voltage and the temperature.
+    pub fn measure(&mut self) {
+        let Some(sensors) = &mut self.sensors else {
+            return;
+        };
+        self.voltage = sensors.cell.measure();
+        self.temperature = sensors.sensor.read_temperature().ok();
+    }
+
+    /// Advances the balancing by `dt_ms` and switches the bleed resistor.
+    pub fn balance(&mut self, dt_ms: u32) {
+        let Some(controller) = &mut self.balancing else {
+            return;
+        };
+        // Without a voltage the removed charge isn't counted.
+        let cell = self.voltage.unwrap_or_default();
+        let on = controller.update(dt_ms, self.temperature, cell);
+        self.bleed.set(on);
+    }
+}
+
+impl bus::Agent for State {
+    fn firmware(&self) -> Version {
+        FIRMWARE
+    }
+
+    fn voltage(&self) -> Option<Millivolts> {
+        self.voltage
+    }
+
+    fn temperature(&self) -> Option<Temperature> {
+        self.temperature
+    }
+
+    fn balancing(&mut self) -> Option<&mut Controller> {
+        self.balancing.as_mut()
+    }
+
+    fn status(&self) -> Status {
+        let mut flags = self.crash_flags;
+        // Temperature faults are reported by the balancing.
+        flags.set(
+            StatusFlags::VOLTAGE_FAULT,
+            self.sensors.is_some() && self.voltage.is_none(),
+        );
+        Status {
+            flags,
+            uptime_s: clock::uptime_s(),
+        }
+    }
+}
+
+/// Cell bus connection to the cellcore.
+pub struct CellBus {
+    serial: Serial<usart::CellBus>,
+    chain: Chain,
+    decoder: Decoder,
+    addressing: Addressing,
+}
+
+impl CellBus {
+    /// Takes over the cell bus with the address restored from `config`.
+    ///
+    /// With a restored address the next cellagent is enabled right away.
+    pub fn new(
+        serial: Serial<usart::CellBus>,
+        mut chain: Chain,
+        config: &mut ConfigStore<Eeprom>,
+    ) -> Self {
+        let addressing = Addressing::load(config);
+        chain.enable_next(addressing.enables_next());
+        Self {
+            serial,
+            chain,
+            decoder: Decoder::new(),
+            addressing,
+        }
+    }
+
+    /// Answers the received requests without blocking.
+    pub fn poll(&mut self, config: &mut ConfigStore<Eeprom>, state: &mut State) {
+        while self.serial.read_ready().unwrap_or(false) {
+            let mut byte = [0];
+            // Garbled bytes end up in a frame with a bad CRC, the cellcore
+            // retries the request.
+            if !matches!(self.serial.read(&mut byte), Ok(1)) {
+                continue;
+            }
+            let Some(Ok(frame)) = self.decoder.push(byte[0]) else {
+                continue;
+            };
+            let enabled = self.chain.is_enabled();
+            let mut reply = [0; MAX_FRAME_LEN];
+            let len = bus::handle(
+                &frame,
+                &mut self.addressing,
+                enabled,
+                config,
+                state,
+                &mut reply,
+            );
+            if let Some(len) = len {
+                let _ = self.serial.write_all(&reply[..len]);
+            }
+            self.chain.enable_next(self.addressing.enables_next());
+        }
+    }
+}
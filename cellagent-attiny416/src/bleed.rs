@@ -0,0 +1,26 @@
+//! Output switching the bleed resistor across the cell.
+
+use crate::pac;
+
+/// Gate of the bleed MOSFET on PA3, active high.
+pub struct Bleed {
+    porta: pac::PORTA,
+}
+
+impl Bleed {
+    /// Configures the pin as output with the bleed resistor switched off.
+    pub fn new(porta: pac::PORTA) -> Self {
+        porta.outclr().write(|w| w.pa3().set_bit());
+        porta.dirset().write(|w| w.pa3().set_bit());
+        Self { porta }
+    }
+
+    /// Switches the bleed resistor on or off.
+    pub fn set(&mut self, on: bool) {
+        if on {
+            self.porta.outset().write(|w| w.pa3().set_bit());
+        } else {
+            self.porta.outclr().write(|w| w.pa3().set_bit());
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Board support for the cellagent.
+
+use cellguard_bsp::Board as _;
+
+use crate::pac;
+
+/// CPU and peripheral clock after reset.
+///
+/// The 20 MHz oscillator is divided by the default main clock prescaler of 6.
+pub const CLK_PER_HZ: u32 = 3_333_333;
+
+/// Busy-wait delay at [`CLK_PER_HZ`].
+pub type Delay = cellguard_bsp::delay::Delay<CLK_PER_HZ>;
+
+/// Cellagent board with the status LED on PB5 and the user switch on PB4.
+pub struct Board {
+    portb: pac::PORTB,
+    delay: Delay,
+}
+
+impl Board {
+    /// Configures the LED and switch pins.
+    pub fn new(portb: pac::PORTB) -> Self {
+        // Set LED as output
+        portb.dirset().write(|w| w.pb5().set_bit());
+        // Ensure SW is input
+        portb.dirclr().write(|w| w.pb4().set_bit());
+        // Enable internal pull-up on SW so it reads high when not pressed
+        portb.pin4ctrl().write(|w| w.pullupen().set_bit());
+        let mut board = Self {
+            portb,
+            delay: Delay::default(),
+        };
+        board.set_status_led(false);
+        board
+    }
+}
+
+impl cellguard_bsp::Board for Board {
+    type Delay = Delay;
+
+    fn set_status_led(&mut self, on: bool) {
+        // From the Users-Guide:
+        // > The LED can be activated by driving the connected I/O line to GND.
+        self.portb.out().modify(|_r, w| w.pb5().bit(!on));
+    }
+
+    fn user_switch_pressed(&mut self) -> bool {
+        // From the Users-Guide:
+        // > when a button is pressed it will drive the I/O line to GND.
+        self.portb.input().read().pb4().bit_is_clear()
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        &mut self.delay
+    }
+}
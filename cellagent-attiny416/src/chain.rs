@@ -0,0 +1,36 @@
+//! Enable lines of the daisy chain used for the address assignment.
+//!
+//! The enable input on PC0 is driven by the enable output of the previous
+//! cellagent and has an external pull-down resistor, the input of the first
+//! cellagent is tied high. The enable output to the next cellagent is on PC1.
+
+use crate::pac;
+
+/// Enable input and output on PC0 and PC1.
+pub struct Chain {
+    portc: pac::PORTC,
+}
+
+impl Chain {
+    /// Configures the pins with the next cellagent disabled.
+    pub fn new(portc: pac::PORTC) -> Self {
+        portc.dirclr().write(|w| w.pc0().set_bit());
+        portc.outclr().write(|w| w.pc1().set_bit());
+        portc.dirset().write(|w| w.pc1().set_bit());
+        Self { portc }
+    }
+
+    /// Returns true if the previous cellagent enables this one.
+    pub fn is_enabled(&self) -> bool {
+        self.portc.input().read().pc0().bit_is_set()
+    }
+
+    /// Enables or disables the next cellagent.
+    pub fn enable_next(&mut self, on: bool) {
+        if on {
+            self.portc.outset().write(|w| w.pc1().set_bit());
+        } else {
+            self.portc.outclr().write(|w| w.pc1().set_bit());
+        }
+    }
+}
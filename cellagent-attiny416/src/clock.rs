@@ -0,0 +1,95 @@
+//! Uptime in seconds counted by the periodic interrupt timer of the RTC.
+//!
+//! The RTC runs from the internal 32.768 kHz oscillator, which is selected
+//! after reset, and the PIT interrupts once every 32768 cycles. The counter
+//! of the RTC runs at the full 32.768 kHz for the [`Stopwatch`].
+
+use core::cell::Cell;
+
+use avr_device::interrupt::{self, Mutex};
+
+use crate::pac;
+
+/// Frequency of the RTC counter.
+const TICK_HZ: u32 = 32_768;
+/// CTRLA: RTC enabled without prescaler.
+const CTRLA_RTCEN: u8 = 1 << 0;
+/// STATUS: CTRLA is being synchronized.
+const STATUS_CTRLABUSY: u8 = 1 << 0;
+/// PITCTRLA: Period of 32768 cycles and PIT enabled.
+const PITCTRLA_1HZ: u8 = (0x0E << 3) | 1;
+/// PITSTATUS: PITCTRLA is being synchronized.
+const PITSTATUS_CTRLBUSY: u8 = 1 << 0;
+/// PITINTCTRL and PITINTFLAGS: Periodic interrupt.
+const PIT_PI: u8 = 1 << 0;
+
+static UPTIME_S: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Starts the PIT and the counter.
+///
+/// The uptime only counts once interrupts are enabled.
+pub fn init(rtc: &pac::RTC) {
+    while rtc.status().read().bits() & STATUS_CTRLABUSY != 0 {}
+    // SAFETY: Valid prescaler and enable bit according to the datasheet.
+    rtc.ctrla().write(|w| unsafe { w.bits(CTRLA_RTCEN) });
+    while rtc.pitstatus().read().bits() & PITSTATUS_CTRLBUSY != 0 {}
+    // SAFETY: Valid period and enable bit according to the datasheet.
+    rtc.pitctrla().write(|w| unsafe { w.bits(PITCTRLA_1HZ) });
+    // SAFETY: Only enables the periodic interrupt.
+    rtc.pitintctrl().write(|w| unsafe { w.bits(PIT_PI) });
+}
+
+/// Returns the time since reset in seconds.
+pub fn uptime_s() -> u32 {
+    interrupt::free(|cs| UPTIME_S.borrow(cs).get())
+}
+
+/// Measures the time between laps with the RTC counter.
+///
+/// The counter wraps after 2 s, so laps have to be shorter.
+pub struct Stopwatch {
+    rtc: pac::RTC,
+    last: u16,
+    /// Time since the last full millisecond in 1/[`TICK_HZ`] ms.
+    fraction: u32,
+}
+
+impl Stopwatch {
+    /// Starts the first lap.
+    pub fn new(rtc: pac::RTC) -> Self {
+        let last = rtc.cnt().read().bits();
+        Self {
+            rtc,
+            last,
+            fraction: 0,
+        }
+    }
+
+    /// Returns the milliseconds since the last lap and starts the next.
+    ///
+    /// Fractions of a millisecond are carried over, so short laps add up to
+    /// the correct time.
+    pub fn lap_ms(&mut self) -> u32 {
+        let now = self.rtc.cnt().read().bits();
+        let ticks = now.wrapping_sub(self.last);
+        self.last = now;
+        // TICK_HZ is a power of two, so this doesn't need a division.
+        self.fraction += u32::from(ticks) * 1000;
+        let ms = self.fraction / TICK_HZ;
+        self.fraction %= TICK_HZ;
+        ms
+    }
+}
+
+#[avr_device::interrupt(attiny416)]
+fn RTC_PIT() {
+    // SAFETY: Only the interrupt flags are accessed, which nothing else
+    // touches after `init`.
+    let rtc = unsafe { pac::Peripherals::steal() }.RTC;
+    // SAFETY: Writing a one clears the flag.
+    rtc.pitintflags().write(|w| unsafe { w.bits(PIT_PI) });
+    interrupt::free(|cs| {
+        let uptime = UPTIME_S.borrow(cs);
+        uptime.set(uptime.get().wrapping_add(1));
+    });
+}
@@ -0,0 +1,81 @@
+//! EEPROM access through NVMCTRL.
+//!
+//! The 128 bytes of EEPROM are mapped into the data space, so reads are plain
+//! loads. Writes fill the page buffer through the mapped EEPROM and commit it
+//! with the erase and write page command, which only touches the bytes
+//! loaded into the buffer.
+
+use core::ptr;
+
+use cellguard_bsp::cpu::{CCP_SPM, ccp_write};
+use cellguard_config::Nvm;
+
+use crate::pac;
+
+/// Start of the EEPROM in the data space.
+const EEPROM_START: usize = 0x1400;
+/// Size of the EEPROM.
+pub const EEPROM_SIZE: usize = 128;
+/// Size of an EEPROM page.
+const PAGE_SIZE: usize = 32;
+/// Data space address of NVMCTRL.CTRLA.
+const NVMCTRL_CTRLA: u16 = 0x1000;
+/// CTRLA: Erase and write the bytes loaded into the page buffer.
+const CMD_ERWP: u8 = 0x3;
+/// CTRLA: Clear the page buffer.
+const CMD_PBC: u8 = 0x4;
+/// STATUS: EEPROM busy.
+const STATUS_EEBUSY: u8 = 1 << 1;
+
+/// EEPROM of the ATtiny416.
+pub struct Eeprom {
+    nvmctrl: pac::NVMCTRL,
+}
+
+impl Eeprom {
+    /// Takes NVMCTRL for the EEPROM.
+    pub fn new(nvmctrl: pac::NVMCTRL) -> Self {
+        Self { nvmctrl }
+    }
+
+    fn wait(&self) {
+        while self.nvmctrl.status().read().bits() & STATUS_EEBUSY != 0 {}
+    }
+
+    /// Writes a command to the protected CTRLA register and waits for it.
+    fn command(&mut self, command: u8) {
+        // SAFETY: Both commands only act on the page buffer and the EEPROM.
+        unsafe { ccp_write(NVMCTRL_CTRLA, CCP_SPM, command) };
+        self.wait();
+    }
+}
+
+impl Nvm for Eeprom {
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= EEPROM_SIZE);
+        self.wait();
+        for (address, byte) in (EEPROM_START + offset..).zip(buf) {
+            // SAFETY: The address lies within the mapped EEPROM.
+            *byte = unsafe { ptr::read_volatile(address as *const u8) };
+        }
+    }
+
+    fn write(&mut self, mut offset: usize, mut data: &[u8]) {
+        assert!(offset + data.len() <= EEPROM_SIZE);
+        self.wait();
+        while !data.is_empty() {
+            // A command writes a single page.
+            let len = data.len().min(PAGE_SIZE - offset % PAGE_SIZE);
+            let (page, rest) = data.split_at(len);
+            self.command(CMD_PBC);
+            for (address, &byte) in (EEPROM_START + offset..).zip(page) {
+                // SAFETY: The address lies within the mapped EEPROM, where
+                // the store loads the byte into the page buffer.
+                unsafe { ptr::write_volatile(address as *mut u8, byte) };
+            }
+            self.command(CMD_ERWP);
+            offset += len;
+            data = rest;
+        }
+    }
+}
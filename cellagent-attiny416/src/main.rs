@@ -4,59 +4,108 @@
 
 use core::panic::PanicInfo;
 
-use avr_device::asm::delay_cycles;
 use avr_device::attiny416 as pac;
+use cellagent::balancing;
+use cellagent::config::ConfigStore;
+use cellguard_bsp::{app, crash};
+use p3t1755::P3t1755;
 
+use crate::board::Board;
 use crate::pac::Peripherals;
 
+mod adc;
+mod agent;
+mod bleed;
+mod board;
+mod chain;
+mod clock;
+mod eeprom;
+mod twi;
+mod usart;
+
+/// Uptime after which a crash no longer counts as part of a crash loop.
+const STABLE_S: u32 = 600;
+
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
     // disable interrupts - firmware has panicked so no ISRs should continue running
     avr_device::interrupt::disable();
 
-    // get the peripherals so we can access serial and the LED.
+    // keep the report for the next run and start over.
     //
-    // SAFETY: Because main() already has references to the peripherals this is an
-    //         unsafe operation - but because no other code can run after the panic
+    // SAFETY: Because main() already holds the crash report this is an unsafe
+    //         operation - but because no other code can run after the panic
     //         handler was called, we know it is okay.
-    let Peripherals { PORTB, .. } = unsafe { Peripherals::steal() };
-    loop {
-        set_led(&PORTB, true);
-        delay_cycles(500);
-        set_led(&PORTB, false);
-        delay_cycles(1000);
-    }
+    // Only messages without arguments are hashed, formatting doesn't fit into
+    // the flash.
+    let message_hash = crash::static_message_hash(info.message().as_str().unwrap_or(""));
+    crash::record(unsafe { crash::retained() }, info, message_hash);
+    crash::reboot()
 }
 
 #[avr_device::entry]
 fn main() -> ! {
-    let Peripherals { PORTB, .. } = unsafe { Peripherals::steal() };
+    let Peripherals {
+        ADC0,
+        NVMCTRL,
+        PORTA,
+        PORTB,
+        PORTC,
+        RTC,
+        TWI0,
+        USART0,
+        VREF,
+        ..
+    } = unsafe { Peripherals::steal() };
 
-    init_portb(&PORTB);
+    let reset_cause = crash::reset_flags();
+    // SAFETY: Only the panic handler takes the block again.
+    let retained = unsafe { crash::retained() };
+    let crash_report = retained.boot(reset_cause);
+    let safe_mode = crash_report.is_some_and(|report| report.safe_mode);
+    // Reported in the status until the next reset.
+    let crash_flags = crash::status_flags(crash_report.as_ref());
+    clock::init(&RTC);
+    let mut stopwatch = clock::Stopwatch::new(RTC);
 
-    loop {
-        let switch_pressed = read_switch(&PORTB);
-        set_led(&PORTB, switch_pressed);
-    }
-}
+    let mut config = ConfigStore::load(eeprom::Eeprom::new(NVMCTRL), 0);
+    // A failed write only repeats the migration after the next reset.
+    let _ = config.finish_migration();
+    let settings = *config.config();
 
-fn init_portb(reg: &pac::PORTB) {
-    // Set LED as output
-    reg.dirset().write(|w| w.pb5().set_bit());
-    // Ensure SW is input
-    reg.dirclr().write(|w| w.pb4().set_bit());
-    // Enable internal pull-up on SW so it reads high when not pressed
-    reg.pin4ctrl().write(|w| w.pullupen().set_bit());
-}
+    let mut cell_bus = agent::CellBus::new(
+        usart::cell_bus(USART0, &PORTB),
+        chain::Chain::new(PORTC),
+        &mut config,
+    );
+    let mut board = Board::new(PORTB);
+    let bleed = bleed::Bleed::new(PORTA);
+    // In safe mode the cell is neither measured nor balanced until a reset
+    // that isn't caused by a crash.
+    let sensors = (!safe_mode).then(|| {
+        let mut cell = adc::CellVoltage::new(ADC0, &VREF, adc::MEASUREMENT, 0);
+        cell.set_calibration(settings.calibration);
+        agent::Sensors {
+            cell,
+            // The sensor next to the cell has all address pins tied to GND.
+            sensor: P3t1755::new(twi::init(TWI0), p3t1755::Address::Addr9),
+        }
+    });
+    let balancing = (!safe_mode).then(|| balancing::Controller::new(settings.balancing));
+    let mut state = agent::State::new(sensors, balancing, bleed, crash_flags);
 
-fn set_led(reg: &pac::PORTB, on: bool) {
-    // From the Users-Guide:
-    // > The LED can be activated by driving the connected I/O line to GND.
-    reg.out().modify(|_r, w| w.pb5().bit(!on));
-}
+    // SAFETY: All shared state is initialized at this point.
+    unsafe { avr_device::interrupt::enable() };
 
-fn read_switch(reg: &pac::PORTB) -> bool {
-    // From the Users-Guide:
-    // > when a button is pressed it will drive the I/O line to GND.
-    reg.input().read().pb4().bit_is_clear()
+    let mut stable = false;
+    loop {
+        state.measure();
+        state.balance(stopwatch.lap_ms());
+        cell_bus.poll(&mut config, &mut state);
+        app::mirror_switch(&mut board);
+        if !stable && clock::uptime_s() >= STABLE_S {
+            retained.stable();
+            stable = true;
+        }
+    }
 }
@@ -0,0 +1,58 @@
+//! Binding of the TWI host driver to the TWI0 peripheral.
+//!
+//! SDA and SCL are on PB1 and PB0 (default routing). The bus requires external
+//! pull-up resistors.
+
+use avr_twi::{Config, Register, Registers};
+
+use crate::board::{CLK_PER_HZ, Delay};
+use crate::pac;
+
+/// I2C bus on TWI0.
+pub type I2c = avr_twi::Twi<Twi0, Delay>;
+
+/// Standard mode at 100 kHz.
+///
+/// Evaluated at compile time so the 32-bit division for the baud rate doesn't
+/// end up in flash.
+const CONFIG: Config = Config::new(CLK_PER_HZ, 100_000);
+
+/// Host mode registers of TWI0.
+pub struct Twi0(pac::TWI0);
+
+impl Registers for Twi0 {
+    #[inline]
+    fn read(&mut self, reg: Register) -> u8 {
+        let twi = &self.0;
+        match reg {
+            Register::MCtrlA => twi.mctrla().read().bits(),
+            Register::MCtrlB => twi.mctrlb().read().bits(),
+            Register::MStatus => twi.mstatus().read().bits(),
+            Register::MBaud => twi.mbaud().read().bits(),
+            Register::MAddr => twi.maddr().read().bits(),
+            Register::MData => twi.mdata().read().bits(),
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, reg: Register, value: u8) {
+        let twi = &self.0;
+        // SAFETY: The driver only writes values that are valid for the respective
+        // register according to the datasheet.
+        unsafe {
+            match reg {
+                Register::MCtrlA => twi.mctrla().write(|w| w.bits(value)),
+                Register::MCtrlB => twi.mctrlb().write(|w| w.bits(value)),
+                Register::MStatus => twi.mstatus().write(|w| w.bits(value)),
+                Register::MBaud => twi.mbaud().write(|w| w.bits(value)),
+                Register::MAddr => twi.maddr().write(|w| w.bits(value)),
+                Register::MData => twi.mdata().write(|w| w.bits(value)),
+            };
+        }
+    }
+}
+
+/// Enables TWI0 as a standard mode I2C host.
+pub fn init(twi: pac::TWI0) -> I2c {
+    avr_twi::Twi::new(Twi0(twi), Delay::default(), CONFIG)
+}
@@ -0,0 +1,110 @@
+//! Binding of the USART driver to USART0 for the cell bus.
+//!
+//! The cell bus is a one-wire bus on PB2 (TxD, default routing) with an
+//! external pull-up resistor.
+
+use core::cell::RefCell;
+
+use avr_device::interrupt::{self, Mutex};
+use avr_usart::{Config, Mode, Register, Registers, Serial, Shared};
+
+use crate::board::CLK_PER_HZ;
+use crate::pac;
+
+/// Size of the receive and transmit buffers.
+///
+/// Kept small because the device only has 256 bytes of SRAM.
+const BUFFER_SIZE: usize = 16;
+
+/// 19200 baud 8N1 on a single wire.
+///
+/// Evaluated at compile time so the 32-bit division for the baud rate doesn't
+/// end up in flash.
+const CONFIG: Config = Config::new(CLK_PER_HZ, 19_200).with_mode(Mode::OneWire);
+
+/// Driver shared with the interrupt handlers.
+type Driver = avr_usart::Usart<Usart0, BUFFER_SIZE>;
+
+/// Registers of USART0.
+pub struct Usart0(pac::USART0);
+
+impl Registers for Usart0 {
+    #[inline]
+    fn read(&mut self, reg: Register) -> u8 {
+        let usart = &self.0;
+        match reg {
+            Register::RxDataL => usart.rxdatal().read().bits(),
+            Register::RxDataH => usart.rxdatah().read().bits(),
+            Register::TxDataL => usart.txdatal().read().bits(),
+            Register::Status => usart.status().read().bits(),
+            Register::CtrlA => usart.ctrla().read().bits(),
+            Register::CtrlB => usart.ctrlb().read().bits(),
+            Register::CtrlC => usart.ctrlc().read().bits(),
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, reg: Register, value: u8) {
+        let usart = &self.0;
+        // SAFETY: The driver only writes values that are valid for the respective
+        // register according to the datasheet.
+        unsafe {
+            match reg {
+                Register::RxDataL | Register::RxDataH => {}
+                Register::TxDataL => usart.txdatal().write(|w| w.bits(value)),
+                Register::Status => usart.status().write(|w| w.bits(value)),
+                Register::CtrlA => usart.ctrla().write(|w| w.bits(value)),
+                Register::CtrlB => usart.ctrlb().write(|w| w.bits(value)),
+                Register::CtrlC => usart.ctrlc().write(|w| w.bits(value)),
+            };
+        }
+    }
+
+    #[inline]
+    fn write_baud(&mut self, baud: u16) {
+        // SAFETY: Every value is a valid baud rate setting.
+        unsafe { self.0.baud().write(|w| w.bits(baud)) };
+    }
+}
+
+static CELL_BUS: Mutex<RefCell<Option<Driver>>> = Mutex::new(RefCell::new(None));
+
+/// Calls `f` with the driver while interrupts are disabled.
+///
+/// Does nothing if the driver wasn't initialized yet.
+fn with<T>(f: impl FnOnce(&mut Driver) -> T) -> Option<T> {
+    interrupt::free(|cs| CELL_BUS.borrow(cs).borrow_mut().as_mut().map(f))
+}
+
+/// Cell bus on USART0.
+pub struct CellBus(());
+
+impl Shared for CellBus {
+    type Target = Driver;
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T {
+        // A `CellBus` only exists after the driver was initialized.
+        with(f).unwrap()
+    }
+}
+
+/// Enables USART0 as the one-wire cell bus.
+///
+/// The receiver only runs once interrupts are enabled.
+pub fn cell_bus(usart: pac::USART0, portb: &pac::PORTB) -> Serial<CellBus> {
+    // The USART drives the pin as open drain.
+    portb.dirset().write(|w| w.pb2().set_bit());
+    let driver = Driver::new(Usart0(usart), CONFIG);
+    interrupt::free(|cs| CELL_BUS.borrow(cs).replace(Some(driver)));
+    Serial::new(CellBus(()))
+}
+
+#[avr_device::interrupt(attiny416)]
+fn USART0_RXC() {
+    with(Driver::on_receive);
+}
+
+#[avr_device::interrupt(attiny416)]
+fn USART0_DRE() {
+    with(Driver::on_data_register_empty);
+}
@@ -0,0 +1,125 @@
+//! Requests to the cellagents on the cell bus.
+//!
+//! The chain is enumerated by the first poll and one cellagent is read per
+//! poll afterwards. A lost cellagent or a duplicate address starts the
+//! enumeration again.
+
+use avr_usart::Serial;
+use cellcore::bus::{Bus, Master};
+use cellcore::enumeration::Config;
+use cellcore::polling::Poller;
+use cellguard_protocol::{DecodeError, Decoder, Frame};
+use embedded_hal::delay::DelayNs;
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::board::Delay;
+use crate::console::State;
+use crate::usart;
+
+/// Time the cellagents have to start their response after a request.
+///
+/// The cellagents answer from their main loop, which also measures the cell.
+const RESPONSE_TIMEOUT_US: u32 = 20_000;
+
+/// Time a started frame is waited for after the response timeout, about four
+/// bytes at 19200 baud.
+const BYTE_TIMEOUT_US: u32 = 2_000;
+
+/// Interval in which the receiver is checked.
+const POLL_US: u32 = 100;
+
+/// The cell bus as seen by the [`Master`].
+pub struct Link {
+    serial: Serial<usart::CellBus>,
+    decoder: Decoder,
+    delay: Delay,
+}
+
+impl Bus for Link {
+    type Error = avr_usart::Error;
+
+    fn exchange(
+        &mut self,
+        frame: &[u8],
+        receive: &mut dyn FnMut(Result<Frame<'_>, DecodeError>),
+    ) -> Result<(), Self::Error> {
+        // Late responses to the previous request would be taken for
+        // responses to this one.
+        while self.serial.read_ready()? {
+            let _ = self.serial.read(&mut [0]);
+        }
+        self.decoder.reset();
+
+        self.serial.write_all(frame)?;
+        self.serial.flush()?;
+
+        let mut wait_us = RESPONSE_TIMEOUT_US;
+        while wait_us > 0 {
+            if !self.serial.read_ready()? {
+                self.delay.delay_us(POLL_US);
+                wait_us = wait_us.saturating_sub(POLL_US);
+                continue;
+            }
+            let mut byte = [0];
+            match self.serial.read(&mut byte) {
+                Ok(_) => {
+                    if let Some(frame) = self.decoder.push(byte[0]) {
+                        receive(frame);
+                    }
+                }
+                // Two cellagents answering at once garble their frames.
+                Err(_) if self.decoder.is_receiving() => {
+                    self.decoder.reset();
+                    receive(Err(DecodeError::Incomplete));
+                }
+                Err(_) => {}
+            }
+            if self.decoder.is_receiving() {
+                wait_us = wait_us.max(BYTE_TIMEOUT_US);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cellagents of the pack.
+pub struct Agents {
+    master: Master<Link>,
+    poller: Poller,
+}
+
+impl Agents {
+    /// Takes over the cell bus for a chain of `cells` cellagents.
+    ///
+    /// Nothing is sent before the first poll.
+    pub fn new(serial: Serial<usart::CellBus>, cells: u8) -> Self {
+        let link = Link {
+            serial,
+            decoder: Decoder::new(),
+            delay: Delay::default(),
+        };
+        Self {
+            master: Master::new(link),
+            poller: Poller::new(Config::new(cells)),
+        }
+    }
+
+    /// Changes the number of cellagents, which enumerates the chain again.
+    pub fn set_cells(&mut self, cells: u8) {
+        if self.poller.config().expected != cells {
+            self.poller.set_config(Config::new(cells));
+        }
+    }
+
+    /// Enumerates the chain or reads the next cell into `state` and sends
+    /// it the balancing request.
+    ///
+    /// Blocks for the response timeout of every request, which adds up to
+    /// about a second for an enumeration.
+    pub fn poll(&mut self, state: &mut State) {
+        let count = state.cell_count();
+        let (cells, requests) = (&mut state.cells[..count], &state.requests[..count]);
+        // Every error is followed by an enumeration in the next poll.
+        let _ = self.poller.poll(&mut self.master, cells, requests);
+    }
+}
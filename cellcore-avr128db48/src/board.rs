@@ -0,0 +1,55 @@
+//! Board support for the cellcore.
+
+use cellguard_bsp::Board as _;
+
+use crate::pac;
+
+/// CPU and peripheral clock after reset.
+///
+/// The internal high-frequency oscillator runs at 4 MHz and the main clock
+/// prescaler is disabled.
+pub const CLK_PER_HZ: u32 = 4_000_000;
+
+/// Busy-wait delay at [`CLK_PER_HZ`].
+pub type Delay = cellguard_bsp::delay::Delay<CLK_PER_HZ>;
+
+/// Cellcore board with the status LED on PB3 and the user switch on PB2.
+pub struct Board {
+    portb: pac::PORTB,
+    delay: Delay,
+}
+
+impl Board {
+    /// Configures the LED and switch pins.
+    pub fn new(portb: pac::PORTB) -> Self {
+        // Set PB3 as output
+        portb.dirset().write(|w| w.pb3().set_bit());
+        // Ensure PB2 is input
+        portb.dirclr().write(|w| w.pb2().set_bit());
+        // Enable internal pull-up on PB2 so it reads high when not pressed
+        portb.pin2ctrl().write(|w| w.pullupen().set_bit());
+        let mut board = Self {
+            portb,
+            delay: Delay::default(),
+        };
+        board.set_status_led(false);
+        board
+    }
+}
+
+impl cellguard_bsp::Board for Board {
+    type Delay = Delay;
+
+    fn set_status_led(&mut self, on: bool) {
+        // LED is likely wired active-low; drive low to turn on
+        self.portb.out().modify(|_r, w| w.pb3().bit(!on));
+    }
+
+    fn user_switch_pressed(&mut self) -> bool {
+        self.portb.in_().read().pb2().bit_is_clear()
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        &mut self.delay
+    }
+}
@@ -0,0 +1,436 @@
+//! Debug console on the virtual COM port.
+//!
+//! The port is shared by the command console and host tools. Frames of host
+//! tools start with [`SYNC`], which can't be typed, so everything from a
+//! [`SYNC`] to the end of the frame is kept from the console.
+
+use core::fmt;
+
+use avr_usart::Serial;
+use cellcore::balancing::{self, Cell, Planner};
+use cellcore::config::{Config, ConfigStore, PackConfig};
+use cellcore::console::{Console, Field, ResetCause, Setting, Target};
+use cellcore::events::{EventLog, Monitor};
+use cellcore::host::{self, Pack};
+use cellcore::limits::{self, Inputs, Limiter, Limits};
+use cellcore::protection::{self, Level, Measurements, Protection, Quantity};
+use cellcore::soc::{self, Estimator};
+use cellguard_protocol::{
+    Balancing, CrashReport, Decoder, Event, EventKind, MAX_FRAME_LEN, SYNC, Version,
+};
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::eeprom::Eeprom;
+use crate::flash::AppData;
+use crate::{clock, twi, usart};
+
+/// Longest command line.
+const LINE_LEN: usize = 48;
+
+/// Number of cells of the pack.
+pub const CELLS: usize = 16;
+
+/// Nominal capacity of the cells, only used for coulomb counting.
+const CAPACITY_MAH: u32 = 100_000;
+
+/// Pack current, the board has no current sensor yet.
+///
+/// Without a current the over-current protection trips, so charging and
+/// discharging stay inhibited and the current limits stay at zero.
+const CURRENT_MA: Option<i32> = None;
+
+/// Firmware version reported to host tools.
+const FIRMWARE: Version = Version::new(
+    version(env!("CARGO_PKG_VERSION_MAJOR")),
+    version(env!("CARGO_PKG_VERSION_MINOR")),
+    version(env!("CARGO_PKG_VERSION_PATCH")),
+);
+
+const fn version(part: &str) -> u8 {
+    match u8::from_str_radix(part, 10) {
+        Ok(part) => part,
+        Err(_) => panic!("version parts must fit into a byte"),
+    }
+}
+
+/// Firmware state shown and changed on the console.
+pub struct State {
+    /// I2C bus of the sensors and I/O expanders.
+    pub i2c: twi::I2c,
+    /// Latest readings of all cells.
+    pub cells: [Cell; CELLS],
+    /// Balancing requests for the cells, sent by the cell bus polling.
+    pub requests: [Balancing; CELLS],
+    /// Selection of the cells to bleed.
+    planner: Planner<CELLS>,
+    /// Protection state.
+    pub protection: Protection,
+    /// Balancing settings.
+    pub balancing: balancing::Config,
+    /// Number of cells and Modbus unit.
+    pub pack: PackConfig,
+    /// State of charge.
+    estimator: Estimator<'static>,
+    /// Current and voltage limits for the energy management system.
+    limiter: Limiter<'static>,
+    /// Time of the last update not yet passed on, below a millisecond.
+    remainder_us: u32,
+    /// Settings in EEPROM.
+    pub config: ConfigStore<Eeprom>,
+    /// Cause of the last reset.
+    pub reset_cause: ResetCause,
+    /// Panic that caused the last reset.
+    pub crash: Option<CrashReport>,
+    /// Event log in flash.
+    pub log: EventLog<AppData>,
+    /// Events that couldn't be written to the log.
+    pub lost_events: u16,
+    /// Source of the protection and contactor events.
+    pub monitor: Monitor,
+}
+
+impl State {
+    /// Creates the state with the stored settings and no readings.
+    ///
+    /// Records the reset and the crash before it in the event log.
+    pub fn new(
+        i2c: twi::I2c,
+        config: ConfigStore<Eeprom>,
+        reset_cause: ResetCause,
+        crash: Option<CrashReport>,
+        log: EventLog<AppData>,
+    ) -> Self {
+        let stored = config.config();
+        let pack = stored.pack();
+        let mut state = Self {
+            i2c,
+            cells: [Cell::default(); CELLS],
+            requests: [Balancing::OFF; CELLS],
+            planner: Planner::new(stored.balancing),
+            protection: Protection::new(stored.protection),
+            balancing: stored.balancing,
+            pack,
+            estimator: Estimator::new(soc::Config::lfp(CAPACITY_MAH)),
+            limiter: Limiter::new(limits_config(pack.cells)),
+            remainder_us: 0,
+            config,
+            reset_cause,
+            crash,
+            log,
+            lost_events: 0,
+            monitor: Monitor::new(),
+        };
+        state.record(EventKind::Reset, reset_cause.0.into());
+        if let Some(report) = crash {
+            state.record(EventKind::Crash, report.line);
+        }
+        state
+    }
+
+    /// Appends an event to the log.
+    fn record(&mut self, kind: EventKind, data: u16) {
+        record(&mut self.log, &mut self.lost_events, kind, data);
+    }
+
+    /// Records the changes of the protection state.
+    pub fn monitor(&mut self) {
+        let (log, lost) = (&mut self.log, &mut self.lost_events);
+        self.monitor
+            .protection(&self.protection, |kind, data| record(log, lost, kind, data));
+    }
+
+    /// Applies protection settings written by the energy management system.
+    ///
+    /// Records every changed field like a change on the console.
+    pub fn set_protection(&mut self, config: protection::Config) {
+        let old = *self.protection.config();
+        self.protection.set_config(config);
+        self.save_config();
+        for quantity in Quantity::ALL {
+            for level in Level::ALL {
+                let old = old.limit(quantity).threshold(level);
+                let new = config.limit(quantity).threshold(level);
+                let fields = [
+                    (Field::Value, old.value != new.value),
+                    (Field::Hysteresis, old.hysteresis != new.hysteresis),
+                    (Field::Delay, old.delay_ms != new.delay_ms),
+                ];
+                for (field, _) in fields.into_iter().filter(|&(_, changed)| changed) {
+                    let setting = Setting::Threshold(quantity, level, field);
+                    self.record(EventKind::ConfigChanged, setting.id());
+                }
+            }
+        }
+    }
+
+    /// Returns the cells of the pack, at most [`CELLS`].
+    pub fn pack_cells(&self) -> &[Cell] {
+        &self.cells[..self.cell_count()]
+    }
+
+    /// Returns the number of cells of the pack, at most [`CELLS`].
+    pub fn cell_count(&self) -> usize {
+        usize::from(self.pack.cells).min(CELLS)
+    }
+
+    /// Advances the balancing, the protection, the state of charge and the
+    /// limits by `dt_us` with the latest cell readings.
+    pub fn update(&mut self, dt_us: u32) {
+        let elapsed_us = self.remainder_us.saturating_add(dt_us);
+        let dt_ms = elapsed_us / 1000;
+        self.remainder_us = elapsed_us % 1000;
+
+        let count = self.cell_count();
+        let cells = &self.cells[..count];
+        self.planner
+            .update(dt_ms, cells, &mut self.requests[..count]);
+        let measurements = Measurements::from_cells(cells, CURRENT_MA);
+        let decision = self.protection.update(dt_ms, &measurements);
+        let soc = self.estimator.update(
+            dt_ms,
+            &soc::Measurements {
+                current_ma: CURRENT_MA,
+                cell_mv: average_mv(cells),
+                // The capacity is lowest in the coldest cell.
+                temperature: measurements.min_temperature,
+            },
+        );
+        let inputs = Inputs {
+            max_cell_mv: measurements.max_cell_mv,
+            min_cell_mv: measurements.min_cell_mv,
+            soc,
+            max_temperature: measurements.max_temperature,
+            min_temperature: measurements.min_temperature,
+        };
+        self.limiter.update(dt_ms, &inputs, &decision);
+    }
+
+    /// Returns the pack voltage, `None` unless all cells were read.
+    pub fn voltage_mv(&self) -> Option<u32> {
+        self.pack_cells()
+            .iter()
+            .map(|cell| cell.voltage_mv.map(u32::from))
+            .sum()
+    }
+
+    /// Returns the pack current, positive while charging.
+    pub const fn current_ma(&self) -> Option<i32> {
+        CURRENT_MA
+    }
+
+    /// Returns the state of charge in 0.01 %.
+    pub fn soc(&self) -> Option<u16> {
+        self.estimator.soc()
+    }
+
+    /// Returns the current and voltage limits.
+    pub fn limits(&self) -> Limits {
+        self.limiter.limits()
+    }
+
+    /// Applies a changed setting and writes it to the EEPROM.
+    fn apply_config(&mut self, setting: Setting) {
+        self.planner.set_config(self.balancing);
+        if self.limiter.config().cells != self.pack.cells {
+            self.limiter = Limiter::new(limits_config(self.pack.cells));
+        }
+        self.save_config();
+        self.record(EventKind::ConfigChanged, setting.id());
+    }
+
+    /// Writes the changed settings to the EEPROM.
+    fn save_config(&mut self) {
+        let config = Config {
+            protection: *self.protection.config(),
+            balancing: self.balancing,
+            cells: self.pack.cells,
+            modbus_unit: self.pack.modbus_unit,
+        };
+        // The settings stay in effect if the write fails. After a reset the
+        // previous copy in the EEPROM is used.
+        let _ = self.config.save(config);
+    }
+}
+
+impl Target for State {
+    type I2c = twi::I2c;
+
+    fn i2c(&mut self) -> &mut Self::I2c {
+        &mut self.i2c
+    }
+
+    fn cells(&self) -> &[Cell] {
+        self.pack_cells()
+    }
+
+    fn protection(&mut self) -> &mut Protection {
+        &mut self.protection
+    }
+
+    fn balancing_config(&mut self) -> &mut balancing::Config {
+        &mut self.balancing
+    }
+
+    fn pack_config(&mut self) -> &mut PackConfig {
+        &mut self.pack
+    }
+
+    fn config_changed(&mut self, setting: Setting) {
+        self.apply_config(setting);
+    }
+
+    fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+
+    fn crash(&self) -> Option<CrashReport> {
+        self.crash
+    }
+
+    fn event_count(&self) -> u16 {
+        self.log.len().try_into().unwrap_or(u16::MAX)
+    }
+
+    fn lost_events(&self) -> u16 {
+        self.lost_events
+    }
+
+    fn event(&self, index: u16) -> Option<Event> {
+        self.log.get(index.into())
+    }
+}
+
+impl Pack for State {
+    fn firmware(&self) -> Version {
+        FIRMWARE
+    }
+
+    fn soc(&self) -> Option<u16> {
+        self.soc()
+    }
+
+    fn voltage_mv(&self) -> Option<u32> {
+        self.voltage_mv()
+    }
+
+    fn current_ma(&self) -> Option<i32> {
+        self.current_ma()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits()
+    }
+
+    fn cells(&self) -> &[Cell] {
+        self.pack_cells()
+    }
+
+    fn is_bleeding(&self, cell: usize) -> bool {
+        self.planner.bleeding().get(cell).copied().unwrap_or(false)
+    }
+
+    fn protection(&mut self) -> &mut Protection {
+        &mut self.protection
+    }
+
+    fn balancing_config(&mut self) -> &mut balancing::Config {
+        &mut self.balancing
+    }
+
+    fn pack_config(&mut self) -> &mut PackConfig {
+        &mut self.pack
+    }
+
+    fn config_changed(&mut self, setting: Setting) {
+        self.apply_config(setting);
+    }
+
+    fn event(&self, index: u16) -> Option<Event> {
+        self.log.get(index.into())
+    }
+
+    fn crash(&self) -> Option<CrashReport> {
+        self.crash
+    }
+}
+
+/// Returns the limits settings for a pack of `cells` cells.
+fn limits_config(cells: u8) -> limits::Config<'static> {
+    limits::Config {
+        cells,
+        ..limits::Config::default()
+    }
+}
+
+/// Returns the average cell voltage, `None` unless all cells were read.
+fn average_mv(cells: &[Cell]) -> Option<u16> {
+    let total: u32 = cells
+        .iter()
+        .map(|cell| cell.voltage_mv.map(u32::from))
+        .sum::<Option<u32>>()?;
+    let count = u32::try_from(cells.len()).ok().filter(|&count| count > 0)?;
+    u16::try_from(total / count).ok()
+}
+
+/// Appends an event with the current uptime to the log.
+///
+/// A worn out slot only loses this event, which is counted in `lost`. The
+/// next one goes to the following slot.
+fn record(log: &mut EventLog<AppData>, lost: &mut u16, kind: EventKind, data: u16) {
+    if log.append(clock::uptime_s(), kind, data).is_err() {
+        *lost = lost.saturating_add(1);
+    }
+}
+
+/// Formatted output on the serial port.
+struct Output<'a>(&'a mut Serial<usart::Console>);
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Command console and host tool requests on the debug serial port.
+pub struct DebugConsole {
+    serial: Serial<usart::Console>,
+    console: Console<LINE_LEN>,
+    decoder: Decoder,
+}
+
+impl DebugConsole {
+    /// Creates the console and shows the prompt.
+    pub fn new(mut serial: Serial<usart::Console>) -> Self {
+        let console = Console::new();
+        let _ = console.prompt(&mut Output(&mut serial));
+        Self {
+            serial,
+            console,
+            decoder: Decoder::new(),
+        }
+    }
+
+    /// Processes the received characters without blocking.
+    pub fn poll(&mut self, state: &mut State) {
+        while self.serial.read_ready().unwrap_or(false) {
+            let mut byte = [0];
+            // Garbled characters are dropped, the user just types again.
+            if !matches!(self.serial.read(&mut byte), Ok(1)) {
+                continue;
+            }
+            let [byte] = byte;
+            if byte == SYNC || self.decoder.is_receiving() {
+                if let Some(Ok(frame)) = self.decoder.push(byte) {
+                    let mut reply = [0; MAX_FRAME_LEN];
+                    if let Some(len) = host::handle(&frame, state, &mut reply) {
+                        let _ = self.serial.write_all(&reply[..len]);
+                    }
+                }
+                continue;
+            }
+            let _ = self
+                .console
+                .feed(byte, state, &mut Output(&mut self.serial));
+        }
+    }
+}
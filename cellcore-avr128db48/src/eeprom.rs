@@ -0,0 +1,72 @@
+//! EEPROM access through NVMCTRL.
+//!
+//! The 512 bytes of EEPROM are mapped into the data space, so reads are plain
+//! loads. Writes use the erase and write command, which erases and writes
+//! every byte stored to the mapped EEPROM while it is active.
+
+use core::ptr;
+
+use cellguard_bsp::cpu::{CCP_SPM, ccp_write};
+use cellguard_config::Nvm;
+
+use crate::pac;
+
+/// Start of the EEPROM in the data space.
+const EEPROM_START: usize = 0x1400;
+/// Size of the EEPROM.
+pub const EEPROM_SIZE: usize = 512;
+/// Data space address of NVMCTRL.CTRLA.
+const NVMCTRL_CTRLA: u16 = 0x1000;
+/// CTRLA: No command, required between two commands.
+const CMD_NOCMD: u8 = 0x00;
+/// CTRLA: EEPROM erase and write enable.
+const CMD_EEERWR: u8 = 0x13;
+/// STATUS: EEPROM busy.
+const STATUS_EEBUSY: u8 = 1 << 1;
+
+/// EEPROM of the AVR128DB48.
+pub struct Eeprom {
+    nvmctrl: pac::NVMCTRL,
+}
+
+impl Eeprom {
+    /// Takes NVMCTRL for the EEPROM.
+    pub fn new(nvmctrl: pac::NVMCTRL) -> Self {
+        Self { nvmctrl }
+    }
+
+    fn wait(&self) {
+        while self.nvmctrl.status().read().bits() & STATUS_EEBUSY != 0 {}
+    }
+
+    /// Writes a command to the protected CTRLA register.
+    fn command(&mut self, command: u8) {
+        // SAFETY: Both commands are valid and don't touch the flash.
+        unsafe { ccp_write(NVMCTRL_CTRLA, CCP_SPM, command) };
+    }
+}
+
+impl Nvm for Eeprom {
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= EEPROM_SIZE);
+        self.wait();
+        for (address, byte) in (EEPROM_START + offset..).zip(buf) {
+            // SAFETY: The address lies within the mapped EEPROM.
+            *byte = unsafe { ptr::read_volatile(address as *const u8) };
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= EEPROM_SIZE);
+        self.wait();
+        self.command(CMD_NOCMD);
+        self.command(CMD_EEERWR);
+        for (address, &byte) in (EEPROM_START + offset..).zip(data) {
+            // SAFETY: The address lies within the mapped EEPROM, where the
+            // store starts the erase and write of this byte.
+            unsafe { ptr::write_volatile(address as *mut u8, byte) };
+            self.wait();
+        }
+        self.command(CMD_NOCMD);
+    }
+}
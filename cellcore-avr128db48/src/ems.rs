@@ -0,0 +1,64 @@
+//! Modbus RTU server for the energy management system.
+//!
+//! Serves the register map of [`modbus_rtu::pack`] on the RS-485 port.
+//! Written protection thresholds are applied and saved like changes on the
+//! console.
+
+use avr_usart::Serial;
+use modbus_rtu::pack::{PackRegisters, Telemetry};
+use modbus_rtu::{Rs485, Server, Timing};
+
+use crate::console::{CELLS, State};
+use crate::usart::{self, DriverEnable};
+
+/// Modbus server on the RS-485 port.
+pub struct Ems {
+    server: Server,
+    port: Rs485<Serial<usart::Ems>, DriverEnable>,
+}
+
+impl Ems {
+    /// Creates the server at `unit` and disables the driver of the
+    /// transceiver.
+    pub fn new((serial, driver_enable): (Serial<usart::Ems>, DriverEnable), unit: u8) -> Self {
+        let Ok(port) = Rs485::new(serial, driver_enable);
+        Self {
+            server: Server::new(unit, Timing::new(usart::EMS_BAUD)),
+            port,
+        }
+    }
+
+    /// Processes the received bytes `dt_us` after the last call and answers
+    /// a complete request.
+    pub fn poll(&mut self, dt_us: u32, state: &mut State) {
+        // The unit address may have been changed on the console or by a host
+        // tool.
+        self.server.set_unit(state.pack.modbus_unit);
+        let mut voltages = [None; CELLS];
+        let mut temperatures = [None; CELLS];
+        let cells = state.pack_cells();
+        for (cell, (voltage, temperature)) in
+            cells.iter().zip(voltages.iter_mut().zip(&mut temperatures))
+        {
+            *voltage = cell.voltage_mv;
+            *temperature = cell.temperature;
+        }
+        let count = cells.len();
+        let mut config = *state.protection.config();
+        let telemetry = Telemetry {
+            soc: state.soc(),
+            voltage_mv: state.voltage_mv(),
+            current_ma: state.current_ma(),
+            cells: &voltages[..count],
+            temperatures: &temperatures[..count],
+            protection: &state.protection,
+            limits: state.limits(),
+        };
+        let mut registers = PackRegisters::new(telemetry, &mut config);
+        // A garbled request goes unanswered and the client repeats it.
+        let _ = self.server.poll(&mut self.port, dt_us, &mut registers);
+        if registers.is_changed() {
+            state.set_protection(config);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Event log pages in the APPDATA section of the flash.
+//!
+//! The log takes the last [`PAGES`] pages of the flash. Code may only write
+//! the flash of a later section, so the fuses split the flash into a boot
+//! section of one page holding the interrupt vectors, the firmware in
+//! APPCODE and the log in APPDATA (BOOTSIZE 0x01, CODESIZE 0xFC). See
+//! [`vectors_in_boot_section`].
+//!
+//! Uploading the firmware leaves the fuses alone, they are programmed once
+//! per board:
+//!
+//! ```text
+//! avrdude -c pkobn_updi -p avr128db48 -U bootsize:w:0x01:m -U codesize:w:0xFC:m
+//! ```
+//!
+//! [`AppData::new`] checks them. With other fuses the log isn't written, so
+//! every event is lost. The linker keeps the firmware out of APPDATA, see
+//! `.cargo/config.toml`.
+//!
+//! The last 32 KiB of the flash are mapped into the data space after reset,
+//! so reads are plain loads. Erasing and writing use the page erase and
+//! write commands, which act on the flash stored to while they are active.
+
+use core::ptr;
+
+use cellguard_bsp::cpu::{CCP_IOREG, CCP_SPM, ccp_write};
+use cellguard_log::Flash;
+
+use crate::pac;
+
+/// Number of pages of the log.
+pub const PAGES: usize = 4;
+/// Size of a flash page.
+const PAGE_SIZE: usize = 512;
+/// Size of the flash.
+const FLASH_SIZE: usize = 0x2_0000;
+/// Data space address of the last 32 KiB of the flash.
+const MAPPED_FLASH: usize = 0x8000;
+/// Size of the mapped flash.
+const MAPPED_SIZE: usize = 0x8000;
+/// Data space address of the log.
+const LOG_START: usize = MAPPED_FLASH + MAPPED_SIZE - PAGES * PAGE_SIZE;
+/// Data space address of FUSE.CODESIZE, followed by FUSE.BOOTSIZE.
+const FUSE_CODESIZE: usize = 0x1057;
+/// FUSE.CODESIZE: End of APPCODE in pages, the log follows.
+const CODESIZE: u8 = ((FLASH_SIZE - PAGES * PAGE_SIZE) / PAGE_SIZE) as u8;
+/// FUSE.BOOTSIZE: Boot section of one page.
+const BOOTSIZE: u8 = 0x01;
+/// Data space address of NVMCTRL.CTRLA.
+const NVMCTRL_CTRLA: u16 = 0x1000;
+/// Data space address of CPUINT.CTRLA.
+const CPUINT_CTRLA: u16 = 0x0110;
+/// CPUINT.CTRLA: Interrupt vectors at the start of the boot section.
+const CPUINT_IVSEL: u8 = 1 << 6;
+/// CTRLA: No command, required between two commands.
+const CMD_NOCMD: u8 = 0x00;
+/// CTRLA: Flash write enable.
+const CMD_FLWR: u8 = 0x02;
+/// CTRLA: Flash page erase enable.
+const CMD_FLPER: u8 = 0x08;
+/// STATUS: Flash busy.
+const STATUS_FBUSY: u8 = 1 << 0;
+
+/// Moves the interrupt vectors to the boot section.
+///
+/// The firmware is linked with the vectors at address 0, which is the boot
+/// section once BOOTSIZE is set. Must run before interrupts are enabled.
+pub fn vectors_in_boot_section() {
+    // SAFETY: The vectors stay where the firmware was linked to.
+    unsafe { ccp_write(CPUINT_CTRLA, CCP_IOREG, CPUINT_IVSEL) };
+}
+
+/// Pages of the event log in the flash of the AVR128DB48.
+pub struct AppData {
+    nvmctrl: pac::NVMCTRL,
+    /// The fuses put the log pages into APPDATA.
+    writable: bool,
+}
+
+impl AppData {
+    /// Takes NVMCTRL for the flash and checks the fuses.
+    ///
+    /// With other fuses the pages read as erased and writes are ignored.
+    pub fn new(nvmctrl: pac::NVMCTRL) -> Self {
+        let mut fuses = [0; 2];
+        for (address, fuse) in (FUSE_CODESIZE..).zip(&mut fuses) {
+            // SAFETY: The fuses are mapped into the data space and readable.
+            *fuse = unsafe { ptr::read_volatile(address as *const u8) };
+        }
+        Self {
+            nvmctrl,
+            writable: fuses == [CODESIZE, BOOTSIZE],
+        }
+    }
+
+    fn wait(&self) {
+        while self.nvmctrl.status().read().bits() & STATUS_FBUSY != 0 {}
+    }
+
+    /// Writes a command to the protected CTRLA register.
+    fn command(&mut self, command: u8) {
+        // SAFETY: Both commands only act on the addresses stored to
+        // afterwards.
+        unsafe { ccp_write(NVMCTRL_CTRLA, CCP_SPM, command) };
+    }
+}
+
+impl Flash for AppData {
+    const PAGE_LEN: usize = PAGE_SIZE;
+    const PAGES: usize = PAGES;
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= PAGES * PAGE_SIZE);
+        if !self.writable {
+            buf.fill(0xFF);
+            return;
+        }
+        for (address, byte) in (LOG_START + offset..).zip(buf) {
+            // SAFETY: The address lies within the mapped log pages.
+            *byte = unsafe { ptr::read_volatile(address as *const u8) };
+        }
+    }
+
+    fn erase(&mut self, page: usize) {
+        assert!(page < PAGES);
+        if !self.writable {
+            return;
+        }
+        self.wait();
+        self.command(CMD_NOCMD);
+        self.command(CMD_FLPER);
+        let address = LOG_START + page * PAGE_SIZE;
+        // SAFETY: The address lies within the mapped log pages, where the
+        // store starts erasing the page.
+        unsafe { ptr::write_volatile(address as *mut u8, 0xFF) };
+        self.wait();
+        self.command(CMD_NOCMD);
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= PAGES * PAGE_SIZE);
+        if !self.writable {
+            return;
+        }
+        self.wait();
+        self.command(CMD_NOCMD);
+        self.command(CMD_FLWR);
+        for (address, &byte) in (LOG_START + offset..).zip(data) {
+            // SAFETY: The address lies within the mapped log pages, where the
+            // store writes this byte.
+            unsafe { ptr::write_volatile(address as *mut u8, byte) };
+            self.wait();
+        }
+        self.command(CMD_NOCMD);
+    }
+}
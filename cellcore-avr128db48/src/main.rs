@@ -2,56 +2,104 @@
 #![no_main]
 #![feature(abi_avr_interrupt)]
 
-use avr_device::asm::delay_cycles;
 use avr_device::avr128db48 as pac;
+use cellcore::config::ConfigStore;
+use cellcore::console::ResetCause;
+use cellcore::events::EventLog;
+use cellguard_bsp::{app, crash};
 
+use crate::board::Board;
 use crate::pac::Peripherals;
 
+mod agents;
+mod board;
+mod clock;
+mod console;
+mod eeprom;
+mod ems;
+mod flash;
+mod twi;
+mod usart;
+
+/// Uptime after which a crash no longer counts as part of a crash loop.
+const STABLE_S: u32 = 600;
+
 #[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
     // disable interrupts - firmware has panicked so no ISRs should continue running
     avr_device::interrupt::disable();
 
-    // get the peripherals so we can access serial and the LED.
+    // keep the report for the next run and start over.
     //
-    // SAFETY: Because main() already has references to the peripherals this is an
-    //         unsafe operation - but because no other code can run after the panic
+    // SAFETY: Because main() already holds the crash report this is an unsafe
+    //         operation - but because no other code can run after the panic
     //         handler was called, we know it is okay.
-    let p = unsafe { Peripherals::steal() };
-    loop {
-        set_led(&p.PORTB, true);
-        delay_cycles(500);
-        set_led(&p.PORTB, false);
-        delay_cycles(1000);
-    }
+    crash::record(
+        unsafe { crash::retained() },
+        info,
+        crash::message_hash(info.message()),
+    );
+    crash::reboot()
 }
 
 #[avr_device::entry]
 fn main() -> ! {
-    let Peripherals { PORTB, .. } = unsafe { Peripherals::steal() };
+    let Peripherals {
+        NVMCTRL,
+        PORTA,
+        PORTB,
+        PORTC,
+        RTC,
+        TWI0,
+        USART0,
+        USART1,
+        USART3,
+        ..
+    } = unsafe { Peripherals::steal() };
+
+    flash::vectors_in_boot_section();
+    let reset_cause = ResetCause(crash::reset_flags());
+    // SAFETY: Only the panic handler takes the block again.
+    let retained = unsafe { crash::retained() };
+    let crash_report = retained.boot(reset_cause.0);
+    let safe_mode = crash_report.is_some_and(|report| report.safe_mode);
+    clock::init(&RTC);
+    let mut stopwatch = clock::Stopwatch::new(RTC);
+    let mut debug = console::DebugConsole::new(usart::console(USART3, &PORTB));
+    let mut board = Board::new(PORTB);
+    let mut config = ConfigStore::load(eeprom::Eeprom::new(NVMCTRL), 0);
+    // A failed write only repeats the migration after the next reset.
+    let _ = config.finish_migration();
+    let mut agents = agents::Agents::new(usart::cell_bus(USART0, &PORTA), config.config().cells);
+    // SAFETY: The EEPROM and the flash are only written from the main loop,
+    // so their commands never overlap.
+    let log = EventLog::mount(flash::AppData::new(unsafe { pac::NVMCTRL::steal() }));
+    let mut state = console::State::new(twi::init(TWI0), config, reset_cause, crash_report, log);
+    let mut ems = ems::Ems::new(usart::ems(USART1, PORTC), state.pack.modbus_unit);
 
-    init_portb(&PORTB);
+    // SAFETY: All shared state is initialized at this point.
+    unsafe { avr_device::interrupt::enable() };
 
+    let mut stable = false;
     loop {
-        let switch_pressed = read_switch(&PORTB);
-        set_led(&PORTB, switch_pressed);
+        debug.poll(&mut state);
+        if safe_mode {
+            // Only the console and host tools are served until a reset that
+            // isn't caused by a crash.
+            continue;
+        }
+        // The first poll enumerates the cellagents, so does the next one
+        // after the number of cells was changed.
+        agents.set_cells(state.pack.cells);
+        agents.poll(&mut state);
+        let dt_us = stopwatch.lap_us();
+        state.update(dt_us);
+        ems.poll(dt_us, &mut state);
+        app::mirror_switch(&mut board);
+        state.monitor();
+        if !stable && clock::uptime_s() >= STABLE_S {
+            retained.stable();
+            stable = true;
+        }
     }
 }
-
-fn init_portb(reg: &pac::PORTB) {
-    // Set PB3 as output
-    reg.dirset().write(|w| w.pb3().set_bit());
-    // Ensure PB2 is input
-    reg.dirclr().write(|w| w.pb2().set_bit());
-    // Enable internal pull-up on PB2 so it reads high when not pressed
-    reg.pin2ctrl().write(|w| w.pullupen().set_bit());
-}
-
-fn set_led(reg: &pac::PORTB, on: bool) {
-    // LED is likely wired active-low; drive low to turn on
-    reg.out().modify(|_r, w| w.pb3().bit(!on));
-}
-
-fn read_switch(reg: &pac::PORTB) -> bool {
-    reg.in_().read().pb2().bit_is_clear()
-}
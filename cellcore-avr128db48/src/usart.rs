@@ -0,0 +1,214 @@
+//! Binding of the USART driver to USART3 for the debug console, USART0 for
+//! the cell bus and USART1 for the energy management system.
+//!
+//! The debug console uses TxD on PB0 and RxD on PB1 (default routing), which
+//! are connected to the virtual COM port of the debugger. The cell bus is a
+//! one-wire bus on PA0 (TxD of USART0, default routing) with an external
+//! pull-up resistor. The RS-485 transceiver of the energy management system
+//! is connected to TxD on PC0 and RxD on PC1 (default routing), its driver
+//! enable to PC3.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::ops::Deref;
+
+use avr_device::interrupt::{self, Mutex};
+use avr_usart::{Config, Mode, Parity, Register, Registers, Serial, Shared};
+use embedded_hal::digital::{ErrorType, OutputPin};
+
+use crate::board::CLK_PER_HZ;
+use crate::pac;
+
+/// Size of the receive and transmit buffers of each USART.
+const BUFFER_SIZE: usize = 64;
+
+/// 115200 baud 8N1.
+///
+/// Evaluated at compile time so the 32-bit division for the baud rate doesn't
+/// end up in flash.
+const CONSOLE_CONFIG: Config = Config::new(CLK_PER_HZ, 115_200);
+
+/// 19200 baud 8N1 on a single wire.
+const CELL_BUS_CONFIG: Config = Config::new(CLK_PER_HZ, 19_200).with_mode(Mode::OneWire);
+
+/// Baud rate of the energy management system.
+pub const EMS_BAUD: u32 = 19_200;
+
+/// 8E1, the default of Modbus RTU.
+const EMS_CONFIG: Config = Config::new(CLK_PER_HZ, EMS_BAUD).with_parity(Parity::Even);
+
+/// Driver shared with the interrupt handlers.
+type Driver<P> = avr_usart::Usart<Usart<P>, BUFFER_SIZE>;
+
+/// Registers of a USART instance.
+pub struct Usart<P>(P);
+
+impl<P: Deref<Target = pac::usart0::RegisterBlock>> Registers for Usart<P> {
+    fn read(&mut self, reg: Register) -> u8 {
+        let usart = &self.0;
+        match reg {
+            Register::RxDataL => usart.rxdatal().read().bits(),
+            Register::RxDataH => usart.rxdatah().read().bits(),
+            Register::TxDataL => usart.txdatal().read().bits(),
+            Register::Status => usart.status().read().bits(),
+            Register::CtrlA => usart.ctrla().read().bits(),
+            Register::CtrlB => usart.ctrlb().read().bits(),
+            Register::CtrlC => usart.ctrlc().read().bits(),
+        }
+    }
+
+    fn write(&mut self, reg: Register, value: u8) {
+        let usart = &self.0;
+        // SAFETY: The driver only writes values that are valid for the respective
+        // register according to the datasheet.
+        unsafe {
+            match reg {
+                Register::RxDataL | Register::RxDataH => {}
+                Register::TxDataL => usart.txdatal().write(|w| w.bits(value)),
+                Register::Status => usart.status().write(|w| w.bits(value)),
+                Register::CtrlA => usart.ctrla().write(|w| w.bits(value)),
+                Register::CtrlB => usart.ctrlb().write(|w| w.bits(value)),
+                Register::CtrlC => usart.ctrlc().write(|w| w.bits(value)),
+            };
+        }
+    }
+
+    fn write_baud(&mut self, baud: u16) {
+        // SAFETY: Every value is a valid baud rate setting.
+        unsafe { self.0.baud().write(|w| w.bits(baud)) };
+    }
+}
+
+static CONSOLE: Mutex<RefCell<Option<Driver<pac::USART3>>>> = Mutex::new(RefCell::new(None));
+static CELL_BUS: Mutex<RefCell<Option<Driver<pac::USART0>>>> = Mutex::new(RefCell::new(None));
+static EMS: Mutex<RefCell<Option<Driver<pac::USART1>>>> = Mutex::new(RefCell::new(None));
+
+/// Calls `f` with the driver in `cell` while interrupts are disabled.
+///
+/// Does nothing if the driver wasn't initialized yet.
+fn with<P, T>(
+    cell: &Mutex<RefCell<Option<Driver<P>>>>,
+    f: impl FnOnce(&mut Driver<P>) -> T,
+) -> Option<T>
+where
+    P: Deref<Target = pac::usart0::RegisterBlock>,
+{
+    interrupt::free(|cs| cell.borrow(cs).borrow_mut().as_mut().map(f))
+}
+
+/// Debug console on USART3.
+pub struct Console(());
+
+impl Shared for Console {
+    type Target = Driver<pac::USART3>;
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T {
+        // A `Console` only exists after the driver was initialized.
+        with(&CONSOLE, f).unwrap()
+    }
+}
+
+/// Cell bus on USART0.
+pub struct CellBus(());
+
+impl Shared for CellBus {
+    type Target = Driver<pac::USART0>;
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T {
+        // A `CellBus` only exists after the driver was initialized.
+        with(&CELL_BUS, f).unwrap()
+    }
+}
+
+/// Energy management system on USART1.
+pub struct Ems(());
+
+impl Shared for Ems {
+    type Target = Driver<pac::USART1>;
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T {
+        // An `Ems` only exists after the driver was initialized.
+        with(&EMS, f).unwrap()
+    }
+}
+
+/// Driver enable of the RS-485 transceiver on PC3.
+pub struct DriverEnable(pac::PORTC);
+
+impl ErrorType for DriverEnable {
+    type Error = Infallible;
+}
+
+impl OutputPin for DriverEnable {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.outclr().write(|w| w.pc3().set_bit());
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.outset().write(|w| w.pc3().set_bit());
+        Ok(())
+    }
+}
+
+/// Enables USART3 as the debug console.
+///
+/// The receiver only runs once interrupts are enabled.
+pub fn console(usart: pac::USART3, portb: &pac::PORTB) -> Serial<Console> {
+    portb.dirset().write(|w| w.pb0().set_bit());
+    let driver = Driver::new(Usart(usart), CONSOLE_CONFIG);
+    interrupt::free(|cs| CONSOLE.borrow(cs).replace(Some(driver)));
+    Serial::new(Console(()))
+}
+
+/// Enables USART0 as the one-wire cell bus.
+///
+/// The receiver only runs once interrupts are enabled.
+pub fn cell_bus(usart: pac::USART0, porta: &pac::PORTA) -> Serial<CellBus> {
+    // The USART drives the pin as open drain.
+    porta.dirset().write(|w| w.pa0().set_bit());
+    let driver = Driver::new(Usart(usart), CELL_BUS_CONFIG);
+    interrupt::free(|cs| CELL_BUS.borrow(cs).replace(Some(driver)));
+    Serial::new(CellBus(()))
+}
+
+/// Enables USART1 for the RS-485 transceiver of the energy management system.
+///
+/// The receiver only runs once interrupts are enabled. The driver enable is
+/// left to the caller, which switches it around every transmission.
+pub fn ems(usart: pac::USART1, portc: pac::PORTC) -> (Serial<Ems>, DriverEnable) {
+    portc.dirset().write(|w| w.pc0().set_bit().pc3().set_bit());
+    let driver = Driver::new(Usart(usart), EMS_CONFIG);
+    interrupt::free(|cs| EMS.borrow(cs).replace(Some(driver)));
+    (Serial::new(Ems(())), DriverEnable(portc))
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART3_RXC() {
+    with(&CONSOLE, Driver::on_receive);
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART3_DRE() {
+    with(&CONSOLE, Driver::on_data_register_empty);
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART0_RXC() {
+    with(&CELL_BUS, Driver::on_receive);
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART0_DRE() {
+    with(&CELL_BUS, Driver::on_data_register_empty);
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART1_RXC() {
+    with(&EMS, Driver::on_receive);
+}
+
+#[avr_device::interrupt(avr128db48)]
+fn USART1_DRE() {
+    with(&EMS, Driver::on_data_register_empty);
+}
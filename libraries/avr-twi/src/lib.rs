@@ -0,0 +1,353 @@
+//! Host mode driver for the TWI peripheral of AVR Dx and tinyAVR 1-series
+//! devices.
+//!
+//! Both families share the same TWI host register interface, so the driver is
+//! written once against the [`Registers`] trait. The firmware provides the
+//! implementation for its device while tests can substitute a simulated
+//! peripheral.
+//!
+//! The driver uses polling. Every wait for the peripheral is bounded by a
+//! timeout, after which the host is flushed and the bus is forced back into
+//! the idle state.
+
+#![no_std]
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{self, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use self::register::*;
+pub use self::register::{Register, Registers};
+
+pub mod register;
+
+/// Default timeout for a single bus operation.
+const DEFAULT_TIMEOUT_US: u32 = 10_000;
+
+/// Calculates the `MBAUD` register value for the given SCL frequency.
+///
+/// Uses the formula from the datasheet
+/// `f_SCL = f_CLK_PER / (10 + 2 * BAUD + f_CLK_PER * t_R)` where `t_R` is the
+/// rise time of the bus lines. The result saturates to the valid range, so the
+/// actual frequency may be higher or lower than requested if it can't be
+/// reached with the given peripheral clock.
+#[must_use]
+pub const fn baud(clk_per_hz: u32, scl_hz: u32, rise_time_ns: u32) -> u8 {
+    let cycles = clk_per_hz / scl_hz;
+    // Split the multiplication to avoid overflowing for realistic clocks.
+    let rise_cycles = (clk_per_hz / 1_000) * rise_time_ns / 1_000_000;
+    let overhead = 10 + rise_cycles;
+    if cycles <= overhead {
+        return 0;
+    }
+    let baud = (cycles - overhead) / 2;
+    if baud > u8::MAX as u32 {
+        u8::MAX
+    } else {
+        baud as u8
+    }
+}
+
+/// Driver configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    baud: u8,
+    timeout_us: u32,
+}
+
+impl Config {
+    /// Creates a configuration for the given peripheral clock and SCL
+    /// frequency.
+    ///
+    /// The rise time of the bus lines is neglected, use [`Self::with_baud`]
+    /// together with [`baud`] to account for it.
+    #[must_use]
+    pub const fn new(clk_per_hz: u32, scl_hz: u32) -> Self {
+        Self {
+            baud: baud(clk_per_hz, scl_hz, 0),
+            timeout_us: DEFAULT_TIMEOUT_US,
+        }
+    }
+
+    /// Returns the `MBAUD` register value.
+    #[must_use]
+    pub const fn baud(self) -> u8 {
+        self.baud
+    }
+
+    /// Sets the `MBAUD` register value.
+    #[must_use]
+    pub const fn with_baud(mut self, baud: u8) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Returns the timeout for a single bus operation in microseconds.
+    #[must_use]
+    pub const fn timeout_us(self) -> u32 {
+        self.timeout_us
+    }
+
+    /// Sets the timeout for a single bus operation in microseconds.
+    ///
+    /// A bus operation is the transfer of the address or of a single data
+    /// byte, or waiting for the bus to become idle before a transaction.
+    #[must_use]
+    pub const fn with_timeout_us(mut self, timeout_us: u32) -> Self {
+        self.timeout_us = timeout_us;
+        self
+    }
+}
+
+/// TWI host error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// An illegal bus condition was detected.
+    Bus,
+    /// Another host won the arbitration.
+    ArbitrationLoss,
+    /// The client didn't acknowledge the address or a data byte.
+    NoAcknowledge(NoAcknowledgeSource),
+    /// The peripheral didn't complete an operation in time.
+    ///
+    /// This is usually caused by a client stretching the clock indefinitely or
+    /// another host occupying the bus.
+    Timeout,
+}
+
+impl i2c::Error for Error {
+    fn kind(&self) -> i2c::ErrorKind {
+        match *self {
+            Self::Bus => i2c::ErrorKind::Bus,
+            Self::ArbitrationLoss => i2c::ErrorKind::ArbitrationLoss,
+            Self::NoAcknowledge(source) => i2c::ErrorKind::NoAcknowledge(source),
+            Self::Timeout => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+/// Direction of a bus transfer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Write,
+    Read,
+}
+
+impl Direction {
+    const fn of(operation: &Operation<'_>) -> Self {
+        match operation {
+            Operation::Write(_) => Self::Write,
+            Operation::Read(_) => Self::Read,
+        }
+    }
+}
+
+/// TWI host driver.
+pub struct Twi<R, D> {
+    regs: R,
+    delay: D,
+    timeout_us: u32,
+}
+
+impl<R: Registers, D: DelayNs> Twi<R, D> {
+    /// Creates a new driver instance and enables the host.
+    pub fn new(mut regs: R, delay: D, config: Config) -> Self {
+        regs.write(Register::MBaud, config.baud);
+        regs.write(Register::MCtrlA, MCTRLA_ENABLE | MCTRLA_TIMEOUT_200US);
+        // The bus state is unknown after enabling the host. We assume that nobody
+        // else is using the bus at this point.
+        regs.write(
+            Register::MStatus,
+            MSTATUS_RIF | MSTATUS_WIF | MSTATUS_ARBLOST | MSTATUS_BUSERR | MSTATUS_BUSSTATE_IDLE,
+        );
+        Self {
+            regs,
+            delay,
+            timeout_us: config.timeout_us,
+        }
+    }
+
+    /// Disables the host and releases the registers and the delay.
+    pub fn release(mut self) -> (R, D) {
+        self.regs.write(Register::MCtrlA, 0);
+        (self.regs, self.delay)
+    }
+
+    /// Changes the SCL frequency.
+    pub fn set_baud(&mut self, baud: u8) {
+        // MBAUD must only be written while the host is disabled.
+        let mctrla = self.regs.read(Register::MCtrlA);
+        self.regs.write(Register::MCtrlA, mctrla & !MCTRLA_ENABLE);
+        self.regs.write(Register::MBaud, baud);
+        self.regs.write(Register::MCtrlA, mctrla);
+        self.regs.write(Register::MStatus, MSTATUS_BUSSTATE_IDLE);
+    }
+
+    fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+        self.wait_for_bus()?;
+
+        let mut previous = None;
+        for i in 0..operations.len() {
+            let direction = Direction::of(&operations[i]);
+            // Adjacent operations of the same type are merged into one transfer.
+            let continued = operations
+                .get(i + 1)
+                .is_some_and(|next| Direction::of(next) == direction);
+            if previous != Some(direction) {
+                self.start(address, direction)?;
+            }
+            match &mut operations[i] {
+                Operation::Write(bytes) => {
+                    for &byte in bytes.iter() {
+                        self.write_byte(byte)?;
+                    }
+                }
+                Operation::Read(buf) => {
+                    let len = buf.len();
+                    for (j, byte) in buf.iter_mut().enumerate() {
+                        let last = j + 1 == len && !continued;
+                        *byte = self.read_byte(last)?;
+                    }
+                    if len == 0 && !continued {
+                        // The host already received a byte after the address. We
+                        // discard it and answer with a NACK.
+                        self.regs.write(Register::MCtrlB, MCTRLB_ACKACT_NACK);
+                    }
+                }
+            }
+            previous = Some(direction);
+        }
+
+        // The ACK action was already set to NACK after the last read byte, so
+        // this also works at the end of a read.
+        self.regs
+            .write(Register::MCtrlB, MCTRLB_ACKACT_NACK | MCTRLB_MCMD_STOP);
+        Ok(())
+    }
+
+    /// Waits until no other host occupies the bus.
+    fn wait_for_bus(&mut self) -> Result<(), Error> {
+        let mut elapsed_us = 0;
+        loop {
+            let state = self.regs.read(Register::MStatus) & MSTATUS_BUSSTATE_MASK;
+            if state == MSTATUS_BUSSTATE_IDLE || state == MSTATUS_BUSSTATE_OWNER {
+                return Ok(());
+            }
+            // The bus is either busy or in an unknown state which the inactive
+            // bus timeout will resolve to idle.
+            self.tick(&mut elapsed_us)?;
+        }
+    }
+
+    /// Issues a (repeated) START condition followed by the address.
+    fn start(&mut self, address: u8, direction: Direction) -> Result<(), Error> {
+        let rw = match direction {
+            Direction::Write => 0,
+            Direction::Read => 1,
+        };
+        self.regs.write(Register::MAddr, (address << 1) | rw);
+        // A read sets RIF once the first byte was received, but a NACK for the
+        // address always sets WIF.
+        let status = self.wait(MSTATUS_RIF | MSTATUS_WIF)?;
+        if status & MSTATUS_WIF != 0 && status & MSTATUS_RXACK != 0 {
+            return Err(Error::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        self.regs.write(Register::MData, byte);
+        let status = self.wait(MSTATUS_WIF)?;
+        if status & MSTATUS_RXACK != 0 {
+            return Err(Error::NoAcknowledge(NoAcknowledgeSource::Data));
+        }
+        Ok(())
+    }
+
+    /// Reads a received byte.
+    ///
+    /// Unless this is the last byte of the transfer, the byte is acknowledged
+    /// and the reception of the next byte is started right away. Otherwise the
+    /// ACK action is set to NACK so the following STOP or repeated START
+    /// terminates the read.
+    fn read_byte(&mut self, last: bool) -> Result<u8, Error> {
+        self.wait(MSTATUS_RIF)?;
+        let byte = self.regs.read(Register::MData);
+        if last {
+            self.regs.write(Register::MCtrlB, MCTRLB_ACKACT_NACK);
+        } else {
+            self.regs.write(Register::MCtrlB, MCTRLB_MCMD_RECVTRANS);
+        }
+        Ok(byte)
+    }
+
+    /// Waits until one of the given status flags is set and returns the
+    /// status.
+    fn wait(&mut self, flags: u8) -> Result<u8, Error> {
+        let mut elapsed_us = 0;
+        loop {
+            let status = self.regs.read(Register::MStatus);
+            if status & MSTATUS_ARBLOST != 0 {
+                return Err(Error::ArbitrationLoss);
+            }
+            if status & MSTATUS_BUSERR != 0 {
+                return Err(Error::Bus);
+            }
+            if status & flags != 0 {
+                return Ok(status);
+            }
+            self.tick(&mut elapsed_us)?;
+        }
+    }
+
+    fn tick(&mut self, elapsed_us: &mut u32) -> Result<(), Error> {
+        if *elapsed_us >= self.timeout_us {
+            return Err(Error::Timeout);
+        }
+        self.delay.delay_us(1);
+        *elapsed_us += 1;
+        Ok(())
+    }
+
+    /// Brings the host back into a usable state after a failed transaction.
+    fn recover(&mut self, error: Error) {
+        match error {
+            Error::NoAcknowledge(_) => {
+                // We still own the bus and have to release it.
+                self.regs.write(Register::MCtrlB, MCTRLB_MCMD_STOP);
+            }
+            Error::ArbitrationLoss | Error::Bus => {
+                // The bus has already been released, only the flags remain.
+                self.regs
+                    .write(Register::MStatus, MSTATUS_ARBLOST | MSTATUS_BUSERR);
+            }
+            Error::Timeout => {
+                // Flushing resets the host without issuing a STOP condition. If a
+                // client is still holding the bus the next transaction will time
+                // out waiting for it.
+                self.regs.write(Register::MCtrlB, MCTRLB_FLUSH);
+                self.regs.write(Register::MStatus, MSTATUS_BUSSTATE_IDLE);
+            }
+        }
+    }
+}
+
+impl<R, D> ErrorType for Twi<R, D> {
+    type Error = Error;
+}
+
+impl<R: Registers, D: DelayNs> I2c for Twi<R, D> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let result = self.run(address, operations);
+        if let Err(error) = result {
+            self.recover(error);
+        }
+        result
+    }
+}
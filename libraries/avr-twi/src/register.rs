@@ -0,0 +1,86 @@
+//! Host mode registers of the TWI peripheral.
+
+/// Host mode register of the TWI peripheral.
+///
+/// Only the registers used by the host driver are listed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    /// Host Control A.
+    MCtrlA,
+    /// Host Control B.
+    MCtrlB,
+    /// Host Status.
+    MStatus,
+    /// Host Baud Rate.
+    MBaud,
+    /// Host Address.
+    MAddr,
+    /// Host Data.
+    MData,
+}
+
+/// Raw access to the host mode registers of a TWI instance.
+///
+/// Implemented by the firmware on top of the device's peripheral access crate
+/// and by simulated peripherals in tests.
+pub trait Registers {
+    /// Reads a register.
+    ///
+    /// Reading [`Register::MData`] clears the read and write interrupt flags.
+    fn read(&mut self, reg: Register) -> u8;
+
+    /// Writes a register.
+    ///
+    /// Writing [`Register::MAddr`] or [`Register::MData`] starts a bus
+    /// operation.
+    fn write(&mut self, reg: Register, value: u8);
+}
+
+impl<R: Registers + ?Sized> Registers for &mut R {
+    #[inline]
+    fn read(&mut self, reg: Register) -> u8 {
+        R::read(self, reg)
+    }
+
+    #[inline]
+    fn write(&mut self, reg: Register, value: u8) {
+        R::write(self, reg, value);
+    }
+}
+
+/// MCTRLA: Enable TWI host.
+pub const MCTRLA_ENABLE: u8 = 1 << 0;
+/// MCTRLA: Inactive bus timeout of 200 µs.
+///
+/// Lets the host move the bus state from unknown to idle on its own.
+pub const MCTRLA_TIMEOUT_200US: u8 = 0b11 << 2;
+
+/// MCTRLB: Flush the host's internal state.
+pub const MCTRLB_FLUSH: u8 = 1 << 3;
+/// MCTRLB: Send NACK instead of ACK after the next received byte.
+pub const MCTRLB_ACKACT_NACK: u8 = 1 << 2;
+/// MCTRLB: Send ACK/NACK and receive the next byte.
+pub const MCTRLB_MCMD_RECVTRANS: u8 = 0b10;
+/// MCTRLB: Send ACK/NACK and issue a STOP condition.
+pub const MCTRLB_MCMD_STOP: u8 = 0b11;
+
+/// MSTATUS: Read interrupt flag.
+pub const MSTATUS_RIF: u8 = 1 << 7;
+/// MSTATUS: Write interrupt flag.
+pub const MSTATUS_WIF: u8 = 1 << 6;
+/// MSTATUS: Received NACK from the client.
+pub const MSTATUS_RXACK: u8 = 1 << 4;
+/// MSTATUS: Arbitration lost.
+pub const MSTATUS_ARBLOST: u8 = 1 << 3;
+/// MSTATUS: Illegal bus condition.
+pub const MSTATUS_BUSERR: u8 = 1 << 2;
+/// MSTATUS: Mask of the bus state field.
+pub const MSTATUS_BUSSTATE_MASK: u8 = 0b11;
+/// MSTATUS: Bus state unknown.
+pub const MSTATUS_BUSSTATE_UNKNOWN: u8 = 0b00;
+/// MSTATUS: Bus idle.
+pub const MSTATUS_BUSSTATE_IDLE: u8 = 0b01;
+/// MSTATUS: Bus owned by this host.
+pub const MSTATUS_BUSSTATE_OWNER: u8 = 0b10;
+/// MSTATUS: Bus owned by another host.
+pub const MSTATUS_BUSSTATE_BUSY: u8 = 0b11;
@@ -0,0 +1,453 @@
+//! Integration tests for the TWI host driver against a simulated peripheral.
+
+use std::collections::VecDeque;
+
+use avr_twi::register::*;
+use avr_twi::{Config, Error, Register, Registers, Twi, baud};
+use embedded_hal::i2c::{I2c, NoAcknowledgeSource, Operation};
+use embedded_hal_mock::eh1::delay::NoopDelay;
+
+/// Bus event observed by the simulated peripheral.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Event {
+    /// (Repeated) START condition with address and R/W bit.
+    Start(u8),
+    /// Byte written by the host.
+    Write(u8),
+    /// Byte received by the host and whether the host acknowledged it.
+    Read(u8, bool),
+    Stop,
+    Flush,
+}
+
+/// Simulated client device.
+struct Client {
+    address: u8,
+    /// Bytes returned on reads.
+    tx: VecDeque<u8>,
+    /// Number of written bytes the client acknowledges before sending NACK.
+    ack_limit: usize,
+    written: usize,
+}
+
+impl Client {
+    fn new(address: u8, tx: &[u8]) -> Self {
+        Self {
+            address,
+            tx: tx.iter().copied().collect(),
+            ack_limit: usize::MAX,
+            written: 0,
+        }
+    }
+}
+
+/// Behavioral model of the TWI host registers.
+#[derive(Default)]
+struct SimTwi {
+    clients: Vec<Client>,
+    mctrla: u8,
+    mbaud: u8,
+    status: u8,
+    mdata: u8,
+    ackact_nack: bool,
+    /// Client selected by the last START.
+    selected: Option<usize>,
+    /// Byte received but not yet acknowledged.
+    pending_read: Option<u8>,
+    events: Vec<Event>,
+    /// Never set any flag after an operation was started.
+    stuck: bool,
+    /// Lose arbitration on the next START.
+    lose_arbitration: bool,
+    /// Detect an illegal bus condition during the next data byte.
+    bus_error: bool,
+    /// Report the bus as busy.
+    busy: bool,
+}
+
+impl SimTwi {
+    fn with_clients(clients: Vec<Client>) -> Self {
+        Self {
+            clients,
+            ..Default::default()
+        }
+    }
+
+    fn bus_state(&self) -> u8 {
+        self.status & MSTATUS_BUSSTATE_MASK
+    }
+
+    fn set_flags(&mut self, flags: u8) {
+        if !self.stuck {
+            self.status = (self.status & MSTATUS_BUSSTATE_MASK) | flags;
+        }
+    }
+
+    fn set_bus_state(&mut self, state: u8) {
+        self.status = (self.status & !MSTATUS_BUSSTATE_MASK) | state;
+    }
+
+    /// Completes a pending read by sending ACK or NACK.
+    fn finish_read(&mut self) {
+        if let Some(byte) = self.pending_read.take() {
+            self.events.push(Event::Read(byte, !self.ackact_nack));
+        }
+    }
+
+    fn receive_next(&mut self) {
+        let index = self.selected.expect("no client selected");
+        let byte = self.clients[index].tx.pop_front().unwrap_or(0xFF);
+        self.mdata = byte;
+        self.pending_read = Some(byte);
+        self.set_flags(MSTATUS_RIF | MSTATUS_CLKHOLD);
+    }
+}
+
+const MSTATUS_CLKHOLD: u8 = 1 << 5;
+
+impl Registers for SimTwi {
+    fn read(&mut self, reg: Register) -> u8 {
+        match reg {
+            Register::MCtrlA => self.mctrla,
+            Register::MCtrlB => 0,
+            Register::MStatus => {
+                if self.busy {
+                    (self.status & !MSTATUS_BUSSTATE_MASK) | MSTATUS_BUSSTATE_BUSY
+                } else {
+                    self.status
+                }
+            }
+            Register::MBaud => self.mbaud,
+            Register::MAddr => 0,
+            Register::MData => {
+                self.status &= !(MSTATUS_RIF | MSTATUS_WIF);
+                self.mdata
+            }
+        }
+    }
+
+    fn write(&mut self, reg: Register, value: u8) {
+        match reg {
+            Register::MCtrlA => self.mctrla = value,
+            Register::MBaud => {
+                assert_eq!(
+                    self.mctrla & MCTRLA_ENABLE,
+                    0,
+                    "MBAUD written while enabled"
+                );
+                self.mbaud = value;
+            }
+            Register::MStatus => {
+                // Flags are cleared by writing one, the bus state can be forced to
+                // idle.
+                self.status &= !(value & !MSTATUS_BUSSTATE_MASK);
+                if value & MSTATUS_BUSSTATE_MASK == MSTATUS_BUSSTATE_IDLE {
+                    self.set_bus_state(MSTATUS_BUSSTATE_IDLE);
+                }
+            }
+            Register::MCtrlB => {
+                if value & MCTRLB_FLUSH != 0 {
+                    self.events.push(Event::Flush);
+                    self.status = MSTATUS_BUSSTATE_UNKNOWN;
+                    self.pending_read = None;
+                    return;
+                }
+                self.ackact_nack = value & MCTRLB_ACKACT_NACK != 0;
+                match value & 0b11 {
+                    0 => {}
+                    MCTRLB_MCMD_RECVTRANS => {
+                        self.finish_read();
+                        self.receive_next();
+                    }
+                    MCTRLB_MCMD_STOP => {
+                        assert_eq!(self.bus_state(), MSTATUS_BUSSTATE_OWNER);
+                        self.finish_read();
+                        self.events.push(Event::Stop);
+                        self.selected = None;
+                        self.status = MSTATUS_BUSSTATE_IDLE;
+                    }
+                    _ => panic!("unexpected command {value:#04X}"),
+                }
+            }
+            Register::MAddr => {
+                self.finish_read();
+                self.events.push(Event::Start(value));
+                self.status &= !(MSTATUS_RIF | MSTATUS_WIF | MSTATUS_RXACK);
+                if self.lose_arbitration {
+                    self.set_flags(MSTATUS_WIF | MSTATUS_ARBLOST);
+                    self.set_bus_state(MSTATUS_BUSSTATE_BUSY);
+                    return;
+                }
+                self.set_bus_state(MSTATUS_BUSSTATE_OWNER);
+                self.selected = self.clients.iter().position(|c| c.address == value >> 1);
+                match (self.selected, value & 1) {
+                    (None, _) => self.set_flags(MSTATUS_WIF | MSTATUS_RXACK),
+                    (Some(_), 0) => self.set_flags(MSTATUS_WIF),
+                    (Some(_), _) => self.receive_next(),
+                }
+            }
+            Register::MData => {
+                self.events.push(Event::Write(value));
+                if self.bus_error {
+                    self.set_flags(MSTATUS_WIF | MSTATUS_BUSERR);
+                    self.set_bus_state(MSTATUS_BUSSTATE_UNKNOWN);
+                    return;
+                }
+                let index = self.selected.expect("no client selected");
+                let client = &mut self.clients[index];
+                client.written += 1;
+                if client.written > client.ack_limit {
+                    self.set_flags(MSTATUS_WIF | MSTATUS_RXACK);
+                } else {
+                    self.set_flags(MSTATUS_WIF);
+                }
+            }
+        }
+    }
+}
+
+fn twi(sim: &mut SimTwi) -> Twi<&mut SimTwi, NoopDelay> {
+    Twi::new(sim, NoopDelay::new(), Config::new(24_000_000, 100_000))
+}
+
+#[test]
+fn test_baud() {
+    // 24 MHz, 100 kHz: (240 - 10) / 2
+    assert_eq!(baud(24_000_000, 100_000, 0), 115);
+    // 24 MHz, 400 kHz, 300 ns rise time: (60 - 10 - 7) / 2
+    assert_eq!(baud(24_000_000, 400_000, 300), 21);
+    // Too slow for the clock, saturates.
+    assert_eq!(baud(24_000_000, 10_000, 0), 255);
+    // Too fast for the clock, saturates.
+    assert_eq!(baud(1_000_000, 1_000_000, 0), 0);
+    assert_eq!(Config::new(24_000_000, 100_000).baud(), 115);
+}
+
+#[test]
+fn test_init() {
+    let mut sim = SimTwi::default();
+    twi(&mut sim);
+    assert_eq!(sim.mbaud, 115);
+    assert_eq!(sim.mctrla, MCTRLA_ENABLE | MCTRLA_TIMEOUT_200US);
+    assert_eq!(sim.bus_state(), MSTATUS_BUSSTATE_IDLE);
+}
+
+#[test]
+fn test_release_disables_host() {
+    let mut sim = SimTwi::default();
+    twi(&mut sim).release();
+    assert_eq!(sim.mctrla, 0);
+}
+
+#[test]
+fn test_set_baud() {
+    let mut sim = SimTwi::default();
+    twi(&mut sim).set_baud(21);
+    assert_eq!(sim.mbaud, 21);
+    assert_eq!(sim.mctrla & MCTRLA_ENABLE, MCTRLA_ENABLE);
+}
+
+#[test]
+fn test_write() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[])]);
+    twi(&mut sim).write(0x20, &[0x02, 0xAA, 0x55]).unwrap();
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x40),
+            Event::Write(0x02),
+            Event::Write(0xAA),
+            Event::Write(0x55),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_read() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x48, &[0x19, 0x10, 0x33])]);
+    let mut buf = [0u8; 3];
+    twi(&mut sim).read(0x48, &mut buf).unwrap();
+    assert_eq!(buf, [0x19, 0x10, 0x33]);
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x91),
+            Event::Read(0x19, true),
+            Event::Read(0x10, true),
+            Event::Read(0x33, false),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_write_read_uses_repeated_start() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[0x34, 0x12])]);
+    let mut buf = [0u8; 2];
+    twi(&mut sim).write_read(0x20, &[0x00], &mut buf).unwrap();
+    assert_eq!(buf, [0x34, 0x12]);
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x40),
+            Event::Write(0x00),
+            Event::Start(0x41),
+            Event::Read(0x34, true),
+            Event::Read(0x12, false),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_transaction_merges_adjacent_operations() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x48, &[0x01, 0x02, 0x03])]);
+    let mut first = [0u8; 1];
+    let mut second = [0u8; 2];
+    twi(&mut sim)
+        .transaction(
+            0x48,
+            &mut [
+                Operation::Write(&[0x01]),
+                Operation::Write(&[0x28]),
+                Operation::Read(&mut first),
+                Operation::Read(&mut second),
+                Operation::Write(&[0x00]),
+            ],
+        )
+        .unwrap();
+    assert_eq!(first, [0x01]);
+    assert_eq!(second, [0x02, 0x03]);
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x90),
+            Event::Write(0x01),
+            Event::Write(0x28),
+            Event::Start(0x91),
+            Event::Read(0x01, true),
+            Event::Read(0x02, true),
+            Event::Read(0x03, false),
+            Event::Start(0x90),
+            Event::Write(0x00),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_empty_write_probes_address() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x48, &[])]);
+    {
+        let mut twi = twi(&mut sim);
+        twi.write(0x48, &[]).unwrap();
+        assert_eq!(
+            twi.write(0x49, &[]),
+            Err(Error::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+    }
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x90),
+            Event::Stop,
+            Event::Start(0x92),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_empty_transaction_does_nothing() {
+    let mut sim = SimTwi::default();
+    twi(&mut sim).transaction(0x48, &mut []).unwrap();
+    assert!(sim.events.is_empty());
+}
+
+#[test]
+fn test_address_nack_on_read() {
+    let mut sim = SimTwi::default();
+    let mut buf = [0u8; 2];
+    assert_eq!(
+        twi(&mut sim).read(0x0C, &mut buf),
+        Err(Error::NoAcknowledge(NoAcknowledgeSource::Address))
+    );
+    assert_eq!(sim.events, [Event::Start(0x19), Event::Stop]);
+}
+
+#[test]
+fn test_data_nack() {
+    let mut client = Client::new(0x20, &[]);
+    client.ack_limit = 1;
+    let mut sim = SimTwi::with_clients(vec![client]);
+    assert_eq!(
+        twi(&mut sim).write(0x20, &[0x02, 0xAA, 0x55]),
+        Err(Error::NoAcknowledge(NoAcknowledgeSource::Data))
+    );
+    assert_eq!(
+        sim.events,
+        [
+            Event::Start(0x40),
+            Event::Write(0x02),
+            Event::Write(0xAA),
+            Event::Stop,
+        ]
+    );
+}
+
+#[test]
+fn test_arbitration_loss() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[])]);
+    sim.lose_arbitration = true;
+    assert_eq!(
+        twi(&mut sim).write(0x20, &[0x00]),
+        Err(Error::ArbitrationLoss)
+    );
+    // No STOP because the bus belongs to the other host.
+    assert_eq!(sim.events, [Event::Start(0x40)]);
+    assert_eq!(sim.status & MSTATUS_ARBLOST, 0);
+}
+
+#[test]
+fn test_bus_error() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[])]);
+    sim.bus_error = true;
+    assert_eq!(twi(&mut sim).write(0x20, &[0x00]), Err(Error::Bus));
+    assert_eq!(sim.events, [Event::Start(0x40), Event::Write(0x00)]);
+    assert_eq!(sim.status & MSTATUS_BUSERR, 0);
+}
+
+#[test]
+fn test_timeout_flushes_host() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[])]);
+    sim.stuck = true;
+    let config = Config::new(24_000_000, 100_000).with_timeout_us(50);
+    let mut twi = Twi::new(&mut sim, NoopDelay::new(), config);
+    assert_eq!(twi.write(0x20, &[0x00]), Err(Error::Timeout));
+    twi.release();
+    assert_eq!(sim.events, [Event::Start(0x40), Event::Flush]);
+    assert_eq!(sim.bus_state(), MSTATUS_BUSSTATE_IDLE);
+}
+
+#[test]
+fn test_busy_bus_times_out() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[])]);
+    let config = Config::new(24_000_000, 100_000).with_timeout_us(50);
+    sim.busy = true;
+    let mut twi = Twi::new(&mut sim, NoopDelay::new(), config);
+    assert_eq!(twi.write(0x20, &[0x00]), Err(Error::Timeout));
+    twi.release();
+    // Nothing was sent while another host owned the bus.
+    assert_eq!(sim.events, [Event::Flush]);
+}
+
+#[test]
+fn test_recovers_after_error() {
+    let mut sim = SimTwi::with_clients(vec![Client::new(0x20, &[0xAB])]);
+    let mut twi = twi(&mut sim);
+    assert!(twi.write(0x21, &[0x00]).is_err());
+    let mut buf = [0u8; 1];
+    twi.read(0x20, &mut buf).unwrap();
+    assert_eq!(buf, [0xAB]);
+}
@@ -0,0 +1,474 @@
+//! Interrupt driven driver for the USART peripheral of AVR Dx and tinyAVR
+//! 1-series devices.
+//!
+//! Both families share the same asynchronous USART register interface, so the
+//! driver is written once against the [`Registers`] trait. The firmware
+//! provides the implementation for its device while tests can substitute a
+//! simulated peripheral.
+//!
+//! The [`Usart`] buffers received and transmitted bytes in ring buffers. Its
+//! interrupt handlers [`Usart::on_receive`] and
+//! [`Usart::on_data_register_empty`] have to be called from the RXC and DRE
+//! interrupts. The application accesses the driver through [`Serial`], which
+//! implements the blocking [`embedded_io`] traits on top of a [`Shared`]
+//! instance that disables interrupts while the driver is borrowed.
+//!
+//! In [`Mode::OneWire`] the transmitter drives the TxD pin as open drain and
+//! the receiver listens on the same pin. The receiver hears every transmitted
+//! byte, which the driver removes again and compares to detect collisions on
+//! the bus.
+
+#![no_std]
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+
+use self::register::*;
+pub use self::register::{Register, Registers};
+use self::ring::RingBuffer;
+
+pub mod register;
+mod ring;
+
+/// Most transmitted bytes whose echo hasn't been received yet in
+/// [`Mode::OneWire`].
+///
+/// Covers the transmit data register, the shift register and the two bytes of
+/// the receive buffer.
+const MAX_ECHOES: usize = 4;
+
+/// Calculates the `BAUD` register value for the given baud rate in normal
+/// speed mode.
+///
+/// Uses the formula from the datasheet
+/// `BAUD = 64 * f_CLK_PER / (16 * f_BAUD)` rounded to the nearest value. The
+/// result saturates to the valid range of 64 and above, so the actual baud
+/// rate may differ if it can't be reached with the given peripheral clock.
+#[must_use]
+pub const fn baud(clk_per_hz: u32, baud_hz: u32) -> u16 {
+    // Split the multiplication to avoid overflowing for realistic clocks.
+    let baud = (clk_per_hz / baud_hz) * 4 + ((clk_per_hz % baud_hz) * 4 + baud_hz / 2) / baud_hz;
+    if baud < 64 {
+        64
+    } else if baud > u16::MAX as u32 {
+        u16::MAX
+    } else {
+        baud as u16
+    }
+}
+
+/// Parity bit of a character.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// The number of ones including the parity bit is even.
+    Even,
+    /// The number of ones including the parity bit is odd.
+    Odd,
+}
+
+/// Number of stop bits of a character.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopBits {
+    /// One stop bit.
+    One,
+    /// Two stop bits.
+    Two,
+}
+
+/// Wiring of the USART.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Separate TxD and RxD pins.
+    FullDuplex,
+    /// Half-duplex on the TxD pin only.
+    ///
+    /// TxD is driven as open drain, so the bus requires a pull-up resistor and
+    /// the pin has to be configured as output.
+    OneWire,
+}
+
+/// Driver configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    baud: u16,
+    parity: Parity,
+    stop_bits: StopBits,
+    mode: Mode,
+}
+
+impl Config {
+    /// Creates a full-duplex configuration with 8 data bits, no parity and one
+    /// stop bit for the given peripheral clock and baud rate.
+    #[must_use]
+    pub const fn new(clk_per_hz: u32, baud_hz: u32) -> Self {
+        Self {
+            baud: baud(clk_per_hz, baud_hz),
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            mode: Mode::FullDuplex,
+        }
+    }
+
+    /// Returns the `BAUD` register value.
+    #[must_use]
+    pub const fn baud(self) -> u16 {
+        self.baud
+    }
+
+    /// Sets the `BAUD` register value.
+    #[must_use]
+    pub const fn with_baud(mut self, baud: u16) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Returns the parity.
+    #[must_use]
+    pub const fn parity(self) -> Parity {
+        self.parity
+    }
+
+    /// Sets the parity.
+    #[must_use]
+    pub const fn with_parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    /// Returns the number of stop bits.
+    #[must_use]
+    pub const fn stop_bits(self) -> StopBits {
+        self.stop_bits
+    }
+
+    /// Sets the number of stop bits.
+    #[must_use]
+    pub const fn with_stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Returns the wiring.
+    #[must_use]
+    pub const fn mode(self) -> Mode {
+        self.mode
+    }
+
+    /// Sets the wiring.
+    #[must_use]
+    pub const fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    const fn ctrlc(self) -> u8 {
+        let pmode = match self.parity {
+            Parity::None => CTRLC_PMODE_DISABLED,
+            Parity::Even => CTRLC_PMODE_EVEN,
+            Parity::Odd => CTRLC_PMODE_ODD,
+        };
+        let sbmode = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => CTRLC_SBMODE_2BIT,
+        };
+        CTRLC_CMODE_ASYNCHRONOUS | pmode | sbmode | CTRLC_CHSIZE_8BIT
+    }
+}
+
+/// USART error.
+///
+/// Errors are reported by the next read after they occurred.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Received bytes were lost because the buffer was full.
+    Overrun,
+    /// A byte was received without a valid stop bit and was discarded.
+    Framing,
+    /// A byte was received with the wrong parity and was discarded.
+    Parity,
+    /// The echo of a transmitted byte differed from the byte in
+    /// [`Mode::OneWire`], so another node was transmitting at the same time.
+    Collision,
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            Self::Framing | Self::Parity => ErrorKind::InvalidData,
+            Self::Overrun | Self::Collision => ErrorKind::Other,
+        }
+    }
+}
+
+/// USART driver with receive and transmit buffers of `N` bytes each.
+pub struct Usart<R, const N: usize> {
+    regs: R,
+    rx: RingBuffer<N>,
+    tx: RingBuffer<N>,
+    /// Transmitted bytes whose echo hasn't been received yet.
+    echoes: RingBuffer<MAX_ECHOES>,
+    one_wire: bool,
+    /// First error since the last read.
+    error: Option<Error>,
+    /// Bytes were queued since the transmitter was last idle.
+    transmitting: bool,
+}
+
+impl<R: Registers, const N: usize> Usart<R, N> {
+    /// Creates a new driver instance and enables the receiver and transmitter.
+    pub fn new(mut regs: R, config: Config) -> Self {
+        let one_wire = config.mode == Mode::OneWire;
+        let (lbme, odme) = if one_wire {
+            (CTRLA_LBME, CTRLB_ODME)
+        } else {
+            (0, 0)
+        };
+        regs.write_baud(config.baud);
+        regs.write(Register::CtrlC, config.ctrlc());
+        regs.write(Register::CtrlA, CTRLA_RXCIE | lbme);
+        regs.write(Register::CtrlB, CTRLB_RXEN | CTRLB_TXEN | odme);
+        Self {
+            regs,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            echoes: RingBuffer::new(),
+            one_wire,
+            error: None,
+            transmitting: false,
+        }
+    }
+
+    /// Disables the receiver, transmitter and interrupts and releases the
+    /// registers.
+    ///
+    /// Buffered bytes are discarded.
+    pub fn release(mut self) -> R {
+        self.regs.write(Register::CtrlB, 0);
+        self.regs.write(Register::CtrlA, 0);
+        self.regs
+    }
+
+    /// Handles the receive complete interrupt.
+    pub fn on_receive(&mut self) {
+        while self.regs.read(Register::Status) & STATUS_RXCIF != 0 {
+            let flags = self.regs.read(Register::RxDataH);
+            let byte = self.regs.read(Register::RxDataL);
+            if flags & RXDATAH_BUFOVF != 0 {
+                self.fail(Error::Overrun);
+            }
+            let error = if flags & RXDATAH_FERR != 0 {
+                Some(Error::Framing)
+            } else if flags & RXDATAH_PERR != 0 {
+                Some(Error::Parity)
+            } else {
+                None
+            };
+
+            if let Some(sent) = self.echoes.pop() {
+                if error.is_some() || byte != sent {
+                    self.fail(Error::Collision);
+                }
+                // The transmitter may be waiting for the echo.
+                if !self.tx.is_empty() {
+                    self.enable_data_register_empty();
+                }
+            } else if let Some(error) = error {
+                self.fail(error);
+            } else if !self.rx.push(byte) {
+                self.fail(Error::Overrun);
+            }
+        }
+    }
+
+    /// Handles the data register empty interrupt.
+    pub fn on_data_register_empty(&mut self) {
+        if self.one_wire && self.echoes.is_full() {
+            // Resumed by the receive interrupt.
+            self.disable_data_register_empty();
+            return;
+        }
+        if let Some(byte) = self.tx.pop() {
+            // The flag is set once the last byte left the shift register.
+            self.regs.write(Register::Status, STATUS_TXCIF);
+            self.regs.write(Register::TxDataL, byte);
+            if self.one_wire {
+                self.echoes.push(byte);
+            }
+        }
+        if self.tx.is_empty() {
+            self.disable_data_register_empty();
+        }
+    }
+
+    /// Moves received bytes to `buf` and returns their number.
+    ///
+    /// Returns a pending error instead if there is one.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let mut n = 0;
+        for slot in buf.iter_mut() {
+            let Some(byte) = self.rx.pop() else {
+                break;
+            };
+            *slot = byte;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Returns true if a read would return a byte or an error.
+    #[must_use]
+    pub const fn read_ready(&self) -> bool {
+        !self.rx.is_empty() || self.error.is_some()
+    }
+
+    /// Queues bytes of `buf` for transmission and returns their number.
+    pub fn write(&mut self, buf: &[u8]) -> usize {
+        let mut n = 0;
+        for &byte in buf {
+            if !self.tx.push(byte) {
+                break;
+            }
+            n += 1;
+        }
+        if n > 0 {
+            self.transmitting = true;
+            self.enable_data_register_empty();
+        }
+        n
+    }
+
+    /// Returns true if a write would queue at least one byte.
+    #[must_use]
+    pub const fn write_ready(&self) -> bool {
+        !self.tx.is_full()
+    }
+
+    /// Returns true once all queued bytes were transmitted.
+    ///
+    /// In [`Mode::OneWire`] this includes receiving their echoes.
+    pub fn is_flushed(&mut self) -> bool {
+        if self.transmitting
+            && self.tx.is_empty()
+            && self.echoes.is_empty()
+            && self.regs.read(Register::Status) & STATUS_TXCIF != 0
+        {
+            self.transmitting = false;
+        }
+        !self.transmitting
+    }
+
+    fn fail(&mut self, error: Error) {
+        self.error.get_or_insert(error);
+    }
+
+    fn enable_data_register_empty(&mut self) {
+        let ctrla = self.regs.read(Register::CtrlA);
+        self.regs.write(Register::CtrlA, ctrla | CTRLA_DREIE);
+    }
+
+    fn disable_data_register_empty(&mut self) {
+        let ctrla = self.regs.read(Register::CtrlA);
+        self.regs.write(Register::CtrlA, ctrla & !CTRLA_DREIE);
+    }
+}
+
+/// Access to a [`Usart`] that is shared with its interrupt handlers.
+pub trait Shared {
+    /// The shared driver.
+    type Target;
+
+    /// Calls `f` with the driver while its interrupts can't run.
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T;
+}
+
+/// Blocking serial port on top of a shared [`Usart`].
+///
+/// Reads and writes wait for the interrupt handlers with interrupts enabled.
+pub struct Serial<S> {
+    shared: S,
+}
+
+impl<S> Serial<S> {
+    /// Creates a serial port.
+    pub const fn new(shared: S) -> Self {
+        Self { shared }
+    }
+
+    /// Releases the shared driver.
+    pub fn release(self) -> S {
+        self.shared
+    }
+}
+
+impl<S, R, const N: usize> ErrorType for Serial<S>
+where
+    S: Shared<Target = Usart<R, N>>,
+    R: Registers,
+{
+    type Error = Error;
+}
+
+impl<S, R, const N: usize> Read for Serial<S>
+where
+    S: Shared<Target = Usart<R, N>>,
+    R: Registers,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.shared.with(|usart| usart.read(buf))?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl<S, R, const N: usize> ReadReady for Serial<S>
+where
+    S: Shared<Target = Usart<R, N>>,
+    R: Registers,
+{
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.shared.with(|usart| usart.read_ready()))
+    }
+}
+
+impl<S, R, const N: usize> Write for Serial<S>
+where
+    S: Shared<Target = Usart<R, N>>,
+    R: Registers,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.shared.with(|usart| usart.write(buf));
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while !self.shared.with(|usart| usart.is_flushed()) {}
+        Ok(())
+    }
+}
+
+impl<S, R, const N: usize> WriteReady for Serial<S>
+where
+    S: Shared<Target = Usart<R, N>>,
+    R: Registers,
+{
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.shared.with(|usart| usart.write_ready()))
+    }
+}
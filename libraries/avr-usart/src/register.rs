@@ -0,0 +1,105 @@
+//! Asynchronous mode registers of the USART peripheral.
+
+/// Register of the USART peripheral.
+///
+/// Only the registers used by the driver are listed. The baud rate register is
+/// written through [`Registers::write_baud`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    /// Receiver Data Low Byte.
+    RxDataL,
+    /// Receiver Data High Byte.
+    RxDataH,
+    /// Transmit Data Low Byte.
+    TxDataL,
+    /// Status.
+    Status,
+    /// Control A.
+    CtrlA,
+    /// Control B.
+    CtrlB,
+    /// Control C.
+    CtrlC,
+}
+
+/// Raw access to the registers of a USART instance.
+///
+/// Implemented by the firmware on top of the device's peripheral access crate
+/// and by simulated peripherals in tests.
+pub trait Registers {
+    /// Reads a register.
+    ///
+    /// Reading [`Register::RxDataL`] removes the byte from the receive buffer,
+    /// so [`Register::RxDataH`] has to be read first.
+    fn read(&mut self, reg: Register) -> u8;
+
+    /// Writes a register.
+    ///
+    /// Writing [`Register::TxDataL`] queues a byte for transmission.
+    fn write(&mut self, reg: Register, value: u8);
+
+    /// Writes the 16-bit baud rate register.
+    fn write_baud(&mut self, baud: u16);
+}
+
+impl<R: Registers + ?Sized> Registers for &mut R {
+    #[inline]
+    fn read(&mut self, reg: Register) -> u8 {
+        R::read(self, reg)
+    }
+
+    #[inline]
+    fn write(&mut self, reg: Register, value: u8) {
+        R::write(self, reg, value);
+    }
+
+    #[inline]
+    fn write_baud(&mut self, baud: u16) {
+        R::write_baud(self, baud);
+    }
+}
+
+/// RXDATAH: Receive complete interrupt flag.
+pub const RXDATAH_RXCIF: u8 = 1 << 7;
+/// RXDATAH: Receive buffer overflow, at least one byte was lost.
+pub const RXDATAH_BUFOVF: u8 = 1 << 6;
+/// RXDATAH: The stop bit of the byte was zero.
+pub const RXDATAH_FERR: u8 = 1 << 2;
+/// RXDATAH: The parity of the byte didn't match.
+pub const RXDATAH_PERR: u8 = 1 << 1;
+
+/// STATUS: Receive complete interrupt flag.
+pub const STATUS_RXCIF: u8 = 1 << 7;
+/// STATUS: Transmit complete interrupt flag.
+///
+/// Cleared by writing a one.
+pub const STATUS_TXCIF: u8 = 1 << 6;
+/// STATUS: Data register empty flag.
+pub const STATUS_DREIF: u8 = 1 << 5;
+
+/// CTRLA: Receive complete interrupt enable.
+pub const CTRLA_RXCIE: u8 = 1 << 7;
+/// CTRLA: Data register empty interrupt enable.
+pub const CTRLA_DREIE: u8 = 1 << 5;
+/// CTRLA: Loop-back mode, connects TxD to the receiver.
+pub const CTRLA_LBME: u8 = 1 << 3;
+
+/// CTRLB: Receiver enable.
+pub const CTRLB_RXEN: u8 = 1 << 7;
+/// CTRLB: Transmitter enable.
+pub const CTRLB_TXEN: u8 = 1 << 6;
+/// CTRLB: Open drain mode of the TxD pin.
+pub const CTRLB_ODME: u8 = 1 << 3;
+
+/// CTRLC: Asynchronous communication mode.
+pub const CTRLC_CMODE_ASYNCHRONOUS: u8 = 0b00 << 6;
+/// CTRLC: Parity disabled.
+pub const CTRLC_PMODE_DISABLED: u8 = 0b00 << 4;
+/// CTRLC: Even parity.
+pub const CTRLC_PMODE_EVEN: u8 = 0b10 << 4;
+/// CTRLC: Odd parity.
+pub const CTRLC_PMODE_ODD: u8 = 0b11 << 4;
+/// CTRLC: Two stop bits instead of one.
+pub const CTRLC_SBMODE_2BIT: u8 = 1 << 3;
+/// CTRLC: 8-bit characters.
+pub const CTRLC_CHSIZE_8BIT: u8 = 0b011;
@@ -0,0 +1,48 @@
+/// Fixed capacity FIFO of bytes.
+#[derive(Clone, Debug)]
+pub(crate) struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Appends a byte, returns false if the buffer is full.
+    pub(crate) fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let tail = (self.head + self.len) % N;
+        self.buf[tail] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Removes the oldest byte.
+    pub(crate) fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
@@ -0,0 +1,390 @@
+//! Integration tests for the USART driver against a simulated peripheral.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use avr_usart::register::*;
+use avr_usart::{
+    Config, Error, Mode, Parity, Register, Registers, Serial, Shared, StopBits, Usart, baud,
+};
+use embedded_io::{Read, ReadReady, Write, WriteReady};
+
+/// Behavioral model of the USART registers.
+#[derive(Default)]
+struct SimState {
+    ctrla: u8,
+    ctrlb: u8,
+    ctrlc: u8,
+    baud: u16,
+    txcif: bool,
+    /// Receive buffer with the RXDATAH error flags of every byte.
+    rx: VecDeque<(u8, u8)>,
+    /// Byte in the transmit data register.
+    tx: Option<u8>,
+    /// Bytes that left the transmitter.
+    line: Vec<u8>,
+    /// Replaces the next echo in loop-back mode, like another node pulling the
+    /// bus low.
+    collision: Option<u8>,
+}
+
+#[derive(Clone, Default)]
+struct SimUsart(Rc<RefCell<SimState>>);
+
+impl SimUsart {
+    /// Receives a byte with the given RXDATAH error flags.
+    fn receive(&self, byte: u8, flags: u8) {
+        self.0.borrow_mut().rx.push_back((byte, flags));
+    }
+
+    /// Transmits the byte in the transmit data register.
+    fn shift(&self) {
+        let mut state = self.0.borrow_mut();
+        let Some(byte) = state.tx.take() else {
+            return;
+        };
+        state.line.push(byte);
+        state.txcif = true;
+        if state.ctrla & CTRLA_LBME != 0 {
+            let echo = state.collision.take().unwrap_or(byte);
+            state.rx.push_back((echo, 0));
+        }
+    }
+
+    fn line(&self) -> Vec<u8> {
+        self.0.borrow().line.clone()
+    }
+}
+
+impl Registers for SimUsart {
+    fn read(&mut self, reg: Register) -> u8 {
+        let mut state = self.0.borrow_mut();
+        match reg {
+            Register::RxDataL => state.rx.pop_front().map_or(0, |(byte, _)| byte),
+            Register::RxDataH => state
+                .rx
+                .front()
+                .map_or(0, |&(_, flags)| flags | RXDATAH_RXCIF),
+            Register::TxDataL => 0,
+            Register::Status => {
+                let mut status = 0;
+                if !state.rx.is_empty() {
+                    status |= STATUS_RXCIF;
+                }
+                if state.txcif {
+                    status |= STATUS_TXCIF;
+                }
+                if state.tx.is_none() {
+                    status |= STATUS_DREIF;
+                }
+                status
+            }
+            Register::CtrlA => state.ctrla,
+            Register::CtrlB => state.ctrlb,
+            Register::CtrlC => state.ctrlc,
+        }
+    }
+
+    fn write(&mut self, reg: Register, value: u8) {
+        let mut state = self.0.borrow_mut();
+        match reg {
+            Register::RxDataL | Register::RxDataH => {}
+            Register::TxDataL => {
+                assert!(state.tx.is_none(), "transmit data register overwritten");
+                assert!(state.ctrlb & CTRLB_TXEN != 0, "transmitter disabled");
+                state.tx = Some(value);
+            }
+            Register::Status => {
+                if value & STATUS_TXCIF != 0 {
+                    state.txcif = false;
+                }
+            }
+            Register::CtrlA => state.ctrla = value,
+            Register::CtrlB => state.ctrlb = value,
+            Register::CtrlC => state.ctrlc = value,
+        }
+    }
+
+    fn write_baud(&mut self, baud: u16) {
+        self.0.borrow_mut().baud = baud;
+    }
+}
+
+const CONFIG: Config = Config::new(4_000_000, 115_200);
+
+/// Driver whose interrupts run whenever the application accesses it.
+struct Interrupts {
+    sim: SimUsart,
+    usart: Usart<SimUsart, 4>,
+}
+
+impl Interrupts {
+    fn new(config: Config) -> Self {
+        let sim = SimUsart::default();
+        let usart = Usart::new(sim.clone(), config);
+        Self { sim, usart }
+    }
+
+    /// Runs pending interrupts and transmits one byte.
+    fn run(&mut self) {
+        self.service();
+        self.sim.shift();
+        self.service();
+    }
+
+    fn service(&mut self) {
+        let mut sim = self.sim.clone();
+        let ctrla = sim.read(Register::CtrlA);
+        if ctrla & CTRLA_RXCIE != 0 && sim.read(Register::Status) & STATUS_RXCIF != 0 {
+            self.usart.on_receive();
+        }
+        if ctrla & CTRLA_DREIE != 0 && sim.read(Register::Status) & STATUS_DREIF != 0 {
+            self.usart.on_data_register_empty();
+        }
+    }
+}
+
+impl Shared for Interrupts {
+    type Target = Usart<SimUsart, 4>;
+
+    fn with<T>(&mut self, f: impl FnOnce(&mut Self::Target) -> T) -> T {
+        self.run();
+        f(&mut self.usart)
+    }
+}
+
+fn serial(config: Config) -> (SimUsart, Serial<Interrupts>) {
+    let interrupts = Interrupts::new(config);
+    (interrupts.sim.clone(), Serial::new(interrupts))
+}
+
+#[test]
+fn test_baud() {
+    assert_eq!(baud(4_000_000, 115_200), 139);
+    assert_eq!(baud(3_333_333, 19_200), 694);
+    assert_eq!(baud(24_000_000, 9_600), 10_000);
+    // Saturates at both ends.
+    assert_eq!(baud(4_000_000, 1_000_000), 64);
+    assert_eq!(baud(24_000_000, 300), u16::MAX);
+}
+
+#[test]
+fn test_new_configures_8n1() {
+    let sim = SimUsart::default();
+    let _usart = Usart::<_, 4>::new(sim.clone(), CONFIG);
+    let state = sim.0.borrow();
+    assert_eq!(state.baud, 139);
+    assert_eq!(state.ctrla, CTRLA_RXCIE);
+    assert_eq!(state.ctrlb, CTRLB_RXEN | CTRLB_TXEN);
+    assert_eq!(state.ctrlc, 0b0000_0011);
+}
+
+#[test]
+fn test_new_configures_parity_and_stop_bits() {
+    for (parity, stop_bits, ctrlc) in [
+        (Parity::Even, StopBits::One, 0b0010_0011),
+        (Parity::Odd, StopBits::One, 0b0011_0011),
+        (Parity::None, StopBits::Two, 0b0000_1011),
+        (Parity::Even, StopBits::Two, 0b0010_1011),
+    ] {
+        let sim = SimUsart::default();
+        let config = CONFIG.with_parity(parity).with_stop_bits(stop_bits);
+        let _usart = Usart::<_, 4>::new(sim.clone(), config);
+        assert_eq!(sim.0.borrow().ctrlc, ctrlc, "{parity:?} {stop_bits:?}");
+    }
+}
+
+#[test]
+fn test_new_configures_one_wire() {
+    let sim = SimUsart::default();
+    let _usart = Usart::<_, 4>::new(sim.clone(), CONFIG.with_mode(Mode::OneWire));
+    let state = sim.0.borrow();
+    assert_eq!(state.ctrla, CTRLA_RXCIE | CTRLA_LBME);
+    assert_eq!(state.ctrlb, CTRLB_RXEN | CTRLB_TXEN | CTRLB_ODME);
+}
+
+#[test]
+fn test_release_disables() {
+    let sim = SimUsart::default();
+    let usart = Usart::<_, 4>::new(sim.clone(), CONFIG);
+    let _ = usart.release();
+    let state = sim.0.borrow();
+    assert_eq!(state.ctrla, 0);
+    assert_eq!(state.ctrlb, 0);
+}
+
+#[test]
+fn test_write_and_flush() {
+    let (sim, mut serial) = serial(CONFIG);
+    assert!(serial.write_ready().unwrap());
+    // More than the transmit buffer holds.
+    serial.write_all(b"hello world").unwrap();
+    serial.flush().unwrap();
+    assert_eq!(sim.line(), b"hello world");
+    // The data register empty interrupt is disabled once there is nothing left.
+    assert_eq!(sim.0.borrow().ctrla & CTRLA_DREIE, 0);
+}
+
+#[test]
+fn test_flush_waits_for_last_byte() {
+    let sim = SimUsart::default();
+    let mut usart = Usart::<_, 4>::new(sim.clone(), CONFIG);
+    assert!(usart.is_flushed());
+    assert_eq!(usart.write(b"ab"), 2);
+    assert!(!usart.is_flushed());
+    usart.on_data_register_empty();
+    sim.shift();
+    usart.on_data_register_empty();
+    // The buffer is empty but the last byte is still being shifted out.
+    assert!(!usart.is_flushed());
+    sim.shift();
+    assert!(usart.is_flushed());
+    assert_eq!(sim.line(), b"ab");
+}
+
+#[test]
+fn test_write_ready() {
+    let sim = SimUsart::default();
+    let mut usart = Usart::<_, 4>::new(sim, CONFIG);
+    assert!(usart.write_ready());
+    assert_eq!(usart.write(b"abcdef"), 4);
+    assert!(!usart.write_ready());
+    assert_eq!(usart.write(b"x"), 0);
+    usart.on_data_register_empty();
+    assert!(usart.write_ready());
+}
+
+#[test]
+fn test_read() {
+    let (sim, mut serial) = serial(CONFIG);
+    assert!(!serial.read_ready().unwrap());
+    sim.receive(0x12, 0);
+    sim.receive(0x34, 0);
+    assert!(serial.read_ready().unwrap());
+    let mut buf = [0; 8];
+    assert_eq!(serial.read(&mut buf).unwrap(), 2);
+    assert_eq!(buf[..2], [0x12, 0x34]);
+    assert!(!serial.read_ready().unwrap());
+}
+
+#[test]
+fn test_read_empty_buffer() {
+    let (_sim, mut serial) = serial(CONFIG);
+    assert_eq!(serial.read(&mut []).unwrap(), 0);
+    assert_eq!(serial.write(&[]).unwrap(), 0);
+}
+
+#[test]
+fn test_framing_error_discards_byte() {
+    let (sim, mut serial) = serial(CONFIG);
+    sim.receive(0x00, RXDATAH_FERR);
+    sim.receive(0x55, 0);
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf), Err(Error::Framing));
+    assert_eq!(serial.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf, [0x55]);
+}
+
+#[test]
+fn test_parity_error_discards_byte() {
+    let (sim, mut serial) = serial(CONFIG.with_parity(Parity::Even));
+    sim.receive(0x01, RXDATAH_PERR);
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf), Err(Error::Parity));
+    assert!(!serial.read_ready().unwrap());
+}
+
+#[test]
+fn test_hardware_overrun_keeps_byte() {
+    let (sim, mut serial) = serial(CONFIG);
+    sim.receive(0x42, RXDATAH_BUFOVF);
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf), Err(Error::Overrun));
+    assert_eq!(serial.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf, [0x42]);
+}
+
+#[test]
+fn test_buffer_overrun() {
+    let (sim, mut serial) = serial(CONFIG);
+    for byte in 0..6 {
+        sim.receive(byte, 0);
+    }
+    let mut buf = [0; 8];
+    assert_eq!(serial.read(&mut buf), Err(Error::Overrun));
+    // The oldest bytes are kept.
+    assert_eq!(serial.read(&mut buf).unwrap(), 4);
+    assert_eq!(buf[..4], [0, 1, 2, 3]);
+}
+
+#[test]
+fn test_first_error_is_reported() {
+    let (sim, mut serial) = serial(CONFIG);
+    sim.receive(0x00, RXDATAH_PERR);
+    sim.receive(0x00, RXDATAH_FERR);
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf), Err(Error::Parity));
+    assert!(!serial.read_ready().unwrap());
+}
+
+#[test]
+fn test_one_wire_discards_echo() {
+    let (sim, mut serial) = serial(CONFIG.with_mode(Mode::OneWire));
+    serial.write_all(b"request").unwrap();
+    serial.flush().unwrap();
+    assert_eq!(sim.line(), b"request");
+    assert!(!serial.read_ready().unwrap());
+
+    sim.receive(0xA5, 0);
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf, [0xA5]);
+}
+
+#[test]
+fn test_one_wire_waits_for_echoes() {
+    let sim = SimUsart::default();
+    let mut usart = Usart::<_, 8>::new(sim.clone(), CONFIG.with_mode(Mode::OneWire));
+    assert_eq!(usart.write(b"abcdef"), 6);
+    // Without receiving any echo only as many bytes as can be tracked are sent.
+    for _ in 0..6 {
+        usart.on_data_register_empty();
+        sim.shift();
+    }
+    assert_eq!(sim.line(), b"abcd");
+    assert_eq!(sim.0.borrow().ctrla & CTRLA_DREIE, 0);
+
+    usart.on_receive();
+    assert_ne!(sim.0.borrow().ctrla & CTRLA_DREIE, 0);
+    for _ in 0..2 {
+        usart.on_data_register_empty();
+        sim.shift();
+    }
+    usart.on_receive();
+    assert_eq!(sim.line(), b"abcdef");
+    assert!(usart.is_flushed());
+    assert!(!usart.read_ready());
+}
+
+#[test]
+fn test_one_wire_collision() {
+    let (sim, mut serial) = serial(CONFIG.with_mode(Mode::OneWire));
+    sim.0.borrow_mut().collision = Some(0x00);
+    serial.write_all(&[0xFF, 0x01]).unwrap();
+    serial.flush().unwrap();
+    let mut buf = [0; 1];
+    assert_eq!(serial.read(&mut buf), Err(Error::Collision));
+    assert!(!serial.read_ready().unwrap());
+}
+
+#[test]
+fn test_full_duplex_keeps_received_bytes_while_transmitting() {
+    let (sim, mut serial) = serial(CONFIG);
+    sim.receive(0x10, 0);
+    serial.write_all(b"ab").unwrap();
+    serial.flush().unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(serial.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf[0], 0x10);
+}
@@ -0,0 +1,125 @@
+//! Address assignment along the daisy chain.
+//!
+//! All cellagents share the cell bus. Next to it, every cellagent has an
+//! enable input driven by the enable output of the previous cellagent in the
+//! daisy chain, the input of the first cellagent is always enabled.
+//!
+//! The cellcore enumerates the cellagents by broadcasting
+//! [`Request::ResetAddresses`](cellguard_protocol::Request::ResetAddresses)
+//! and then assigning addresses one by one to [`Address::UNASSIGNED`]. A
+//! cellagent without an address keeps its enable output low and only accepts
+//! requests to [`Address::UNASSIGNED`] while its enable input is high, so only
+//! the first unassigned cellagent of the chain receives the assignment. Once
+//! it has an address it raises its enable output and the next cellagent
+//! becomes reachable.
+//!
+//! The address is persisted so the chain doesn't have to be enumerated after
+//! every reset.
+
+use cellguard_protocol::{Address, ErrorCode, Response};
+
+/// Non-volatile storage of the address.
+pub trait AddressStore {
+    /// Returns the stored address, if any.
+    fn load(&mut self) -> Option<Address>;
+
+    /// Stores the address.
+    fn store(&mut self, address: Address);
+}
+
+/// Address state of a cellagent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Addressing {
+    address: Address,
+}
+
+impl Addressing {
+    /// Restores the address from `store`.
+    ///
+    /// Invalid stored addresses are ignored.
+    pub fn load<S: AddressStore>(store: &mut S) -> Self {
+        let address = match store.load() {
+            Some(address) if address.is_agent() => address,
+            _ => Address::UNASSIGNED,
+        };
+        Self { address }
+    }
+
+    /// Returns the current address.
+    #[must_use]
+    pub const fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Returns true if the cellagent has been assigned an address.
+    #[must_use]
+    pub const fn is_assigned(&self) -> bool {
+        self.address.is_agent()
+    }
+
+    /// Returns true if the enable output to the next cellagent is high.
+    #[must_use]
+    pub const fn enables_next(&self) -> bool {
+        self.is_assigned()
+    }
+
+    /// Returns true if a request sent to `destination` is processed.
+    #[must_use]
+    pub const fn accepts(&self, destination: Address) -> bool {
+        destination.accepts(self.address)
+    }
+
+    /// Forgets the address until the next assignment.
+    ///
+    /// The stored address is kept and overwritten by the next assignment, so
+    /// an interrupted enumeration doesn't leave the cellagent without an
+    /// address after a reset.
+    pub fn reset(&mut self) {
+        self.address = Address::UNASSIGNED;
+    }
+
+    /// Processes an address assignment sent to `destination`.
+    ///
+    /// Returns the response, or `None` if the request isn't answered because
+    /// the cellagent already has an address or it was sent as broadcast.
+    pub fn assign<S: AddressStore>(
+        &mut self,
+        destination: Address,
+        address: Address,
+        store: &mut S,
+    ) -> Option<Response> {
+        if destination != Address::UNASSIGNED || self.is_assigned() {
+            return None;
+        }
+        if !address.is_agent() {
+            return Some(Response::Error(ErrorCode::InvalidPayload));
+        }
+        if store.load() != Some(address) {
+            store.store(address);
+        }
+        self.address = address;
+        Some(Response::Address(address))
+    }
+}
+
+/// Address store keeping the address in RAM.
+///
+/// Meant for host tests and for boards without persistent storage.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RamStore {
+    /// Stored address.
+    pub address: Option<Address>,
+    /// Number of writes, to check that unchanged addresses aren't rewritten.
+    pub writes: u32,
+}
+
+impl AddressStore for RamStore {
+    fn load(&mut self) -> Option<Address> {
+        self.address
+    }
+
+    fn store(&mut self, address: Address) {
+        self.address = Some(address);
+        self.writes += 1;
+    }
+}
@@ -0,0 +1,247 @@
+//! Passive balancing by switching a bleed resistor across the cell.
+//!
+//! Balancing is only started on request of the cellcore and always ends on
+//! its own: after the requested duration, after [`Config::max_on_time_s`] or
+//! if no request arrived within [`Config::command_timeout_ms`]. Repeated
+//! requests don't extend the maximum on-time, only a stop request or the
+//! command timeout start it over.
+//!
+//! The bleed resistor heats the board, so the on-time is reduced linearly
+//! above [`Config::derate_temperature`] by switching it on for only part of
+//! every [`Config::period_ms`]. At [`Config::stop_temperature`] balancing
+//! pauses until the temperature has fallen to
+//! [`Config::resume_temperature`]. Without a temperature reading balancing is
+//! stopped as well.
+
+use cellguard_protocol::{Balancing, BalancingReport, StatusFlags};
+use p3t1755::Temperature;
+
+use crate::voltage::Millivolts;
+
+/// Full duty cycle.
+const DUTY_FULL: u32 = 256;
+
+/// Balancing settings.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Upper limit for the time balanced between two stops.
+    pub max_on_time_s: u16,
+    /// Time without requests after which balancing stops.
+    pub command_timeout_ms: u32,
+    /// Temperature above which the on-time is reduced.
+    pub derate_temperature: Temperature,
+    /// Temperature at which balancing pauses.
+    pub stop_temperature: Temperature,
+    /// Temperature at which a paused balancing resumes.
+    pub resume_temperature: Temperature,
+    /// Resistance of the bleed path including the switch in milliohms.
+    pub resistance_mohm: u32,
+    /// Period over which the reduced on-time is spread.
+    pub period_ms: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_on_time_s: 3600,
+            command_timeout_ms: 5000,
+            derate_temperature: Temperature::from_degrees_celsius(50),
+            stop_temperature: Temperature::from_degrees_celsius(60),
+            resume_temperature: Temperature::from_degrees_celsius(55),
+            resistance_mohm: 33_000,
+            period_ms: 1000,
+        }
+    }
+}
+
+/// Balancing state machine.
+#[derive(Clone)]
+pub struct Controller {
+    config: Config,
+    /// Remaining time of the current request, zero if balancing is off.
+    remaining_ms: u32,
+    /// Time balanced since the last stop.
+    session_ms: u32,
+    since_command_ms: u32,
+    timed_out: bool,
+    paused: bool,
+    temperature_fault: bool,
+    duty: u32,
+    phase_ms: u16,
+    output: bool,
+    on_time_ms: u64,
+    /// Removed charge in milliampere-milliseconds.
+    charge_mams: u64,
+}
+
+impl Controller {
+    /// Creates a controller with balancing off.
+    #[must_use]
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            remaining_ms: 0,
+            session_ms: 0,
+            since_command_ms: 0,
+            timed_out: false,
+            paused: false,
+            temperature_fault: false,
+            duty: DUTY_FULL,
+            phase_ms: 0,
+            output: false,
+            on_time_ms: 0,
+            charge_mams: 0,
+        }
+    }
+
+    /// Returns the settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Processes a balancing request of the cellcore.
+    ///
+    /// Returns the accepted request, whose duration is limited to what is
+    /// left of [`Config::max_on_time_s`].
+    pub fn command(&mut self, request: Balancing) -> Balancing {
+        self.keep_alive();
+        if request.enable && request.duration_s > 0 {
+            let left_ms =
+                (u32::from(self.config.max_on_time_s) * 1000).saturating_sub(self.session_ms);
+            self.remaining_ms = (u32::from(request.duration_s) * 1000).min(left_ms);
+            self.state()
+        } else {
+            self.stop();
+            Balancing::OFF
+        }
+    }
+
+    /// Restarts the command timeout.
+    ///
+    /// To be called for every valid request of the cellcore.
+    pub fn keep_alive(&mut self) {
+        self.since_command_ms = 0;
+        self.timed_out = false;
+    }
+
+    /// Stops balancing immediately.
+    ///
+    /// The next request may balance for [`Config::max_on_time_s`] again.
+    pub fn stop(&mut self) {
+        self.remaining_ms = 0;
+        self.session_ms = 0;
+        self.output = false;
+    }
+
+    /// Advances the controller by `dt_ms`.
+    ///
+    /// `cell` is the cell voltage during the elapsed time, which determines
+    /// the removed charge. Returns the new state of the bleed output.
+    pub fn update(
+        &mut self,
+        dt_ms: u32,
+        temperature: Option<Temperature>,
+        cell: Millivolts,
+    ) -> bool {
+        if self.output {
+            self.on_time_ms += u64::from(dt_ms);
+            // mV * ms / mOhm = A * ms, scaled to mA * ms
+            self.charge_mams += u64::from(cell.0) * u64::from(dt_ms) * 1000
+                / u64::from(self.config.resistance_mohm.max(1));
+        }
+
+        if self.is_active() {
+            self.session_ms = self.session_ms.saturating_add(dt_ms.min(self.remaining_ms));
+        }
+        self.remaining_ms = self.remaining_ms.saturating_sub(dt_ms);
+        self.since_command_ms = self.since_command_ms.saturating_add(dt_ms);
+        if self.since_command_ms >= self.config.command_timeout_ms {
+            self.timed_out = true;
+            self.remaining_ms = 0;
+            self.session_ms = 0;
+        }
+
+        self.update_temperature(temperature);
+
+        let period = u32::from(self.config.period_ms.max(1));
+        self.phase_ms = ((u32::from(self.phase_ms) + dt_ms) % period) as u16;
+        let on_ms = period * self.duty / DUTY_FULL;
+        self.output = self.is_active() && u32::from(self.phase_ms) < on_ms;
+        self.output
+    }
+
+    fn update_temperature(&mut self, temperature: Option<Temperature>) {
+        let Some(temperature) = temperature else {
+            self.temperature_fault = true;
+            self.duty = 0;
+            return;
+        };
+        self.temperature_fault = false;
+
+        let t = i32::from(temperature.raw());
+        let derate = i32::from(self.config.derate_temperature.raw());
+        let stop = i32::from(self.config.stop_temperature.raw());
+        if t >= stop {
+            self.paused = true;
+        } else if t <= i32::from(self.config.resume_temperature.raw()) {
+            self.paused = false;
+        }
+
+        self.duty = if self.paused {
+            0
+        } else if t <= derate {
+            DUTY_FULL
+        } else {
+            // Linear from full at the derating to zero at the stop temperature.
+            ((stop - t) * DUTY_FULL as i32 / (stop - derate).max(1)) as u32
+        };
+    }
+
+    /// Returns true while a balancing request is in progress.
+    ///
+    /// The output may still be off due to the temperature.
+    #[must_use]
+    pub const fn is_active(&self) -> bool {
+        self.remaining_ms > 0
+    }
+
+    /// Returns the state of the bleed output.
+    #[must_use]
+    pub const fn output(&self) -> bool {
+        self.output
+    }
+
+    /// Returns the current request with its remaining duration.
+    #[must_use]
+    pub const fn state(&self) -> Balancing {
+        if self.is_active() {
+            Balancing::on(self.remaining_ms.div_ceil(1000) as u16)
+        } else {
+            Balancing::OFF
+        }
+    }
+
+    /// Returns the balancing related status flags.
+    #[must_use]
+    pub fn flags(&self) -> StatusFlags {
+        let mut flags = StatusFlags::NONE;
+        flags.set(StatusFlags::BALANCING, self.output);
+        flags.set(
+            StatusFlags::OVER_TEMPERATURE,
+            !self.temperature_fault && self.duty < DUTY_FULL,
+        );
+        flags.set(StatusFlags::COMMAND_TIMEOUT, self.timed_out);
+        flags.set(StatusFlags::TEMPERATURE_FAULT, self.temperature_fault);
+        flags
+    }
+
+    /// Returns the totals since reset.
+    #[must_use]
+    pub const fn report(&self) -> BalancingReport {
+        BalancingReport {
+            on_time_s: (self.on_time_ms / 1000) as u32,
+            charge_mas: (self.charge_mams / 1000) as u32,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Requests of the cellcore received on the cell bus.
+//!
+//! | Request                      | Response                      |
+//! |------------------------------|-------------------------------|
+//! | [`Request::Ping`]            | [`Response::Pong`]            |
+//! | [`Request::ReadVoltage`]     | [`Response::Voltage`]         |
+//! | [`Request::ReadTemperature`] | [`Response::Temperature`]     |
+//! | [`Request::SetBalancing`]    | [`Response::Balancing`]       |
+//! | [`Request::ReadBalancing`]   | [`Response::BalancingReport`] |
+//! | [`Request::ReadStatus`]      | [`Response::Status`]          |
+//! | [`Request::ResetAddresses`]  | none, sent as broadcast       |
+//! | [`Request::AssignAddress`]   | [`Response::Address`]         |
+//!
+//! Requests sent to [`Address::BROADCAST`] are processed but not answered.
+//! Without an address, requests to [`Address::UNASSIGNED`] are ignored while
+//! the enable input is low, as described in the `addressing` module.
+//! Every request but the addressing ones restarts the command timeout of the
+//! balancing. Readings that aren't available are answered with
+//! [`ErrorCode::HardwareFault`], balancing requests in safe mode with
+//! [`ErrorCode::Busy`] and requests meant for the cellcore with
+//! [`ErrorCode::Unsupported`].
+
+use cellguard_protocol::{
+    Address, ErrorCode, Frame, MAX_FRAME_LEN, NodeInfo, NodeKind, Request, Response, Status,
+    Version,
+};
+use p3t1755::Temperature;
+
+use crate::addressing::{AddressStore, Addressing};
+use crate::balancing::Controller;
+use crate::voltage::Millivolts;
+
+/// The firmware state the cellcore operates on.
+pub trait Agent {
+    /// Returns the firmware version.
+    fn firmware(&self) -> Version;
+
+    /// Returns the latest cell voltage.
+    fn voltage(&self) -> Option<Millivolts>;
+
+    /// Returns the latest temperature next to the cell.
+    fn temperature(&self) -> Option<Temperature>;
+
+    /// Returns the balancing, `None` if the cellagent doesn't balance.
+    fn balancing(&mut self) -> Option<&mut Controller>;
+
+    /// Returns the status without the flags of the balancing.
+    fn status(&self) -> Status;
+}
+
+/// Answers a frame received on the cell bus.
+///
+/// `enabled` is the state of the enable input from the previous cellagent.
+/// Returns the length of the response written to `buf`, or `None` if the
+/// frame isn't answered. The enable output to the next cellagent follows
+/// [`Addressing::enables_next`] afterwards.
+pub fn handle<A: Agent, S: AddressStore>(
+    frame: &Frame<'_>,
+    addressing: &mut Addressing,
+    enabled: bool,
+    store: &mut S,
+    agent: &mut A,
+    buf: &mut [u8; MAX_FRAME_LEN],
+) -> Option<usize> {
+    let destination = frame.header.destination;
+    if destination == Address::UNASSIGNED && !enabled {
+        return None;
+    }
+    let response = match Request::decode(frame) {
+        // Assignments are sent to `Address::UNASSIGNED`, which an assigned
+        // cellagent doesn't accept.
+        Ok(Request::AssignAddress(address)) => addressing.assign(destination, address, store)?,
+        _ if !addressing.accepts(destination) => return None,
+        Ok(Request::ResetAddresses) => {
+            addressing.reset();
+            return None;
+        }
+        Ok(request) => respond(request, agent).unwrap_or_else(Response::Error),
+        Err(code) => Response::Error(code),
+    };
+    if destination == Address::BROADCAST {
+        return None;
+    }
+    response
+        .encode(frame.header.reply(addressing.address()), buf)
+        .ok()
+}
+
+fn respond<A: Agent>(request: Request, agent: &mut A) -> Result<Response, ErrorCode> {
+    if let Some(controller) = agent.balancing() {
+        controller.keep_alive();
+    }
+    Ok(match request {
+        Request::Ping => Response::Pong(NodeInfo::new(NodeKind::Agent, agent.firmware())),
+        Request::ReadVoltage => {
+            let Millivolts(mv) = agent.voltage().ok_or(ErrorCode::HardwareFault)?;
+            Response::Voltage(mv)
+        }
+        Request::ReadTemperature => {
+            let temperature = agent.temperature().ok_or(ErrorCode::HardwareFault)?;
+            Response::Temperature(temperature.raw())
+        }
+        Request::SetBalancing(balancing) => {
+            let controller = agent.balancing().ok_or(ErrorCode::Busy)?;
+            Response::Balancing(controller.command(balancing))
+        }
+        Request::ReadBalancing => {
+            let controller = agent.balancing().ok_or(ErrorCode::Busy)?;
+            Response::BalancingReport(controller.report())
+        }
+        Request::ReadStatus => {
+            let mut status = agent.status();
+            if let Some(controller) = agent.balancing() {
+                status.flags = status.flags | controller.flags();
+            }
+            Response::Status(status)
+        }
+        Request::ResetAddresses
+        | Request::AssignAddress(_)
+        | Request::ReadPack
+        | Request::ReadCells(_)
+        | Request::ReadSetting(_)
+        | Request::WriteSetting(_)
+        | Request::ReadEvent(_)
+        | Request::ReadCrash => return Err(ErrorCode::Unsupported),
+    })
+}
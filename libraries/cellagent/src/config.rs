@@ -0,0 +1,89 @@
+//! Settings of the cellagent kept in EEPROM.
+//!
+//! [`Config`] holds what differs between cellagents: the address of the last
+//! enumeration, the calibration of the voltage measurement and the limits of
+//! the bleed resistor. The firmware keeps it in a [`ConfigStore`], which also
+//! persists the address as the [`AddressStore`] of the
+//! [`Addressing`](crate::addressing::Addressing).
+
+use cellguard_config::{Nvm, Reader, Schema, Store, Writer};
+use cellguard_protocol::Address;
+use p3t1755::Temperature;
+
+use crate::addressing::AddressStore;
+use crate::balancing;
+use crate::voltage::Calibration;
+
+/// Length of each of the two slots.
+///
+/// Leaves room for the configuration to grow.
+pub const SLOT_LEN: usize = 48;
+
+/// Store of the configuration in two slots of [`SLOT_LEN`] bytes.
+pub type ConfigStore<M> = Store<Config, M, SLOT_LEN>;
+
+/// Persistent settings of a cellagent.
+///
+/// The default is the factory configuration of an uncalibrated cellagent
+/// without an address.
+#[derive(Clone, Copy, Default)]
+pub struct Config {
+    /// Address assigned by the last enumeration.
+    pub address: Option<Address>,
+    /// Per-unit correction of the cell voltage.
+    pub calibration: Calibration,
+    /// Balancing settings.
+    pub balancing: balancing::Config,
+}
+
+impl Schema for Config {
+    const VERSION: u8 = 1;
+    const LEN: usize = 23;
+
+    fn encode(&self, writer: &mut Writer<'_>) {
+        writer.u8(self.address.unwrap_or(Address::UNASSIGNED).0);
+        writer.u16(self.calibration.gain_q15());
+        writer.i16(self.calibration.offset_mv());
+        let balancing = &self.balancing;
+        writer.u16(balancing.max_on_time_s);
+        writer.u32(balancing.command_timeout_ms);
+        writer.i16(balancing.derate_temperature.raw());
+        writer.i16(balancing.stop_temperature.raw());
+        writer.i16(balancing.resume_temperature.raw());
+        writer.u32(balancing.resistance_mohm);
+        writer.u16(balancing.period_ms);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Option<Self> {
+        let address = Address(reader.u8()?);
+        Some(Self {
+            // Anything but an agent address means no address.
+            address: address.is_agent().then_some(address),
+            calibration: Calibration::new(reader.u16()?, reader.i16()?),
+            balancing: balancing::Config {
+                max_on_time_s: reader.u16()?,
+                command_timeout_ms: reader.u32()?,
+                derate_temperature: Temperature::from_raw(reader.i16()?)?,
+                stop_temperature: Temperature::from_raw(reader.i16()?)?,
+                resume_temperature: Temperature::from_raw(reader.i16()?)?,
+                resistance_mohm: reader.u32()?,
+                period_ms: reader.u16()?,
+            },
+        })
+    }
+}
+
+impl<M: Nvm> AddressStore for ConfigStore<M> {
+    fn load(&mut self) -> Option<Address> {
+        self.config().address
+    }
+
+    fn store(&mut self, address: Address) {
+        let config = Config {
+            address: Some(address),
+            ..*self.config()
+        };
+        // After a failed write the address is only kept until the next reset.
+        let _ = self.save(config);
+    }
+}
@@ -0,0 +1,12 @@
+//! Hardware independent logic of the cellagent firmware.
+//!
+//! Everything in here is free of register access so it can be tested on the
+//! host. The firmware binds it to the peripherals of the ATtiny416.
+
+#![no_std]
+
+pub mod addressing;
+pub mod balancing;
+pub mod bus;
+pub mod config;
+pub mod voltage;
@@ -0,0 +1,303 @@
+//! Conversion of ADC0 results into the cell voltage.
+//!
+//! The cellagent is powered directly by its cell, so the cell voltage can be
+//! measured without any external components by converting the internal
+//! reference against VDD ([`Source::Vdd`]). Alternatively the cell voltage is
+//! fed to an analog input through a resistive divider ([`Source::Divider`]).
+//!
+//! ADC0 accumulates up to 64 samples into a 16-bit result, which is used for
+//! oversampling. All math is integer only and, except for the divider, fits in
+//! 32 bits.
+
+/// A voltage in millivolts.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Millivolts(pub u16);
+
+/// Maximum result of a single 10-bit conversion.
+const MAX_RESULT: u32 = 1023;
+
+/// Internal voltage reference of ADC0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Reference {
+    /// 0.55 V
+    V0_55 = 0x0,
+    /// 1.1 V
+    V1_1 = 0x1,
+    /// 2.5 V
+    V2_5 = 0x2,
+    /// 4.34 V
+    V4_34 = 0x3,
+    /// 1.5 V
+    V1_5 = 0x4,
+}
+
+impl Reference {
+    /// Returns the nominal reference voltage.
+    #[must_use]
+    pub const fn millivolts(self) -> u16 {
+        match self {
+            Self::V0_55 => 550,
+            Self::V1_1 => 1100,
+            Self::V2_5 => 2500,
+            Self::V4_34 => 4340,
+            Self::V1_5 => 1500,
+        }
+    }
+
+    /// Returns the value of the `ADC0REFSEL` field in `VREF.CTRLA`.
+    #[must_use]
+    pub const fn adc0refsel(self) -> u8 {
+        (self as u8) << 4
+    }
+}
+
+/// Number of samples accumulated into one result.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+pub enum Accumulation {
+    /// Single conversion.
+    Acc1 = 0x0,
+    Acc2 = 0x1,
+    Acc4 = 0x2,
+    Acc8 = 0x3,
+    #[default]
+    Acc16 = 0x4,
+    Acc32 = 0x5,
+    Acc64 = 0x6,
+}
+
+impl Accumulation {
+    /// Returns the number of accumulated samples.
+    #[must_use]
+    pub const fn samples(self) -> u8 {
+        1 << (self as u8)
+    }
+
+    /// Returns the value of the `SAMPNUM` field in `ADC0.CTRLB`.
+    #[must_use]
+    pub const fn sampnum(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Resistive divider in front of an analog input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Divider {
+    /// Ratio of input to output voltage in Q16 fixed point.
+    ratio_q16: u32,
+}
+
+impl Divider {
+    /// Creates a divider from its resistor values.
+    ///
+    /// `top` is connected between the cell and the analog input, `bottom`
+    /// between the analog input and ground.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bottom` is zero or the ratio is too large. Both are caught
+    /// at compile time when used in a constant.
+    #[must_use]
+    pub const fn new(top_ohms: u32, bottom_ohms: u32) -> Self {
+        assert!(bottom_ohms > 0, "bottom resistor must not be zero");
+        let total = top_ohms as u64 + bottom_ohms as u64;
+        let ratio_q16 = (total << 16) / bottom_ohms as u64;
+        assert!(ratio_q16 <= u32::MAX as u64, "divider ratio too large");
+        Self {
+            ratio_q16: ratio_q16 as u32,
+        }
+    }
+}
+
+/// What ADC0 converts to determine the cell voltage.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    /// Converts the internal reference with VDD as the ADC reference.
+    ///
+    /// Requires the cellagent to be supplied directly by the cell.
+    Vdd(Reference),
+    /// Converts an analog input behind a divider with the internal reference
+    /// as the ADC reference.
+    Divider(Reference, Divider),
+}
+
+impl Source {
+    /// Converts an accumulated ADC result to the voltage before calibration.
+    ///
+    /// Returns `None` for a VDD measurement with a zero result, which can
+    /// only happen if the ADC is misconfigured.
+    #[must_use]
+    pub const fn convert(self, result: u16, accumulation: Accumulation) -> Option<Millivolts> {
+        let samples = accumulation.samples() as u32;
+        let result = result as u32;
+        let mv = match self {
+            Self::Vdd(reference) => {
+                // result = MAX_RESULT * samples * VREF / VDD
+                if result == 0 {
+                    return None;
+                }
+                let numerator = reference.millivolts() as u32 * MAX_RESULT * samples;
+                (numerator + result / 2) / result
+            }
+            Self::Divider(reference, divider) => {
+                // result = MAX_RESULT * samples * VIN / VREF
+                let input =
+                    result as u64 * reference.millivolts() as u64 * divider.ratio_q16 as u64;
+                let denominator = (MAX_RESULT * samples) as u64;
+                let mv = (input + (denominator << 15)) / (denominator << 16);
+                if mv > u32::MAX as u64 {
+                    u32::MAX
+                } else {
+                    mv as u32
+                }
+            }
+        };
+        Some(saturate(mv))
+    }
+}
+
+/// Per-unit gain and offset correction.
+///
+/// The corrected voltage is `measured * gain + offset`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Calibration {
+    /// Gain in Q15 fixed point, so the range is 0 to 2.
+    gain_q15: u16,
+    offset_mv: i16,
+}
+
+impl Calibration {
+    /// Calibration that doesn't change the measured voltage.
+    pub const IDENTITY: Self = Self {
+        gain_q15: 1 << 15,
+        offset_mv: 0,
+    };
+
+    /// Creates a calibration from a gain in Q15 fixed point and an offset.
+    #[must_use]
+    pub const fn new(gain_q15: u16, offset_mv: i16) -> Self {
+        Self {
+            gain_q15,
+            offset_mv,
+        }
+    }
+
+    /// Derives a calibration from measurements at two known voltages.
+    ///
+    /// Returns `None` if both measurements are equal or if the resulting gain
+    /// or offset is out of range.
+    #[must_use]
+    pub const fn from_two_points(
+        measured_low: Millivolts,
+        actual_low: Millivolts,
+        measured_high: Millivolts,
+        actual_high: Millivolts,
+    ) -> Option<Self> {
+        let measured_span = measured_high.0 as i64 - measured_low.0 as i64;
+        let actual_span = actual_high.0 as i64 - actual_low.0 as i64;
+        if measured_span <= 0 || actual_span <= 0 {
+            return None;
+        }
+        let gain_q15 = ((actual_span << 15) + measured_span / 2) / measured_span;
+        if gain_q15 > u16::MAX as i64 {
+            return None;
+        }
+        let offset = actual_low.0 as i64 - ((measured_low.0 as i64 * gain_q15 + (1 << 14)) >> 15);
+        if offset < i16::MIN as i64 || offset > i16::MAX as i64 {
+            return None;
+        }
+        Some(Self::new(gain_q15 as u16, offset as i16))
+    }
+
+    /// Returns the gain in Q15 fixed point.
+    #[must_use]
+    pub const fn gain_q15(self) -> u16 {
+        self.gain_q15
+    }
+
+    /// Returns the offset in millivolts.
+    #[must_use]
+    pub const fn offset_mv(self) -> i16 {
+        self.offset_mv
+    }
+
+    /// Applies the calibration to a measured voltage.
+    ///
+    /// Saturates to the range of [`Millivolts`].
+    #[must_use]
+    pub const fn apply(self, measured: Millivolts) -> Millivolts {
+        // Can't overflow: u16::MAX * u16::MAX + 2^14 < u32::MAX
+        let scaled = (measured.0 as u32 * self.gain_q15 as u32 + (1 << 14)) >> 15;
+        let corrected = scaled as i32 + self.offset_mv as i32;
+        if corrected < 0 {
+            Millivolts(0)
+        } else {
+            saturate(corrected as u32)
+        }
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Cell voltage measurement settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Measurement {
+    /// What is converted.
+    pub source: Source,
+    /// Number of samples per measurement.
+    pub accumulation: Accumulation,
+    /// Per-unit correction.
+    pub calibration: Calibration,
+}
+
+impl Measurement {
+    /// Converts an accumulated ADC result to the calibrated cell voltage.
+    ///
+    /// Returns `None` if the result is implausible, see [`Source::convert`].
+    #[must_use]
+    pub const fn convert(&self, result: u16) -> Option<Millivolts> {
+        match self.source.convert(result, self.accumulation) {
+            Some(mv) => Some(self.calibration.apply(mv)),
+            None => None,
+        }
+    }
+}
+
+const fn saturate(mv: u32) -> Millivolts {
+    if mv > u16::MAX as u32 {
+        Millivolts(u16::MAX)
+    } else {
+        Millivolts(mv as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulation_samples() {
+        assert_eq!(Accumulation::Acc1.samples(), 1);
+        assert_eq!(Accumulation::Acc16.samples(), 16);
+        assert_eq!(Accumulation::Acc64.samples(), 64);
+        assert_eq!(Accumulation::Acc64.sampnum(), 0x6);
+    }
+
+    #[test]
+    fn reference_bits() {
+        assert_eq!(Reference::V1_1.adc0refsel(), 0x10);
+        assert_eq!(Reference::V1_5.adc0refsel(), 0x40);
+    }
+
+    #[test]
+    fn divider_ratio() {
+        assert_eq!(Divider::new(0, 1).ratio_q16, 1 << 16);
+        assert_eq!(Divider::new(100_000, 100_000).ratio_q16, 2 << 16);
+        assert_eq!(Divider::new(200_000, 100_000).ratio_q16, 3 << 16);
+    }
+}
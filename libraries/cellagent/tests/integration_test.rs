@@ -0,0 +1,682 @@
+//! Integration tests for the cellagent logic.
+
+use cellagent::addressing::{AddressStore, Addressing, RamStore};
+use cellagent::balancing::{Config, Controller};
+use cellagent::bus::{self, Agent};
+use cellagent::config::{ConfigStore, SLOT_LEN};
+use cellagent::voltage::{
+    Accumulation, Calibration, Divider, Measurement, Millivolts, Reference, Source,
+};
+use cellguard_config::mem::MemNvm;
+use cellguard_config::{Origin, Schema};
+use cellguard_protocol::{
+    Address, Balancing, BalancingReport, ErrorCode, Frame, Header, MAX_FRAME_LEN, NodeInfo,
+    NodeKind, Request, Response, Status, StatusFlags, Version,
+};
+use p3t1755::Temperature;
+
+/// Returns the ideal accumulated ADC result for a VDD measurement.
+fn vdd_result(vdd_mv: u32, reference: Reference, accumulation: Accumulation) -> u16 {
+    let samples = u32::from(accumulation.samples());
+    (1023 * samples * u32::from(reference.millivolts()) / vdd_mv) as u16
+}
+
+#[test]
+fn test_vdd_single_sample() {
+    let source = Source::Vdd(Reference::V1_1);
+    // 1.1 V against 3.3 V is a third of the range.
+    assert_eq!(
+        source.convert(341, Accumulation::Acc1),
+        Some(Millivolts(3300))
+    );
+    // Full scale means VDD equals the reference.
+    assert_eq!(
+        source.convert(1023, Accumulation::Acc1),
+        Some(Millivolts(1100))
+    );
+    assert_eq!(source.convert(0, Accumulation::Acc1), None);
+}
+
+#[test]
+fn test_vdd_accumulated_over_cell_range() {
+    let source = Source::Vdd(Reference::V1_1);
+    for accumulation in [Accumulation::Acc1, Accumulation::Acc16, Accumulation::Acc64] {
+        // One LSB is about 16 mV at 4.3 V. Oversampling improves the resolution,
+        // so the error shrinks with more samples.
+        let tolerance = 17 / u16::from(accumulation.samples()) + 1;
+        for vdd in (2500..=4300).step_by(50) {
+            let result = vdd_result(vdd, Reference::V1_1, accumulation);
+            let Millivolts(mv) = source.convert(result, accumulation).unwrap();
+            assert!(
+                mv.abs_diff(vdd as u16) <= tolerance,
+                "{vdd} mV converted to {mv} mV with {} samples",
+                accumulation.samples()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_divider() {
+    // Halving divider against 2.5 V: half scale is 1.25 V at the pin.
+    let source = Source::Divider(Reference::V2_5, Divider::new(100_000, 100_000));
+    assert_eq!(
+        source.convert(512 * 16, Accumulation::Acc16),
+        Some(Millivolts(2502))
+    );
+    assert_eq!(source.convert(0, Accumulation::Acc16), Some(Millivolts(0)));
+    assert_eq!(
+        source.convert(1023 * 64, Accumulation::Acc64),
+        Some(Millivolts(5000))
+    );
+}
+
+#[test]
+fn test_divider_saturates() {
+    let source = Source::Divider(Reference::V4_34, Divider::new(10_000_000, 1_000));
+    assert_eq!(
+        source.convert(1023, Accumulation::Acc1),
+        Some(Millivolts(u16::MAX))
+    );
+}
+
+#[test]
+fn test_calibration_identity() {
+    for mv in [0, 1, 3300, u16::MAX] {
+        assert_eq!(Calibration::IDENTITY.apply(Millivolts(mv)), Millivolts(mv));
+    }
+}
+
+#[test]
+fn test_calibration_gain_and_offset() {
+    // +1 % gain, -20 mV offset.
+    let calibration = Calibration::new(33_096, -20);
+    assert_eq!(calibration.apply(Millivolts(3300)), Millivolts(3313));
+    assert_eq!(calibration.apply(Millivolts(10)), Millivolts(0));
+    assert_eq!(
+        Calibration::new(u16::MAX, 0).apply(Millivolts(u16::MAX)),
+        Millivolts(u16::MAX)
+    );
+}
+
+#[test]
+fn test_calibration_from_two_points() {
+    // The unit reads 1 % high with a 15 mV offset.
+    let read = |actual: u16| Millivolts((u32::from(actual) * 101 / 100 + 15) as u16);
+    let calibration =
+        Calibration::from_two_points(read(3000), Millivolts(3000), read(4200), Millivolts(4200))
+            .unwrap();
+    for actual in (2800..=4300).step_by(100) {
+        let Millivolts(corrected) = calibration.apply(read(actual));
+        assert!(corrected.abs_diff(actual) <= 1, "{actual} -> {corrected}");
+    }
+}
+
+#[test]
+fn test_calibration_from_two_points_invalid() {
+    let mv = Millivolts;
+    assert_eq!(
+        Calibration::from_two_points(mv(3000), mv(3000), mv(3000), mv(4000)),
+        None
+    );
+    assert_eq!(
+        Calibration::from_two_points(mv(4000), mv(3000), mv(3000), mv(4000)),
+        None
+    );
+    // Gain above 2.
+    assert_eq!(
+        Calibration::from_two_points(mv(3000), mv(3000), mv(3100), mv(4000)),
+        None
+    );
+}
+
+#[test]
+fn test_measurement() {
+    let measurement = Measurement {
+        source: Source::Vdd(Reference::V1_1),
+        accumulation: Accumulation::Acc16,
+        calibration: Calibration::new(1 << 15, 10),
+    };
+    let result = vdd_result(3700, Reference::V1_1, Accumulation::Acc16);
+    let Millivolts(mv) = measurement.convert(result).unwrap();
+    assert!(mv.abs_diff(3710) <= 1);
+    assert_eq!(measurement.convert(0), None);
+}
+
+#[test]
+fn test_address_assignment() {
+    let mut store = RamStore::default();
+    let mut addressing = Addressing::load(&mut store);
+    assert!(!addressing.is_assigned());
+    assert!(!addressing.enables_next());
+    assert!(addressing.accepts(Address::UNASSIGNED));
+
+    assert_eq!(
+        addressing.assign(Address::UNASSIGNED, Address(0x04), &mut store),
+        Some(Response::Address(Address(0x04)))
+    );
+    assert!(addressing.enables_next());
+    assert!(addressing.accepts(Address(0x04)));
+    assert!(!addressing.accepts(Address::UNASSIGNED));
+    assert_eq!(store.load(), Some(Address(0x04)));
+
+    // Assigned agents ignore further assignments until they are reset.
+    assert_eq!(
+        addressing.assign(Address::UNASSIGNED, Address(0x05), &mut store),
+        None
+    );
+    addressing.reset();
+    assert_eq!(addressing.address(), Address::UNASSIGNED);
+    assert_eq!(store.load(), Some(Address(0x04)));
+    assert_eq!(Addressing::load(&mut store).address(), Address(0x04));
+}
+
+#[test]
+fn test_address_assignment_rejects_invalid() {
+    let mut store = RamStore::default();
+    let mut addressing = Addressing::load(&mut store);
+    assert_eq!(
+        addressing.assign(Address::UNASSIGNED, Address::HOST, &mut store),
+        Some(Response::Error(ErrorCode::InvalidPayload))
+    );
+    assert_eq!(
+        addressing.assign(Address::BROADCAST, Address(0x01), &mut store),
+        None
+    );
+    assert!(!addressing.is_assigned());
+    assert_eq!(store.writes, 0);
+
+    let mut store = RamStore {
+        address: Some(Address::BROADCAST),
+        writes: 0,
+    };
+    assert_eq!(Addressing::load(&mut store).address(), Address::UNASSIGNED);
+}
+
+/// Firmware state of a simulated cellagent.
+struct SimAgent {
+    voltage: Option<Millivolts>,
+    temperature: Option<Temperature>,
+    balancing: Option<Controller>,
+    flags: StatusFlags,
+}
+
+impl Agent for SimAgent {
+    fn firmware(&self) -> Version {
+        Version::new(0, 1, 0)
+    }
+
+    fn voltage(&self) -> Option<Millivolts> {
+        self.voltage
+    }
+
+    fn temperature(&self) -> Option<Temperature> {
+        self.temperature
+    }
+
+    fn balancing(&mut self) -> Option<&mut Controller> {
+        self.balancing.as_mut()
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            flags: self.flags,
+            uptime_s: 42,
+        }
+    }
+}
+
+/// Cellagent answering requests on the cell bus.
+struct Bench {
+    addressing: Addressing,
+    /// State of the enable input.
+    enabled: bool,
+    store: RamStore,
+    agent: SimAgent,
+}
+
+impl Bench {
+    fn new() -> Self {
+        let mut store = RamStore::default();
+        Self {
+            addressing: Addressing::load(&mut store),
+            enabled: true,
+            store,
+            agent: SimAgent {
+                voltage: Some(CELL),
+                temperature: ROOM,
+                balancing: Some(Controller::new(Config::default())),
+                flags: StatusFlags::NONE,
+            },
+        }
+    }
+
+    /// Sends a request of the cellcore and returns the response.
+    fn request(&mut self, destination: Address, request: Request) -> Option<Response> {
+        let header = Header::new(destination, Address::CORE, 3);
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = request.encode(header, &mut buf).unwrap();
+        let (frame, _) = Frame::decode(&buf[..len]).unwrap();
+        let mut reply = [0; MAX_FRAME_LEN];
+        let len = bus::handle(
+            &frame,
+            &mut self.addressing,
+            self.enabled,
+            &mut self.store,
+            &mut self.agent,
+            &mut reply,
+        )?;
+        let (frame, _) = Frame::decode(&reply[..len]).unwrap();
+        assert!(frame.header.is_reply_to(&header));
+        assert_eq!(frame.header.source, self.addressing.address());
+        Some(Response::decode(&frame).unwrap())
+    }
+}
+
+#[test]
+fn test_bus_addressing() {
+    let mut bench = Bench::new();
+    let pong = Response::Pong(NodeInfo::new(NodeKind::Agent, Version::new(0, 1, 0)));
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::Ping),
+        Some(pong)
+    );
+    assert_eq!(bench.request(Address(0x02), Request::Ping), None);
+
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::AssignAddress(Address(0x02))),
+        Some(Response::Address(Address(0x02)))
+    );
+    assert_eq!(bench.store.address, Some(Address(0x02)));
+    assert!(bench.addressing.enables_next());
+    assert_eq!(bench.request(Address(0x02), Request::Ping), Some(pong));
+    assert_eq!(bench.request(Address::UNASSIGNED, Request::Ping), None);
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::AssignAddress(Address(0x03))),
+        None
+    );
+
+    // Broadcasts are processed but not answered.
+    assert_eq!(bench.request(Address::BROADCAST, Request::Ping), None);
+    assert_eq!(
+        bench.request(Address::BROADCAST, Request::ResetAddresses),
+        None
+    );
+    assert!(!bench.addressing.is_assigned());
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::AssignAddress(Address(0x02))),
+        Some(Response::Address(Address(0x02)))
+    );
+    assert_eq!(bench.store.writes, 1);
+}
+
+#[test]
+fn test_bus_enable_input() {
+    let mut bench = Bench::new();
+    bench.enabled = false;
+    assert_eq!(bench.request(Address::UNASSIGNED, Request::Ping), None);
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::AssignAddress(Address(0x02))),
+        None
+    );
+    assert!(!bench.addressing.is_assigned());
+
+    bench.enabled = true;
+    bench.request(Address::UNASSIGNED, Request::AssignAddress(Address(0x02)));
+    // An assigned agent keeps answering when the previous one is reset.
+    bench.enabled = false;
+    assert!(bench.request(Address(0x02), Request::Ping).is_some());
+    bench.request(Address::BROADCAST, Request::ResetAddresses);
+    assert!(!bench.addressing.is_assigned());
+    assert!(!bench.addressing.enables_next());
+}
+
+#[test]
+fn test_bus_readings() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadVoltage),
+        Some(Response::Voltage(3300))
+    );
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadTemperature),
+        Some(Response::Temperature(25 * 16))
+    );
+
+    bench.agent.voltage = None;
+    bench.agent.temperature = None;
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadVoltage),
+        Some(Response::Error(ErrorCode::HardwareFault))
+    );
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadTemperature),
+        Some(Response::Error(ErrorCode::HardwareFault))
+    );
+}
+
+#[test]
+fn test_bus_balancing() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(
+            Address::UNASSIGNED,
+            Request::SetBalancing(Balancing::on(60))
+        ),
+        Some(Response::Balancing(Balancing::on(60)))
+    );
+
+    // Any request restarts the command timeout.
+    let controller = bench.agent.balancing.as_mut().unwrap();
+    assert!(controller.update(4000, ROOM, CELL));
+    bench.request(Address::UNASSIGNED, Request::ReadVoltage);
+    let controller = bench.agent.balancing.as_mut().unwrap();
+    assert!(controller.update(4000, ROOM, CELL));
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadBalancing),
+        Some(Response::BalancingReport(BalancingReport {
+            on_time_s: 4,
+            charge_mas: 400,
+        }))
+    );
+
+    bench.agent.balancing = None;
+    assert_eq!(
+        bench.request(
+            Address::UNASSIGNED,
+            Request::SetBalancing(Balancing::on(60))
+        ),
+        Some(Response::Error(ErrorCode::Busy))
+    );
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadBalancing),
+        Some(Response::Error(ErrorCode::Busy))
+    );
+}
+
+#[test]
+fn test_bus_status() {
+    let mut bench = Bench::new();
+    bench.agent.flags = StatusFlags::CRASHED;
+    bench.request(
+        Address::UNASSIGNED,
+        Request::SetBalancing(Balancing::on(60)),
+    );
+    let controller = bench.agent.balancing.as_mut().unwrap();
+    assert!(controller.update(100, ROOM, CELL));
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadStatus),
+        Some(Response::Status(Status {
+            flags: StatusFlags::CRASHED | StatusFlags::BALANCING,
+            uptime_s: 42,
+        }))
+    );
+
+    bench.agent.balancing = None;
+    bench.agent.flags = StatusFlags::CRASHED | StatusFlags::SAFE_MODE;
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadStatus),
+        Some(Response::Status(Status {
+            flags: StatusFlags::CRASHED | StatusFlags::SAFE_MODE,
+            uptime_s: 42,
+        }))
+    );
+}
+
+#[test]
+fn test_bus_rejects_requests() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::ReadPack),
+        Some(Response::Error(ErrorCode::Unsupported))
+    );
+    assert_eq!(
+        bench.request(Address::UNASSIGNED, Request::AssignAddress(Address::CORE)),
+        Some(Response::Error(ErrorCode::InvalidPayload))
+    );
+    assert!(!bench.addressing.is_assigned());
+}
+
+const ROOM: Option<Temperature> = Some(Temperature::from_degrees_celsius(25));
+const CELL: Millivolts = Millivolts(3300);
+
+/// Runs the controller in steps of `dt_ms` and returns the on-time.
+fn run(controller: &mut Controller, ms: u32, dt_ms: u32, temperature: Option<Temperature>) -> u32 {
+    let mut on_ms = 0;
+    for _ in 0..ms / dt_ms {
+        controller.keep_alive();
+        if controller.update(dt_ms, temperature, CELL) {
+            on_ms += dt_ms;
+        }
+    }
+    on_ms
+}
+
+#[test]
+fn test_balancing_duration() {
+    let mut controller = Controller::new(Config::default());
+    assert!(!controller.update(100, ROOM, CELL));
+    assert_eq!(controller.command(Balancing::on(2)), Balancing::on(2));
+    assert!(controller.update(100, ROOM, CELL));
+    assert_eq!(controller.state(), Balancing::on(2));
+    assert!(controller.flags().contains(StatusFlags::BALANCING));
+
+    // The output is switched off by the step ending the request.
+    assert_eq!(run(&mut controller, 1900, 100, ROOM), 1800);
+    assert!(!controller.is_active());
+    assert!(!controller.output());
+    assert_eq!(controller.state(), Balancing::OFF);
+}
+
+#[test]
+fn test_balancing_limits_duration() {
+    let config = Config {
+        max_on_time_s: 60,
+        ..Config::default()
+    };
+    let mut controller = Controller::new(config);
+    assert_eq!(controller.command(Balancing::on(600)), Balancing::on(60));
+    assert_eq!(controller.command(Balancing::on(0)), Balancing::OFF);
+    assert!(!controller.is_active());
+}
+
+#[test]
+fn test_balancing_resend_keeps_limit() {
+    let config = Config {
+        max_on_time_s: 60,
+        ..Config::default()
+    };
+    let mut controller = Controller::new(config);
+    let mut on_ms = 0;
+    // The cellcore repeats its request every second, within the timeout.
+    for _ in 0..120 {
+        controller.command(Balancing::on(600));
+        on_ms += run(&mut controller, 1000, 100, ROOM);
+    }
+    // Less the step ending the request, as in the duration test.
+    assert_eq!(on_ms, 59_900);
+    assert!(!controller.is_active());
+    assert_eq!(controller.command(Balancing::on(600)), Balancing::OFF);
+
+    // A stop request starts over.
+    controller.command(Balancing::OFF);
+    assert_eq!(controller.command(Balancing::on(600)), Balancing::on(60));
+}
+
+#[test]
+fn test_balancing_stop_command() {
+    let mut controller = Controller::new(Config::default());
+    controller.command(Balancing::on(10));
+    assert!(controller.update(100, ROOM, CELL));
+    assert_eq!(controller.command(Balancing::OFF), Balancing::OFF);
+    assert!(!controller.output());
+    assert!(!controller.update(100, ROOM, CELL));
+}
+
+#[test]
+fn test_balancing_command_timeout() {
+    let mut controller = Controller::new(Config::default());
+    controller.command(Balancing::on(60));
+    for _ in 0..49 {
+        assert!(controller.update(100, ROOM, CELL));
+    }
+    assert!(!controller.update(100, ROOM, CELL));
+    assert!(!controller.is_active());
+    assert!(controller.flags().contains(StatusFlags::COMMAND_TIMEOUT));
+
+    // Other requests keep balancing alive but only a new command restarts it.
+    controller.keep_alive();
+    assert!(!controller.flags().contains(StatusFlags::COMMAND_TIMEOUT));
+    assert!(!controller.update(100, ROOM, CELL));
+    controller.command(Balancing::on(60));
+    assert!(controller.update(100, ROOM, CELL));
+}
+
+#[test]
+fn test_balancing_derating() {
+    let mut controller = Controller::new(Config::default());
+    controller.command(Balancing::on(60));
+    let hot = Some(Temperature::from_degrees_celsius(55));
+    // Halfway between derating and stop temperature.
+    assert_eq!(run(&mut controller, 10_000, 10, hot), 5000);
+    let flags = controller.flags();
+    assert!(flags.contains(StatusFlags::OVER_TEMPERATURE));
+}
+
+#[test]
+fn test_balancing_pause_with_hysteresis() {
+    let mut controller = Controller::new(Config::default());
+    controller.command(Balancing::on(600));
+    let stop = Some(Temperature::from_degrees_celsius(60));
+    let between = Some(Temperature::from_degrees_celsius(57));
+    let resume = Some(Temperature::from_degrees_celsius(55));
+
+    assert_eq!(run(&mut controller, 2000, 10, stop), 0);
+    assert!(controller.is_active());
+    assert_eq!(run(&mut controller, 2000, 10, between), 0);
+    assert!(run(&mut controller, 2000, 10, resume) > 0);
+    assert!(run(&mut controller, 2000, 10, between) > 0);
+}
+
+#[test]
+fn test_balancing_sensor_fault() {
+    let mut controller = Controller::new(Config::default());
+    controller.command(Balancing::on(60));
+    assert!(!controller.update(100, None, CELL));
+    assert!(controller.flags().contains(StatusFlags::TEMPERATURE_FAULT));
+    assert!(controller.update(100, ROOM, CELL));
+    assert!(!controller.flags().contains(StatusFlags::TEMPERATURE_FAULT));
+}
+
+#[test]
+fn test_balancing_report() {
+    let config = Config {
+        resistance_mohm: 33_000,
+        ..Config::default()
+    };
+    let mut controller = Controller::new(config);
+    controller.command(Balancing::on(1000));
+    controller.update(0, ROOM, CELL);
+    run(&mut controller, 600_000, 500, ROOM);
+    // 3.3 V over 33 Ohm is 100 mA, so 10 minutes remove 60 As.
+    assert_eq!(
+        controller.report(),
+        BalancingReport {
+            on_time_s: 600,
+            charge_mas: 60_000,
+        }
+    );
+
+    // Nothing is accounted while the output is off.
+    controller.command(Balancing::OFF);
+    run(&mut controller, 10_000, 500, ROOM);
+    assert_eq!(controller.report().on_time_s, 600);
+}
+
+type Eeprom = MemNvm<128>;
+
+#[test]
+fn test_config_defaults() {
+    let store = ConfigStore::load(Eeprom::new(), 0);
+    assert_eq!(store.origin(), Origin::Defaults);
+    let config = store.config();
+    assert_eq!(config.address, None);
+    assert_eq!(config.calibration, Calibration::IDENTITY);
+    assert_eq!(config.balancing.max_on_time_s, 3600);
+}
+
+#[test]
+fn test_config_roundtrip() {
+    let mut store = ConfigStore::load(Eeprom::new(), 0);
+    let config = cellagent::config::Config {
+        address: Some(Address(0x07)),
+        calibration: Calibration::new(33_000, -12),
+        balancing: Config {
+            max_on_time_s: 600,
+            command_timeout_ms: 2500,
+            derate_temperature: Temperature::from_degrees_celsius(45),
+            stop_temperature: Temperature::from_degrees_celsius(58),
+            resume_temperature: Temperature::from_raw(-8).unwrap(),
+            resistance_mohm: 47_000,
+            period_ms: 250,
+        },
+    };
+    store.save(config).unwrap();
+
+    let store = ConfigStore::load(store.release(), 0);
+    assert_eq!(store.origin(), Origin::Stored);
+    let loaded = store.config();
+    assert_eq!(loaded.address, Some(Address(0x07)));
+    assert_eq!(loaded.calibration, Calibration::new(33_000, -12));
+    let balancing = &loaded.balancing;
+    assert_eq!(balancing.max_on_time_s, 600);
+    assert_eq!(balancing.command_timeout_ms, 2500);
+    assert_eq!(balancing.derate_temperature.raw(), 45 * 16);
+    assert_eq!(balancing.stop_temperature.raw(), 58 * 16);
+    assert_eq!(balancing.resume_temperature.raw(), -8);
+    assert_eq!(balancing.resistance_mohm, 47_000);
+    assert_eq!(balancing.period_ms, 250);
+
+    // Both slots fit into the EEPROM of the ATtiny416.
+    let eeprom = store.release();
+    assert!(
+        eeprom.bytes[2 * SLOT_LEN..]
+            .iter()
+            .all(|&byte| byte == 0xFF)
+    );
+}
+
+#[test]
+fn test_config_stores_address() {
+    let mut store = ConfigStore::load(Eeprom::new(), 0);
+    let mut addressing = Addressing::load(&mut store);
+    addressing.assign(Address::UNASSIGNED, Address(0x09), &mut store);
+    // Reassigning the same address after a reset doesn't write.
+    addressing.reset();
+    addressing.assign(Address::UNASSIGNED, Address(0x09), &mut store);
+
+    let mut store = ConfigStore::load(store.release(), 0);
+    assert_eq!(Addressing::load(&mut store).address(), Address(0x09));
+    assert_eq!(store.release().max_wear(), 1);
+}
+
+#[test]
+fn test_config_rejects_invalid_temperature() {
+    let mut store = ConfigStore::load(Eeprom::new(), 0);
+    store
+        .save(cellagent::config::Config {
+            address: Some(Address(0x02)),
+            ..Default::default()
+        })
+        .unwrap();
+    let mut eeprom = store.release();
+    // Make the stop temperature 0x7FFF, above the range of the sensor, and fix
+    // up the CRC so only the value is wrong.
+    eeprom.bytes[5 + 13..5 + 15].copy_from_slice(&i16::MAX.to_le_bytes());
+    let end = 5 + cellagent::config::Config::LEN;
+    let crc = cellguard_protocol::crc16(&eeprom.bytes[..end]);
+    eeprom.bytes[end..end + 2].copy_from_slice(&crc.to_be_bytes());
+    let store = ConfigStore::load(eeprom, 0);
+    assert_eq!(store.origin(), Origin::Defaults);
+    assert_eq!(store.config().address, None);
+}
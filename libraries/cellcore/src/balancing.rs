@@ -0,0 +1,255 @@
+//! Selection of the cells to bleed.
+//!
+//! The [`Planner`] is run periodically with the latest readings of all cells
+//! and returns a [`Balancing`] request for every cellagent. Requests to bleed
+//! last [`Config::duration_s`], which has to be longer than the planning
+//! period so the cellagents don't stop between two plans.
+//!
+//! A cell starts bleeding once it is more than [`Config::threshold_mv`] above
+//! the lowest cell and stops when the difference has fallen below the
+//! threshold minus [`Config::hysteresis_mv`]. If more cells qualify than
+//! [`Config::max_bleeding`] allows, the highest ones are chosen.
+
+use cellguard_protocol::Balancing;
+use p3t1755::Temperature;
+
+/// When balancing is allowed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    /// Only balance while the highest cell is at or above `start_mv`.
+    ///
+    /// Balancing continues until the highest cell falls below `start_mv`
+    /// minus the hysteresis.
+    Top {
+        /// Voltage of the highest cell at which balancing starts.
+        start_mv: u16,
+    },
+    /// Balance whenever the cells drift apart.
+    Continuous,
+}
+
+/// Planner settings.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// When balancing is allowed.
+    pub strategy: Strategy,
+    /// Difference to the lowest cell above which a cell is bled.
+    pub threshold_mv: u16,
+    /// Hysteresis of the threshold and of [`Strategy::Top`].
+    pub hysteresis_mv: u16,
+    /// Cells at or below this voltage are never bled.
+    pub min_voltage_mv: u16,
+    /// Maximum number of cells bleeding at the same time.
+    pub max_bleeding: u8,
+    /// Cells whose cellagent is at or above this temperature are not bled.
+    pub max_temperature: Temperature,
+    /// Duration of each request to bleed.
+    pub duration_s: u16,
+    /// Time of balancing after which a rest period starts, zero to never rest.
+    pub balance_ms: u32,
+    /// Length of the rest period, during which no cell is bled so the cell
+    /// voltages can settle.
+    pub rest_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::Top { start_mv: 3400 },
+            threshold_mv: 15,
+            hysteresis_mv: 5,
+            min_voltage_mv: 3000,
+            max_bleeding: 4,
+            max_temperature: Temperature::from_degrees_celsius(50),
+            duration_s: 10,
+            balance_ms: 300_000,
+            rest_ms: 60_000,
+        }
+    }
+}
+
+/// Latest readings of a cell.
+///
+/// `None` marks a reading that is missing or invalid.
+#[derive(Clone, Copy, Default)]
+pub struct Cell {
+    /// Cell voltage in millivolts.
+    pub voltage_mv: Option<u16>,
+    /// Temperature of the cellagent.
+    pub temperature: Option<Temperature>,
+}
+
+/// What the planner is doing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    /// No cell needs bleeding or balancing isn't allowed.
+    Idle,
+    /// At least one cell is bleeding.
+    Balancing,
+    /// Balancing is paused for the cell voltages to settle.
+    Resting,
+    /// A cell voltage is missing, so the lowest cell is unknown.
+    Inhibited,
+}
+
+/// Result of a planning step.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Plan {
+    /// What the planner is doing.
+    pub phase: Phase,
+    /// Number of cells to bleed.
+    pub bleeding: u8,
+}
+
+/// Balancing planner for up to `N` cells.
+#[derive(Clone)]
+pub struct Planner<const N: usize> {
+    config: Config,
+    bleeding: [bool; N],
+    top_active: bool,
+    resting: bool,
+    /// Time spent in the current phase of the balance/rest cycle.
+    elapsed_ms: u32,
+}
+
+impl<const N: usize> Planner<N> {
+    /// Creates a planner with no cell bleeding.
+    #[must_use]
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            bleeding: [false; N],
+            top_active: false,
+            resting: false,
+            elapsed_ms: 0,
+        }
+    }
+
+    /// Returns the settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Changes the settings, which apply from the next plan on.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Returns which cells were selected by the last plan.
+    #[must_use]
+    pub const fn bleeding(&self) -> &[bool; N] {
+        &self.bleeding
+    }
+
+    /// Plans the next balancing step.
+    ///
+    /// `dt_ms` is the time since the last call. Writes a request for each
+    /// cell to `requests`, which must have the same length as `cells`. Cells
+    /// beyond `N` are never bled.
+    pub fn update(&mut self, dt_ms: u32, cells: &[Cell], requests: &mut [Balancing]) -> Plan {
+        let was_bleeding = self.bleeding.iter().any(|&b| b);
+        let phase = self.select(dt_ms, was_bleeding, cells);
+
+        let mut bleeding = 0;
+        for (i, request) in requests.iter_mut().enumerate() {
+            *request = if self.bleeding.get(i).copied().unwrap_or(false) {
+                bleeding += 1;
+                Balancing::on(self.config.duration_s)
+            } else {
+                Balancing::OFF
+            };
+        }
+        Plan { phase, bleeding }
+    }
+
+    /// Advances the balance/rest cycle and updates the selection.
+    fn select(&mut self, dt_ms: u32, was_bleeding: bool, cells: &[Cell]) -> Phase {
+        let cfg = self.config;
+
+        if self.resting || was_bleeding {
+            self.elapsed_ms = self.elapsed_ms.saturating_add(dt_ms);
+        }
+        if self.resting {
+            if self.elapsed_ms < cfg.rest_ms {
+                return Phase::Resting;
+            }
+            self.resting = false;
+            self.elapsed_ms = 0;
+        } else if cfg.balance_ms > 0 && self.elapsed_ms >= cfg.balance_ms {
+            self.resting = true;
+            self.elapsed_ms = 0;
+            self.bleeding = [false; N];
+            return Phase::Resting;
+        }
+
+        let mut min_mv = u16::MAX;
+        let mut max_mv = 0;
+        for cell in cells {
+            let Some(mv) = cell.voltage_mv else {
+                self.stop();
+                return Phase::Inhibited;
+            };
+            min_mv = min_mv.min(mv);
+            max_mv = max_mv.max(mv);
+        }
+
+        let allowed = match cfg.strategy {
+            Strategy::Top { start_mv } => {
+                if max_mv >= start_mv {
+                    self.top_active = true;
+                } else if max_mv < start_mv.saturating_sub(cfg.hysteresis_mv) {
+                    self.top_active = false;
+                }
+                self.top_active
+            }
+            Strategy::Continuous => true,
+        };
+        if !allowed || cells.is_empty() {
+            self.stop();
+            return Phase::Idle;
+        }
+
+        // Candidates keep their state until chosen below.
+        let mut candidates = [false; N];
+        for (i, cell) in cells.iter().enumerate().take(N) {
+            let Some(mv) = cell.voltage_mv else {
+                continue;
+            };
+            let threshold = if self.bleeding[i] {
+                cfg.threshold_mv.saturating_sub(cfg.hysteresis_mv)
+            } else {
+                cfg.threshold_mv
+            };
+            let cool = cell
+                .temperature
+                .is_some_and(|t| t.raw() < cfg.max_temperature.raw());
+            candidates[i] = mv - min_mv > threshold && mv > cfg.min_voltage_mv && cool;
+        }
+
+        // Choose the highest cells up to the limit.
+        self.bleeding = [false; N];
+        for _ in 0..cfg.max_bleeding {
+            let highest = (0..cells.len().min(N))
+                .filter(|&i| candidates[i] && !self.bleeding[i])
+                .max_by_key(|&i| (cells[i].voltage_mv, core::cmp::Reverse(i)));
+            match highest {
+                Some(i) => self.bleeding[i] = true,
+                None => break,
+            }
+        }
+
+        if self.bleeding.iter().any(|&b| b) {
+            Phase::Balancing
+        } else {
+            self.elapsed_ms = 0;
+            Phase::Idle
+        }
+    }
+
+    /// Deselects all cells and restarts the balance/rest cycle.
+    fn stop(&mut self) {
+        self.bleeding = [false; N];
+        self.elapsed_ms = 0;
+    }
+}
@@ -0,0 +1,113 @@
+//! Requests to the cellagents over the cell bus.
+
+use cellguard_protocol::{Address, DecodeError, Frame, Header, MAX_FRAME_LEN, Request, Response};
+
+/// Physical link to the cellagents.
+pub trait Bus {
+    /// Error of the link.
+    type Error;
+
+    /// Transmits an encoded frame and passes every frame received until the
+    /// response timeout to `receive`.
+    ///
+    /// Implementations wait for the full timeout even after the first frame
+    /// so that multiple responders can be detected.
+    fn exchange(
+        &mut self,
+        frame: &[u8],
+        receive: &mut dyn FnMut(Result<Frame<'_>, DecodeError>),
+    ) -> Result<(), Self::Error>;
+}
+
+/// Everything received in response to a request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Replies {
+    /// Source and content of the first valid response.
+    pub first: Option<(Address, Response)>,
+    /// Number of valid responses.
+    pub count: u8,
+    /// Number of corrupted frames and undecodable responses.
+    pub invalid: u8,
+}
+
+impl Replies {
+    /// Returns the only response, or `None` if there was no valid response
+    /// or more than one.
+    #[must_use]
+    pub fn single(&self) -> Option<(Address, Response)> {
+        if self.count == 1 { self.first } else { None }
+    }
+}
+
+/// Sends requests and collects their responses.
+#[derive(Debug)]
+pub struct Master<B> {
+    bus: B,
+    address: Address,
+    sequence: u8,
+}
+
+impl<B: Bus> Master<B> {
+    /// Creates a master sending from [`Address::CORE`].
+    pub const fn new(bus: B) -> Self {
+        Self::with_address(bus, Address::CORE)
+    }
+
+    /// Creates a master sending from `address`.
+    pub const fn with_address(bus: B, address: Address) -> Self {
+        Self {
+            bus,
+            address,
+            sequence: 0,
+        }
+    }
+
+    /// Returns the bus.
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Releases the bus.
+    pub fn release(self) -> B {
+        self.bus
+    }
+
+    /// Sends a request and collects the responses.
+    ///
+    /// Frames that don't answer this request, such as the echo of the request
+    /// on a shared line, are ignored.
+    pub fn request(
+        &mut self,
+        destination: Address,
+        request: &Request,
+    ) -> Result<Replies, B::Error> {
+        let header = Header::new(destination, self.address, self.sequence);
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let Ok(len) = request.encode(header, &mut buf) else {
+            unreachable!("requests always fit into a frame");
+        };
+
+        let mut replies = Replies::default();
+        self.bus.exchange(&buf[..len], &mut |frame| {
+            let Ok(frame) = frame else {
+                replies.invalid = replies.invalid.saturating_add(1);
+                return;
+            };
+            if !frame.header.is_reply_to(&header) {
+                return;
+            }
+            match Response::decode(&frame) {
+                Ok(response) => {
+                    if replies.first.is_none() {
+                        replies.first = Some((frame.header.source, response));
+                    }
+                    replies.count = replies.count.saturating_add(1);
+                }
+                Err(_) => replies.invalid = replies.invalid.saturating_add(1),
+            }
+        })?;
+        Ok(replies)
+    }
+}
@@ -0,0 +1,189 @@
+//! Settings of the cellcore kept in EEPROM.
+//!
+//! [`Config`] holds the thresholds of the protection, the balancing settings,
+//! the number of cellagents and the Modbus unit address. The firmware keeps
+//! it in a [`ConfigStore`] and saves it whenever a setting is changed on the
+//! console or by a host tool.
+
+use cellguard_config::{Reader, Schema, Store, Writer};
+use cellguard_protocol::Address;
+use p3t1755::Temperature;
+
+use crate::balancing::{self, Strategy};
+use crate::protection::{self, Limit, Quantity, Threshold};
+
+/// Length of each of the two slots.
+///
+/// Both slots together take the 512 bytes of the AVR128DB48 EEPROM.
+pub const SLOT_LEN: usize = 256;
+
+/// Store of the configuration in two slots of [`SLOT_LEN`] bytes.
+pub type ConfigStore<M> = Store<Config, M, SLOT_LEN>;
+
+/// Highest number of cellagents, one per agent address.
+pub const MAX_CELLS: u8 = Address::LAST_AGENT.0 - Address::FIRST_AGENT.0 + 1;
+
+/// Highest Modbus unit address.
+pub const MAX_MODBUS_UNIT: u8 = 247;
+
+/// Persistent settings of the cellcore.
+///
+/// The default is the factory configuration for a 16 cell LiFePO4 pack.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Protection thresholds.
+    pub protection: protection::Config,
+    /// Balancing settings.
+    pub balancing: balancing::Config,
+    /// Number of cellagents in the pack.
+    pub cells: u8,
+    /// Unit address of the Modbus RTU server.
+    pub modbus_unit: u8,
+}
+
+impl Config {
+    /// Returns the number of cellagents and the Modbus unit address.
+    #[must_use]
+    pub const fn pack(&self) -> PackConfig {
+        PackConfig {
+            cells: self.cells,
+            modbus_unit: self.modbus_unit,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let pack = PackConfig::default();
+        Self {
+            protection: protection::Config::default(),
+            balancing: balancing::Config::default(),
+            cells: pack.cells,
+            modbus_unit: pack.modbus_unit,
+        }
+    }
+}
+
+/// Settings of the pack besides the protection and the balancing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PackConfig {
+    /// Number of cellagents in the pack.
+    pub cells: u8,
+    /// Unit address of the Modbus RTU server.
+    pub modbus_unit: u8,
+}
+
+impl PackConfig {
+    /// Returns true if there are 1 to [`MAX_CELLS`] cellagents and the unit
+    /// address is between 1 and [`MAX_MODBUS_UNIT`].
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CELLS).contains(&self.cells) && (1..=MAX_MODBUS_UNIT).contains(&self.modbus_unit)
+    }
+}
+
+impl Default for PackConfig {
+    fn default() -> Self {
+        Self {
+            cells: 16,
+            modbus_unit: 1,
+        }
+    }
+}
+
+impl Schema for Config {
+    const VERSION: u8 = 1;
+    const LEN: usize = 240;
+
+    fn encode(&self, writer: &mut Writer<'_>) {
+        for quantity in Quantity::ALL {
+            let limit = self.protection.limit(quantity);
+            for threshold in [limit.warning, limit.alarm, limit.trip] {
+                writer.i32(threshold.value);
+                writer.i32(threshold.hysteresis);
+                writer.u32(threshold.delay_ms);
+            }
+        }
+
+        let balancing = &self.balancing;
+        match balancing.strategy {
+            Strategy::Top { start_mv } => {
+                writer.u8(0);
+                writer.u16(start_mv);
+            }
+            Strategy::Continuous => {
+                writer.u8(1);
+                writer.u16(0);
+            }
+        }
+        writer.u16(balancing.threshold_mv);
+        writer.u16(balancing.hysteresis_mv);
+        writer.u16(balancing.min_voltage_mv);
+        writer.u8(balancing.max_bleeding);
+        writer.i16(balancing.max_temperature.raw());
+        writer.u16(balancing.duration_s);
+        writer.u32(balancing.balance_ms);
+        writer.u32(balancing.rest_ms);
+
+        writer.u8(self.cells);
+        writer.u8(self.modbus_unit);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Option<Self> {
+        let protection = protection::Config {
+            over_voltage: limit(reader)?,
+            under_voltage: limit(reader)?,
+            over_temperature: limit(reader)?,
+            under_temperature: limit(reader)?,
+            charge_over_current: limit(reader)?,
+            discharge_over_current: limit(reader)?,
+        };
+
+        let strategy = match (reader.u8()?, reader.u16()?) {
+            (0, start_mv) => Strategy::Top { start_mv },
+            (1, _) => Strategy::Continuous,
+            _ => return None,
+        };
+        let balancing = balancing::Config {
+            strategy,
+            threshold_mv: reader.u16()?,
+            hysteresis_mv: reader.u16()?,
+            min_voltage_mv: reader.u16()?,
+            max_bleeding: reader.u8()?,
+            max_temperature: Temperature::from_raw(reader.i16()?)?,
+            duration_s: reader.u16()?,
+            balance_ms: reader.u32()?,
+            rest_ms: reader.u32()?,
+        };
+
+        let pack = PackConfig {
+            cells: reader.u8()?,
+            modbus_unit: reader.u8()?,
+        };
+        if !pack.is_valid() {
+            return None;
+        }
+        Some(Self {
+            protection,
+            balancing,
+            cells: pack.cells,
+            modbus_unit: pack.modbus_unit,
+        })
+    }
+}
+
+/// Reads the thresholds of a quantity in the order of [`Level::ALL`].
+///
+/// [`Level::ALL`]: crate::protection::Level::ALL
+fn limit(reader: &mut Reader<'_>) -> Option<Limit> {
+    let mut threshold = || {
+        let threshold = Threshold::new(reader.i32()?, reader.i32()?, reader.u32()?);
+        // Like on the console, a negative hysteresis is invalid.
+        (threshold.hysteresis >= 0).then_some(threshold)
+    };
+    Some(Limit {
+        warning: threshold()?,
+        alarm: threshold()?,
+        trip: threshold()?,
+    })
+}
@@ -0,0 +1,1101 @@
+//! Line based command console for bench bring-up.
+//!
+//! The [`Console`] echoes received characters and runs a line once it is
+//! terminated by CR or LF:
+//!
+//! | Command                     | Action                                        |
+//! |-----------------------------|-----------------------------------------------|
+//! | `help`                      | Lists the commands                            |
+//! | `scan`                      | Lists the addresses that acknowledge on I2C   |
+//! | `p3t <addr> <reg> [value]`  | Reads or writes a P3T1755 register            |
+//! | `tca <addr> <reg> [value]`  | Reads or writes a TCA9535 register            |
+//! | `cells`                     | Shows the cell voltages and temperatures      |
+//! | `config [prefix]`           | Shows the settings starting with `prefix`     |
+//! | `set <name> <value>`        | Changes a setting                             |
+//! | `clear`                     | Clears latched trips and shows active levels  |
+//! | `reset`                     | Shows the reset cause and the crash before it |
+//! | `events [count]`            | Shows the newest entries of the event log     |
+//!
+//! Addresses, registers and register values are decimal or hexadecimal with a
+//! `0x` prefix. Settings are named after their quantity, level and field like
+//! `ov.trip.value`, `bal.` followed by the balancing setting or `pack.cells`
+//! and `pack.modbus_unit`. Voltages are in mV, currents in mA and temperatures
+//! in °C with one decimal.
+
+use core::fmt::{self, Write};
+
+use cellguard_protocol::{CrashReport, Event, EventKind};
+use embedded_hal::i2c::{self, I2c};
+use p3t1755::Temperature;
+
+use crate::balancing::{self, Cell};
+use crate::config::PackConfig;
+use crate::contactor::Fault;
+use crate::protection::{self, Level, Protection, Quantity};
+
+/// Printed before every line.
+const PROMPT: &str = "> ";
+
+/// First and last address that isn't reserved on the I2C bus.
+const SCAN_ADDRESSES: core::ops::RangeInclusive<u8> = 0x08..=0x77;
+
+/// P3T1755 configuration register, the only one with a single byte.
+const P3T1755_CONFIG: u8 = 1;
+/// Number of P3T1755 registers.
+const P3T1755_REGISTERS: u8 = 4;
+/// Number of TCA9535 registers.
+const TCA9535_REGISTERS: u8 = 8;
+
+/// Name, arguments and description of every command.
+const COMMANDS: [(&str, &str, &str); 10] = [
+    ("help", "", "list the commands"),
+    ("scan", "", "scan the I2C bus"),
+    (
+        "p3t",
+        "<addr> <reg> [value]",
+        "read or write a P3T1755 register",
+    ),
+    (
+        "tca",
+        "<addr> <reg> [value]",
+        "read or write a TCA9535 register",
+    ),
+    ("cells", "", "show the cell telemetry"),
+    ("config", "[prefix]", "show the settings"),
+    ("set", "<name> <value>", "change a setting"),
+    ("clear", "", "clear latched trips"),
+    ("reset", "", "show the reset cause and crash"),
+    ("events", "[count]", "show the event log"),
+];
+
+/// Number of events shown by `events` without a count.
+const DEFAULT_EVENTS: u16 = 10;
+
+/// Cause of the last reset, as reported by the reset controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ResetCause(pub u8);
+
+impl ResetCause {
+    /// Power-on reset.
+    pub const POWER_ON: Self = Self(1 << 0);
+    /// Brown-out reset.
+    pub const BROWN_OUT: Self = Self(1 << 1);
+    /// External reset pin.
+    pub const EXTERNAL: Self = Self(1 << 2);
+    /// Watchdog reset.
+    pub const WATCHDOG: Self = Self(1 << 3);
+    /// Software reset.
+    pub const SOFTWARE: Self = Self(1 << 4);
+    /// Reset by the programming interface.
+    pub const UPDI: Self = Self(1 << 5);
+
+    /// No flag is set.
+    pub const NONE: Self = Self(0);
+
+    /// Names of all flags.
+    const NAMES: [(Self, &'static str); 6] = [
+        (Self::POWER_ON, "power-on"),
+        (Self::BROWN_OUT, "brown-out"),
+        (Self::EXTERNAL, "external"),
+        (Self::WATCHDOG, "watchdog"),
+        (Self::SOFTWARE, "software"),
+        (Self::UPDI, "UPDI"),
+    ];
+
+    /// Returns true if all flags of `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for ResetCause {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for ResetCause {
+    /// Lists the names of the flags, or "unknown" if none is set.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::NONE {
+            return f.write_str("unknown");
+        }
+        let mut separator = "";
+        for (flag, name) in Self::NAMES {
+            if self.contains(flag) {
+                write!(f, "{separator}{name}")?;
+                separator = ", ";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Field of a protection threshold.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Field {
+    /// [`protection::Threshold::value`].
+    Value,
+    /// [`protection::Threshold::hysteresis`].
+    Hysteresis,
+    /// [`protection::Threshold::delay_ms`].
+    Delay,
+}
+
+impl Field {
+    const ALL: [Self; 3] = [Self::Value, Self::Hysteresis, Self::Delay];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Value => "value",
+            Self::Hysteresis => "hysteresis",
+            Self::Delay => "delay",
+        }
+    }
+}
+
+/// Balancing setting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BalancingSetting {
+    /// [`balancing::Config::threshold_mv`].
+    Threshold,
+    /// [`balancing::Config::hysteresis_mv`].
+    Hysteresis,
+    /// [`balancing::Config::min_voltage_mv`].
+    MinVoltage,
+    /// [`balancing::Config::max_bleeding`].
+    MaxBleeding,
+    /// [`balancing::Config::max_temperature`].
+    MaxTemperature,
+    /// [`balancing::Config::duration_s`].
+    Duration,
+}
+
+impl BalancingSetting {
+    const ALL: [Self; 6] = [
+        Self::Threshold,
+        Self::Hysteresis,
+        Self::MinVoltage,
+        Self::MaxBleeding,
+        Self::MaxTemperature,
+        Self::Duration,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Threshold => "threshold",
+            Self::Hysteresis => "hysteresis",
+            Self::MinVoltage => "min_voltage",
+            Self::MaxBleeding => "max_bleeding",
+            Self::MaxTemperature => "max_temperature",
+            Self::Duration => "duration",
+        }
+    }
+}
+
+/// Setting of the pack.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PackSetting {
+    /// [`PackConfig::cells`].
+    Cells,
+    /// [`PackConfig::modbus_unit`].
+    ModbusUnit,
+}
+
+impl PackSetting {
+    const ALL: [Self; 2] = [Self::Cells, Self::ModbusUnit];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Cells => "cells",
+            Self::ModbusUnit => "modbus_unit",
+        }
+    }
+}
+
+/// Setting changeable from the console.
+///
+/// Host tools refer to settings by [`Setting::id`]. Thresholds use the layout
+/// of the Modbus holding registers, quantity index times 9 plus level index
+/// times 3 plus field index. Balancing settings start at
+/// [`Setting::BALANCING_ID`] and pack settings at [`Setting::PACK_ID`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Setting {
+    /// Field of a protection threshold.
+    Threshold(Quantity, Level, Field),
+    /// Balancing setting.
+    Balancing(BalancingSetting),
+    /// Pack setting.
+    Pack(PackSetting),
+}
+
+/// Unit of the value of a setting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Unit {
+    /// Millivolts.
+    Millivolt,
+    /// Stored in 1/16 °C and shown in °C.
+    Celsius,
+    /// Milliamperes.
+    Milliampere,
+    /// Milliseconds.
+    Millisecond,
+    /// Seconds.
+    Second,
+    /// Plain number.
+    Count,
+}
+
+impl Unit {
+    /// Returns the symbol with a leading space, or nothing for counts.
+    #[must_use]
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Millivolt => " mV",
+            Self::Celsius => " C",
+            Self::Milliampere => " mA",
+            Self::Millisecond => " ms",
+            Self::Second => " s",
+            Self::Count => "",
+        }
+    }
+}
+
+impl Setting {
+    /// Identifier of the first balancing setting.
+    pub const BALANCING_ID: u16 = 0x100;
+    /// Identifier of the first pack setting.
+    pub const PACK_ID: u16 = 0x200;
+
+    /// Returns all settings.
+    pub fn all() -> impl Iterator<Item = Self> {
+        let thresholds = Quantity::ALL.into_iter().flat_map(|quantity| {
+            Level::ALL.into_iter().flat_map(move |level| {
+                Field::ALL
+                    .into_iter()
+                    .map(move |field| Self::Threshold(quantity, level, field))
+            })
+        });
+        thresholds
+            .chain(BalancingSetting::ALL.into_iter().map(Self::Balancing))
+            .chain(PackSetting::ALL.into_iter().map(Self::Pack))
+    }
+
+    /// Looks up a setting by its name.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut parts = name.split('.');
+        let first = parts.next()?;
+        let setting = if first == "bal" {
+            let name = parts.next()?;
+            Self::Balancing(
+                BalancingSetting::ALL
+                    .into_iter()
+                    .find(|setting| setting.name() == name)?,
+            )
+        } else if first == "pack" {
+            let name = parts.next()?;
+            Self::Pack(
+                PackSetting::ALL
+                    .into_iter()
+                    .find(|setting| setting.name() == name)?,
+            )
+        } else {
+            let quantity = Quantity::ALL
+                .into_iter()
+                .find(|&quantity| quantity.name() == first)?;
+            let level = parts.next()?;
+            let level = Level::ALL.into_iter().find(|l| l.name() == level)?;
+            let field = parts.next()?;
+            let field = Field::ALL.into_iter().find(|f| f.name() == field)?;
+            Self::Threshold(quantity, level, field)
+        };
+        parts.next().is_none().then_some(setting)
+    }
+
+    /// Returns the identifier used by host tools.
+    #[must_use]
+    pub fn id(self) -> u16 {
+        // The variants are declared in the order of their `ALL` arrays.
+        match self {
+            Self::Threshold(quantity, level, field) => {
+                quantity as u16 * 9 + level as u16 * 3 + field as u16
+            }
+            Self::Balancing(setting) => Self::BALANCING_ID + setting as u16,
+            Self::Pack(setting) => Self::PACK_ID + setting as u16,
+        }
+    }
+
+    /// Looks up a setting by its identifier.
+    #[must_use]
+    pub fn from_id(id: u16) -> Option<Self> {
+        if let Some(index) = id.checked_sub(Self::PACK_ID) {
+            let setting = PackSetting::ALL.get(usize::from(index))?;
+            return Some(Self::Pack(*setting));
+        }
+        if let Some(index) = id.checked_sub(Self::BALANCING_ID) {
+            let setting = BalancingSetting::ALL.get(usize::from(index))?;
+            return Some(Self::Balancing(*setting));
+        }
+        let id = usize::from(id);
+        let quantity = *Quantity::ALL.get(id / 9)?;
+        Some(Self::Threshold(
+            quantity,
+            Level::ALL[id % 9 / 3],
+            Field::ALL[id % 3],
+        ))
+    }
+
+    /// Returns the unit of the value.
+    #[must_use]
+    pub const fn unit(self) -> Unit {
+        match self {
+            Self::Threshold(_, _, Field::Delay) => Unit::Millisecond,
+            Self::Threshold(quantity, _, _) => match quantity {
+                Quantity::OverVoltage | Quantity::UnderVoltage => Unit::Millivolt,
+                Quantity::OverTemperature | Quantity::UnderTemperature => Unit::Celsius,
+                Quantity::ChargeOverCurrent | Quantity::DischargeOverCurrent => Unit::Milliampere,
+            },
+            Self::Balancing(setting) => match setting {
+                BalancingSetting::Threshold
+                | BalancingSetting::Hysteresis
+                | BalancingSetting::MinVoltage => Unit::Millivolt,
+                BalancingSetting::MaxBleeding => Unit::Count,
+                BalancingSetting::MaxTemperature => Unit::Celsius,
+                BalancingSetting::Duration => Unit::Second,
+            },
+            Self::Pack(_) => Unit::Count,
+        }
+    }
+
+    /// Returns the value, temperatures in 1/16 °C.
+    pub(crate) fn get(
+        self,
+        protection: &protection::Config,
+        balancing: &balancing::Config,
+        pack: &PackConfig,
+    ) -> i32 {
+        match self {
+            Self::Threshold(quantity, level, field) => {
+                let threshold = protection.limit(quantity).threshold(level);
+                match field {
+                    Field::Value => threshold.value,
+                    Field::Hysteresis => threshold.hysteresis,
+                    Field::Delay => threshold.delay_ms.try_into().unwrap_or(i32::MAX),
+                }
+            }
+            Self::Balancing(setting) => match setting {
+                BalancingSetting::Threshold => balancing.threshold_mv.into(),
+                BalancingSetting::Hysteresis => balancing.hysteresis_mv.into(),
+                BalancingSetting::MinVoltage => balancing.min_voltage_mv.into(),
+                BalancingSetting::MaxBleeding => balancing.max_bleeding.into(),
+                BalancingSetting::MaxTemperature => balancing.max_temperature.raw().into(),
+                BalancingSetting::Duration => balancing.duration_s.into(),
+            },
+            Self::Pack(setting) => match setting {
+                PackSetting::Cells => pack.cells.into(),
+                PackSetting::ModbusUnit => pack.modbus_unit.into(),
+            },
+        }
+    }
+
+    /// Changes the value, temperatures in 1/16 °C.
+    ///
+    /// Returns `None` if the value is out of range, the thresholds of the
+    /// quantity would no longer be valid by [`protection::Limit::is_valid`]
+    /// or the pack settings by [`PackConfig::is_valid`].
+    pub(crate) fn set(
+        self,
+        value: i32,
+        protection: &mut protection::Config,
+        balancing: &mut balancing::Config,
+        pack: &mut PackConfig,
+    ) -> Option<()> {
+        match self {
+            Self::Threshold(quantity, level, field) => {
+                let mut limit = *protection.limit(quantity);
+                let threshold = limit.threshold_mut(level);
+                match field {
+                    Field::Value => threshold.value = value,
+                    Field::Hysteresis => threshold.hysteresis = value,
+                    Field::Delay => threshold.delay_ms = value.try_into().ok()?,
+                }
+                *protection.limit_mut(quantity) = limit.is_valid(quantity).then_some(limit)?;
+            }
+            Self::Balancing(setting) => match setting {
+                BalancingSetting::Threshold => balancing.threshold_mv = value.try_into().ok()?,
+                BalancingSetting::Hysteresis => balancing.hysteresis_mv = value.try_into().ok()?,
+                BalancingSetting::MinVoltage => balancing.min_voltage_mv = value.try_into().ok()?,
+                BalancingSetting::MaxBleeding => balancing.max_bleeding = value.try_into().ok()?,
+                BalancingSetting::MaxTemperature => {
+                    balancing.max_temperature = Temperature::from_raw(value.try_into().ok()?)?;
+                }
+                BalancingSetting::Duration => balancing.duration_s = value.try_into().ok()?,
+            },
+            Self::Pack(setting) => {
+                let mut changed = *pack;
+                match setting {
+                    PackSetting::Cells => changed.cells = value.try_into().ok()?,
+                    PackSetting::ModbusUnit => changed.modbus_unit = value.try_into().ok()?,
+                }
+                *pack = changed.is_valid().then_some(changed)?;
+            }
+        }
+        Some(())
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Threshold(quantity, level, field) => {
+                write!(f, "{}.{}.{}", quantity.name(), level.name(), field.name())
+            }
+            Self::Balancing(setting) => write!(f, "bal.{}", setting.name()),
+            Self::Pack(setting) => write!(f, "pack.{}", setting.name()),
+        }
+    }
+}
+
+/// Parsed command line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    /// Lists the commands.
+    Help,
+    /// Lists the addresses that acknowledge on the I2C bus.
+    Scan,
+    /// Reads or writes a P3T1755 register.
+    P3t1755 {
+        /// Valid P3T1755 address.
+        address: u8,
+        /// Register pointer.
+        register: u8,
+        /// Value to write.
+        value: Option<u16>,
+    },
+    /// Reads or writes a TCA9535 register.
+    Tca9535 {
+        /// Valid TCA9535 address.
+        address: u8,
+        /// Command byte.
+        register: u8,
+        /// Value to write.
+        value: Option<u8>,
+    },
+    /// Shows the cell telemetry.
+    Cells,
+    /// Shows the settings whose name starts with the prefix.
+    Config(&'a str),
+    /// Changes a setting, temperatures in 1/16 °C.
+    Set(Setting, i32),
+    /// Clears latched trips.
+    Clear,
+    /// Shows the reset cause and the crash before the reset.
+    Reset,
+    /// Shows the given number of the newest events and how many were lost.
+    Events(u16),
+}
+
+/// Reason why a command line was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    /// The command doesn't exist.
+    UnknownCommand,
+    /// The setting doesn't exist.
+    UnknownSetting,
+    /// An argument is missing.
+    MissingArgument,
+    /// An argument isn't a number or out of range.
+    InvalidArgument,
+    /// There are more arguments than the command takes.
+    TooManyArguments,
+}
+
+impl ParseError {
+    const fn message(self) -> &'static str {
+        match self {
+            Self::UnknownCommand => "unknown command, try help",
+            Self::UnknownSetting => "unknown setting",
+            Self::MissingArgument => "missing argument",
+            Self::InvalidArgument => "invalid argument",
+            Self::TooManyArguments => "too many arguments",
+        }
+    }
+}
+
+/// Parses a command line.
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let mut args = line.split_ascii_whitespace();
+    let name = args.next().ok_or(ParseError::UnknownCommand)?;
+    let mut next = || args.next().ok_or(ParseError::MissingArgument);
+    let command = match name {
+        "help" => Command::Help,
+        "scan" => Command::Scan,
+        "p3t" => {
+            let address = parse_byte(next()?)?;
+            p3t1755::Address::new(address).ok_or(ParseError::InvalidArgument)?;
+            let register = parse_register(next()?, P3T1755_REGISTERS)?;
+            let max = if register == P3T1755_CONFIG {
+                u8::MAX.into()
+            } else {
+                u16::MAX
+            };
+            let value = args.next().map(parse_number).transpose()?;
+            if value.is_some_and(|value| value > max.into()) {
+                return Err(ParseError::InvalidArgument);
+            }
+            Command::P3t1755 {
+                address,
+                register,
+                value: value.map(|value| value as u16),
+            }
+        }
+        "tca" => {
+            let address = parse_byte(next()?)?;
+            tca9535::Address::new(address).ok_or(ParseError::InvalidArgument)?;
+            let register = parse_register(next()?, TCA9535_REGISTERS)?;
+            let value = args.next().map(parse_byte).transpose()?;
+            Command::Tca9535 {
+                address,
+                register,
+                value,
+            }
+        }
+        "cells" => Command::Cells,
+        "config" => Command::Config(args.next().unwrap_or("")),
+        "set" => {
+            let setting = Setting::from_name(next()?).ok_or(ParseError::UnknownSetting)?;
+            let value = next()?;
+            let value = if setting.unit() == Unit::Celsius {
+                // Tenths of a degree to 1/16 °C.
+                parse_decimal(value)?
+                    .checked_mul(16)
+                    .ok_or(ParseError::InvalidArgument)?
+                    / 10
+            } else {
+                value.parse().map_err(|_| ParseError::InvalidArgument)?
+            };
+            Command::Set(setting, value)
+        }
+        "clear" => Command::Clear,
+        "reset" => Command::Reset,
+        "events" => Command::Events(
+            args.next()
+                .map(|count| count.parse().map_err(|_| ParseError::InvalidArgument))
+                .transpose()?
+                .unwrap_or(DEFAULT_EVENTS),
+        ),
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    if args.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(s: &str) -> Result<u32, ParseError> {
+    let value = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    value.map_err(|_| ParseError::InvalidArgument)
+}
+
+fn parse_byte(s: &str) -> Result<u8, ParseError> {
+    u8::try_from(parse_number(s)?).map_err(|_| ParseError::InvalidArgument)
+}
+
+/// Parses a register pointer below `count`.
+fn parse_register(s: &str, count: u8) -> Result<u8, ParseError> {
+    let register = parse_byte(s)?;
+    if register >= count {
+        return Err(ParseError::InvalidArgument);
+    }
+    Ok(register)
+}
+
+/// Parses a number with at most one decimal into tenths.
+fn parse_decimal(s: &str) -> Result<i32, ParseError> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (whole, tenths) = match s.split_once('.') {
+        Some((whole, tenth)) if tenth.len() == 1 => (whole, tenth),
+        Some(_) => return Err(ParseError::InvalidArgument),
+        None => (s, "0"),
+    };
+    let digits = |s: &str| {
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse::<i32>().ok()
+        } else {
+            None
+        }
+    };
+    let value = digits(whole)
+        .zip(digits(tenths))
+        .and_then(|(whole, tenths)| whole.checked_mul(10)?.checked_add(tenths))
+        .ok_or(ParseError::InvalidArgument)?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Formats a value in 1/16 °C as °C with one decimal.
+struct Celsius(i32);
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tenths = self.0 * 10 / 16;
+        let sign = if tenths < 0 { "-" } else { "" };
+        let tenths = tenths.unsigned_abs();
+        let mut text = TextBuffer::new();
+        write!(text, "{sign}{}.{}", tenths / 10, tenths % 10)?;
+        // Pads to the width of the format string.
+        f.pad(text.as_str())
+    }
+}
+
+/// Formats a value of a setting with its unit.
+struct Value(Unit, i32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(unit, value) = *self;
+        if unit == Unit::Celsius {
+            write!(f, "{}", Celsius(value))?;
+        } else {
+            write!(f, "{value}")?;
+        }
+        f.write_str(unit.symbol())
+    }
+}
+
+/// The firmware state the console operates on.
+pub trait Target {
+    /// I2C bus of the sensors and I/O expanders.
+    type I2c: I2c;
+
+    /// Returns the I2C bus.
+    fn i2c(&mut self) -> &mut Self::I2c;
+
+    /// Returns the latest readings of all cells.
+    fn cells(&self) -> &[Cell];
+
+    /// Returns the protection state.
+    fn protection(&mut self) -> &mut Protection;
+
+    /// Returns the balancing settings.
+    fn balancing_config(&mut self) -> &mut balancing::Config;
+
+    /// Returns the pack settings.
+    fn pack_config(&mut self) -> &mut PackConfig;
+
+    /// Called after a setting was changed.
+    ///
+    /// Protection settings are already applied at this point.
+    fn config_changed(&mut self, setting: Setting);
+
+    /// Returns the cause of the last reset.
+    fn reset_cause(&self) -> ResetCause;
+
+    /// Returns the report of the panic that caused the last reset.
+    fn crash(&self) -> Option<CrashReport>;
+
+    /// Returns the number of entries in the event log.
+    fn event_count(&self) -> u16;
+
+    /// Returns the number of events that couldn't be written to the log.
+    fn lost_events(&self) -> u16;
+
+    /// Returns an entry of the event log, index 0 being the oldest.
+    fn event(&self, index: u16) -> Option<Event>;
+}
+
+/// Writes `\r\n` for every `\n` so terminals return to the first column.
+struct Crlf<'a, W>(&'a mut W);
+
+impl<W: Write> Write for Crlf<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.0.write_str(first)?;
+        }
+        for line in lines {
+            self.0.write_str("\r\n")?;
+            self.0.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Command console with a line buffer of `N` characters.
+pub struct Console<const N: usize> {
+    line: [u8; N],
+    len: usize,
+    /// Characters were dropped because the line was full.
+    overflow: bool,
+    /// The previous character was a CR, so a following LF is ignored.
+    after_cr: bool,
+}
+
+impl<const N: usize> Default for Console<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Console<N> {
+    /// Creates a console with an empty line.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            line: [0; N],
+            len: 0,
+            overflow: false,
+            after_cr: false,
+        }
+    }
+
+    /// Writes the prompt.
+    pub fn prompt(&self, out: &mut impl Write) -> fmt::Result {
+        out.write_str(PROMPT)
+    }
+
+    /// Processes a received character.
+    ///
+    /// Printable ASCII characters are echoed and appended to the line.
+    /// Backspace and DEL remove the last character. CR or LF runs the line,
+    /// other characters are ignored.
+    pub fn feed<T: Target>(
+        &mut self,
+        byte: u8,
+        target: &mut T,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => Ok(()),
+            b'\r' | b'\n' => {
+                out.write_str("\r\n")?;
+                if self.overflow {
+                    out.write_str("error: line too long\r\n")?;
+                } else {
+                    // Only printable ASCII characters are stored.
+                    let line = core::str::from_utf8(&self.line[..self.len]).unwrap_or("");
+                    if !line.trim_ascii().is_empty() {
+                        run(line, target, out)?;
+                    }
+                }
+                self.len = 0;
+                self.overflow = false;
+                out.write_str(PROMPT)
+            }
+            0x08 | 0x7F => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    out.write_str("\x08 \x08")?;
+                }
+                Ok(())
+            }
+            0x20..=0x7E => {
+                if self.len < N {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    out.write_char(byte.into())
+                } else {
+                    self.overflow = true;
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses and executes a command line.
+pub fn run<T: Target>(line: &str, target: &mut T, out: &mut impl Write) -> fmt::Result {
+    let mut out = Crlf(out);
+    match parse(line) {
+        Ok(command) => execute(command, target, &mut out),
+        Err(error) => writeln!(out, "error: {}", error.message()),
+    }
+}
+
+/// Executes a command.
+fn execute<T: Target>(command: Command<'_>, target: &mut T, out: &mut impl Write) -> fmt::Result {
+    match command {
+        Command::Help => {
+            for (name, args, help) in COMMANDS {
+                writeln!(out, "{name} {args:<22}{help}")?;
+            }
+            Ok(())
+        }
+        Command::Scan => scan(target.i2c(), out),
+        Command::P3t1755 {
+            address,
+            register,
+            value,
+        } => {
+            let len = if register == P3T1755_CONFIG { 1 } else { 2 };
+            let i2c = target.i2c();
+            match value {
+                Some(value) => {
+                    let [high, low] = value.to_be_bytes();
+                    let bytes = if len == 1 {
+                        [register, low, 0]
+                    } else {
+                        [register, high, low]
+                    };
+                    report(out, i2c.write(address, &bytes[..1 + len]))
+                }
+                None => {
+                    let mut buf = [0; 2];
+                    let result = i2c.write_read(address, &[register], &mut buf[..len]);
+                    let value = result.map(|()| {
+                        if len == 1 {
+                            u16::from(buf[0])
+                        } else {
+                            u16::from_be_bytes(buf)
+                        }
+                    });
+                    match value {
+                        Ok(value) if len == 1 => writeln!(out, "0x{value:02X}"),
+                        // The temperature is left aligned in 1/16 °C.
+                        Ok(value) => writeln!(
+                            out,
+                            "0x{value:04X} ({} C)",
+                            Celsius(i32::from(value as i16 >> 4))
+                        ),
+                        Err(error) => report(out, Err(error)),
+                    }
+                }
+            }
+        }
+        Command::Tca9535 {
+            address,
+            register,
+            value,
+        } => {
+            let i2c = target.i2c();
+            match value {
+                Some(value) => report(out, i2c.write(address, &[register, value])),
+                None => {
+                    let mut buf = [0];
+                    match i2c.write_read(address, &[register], &mut buf) {
+                        Ok(()) => writeln!(out, "0x{:02X} 0b{:08b}", buf[0], buf[0]),
+                        Err(error) => report(out, Err(error)),
+                    }
+                }
+            }
+        }
+        Command::Cells => cells(target.cells(), out),
+        Command::Config(prefix) => {
+            let protection = *target.protection().config();
+            let balancing = *target.balancing_config();
+            let pack = *target.pack_config();
+            for setting in Setting::all() {
+                let mut name = TextBuffer::new();
+                write!(name, "{setting}")?;
+                if name.as_str().starts_with(prefix) {
+                    let value = setting.get(&protection, &balancing, &pack);
+                    writeln!(out, "{} = {}", name.as_str(), Value(setting.unit(), value))?;
+                }
+            }
+            Ok(())
+        }
+        Command::Set(setting, value) => {
+            let mut protection = *target.protection().config();
+            let mut balancing = *target.balancing_config();
+            let mut pack = *target.pack_config();
+            if setting
+                .set(value, &mut protection, &mut balancing, &mut pack)
+                .is_none()
+            {
+                return writeln!(out, "error: out of range");
+            }
+            target.protection().set_config(protection);
+            *target.balancing_config() = balancing;
+            *target.pack_config() = pack;
+            target.config_changed(setting);
+            let value = setting.get(&protection, &balancing, &pack);
+            writeln!(out, "{setting} = {}", Value(setting.unit(), value))
+        }
+        Command::Clear => {
+            let protection = target.protection();
+            if protection.reset() {
+                writeln!(out, "trips cleared")?;
+            } else {
+                writeln!(out, "trips persist")?;
+            }
+            for quantity in Quantity::ALL {
+                if let Some(level) = protection.level(quantity) {
+                    writeln!(out, "{} {}", quantity.name(), level.name())?;
+                }
+            }
+            Ok(())
+        }
+        Command::Reset => {
+            writeln!(out, "{}", target.reset_cause())?;
+            match target.crash() {
+                Some(report) => crash(&report, out),
+                None => Ok(()),
+            }
+        }
+        Command::Events(count) => {
+            let total = target.event_count();
+            for index in total.saturating_sub(count)..total {
+                match target.event(index) {
+                    Some(event) => self::event(event, out)?,
+                    None => break,
+                }
+            }
+            write!(out, "{total} events")?;
+            match target.lost_events() {
+                0 => writeln!(out),
+                lost => writeln!(out, ", {lost} lost"),
+            }
+        }
+    }
+}
+
+/// Writes "ok" or the error of an I2C transfer.
+fn report<E: i2c::Error>(out: &mut impl Write, result: Result<(), E>) -> fmt::Result {
+    match result {
+        Ok(()) => writeln!(out, "ok"),
+        Err(error) => writeln!(out, "error: {}", error.kind()),
+    }
+}
+
+fn scan(i2c: &mut impl I2c, out: &mut impl Write) -> fmt::Result {
+    let mut found = 0;
+    for address in SCAN_ADDRESSES {
+        if i2c.write(address, &[]).is_err() {
+            continue;
+        }
+        found += 1;
+        write!(out, "0x{address:02X}")?;
+        if p3t1755::Address::new(address).is_some() {
+            write!(out, " P3T1755")?;
+        }
+        if tca9535::Address::new(address).is_some() {
+            write!(out, " TCA9535")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "{found} devices")
+}
+
+fn cells(cells: &[Cell], out: &mut impl Write) -> fmt::Result {
+    let mut min: Option<(usize, u16)> = None;
+    let mut max: Option<(usize, u16)> = None;
+    for (index, cell) in cells.iter().enumerate() {
+        write!(out, "{index:>3}")?;
+        match cell.voltage_mv {
+            Some(voltage) => {
+                write!(out, " {voltage:>5} mV")?;
+                if min.is_none_or(|(_, min)| voltage < min) {
+                    min = Some((index, voltage));
+                }
+                if max.is_none_or(|(_, max)| voltage > max) {
+                    max = Some((index, voltage));
+                }
+            }
+            None => write!(out, "     - mV")?,
+        }
+        match cell.temperature {
+            Some(temperature) => writeln!(out, " {:>6} C", Celsius(temperature.raw().into()))?,
+            None => writeln!(out, "      - C")?,
+        }
+    }
+    if let (Some((min_index, min)), Some((max_index, max))) = (min, max) {
+        writeln!(
+            out,
+            "min {min} mV (cell {min_index}), max {max} mV (cell {max_index}), delta {} mV",
+            max - min
+        )?;
+    }
+    Ok(())
+}
+
+fn crash(report: &CrashReport, out: &mut impl Write) -> fmt::Result {
+    writeln!(
+        out,
+        "crash: {}:{}, message 0x{:04X}",
+        report.file(),
+        report.line,
+        report.message_hash
+    )?;
+    writeln!(out, "crashed run: {}", ResetCause(report.reset_cause))?;
+    write!(out, "crashes in a row: {}", report.crashes)?;
+    if report.safe_mode {
+        write!(out, ", safe mode")?;
+    }
+    writeln!(out)
+}
+
+/// Writes an entry of the event log on one line.
+fn event(event: Event, out: &mut impl Write) -> fmt::Result {
+    write!(out, "#{:<6} {:>9} s  ", event.sequence, event.uptime_s)?;
+    let data = event.data;
+    match event.kind {
+        EventKind::Reset => writeln!(out, "reset: {}", ResetCause(data as u8)),
+        EventKind::Warning => level_event(Level::Warning, data, out),
+        EventKind::Alarm => level_event(Level::Alarm, data, out),
+        EventKind::Trip => level_event(Level::Trip, data, out),
+        EventKind::TripsCleared => writeln!(out, "trips cleared"),
+        EventKind::ConfigChanged => match Setting::from_id(data) {
+            Some(setting) => writeln!(out, "config changed: {setting}"),
+            None => writeln!(out, "config changed: setting {data}"),
+        },
+        EventKind::ContactorFault => match Fault::from_code(data) {
+            Some(fault) => writeln!(out, "contactor fault: {fault}"),
+            None => writeln!(out, "contactor fault: 0x{data:04X}"),
+        },
+        EventKind::ContactorsClosed => writeln!(out, "contactors closed"),
+        EventKind::ContactorsOpened => writeln!(out, "contactors opened"),
+        EventKind::Crash => writeln!(out, "crash: line {data}"),
+    }
+}
+
+/// Writes the quantity of an event that a level became active.
+fn level_event(level: Level, quantity: u16, out: &mut impl Write) -> fmt::Result {
+    match Quantity::ALL.get(usize::from(quantity)) {
+        Some(quantity) => writeln!(out, "{}: {}", level.name(), quantity.name()),
+        None => writeln!(out, "{}: quantity {quantity}", level.name()),
+    }
+}
+
+/// Buffer for short formatted texts.
+struct TextBuffer {
+    buf: [u8; 32],
+    len: usize,
+}
+
+impl TextBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; 32],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only complete strings are written.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for TextBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+//! Contactor and precharge sequencing.
+//!
+//! The coils of the precharge relay and of both main contactors are driven by
+//! outputs of a TCA9535, which also reads the auxiliary contacts of the main
+//! contactors. Closing follows these steps, each checked by a timeout:
+//!
+//! 1. Close the main negative contactor and wait for its auxiliary contact.
+//! 2. Close the precharge relay and wait until the bus voltage has converged to
+//!    the pack voltage.
+//! 3. Close the main positive contactor and wait for its auxiliary contact.
+//! 4. Open the precharge relay.
+//!
+//! Opening first opens the main positive contactor and then the main
+//! negative one, each confirmed by its auxiliary contact.
+//! [`Contactors::emergency_open`] opens everything at once.
+//!
+//! Every failure opens all contactors and latches a [`Fault`] until
+//! [`Contactors::reset`].
+
+use core::fmt;
+
+use embedded_hal::i2c::I2c;
+use tca9535::{Configuration, Input, Output, PinIndex, PolarityInversion, Tca9535};
+
+/// Assignment of the coils and auxiliary contacts to expander pins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinMap {
+    /// Active-high coil of the precharge relay.
+    pub precharge: PinIndex,
+    /// Active-high coil of the main positive contactor.
+    pub main_positive: PinIndex,
+    /// Active-high coil of the main negative contactor.
+    pub main_negative: PinIndex,
+    /// Auxiliary contact of the main positive contactor.
+    pub positive_aux: PinIndex,
+    /// Auxiliary contact of the main negative contactor.
+    pub negative_aux: PinIndex,
+    /// The auxiliary contacts pull their input low when closed.
+    pub aux_active_low: bool,
+}
+
+impl PinMap {
+    const fn coil(&self, contactor: Contactor) -> PinIndex {
+        match contactor {
+            Contactor::Precharge => self.precharge,
+            Contactor::MainPositive => self.main_positive,
+            Contactor::MainNegative => self.main_negative,
+        }
+    }
+}
+
+/// Sequencing settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Time for an auxiliary contact to follow its coil.
+    pub feedback_timeout_ms: u32,
+    /// Time for the bus voltage to converge while precharging.
+    pub precharge_timeout_ms: u32,
+    /// Maximum difference between pack and bus voltage to end precharging.
+    pub convergence_mv: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            feedback_timeout_ms: 100,
+            precharge_timeout_ms: 3000,
+            convergence_mv: 2000,
+        }
+    }
+}
+
+/// A switched element.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Contactor {
+    /// Precharge relay.
+    Precharge,
+    /// Main positive contactor.
+    MainPositive,
+    /// Main negative contactor.
+    MainNegative,
+}
+
+impl fmt::Display for Contactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Precharge => "precharge relay",
+            Self::MainPositive => "main positive",
+            Self::MainNegative => "main negative",
+        })
+    }
+}
+
+/// Reason for opening all contactors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fault {
+    /// The auxiliary contact didn't close after energizing the coil.
+    FailedToClose(Contactor),
+    /// The auxiliary contact didn't open after releasing the coil.
+    Welded(Contactor),
+    /// The auxiliary contact opened while the coil was energized.
+    Dropped(Contactor),
+    /// The bus voltage didn't converge within the precharge timeout.
+    PrechargeTimeout,
+    /// [`Contactors::emergency_open`] was called.
+    Emergency,
+    /// The expander didn't respond.
+    Communication,
+}
+
+impl Fault {
+    /// Returns the code recorded in the event log.
+    ///
+    /// The high byte identifies the fault and the low byte the contactor.
+    #[must_use]
+    pub const fn code(self) -> u16 {
+        match self {
+            Self::FailedToClose(contactor) => 0x0100 | contactor as u16,
+            Self::Welded(contactor) => 0x0200 | contactor as u16,
+            Self::Dropped(contactor) => 0x0300 | contactor as u16,
+            Self::PrechargeTimeout => 0x0400,
+            Self::Emergency => 0x0500,
+            Self::Communication => 0x0600,
+        }
+    }
+
+    /// Converts a code returned by [`Fault::code`] back to the fault.
+    #[must_use]
+    pub const fn from_code(code: u16) -> Option<Self> {
+        let [fault, contactor] = code.to_be_bytes();
+        let contactor = match contactor {
+            0 => Contactor::Precharge,
+            1 => Contactor::MainPositive,
+            2 => Contactor::MainNegative,
+            _ => return None,
+        };
+        Some(match (fault, contactor) {
+            (0x01, contactor) => Self::FailedToClose(contactor),
+            (0x02, contactor) => Self::Welded(contactor),
+            (0x03, contactor) => Self::Dropped(contactor),
+            (0x04, Contactor::Precharge) => Self::PrechargeTimeout,
+            (0x05, Contactor::Precharge) => Self::Emergency,
+            (0x06, Contactor::Precharge) => Self::Communication,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::FailedToClose(contactor) => write!(f, "{contactor} failed to close"),
+            Self::Welded(contactor) => write!(f, "{contactor} welded"),
+            Self::Dropped(contactor) => write!(f, "{contactor} dropped"),
+            Self::PrechargeTimeout => f.write_str("precharge timeout"),
+            Self::Emergency => f.write_str("emergency open"),
+            Self::Communication => f.write_str("expander not responding"),
+        }
+    }
+}
+
+/// Sequencing state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    /// All contactors are open.
+    Open,
+    /// Waiting for the main negative contactor to close.
+    ClosingNegative,
+    /// Waiting for the bus voltage to converge.
+    Precharging,
+    /// Waiting for the main positive contactor to close.
+    ClosingPositive,
+    /// Both main contactors are closed.
+    Closed,
+    /// Waiting for the main positive contactor to open.
+    OpeningPositive,
+    /// Waiting for the main negative contactor to open.
+    OpeningNegative,
+    /// All contactors are released due to a fault.
+    Fault(Fault),
+}
+
+/// Contactor state machine driving a TCA9535.
+pub struct Contactors<I> {
+    expander: Tca9535<I>,
+    pins: PinMap,
+    config: Config,
+    state: State,
+    /// Time spent in the current state.
+    elapsed_ms: u32,
+    output: Output,
+}
+
+impl<I: I2c> Contactors<I> {
+    /// Configures the expander with all coils released.
+    ///
+    /// All other pins are configured as inputs.
+    pub fn new(mut expander: Tca9535<I>, pins: PinMap, config: Config) -> Result<Self, I::Error> {
+        let output = Output(0);
+        expander.write_output(output)?;
+        let mut polarity = PolarityInversion(0);
+        if pins.aux_active_low {
+            polarity = polarity
+                .with_inverted(pins.positive_aux)
+                .with_inverted(pins.negative_aux);
+        }
+        expander.write_polarity_inversion(polarity)?;
+        let configuration = Configuration(u16::MAX)
+            .with_output(pins.precharge)
+            .with_output(pins.main_positive)
+            .with_output(pins.main_negative);
+        expander.write_configuration(configuration)?;
+        Ok(Self {
+            expander,
+            pins,
+            config,
+            state: State::Open,
+            elapsed_ms: 0,
+            output,
+        })
+    }
+
+    /// Releases the expander.
+    pub fn release(self) -> Tca9535<I> {
+        self.expander
+    }
+
+    /// Returns the current state.
+    #[must_use]
+    pub const fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns true if the coil of a contactor is energized.
+    #[must_use]
+    pub const fn is_energized(&self, contactor: Contactor) -> bool {
+        self.output.is_high(self.pins.coil(contactor))
+    }
+
+    /// Starts closing the contactors.
+    ///
+    /// Ignored unless all contactors are open.
+    pub fn close(&mut self) {
+        if self.state == State::Open {
+            self.enter(State::ClosingNegative);
+        }
+    }
+
+    /// Starts opening the contactors in sequence.
+    ///
+    /// Aborts an ongoing close sequence.
+    pub fn open(&mut self) {
+        match self.state {
+            State::ClosingNegative
+            | State::Precharging
+            | State::ClosingPositive
+            | State::Closed => {
+                self.enter(State::OpeningPositive);
+            }
+            State::Open | State::OpeningPositive | State::OpeningNegative | State::Fault(_) => {}
+        }
+    }
+
+    /// Releases all coils immediately and latches [`Fault::Emergency`].
+    pub fn emergency_open(&mut self) -> Result<(), I::Error> {
+        self.fail(Fault::Emergency)
+    }
+
+    /// Clears a latched fault.
+    ///
+    /// Refused while an auxiliary contact still reports closed, since the
+    /// contactor is then likely welded. Returns true if the fault was
+    /// cleared.
+    pub fn reset(&mut self) -> Result<bool, I::Error> {
+        if !matches!(self.state, State::Fault(_)) {
+            return Ok(true);
+        }
+        let input = self.read_input()?;
+        if self.aux_closed(input, Contactor::MainPositive)
+            || self.aux_closed(input, Contactor::MainNegative)
+        {
+            return Ok(false);
+        }
+        self.enter(State::Open);
+        Ok(true)
+    }
+
+    /// Advances the state machine by `dt_ms`.
+    ///
+    /// `pack_mv` and `bus_mv` are the voltages on both sides of the main
+    /// positive contactor. Returns the new state.
+    pub fn update(&mut self, dt_ms: u32, pack_mv: u32, bus_mv: u32) -> Result<State, I::Error> {
+        self.elapsed_ms = self.elapsed_ms.saturating_add(dt_ms);
+        let input = self.read_input()?;
+        let positive = self.aux_closed(input, Contactor::MainPositive);
+        let negative = self.aux_closed(input, Contactor::MainNegative);
+        let timed_out = self.elapsed_ms >= self.config.feedback_timeout_ms;
+
+        match self.state {
+            State::Open => {
+                if positive {
+                    self.fail(Fault::Welded(Contactor::MainPositive))?;
+                } else if negative {
+                    self.fail(Fault::Welded(Contactor::MainNegative))?;
+                }
+            }
+            State::ClosingNegative => {
+                if positive {
+                    self.fail(Fault::Welded(Contactor::MainPositive))?;
+                } else if negative {
+                    self.enter(State::Precharging);
+                } else if timed_out {
+                    self.fail(Fault::FailedToClose(Contactor::MainNegative))?;
+                }
+            }
+            State::Precharging => {
+                if positive {
+                    self.fail(Fault::Welded(Contactor::MainPositive))?;
+                } else if !negative {
+                    self.fail(Fault::Dropped(Contactor::MainNegative))?;
+                } else if pack_mv.abs_diff(bus_mv) <= self.config.convergence_mv {
+                    self.enter(State::ClosingPositive);
+                } else if self.elapsed_ms >= self.config.precharge_timeout_ms {
+                    self.fail(Fault::PrechargeTimeout)?;
+                }
+            }
+            State::ClosingPositive => {
+                if !negative {
+                    self.fail(Fault::Dropped(Contactor::MainNegative))?;
+                } else if positive {
+                    self.enter(State::Closed);
+                } else if timed_out {
+                    self.fail(Fault::FailedToClose(Contactor::MainPositive))?;
+                }
+            }
+            State::Closed => {
+                if !positive {
+                    self.fail(Fault::Dropped(Contactor::MainPositive))?;
+                } else if !negative {
+                    self.fail(Fault::Dropped(Contactor::MainNegative))?;
+                }
+            }
+            State::OpeningPositive => {
+                if !positive {
+                    self.enter(State::OpeningNegative);
+                } else if timed_out {
+                    self.fail(Fault::Welded(Contactor::MainPositive))?;
+                }
+            }
+            State::OpeningNegative => {
+                if !negative {
+                    self.enter(State::Open);
+                } else if timed_out {
+                    self.fail(Fault::Welded(Contactor::MainNegative))?;
+                }
+            }
+            State::Fault(_) => {}
+        }
+        self.apply()?;
+        Ok(self.state)
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.elapsed_ms = 0;
+    }
+
+    /// Latches a fault and releases all coils.
+    fn fail(&mut self, fault: Fault) -> Result<(), I::Error> {
+        self.enter(State::Fault(fault));
+        self.apply()
+    }
+
+    /// Returns the coils energized in the current state.
+    const fn coils(&self) -> [bool; 3] {
+        // Precharge, main positive, main negative
+        match self.state {
+            State::Open | State::OpeningNegative | State::Fault(_) => [false, false, false],
+            State::ClosingNegative => [false, false, true],
+            State::Precharging => [true, false, true],
+            State::ClosingPositive => [true, true, true],
+            State::Closed => [false, true, true],
+            State::OpeningPositive => [false, false, true],
+        }
+    }
+
+    /// Writes the coil outputs of the current state if they changed.
+    fn apply(&mut self) -> Result<(), I::Error> {
+        let [precharge, positive, negative] = self.coils();
+        let mut output = self.output;
+        for (contactor, on) in [
+            (Contactor::Precharge, precharge),
+            (Contactor::MainPositive, positive),
+            (Contactor::MainNegative, negative),
+        ] {
+            let pin = self.pins.coil(contactor);
+            output = if on {
+                output.with_high(pin)
+            } else {
+                output.with_low(pin)
+            };
+        }
+        if output != self.output {
+            if let Err(e) = self.expander.write_output(output) {
+                self.state = State::Fault(Fault::Communication);
+                return Err(e);
+            }
+            self.output = output;
+        }
+        Ok(())
+    }
+
+    /// Reads the auxiliary contacts.
+    ///
+    /// On failure the coils are released right away if the expander still
+    /// accepts writes, the read error is returned either way.
+    fn read_input(&mut self) -> Result<Input, I::Error> {
+        self.expander.read_input().inspect_err(|_| {
+            if !matches!(self.state, State::Fault(_)) {
+                self.enter(State::Fault(Fault::Communication));
+            }
+            let _ = self.apply();
+        })
+    }
+
+    const fn aux_closed(&self, input: Input, contactor: Contactor) -> bool {
+        let pin = match contactor {
+            Contactor::MainPositive => self.pins.positive_aux,
+            Contactor::MainNegative => self.pins.negative_aux,
+            Contactor::Precharge => return false,
+        };
+        input.is_high(pin)
+    }
+}
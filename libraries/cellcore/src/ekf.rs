@@ -0,0 +1,483 @@
+//! Model based state of charge and state of health estimation.
+//!
+//! An extended Kalman filter tracks the state of charge together with the
+//! parameters of an equivalent circuit of the cell: the open circuit voltage
+//! from an [`OcvCurve`] in series with the ohmic resistance R0 and one RC
+//! pair. The terminal voltage of a cell carrying the current `i`, positive
+//! while charging, is
+//!
+//! ```text
+//! v = ocv(soc) + v1 + r0 * i
+//! ```
+//!
+//! where `v1` is the voltage across the RC pair. Over a step of `dt` the
+//! states evolve as
+//!
+//! ```text
+//! soc' = soc + eta * i * dt / capacity
+//! v1'  = a * v1 + (1 - a) * r1 * i,  a = tau / (tau + dt)
+//! r0'  = r0
+//! ```
+//!
+//! with the backward Euler discretization of the RC pair, which stays stable
+//! for any step. R0 is part of the filter state and follows a random walk, so
+//! a rising resistance is tracked. The capacity is estimated by a separate
+//! scalar filter, which compares the charge counted over a window with the
+//! change of the filtered state of charge across the same window.
+//!
+//! The filter runs in integers only. The states are held in 32 bits: the
+//! state of charge in parts per million, voltages in microvolts and
+//! resistances in microohms. The covariance is held in the squares of these
+//! units, except that the state of charge counts in steps of 2 ppm there, so
+//! its initial variance fits into 32 bits as well. Products of two such values
+//! are formed in 64 bits. The Kalman gain is kept in Q20, which keeps every
+//! product of the update within 64 bits.
+
+use crate::soc::{FULL, OcvCurve};
+
+/// Full state of charge in parts per million.
+const PPM: i64 = 1_000_000;
+/// Parts per million per 0.01 %.
+const PPM_PER_SOC: i64 = PPM / FULL as i64;
+/// Parts per million per step of the state of charge in the covariance.
+const STEP_PPM: i64 = 2;
+/// One in Q16.
+const ONE: i64 = 1 << 16;
+/// One in Q20, the format of the Kalman gain.
+const GAIN_ONE: i64 = 1 << 20;
+/// Indices of the filter states.
+const SOC: usize = 0;
+const V1: usize = 1;
+const R0: usize = 2;
+
+/// Returns `a * b / c`, saturated to `i64`.
+///
+/// The operands are 32-bit values or their products with Q16 factors or
+/// Q20 gains, so the product fits into 64 bits.
+const fn mul_div(a: i64, b: i64, c: i64) -> i64 {
+    a.saturating_mul(b) / c
+}
+
+/// Saturates a value to the 32 bits of the filter state.
+const fn narrow(value: i64) -> i32 {
+    if value > i32::MAX as i64 {
+        i32::MAX
+    } else if value < i32::MIN as i64 {
+        i32::MIN
+    } else {
+        value as i32
+    }
+}
+
+/// Returns the variance of a standard deviation, both in `step` units.
+const fn variance(std: u32, step: i64) -> i32 {
+    narrow((std as i64 / step) * (std as i64 / step))
+}
+
+/// Model and noise settings.
+///
+/// Noise is given as standard deviation. Process noise is given per square
+/// root of a second, so the variance added per step grows with its length.
+#[derive(Clone, Copy)]
+pub struct Config<'a> {
+    /// Nominal capacity of the cells in milliampere-hours.
+    pub capacity_mah: u32,
+    /// Open circuit voltage curve of a cell.
+    pub ocv: OcvCurve<'a>,
+    /// Nominal ohmic resistance of a cell in microohms.
+    pub r0_uohm: u32,
+    /// Resistance of the RC pair in microohms.
+    pub r1_uohm: u32,
+    /// Time constant of the RC pair.
+    pub tau_ms: u32,
+    /// Share of the charge current that is stored in 0.01 %.
+    pub charge_efficiency: u16,
+    /// Initial uncertainty of the state of charge in parts per million.
+    pub initial_soc_ppm: u32,
+    /// Initial uncertainty of R0 in microohms.
+    pub initial_r0_uohm: u32,
+    /// Process noise of the state of charge in parts per million.
+    pub process_soc_ppm: u32,
+    /// Process noise of the RC voltage in microvolts.
+    pub process_v1_uv: u32,
+    /// Process noise of R0 in microohms.
+    pub process_r0_uohm: u32,
+    /// Noise of the cell voltage measurement in microvolts.
+    pub measurement_uv: u32,
+    /// Change of the state of charge over which the capacity is measured in
+    /// 0.01 %.
+    pub capacity_window: u16,
+    /// Uncertainty of a single capacity measurement in 0.01 % of the nominal
+    /// capacity.
+    pub capacity_noise: u16,
+    /// Drift of the capacity between two measurements in 0.01 % of the
+    /// nominal capacity.
+    pub capacity_drift: u16,
+    /// Uncertainty of the state of charge above which no capacity is
+    /// measured in parts per million.
+    pub capacity_max_soc_ppm: u32,
+}
+
+impl<'a> Config<'a> {
+    /// Settings for LiFePO4 cells.
+    ///
+    /// The flat curve of LiFePO4 cells makes the state of charge poorly
+    /// observable on the plateau, so the filter mostly counts there.
+    #[must_use]
+    pub const fn lfp(capacity_mah: u32) -> Self {
+        Self {
+            capacity_mah,
+            ocv: OcvCurve::LFP,
+            r0_uohm: 500,
+            r1_uohm: 300,
+            tau_ms: 60_000,
+            charge_efficiency: 9990,
+            ..Self::nmc(capacity_mah)
+        }
+    }
+
+    /// Settings for NMC cells.
+    #[must_use]
+    pub const fn nmc(capacity_mah: u32) -> Self {
+        Self {
+            capacity_mah,
+            ocv: OcvCurve::NMC,
+            r0_uohm: 800,
+            r1_uohm: 500,
+            tau_ms: 30_000,
+            charge_efficiency: 9980,
+            initial_soc_ppm: 50_000,
+            initial_r0_uohm: 300,
+            process_soc_ppm: 20,
+            process_v1_uv: 200,
+            process_r0_uohm: 2,
+            measurement_uv: 5000,
+            capacity_window: 2000,
+            capacity_noise: 300,
+            capacity_drift: 50,
+            capacity_max_soc_ppm: 20_000,
+        }
+    }
+
+    /// Returns the open circuit voltage at a state of charge in microvolts
+    /// and the slope of the curve there in Q16 microvolts per step of
+    /// [`STEP_PPM`].
+    ///
+    /// Outside the curve the slope of the nearest segment is used.
+    fn ocv(&self, soc_ppm: i64) -> Option<(i64, i64)> {
+        let points = self.ocv.0;
+        let ppm = |soc: u16| i64::from(soc) * PPM_PER_SOC;
+        let uv = |mv: u16| i64::from(mv) * 1000;
+        let (low, high) = match points {
+            [] => return None,
+            [point] => return Some((uv(point.mv), 0)),
+            _ => points
+                .windows(2)
+                .find(|pair| soc_ppm <= ppm(pair[1].soc))
+                .map_or(
+                    (points[points.len() - 2], points[points.len() - 1]),
+                    |pair| (pair[0], pair[1]),
+                ),
+        };
+        let span = (ppm(high.soc) - ppm(low.soc)).max(1);
+        let rise = uv(high.mv) - uv(low.mv);
+        let offset = soc_ppm.clamp(0, PPM) - ppm(low.soc);
+        let ocv = uv(low.mv) + mul_div(offset, rise, span);
+        Some((ocv, mul_div(rise, ONE * STEP_PPM, span)))
+    }
+
+    /// Returns the initial state of charge for a resting cell in parts per
+    /// million.
+    fn soc_at_rest(&self, mv: u16) -> Option<i32> {
+        Some((i64::from(self.ocv.soc(mv)?) * PPM_PER_SOC) as i32)
+    }
+}
+
+/// Inputs of the filter.
+///
+/// `None` marks a measurement that is missing or invalid.
+#[derive(Clone, Copy, Default)]
+pub struct Measurements {
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+    /// Average cell voltage in millivolts.
+    pub cell_mv: Option<u16>,
+}
+
+/// State of health of the cells.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Health {
+    /// Estimated capacity in milliampere-hours.
+    pub capacity_mah: u32,
+    /// Estimated capacity in 0.01 % of the nominal capacity.
+    pub capacity: u16,
+    /// Estimated ohmic resistance in microohms.
+    pub r0_uohm: u32,
+    /// Estimated ohmic resistance in 0.01 % of the nominal resistance.
+    pub resistance: u16,
+}
+
+/// Extended Kalman filter for the state of charge and health.
+#[derive(Clone)]
+pub struct Ekf<'a> {
+    config: Config<'a>,
+    initialized: bool,
+    /// State of charge, RC voltage and R0.
+    x: [i32; 3],
+    /// Covariance of `x`, with the state of charge in steps of
+    /// [`STEP_PPM`].
+    p: [[i32; 3]; 3],
+    /// Fraction of a part per million of charge not yet added to the state.
+    remainder: i64,
+    /// Estimated capacity in milliampere-hours.
+    capacity_mah: i64,
+    /// Variance of `capacity_mah`.
+    capacity_var: i64,
+    /// State of charge at the start of the capacity window.
+    window_soc_ppm: i32,
+    /// Charge counted since the start of the capacity window in
+    /// milliampere-milliseconds.
+    window_mams: i64,
+}
+
+impl<'a> Ekf<'a> {
+    /// Creates a filter without an estimate.
+    #[must_use]
+    pub const fn new(config: Config<'a>) -> Self {
+        let r0 = narrow(config.r0_uohm as i64);
+        let r0_var = variance(config.initial_r0_uohm, 1);
+        let drift = config.capacity_mah as i64 * config.capacity_drift as i64 / FULL as i64;
+        Self {
+            config,
+            initialized: false,
+            x: [0, 0, r0],
+            p: [[0, 0, 0], [0, 0, 0], [0, 0, r0_var]],
+            remainder: 0,
+            capacity_mah: config.capacity_mah as i64,
+            capacity_var: drift * drift,
+            window_soc_ppm: 0,
+            window_mams: 0,
+        }
+    }
+
+    /// Returns the settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config<'a> {
+        &self.config
+    }
+
+    /// Sets the state of charge in 0.01 %, for example from a value saved
+    /// before a reset.
+    pub fn set(&mut self, soc: u16) {
+        self.start((i64::from(soc.min(FULL)) * PPM_PER_SOC) as i32);
+    }
+
+    /// Sets the state of health, for example from values saved before a
+    /// reset.
+    pub fn set_health(&mut self, capacity_mah: u32, r0_uohm: u32) {
+        self.capacity_mah = i64::from(capacity_mah.max(1));
+        self.x[R0] = narrow(r0_uohm.into());
+    }
+
+    /// Starts filtering from a state of charge in parts per million.
+    fn start(&mut self, soc_ppm: i32) {
+        let soc_var = variance(self.config.initial_soc_ppm, STEP_PPM);
+        let r0_var = self.p[R0][R0];
+        self.x[SOC] = soc_ppm;
+        self.x[V1] = 0;
+        self.p = [[soc_var, 0, 0], [0, 0, 0], [0, 0, r0_var]];
+        self.remainder = 0;
+        self.initialized = true;
+        self.restart_window();
+    }
+
+    /// Advances the filter by `dt_ms`.
+    ///
+    /// `measurements` are the values at the end of the elapsed time. The
+    /// first cell voltage is taken as open circuit voltage to start from
+    /// unless [`Ekf::set`] was called. Without a current nothing is
+    /// estimated, without a cell voltage the filter only predicts. Returns
+    /// the new state of charge in 0.01 %.
+    pub fn update(&mut self, dt_ms: u32, measurements: &Measurements) -> Option<u16> {
+        if !self.initialized {
+            let soc_ppm = measurements
+                .cell_mv
+                .and_then(|mv| self.config.soc_at_rest(mv))?;
+            self.start(soc_ppm);
+            return self.soc();
+        }
+        let Some(current_ma) = measurements.current_ma else {
+            return self.soc();
+        };
+        let current_ma = i64::from(current_ma);
+
+        self.predict(dt_ms, current_ma);
+        if let Some(mv) = measurements.cell_mv {
+            self.correct(i64::from(mv) * 1000, current_ma);
+        }
+        self.update_capacity();
+        self.soc()
+    }
+
+    /// Propagates the state and covariance over `dt_ms`.
+    fn predict(&mut self, dt_ms: u32, current_ma: i64) {
+        let dt_ms = i64::from(dt_ms);
+        let mut mams = current_ma * dt_ms;
+        if mams > 0 {
+            mams = mams * i64::from(self.config.charge_efficiency) / i64::from(FULL);
+        }
+        self.window_mams += mams;
+
+        // ppm = mAms * 1e6 / (mAh * 3.6e6)
+        let numerator = mams * 10 + self.remainder;
+        let denominator = self.capacity_mah.max(1) * 36;
+        let soc_ppm = i64::from(self.x[SOC]) + numerator / denominator;
+        self.remainder = numerator % denominator;
+        if !(0..=PPM).contains(&soc_ppm) {
+            self.remainder = 0;
+        }
+        self.x[SOC] = soc_ppm.clamp(0, PPM) as i32;
+
+        let tau = i64::from(self.config.tau_ms.max(1));
+        let a = mul_div(tau, ONE, tau + dt_ms);
+        // r1 * i is in nanovolts.
+        let target_uv = narrow(i64::from(self.config.r1_uohm) * current_ma / 1000);
+        self.x[V1] = narrow((a * i64::from(self.x[V1]) + (ONE - a) * i64::from(target_uv)) / ONE);
+
+        // P = F P F' + Q with F = diag(1, a, 1)
+        let scale = |value: i32| narrow(mul_div(value.into(), a, ONE));
+        let p = &mut self.p;
+        p[SOC][V1] = scale(p[SOC][V1]);
+        p[V1][SOC] = p[SOC][V1];
+        p[V1][R0] = scale(p[V1][R0]);
+        p[R0][V1] = p[V1][R0];
+        p[V1][V1] = scale(scale(p[V1][V1]));
+        let noise = [
+            (self.config.process_soc_ppm, STEP_PPM),
+            (self.config.process_v1_uv, 1),
+            (self.config.process_r0_uohm, 1),
+        ];
+        for (i, (sigma, step)) in noise.into_iter().enumerate() {
+            let sigma = i64::from(sigma);
+            let added = sigma * sigma * dt_ms / (1000 * step * step);
+            p[i][i] = p[i][i].saturating_add(narrow(added));
+        }
+    }
+
+    /// Corrects the state with a cell voltage in microvolts.
+    fn correct(&mut self, measured_uv: i64, current_ma: i64) {
+        let Some((ocv_uv, slope)) = self.config.ocv(self.x[SOC].into()) else {
+            return;
+        };
+        let x = self.x.map(i64::from);
+        // Jacobian of the terminal voltage in Q16
+        let h = [slope, ONE, current_ma * ONE / 1000];
+        let predicted_uv = ocv_uv + x[V1] + x[R0] * current_ma / 1000;
+        let innovation = measured_uv - predicted_uv;
+
+        let mut ph = [0; 3];
+        for (i, ph) in ph.iter_mut().enumerate() {
+            *ph = (0..3)
+                .map(|j| mul_div(self.p[i][j].into(), h[j], ONE))
+                .sum();
+        }
+        let noise = i64::from(self.config.measurement_uv);
+        let s = (0..3).map(|i| mul_div(h[i], ph[i], ONE)).sum::<i64>() + noise * noise;
+        let s = s.max(1);
+        let gain = ph.map(|ph| mul_div(ph, GAIN_ONE, s));
+
+        // The gain of the state of charge is in steps of the covariance.
+        let steps = [STEP_PPM, 1, 1];
+        for i in 0..3 {
+            let correction = mul_div(gain[i] * steps[i], innovation, GAIN_ONE);
+            self.x[i] = narrow(x[i] + correction);
+            for (j, &ph) in ph.iter().enumerate().skip(i) {
+                let p = i64::from(self.p[i][j]) - mul_div(gain[i], ph, GAIN_ONE);
+                self.p[i][j] = narrow(p);
+                self.p[j][i] = self.p[i][j];
+            }
+        }
+        self.x[SOC] = self.x[SOC].clamp(0, PPM as i32);
+        self.x[R0] = self.x[R0].max(0);
+        // Rounding must not make the covariance indefinite.
+        for i in 0..3 {
+            self.p[i][i] = self.p[i][i].max(1);
+        }
+    }
+
+    /// Measures the capacity once the state of charge has changed by the
+    /// window.
+    fn update_capacity(&mut self) {
+        let max_var = variance(self.config.capacity_max_soc_ppm, STEP_PPM);
+        if self.p[SOC][SOC] > max_var {
+            self.restart_window();
+            return;
+        }
+        let delta_ppm = i64::from(self.x[SOC] - self.window_soc_ppm);
+        if delta_ppm.abs() < i64::from(self.config.capacity_window) * PPM_PER_SOC {
+            return;
+        }
+        // mAh = mAms / 3.6e6 / (ppm / 1e6)
+        let measured = mul_div(self.window_mams, 10, delta_ppm * 36);
+        let nominal = i64::from(self.config.capacity_mah);
+        self.restart_window();
+        // Measurements far off are caused by something else, like a wrong
+        // state of charge.
+        if measured < nominal / 2 || measured > nominal * 3 / 2 {
+            return;
+        }
+
+        let drift = nominal * i64::from(self.config.capacity_drift) / i64::from(FULL);
+        let noise = nominal * i64::from(self.config.capacity_noise) / i64::from(FULL);
+        let prior = self.capacity_var + drift * drift;
+        let total = (prior + noise * noise).max(1);
+        self.capacity_mah += mul_div(prior, measured - self.capacity_mah, total);
+        self.capacity_var = mul_div(prior, noise * noise, total);
+    }
+
+    fn restart_window(&mut self) {
+        self.window_soc_ppm = self.x[SOC];
+        self.window_mams = 0;
+    }
+
+    /// Returns the state of charge in 0.01 %.
+    #[must_use]
+    pub fn soc(&self) -> Option<u16> {
+        self.soc_ppm()
+            .map(|ppm| (i64::from(ppm) / PPM_PER_SOC) as u16)
+    }
+
+    /// Returns the state of charge in parts per million.
+    #[must_use]
+    pub fn soc_ppm(&self) -> Option<u32> {
+        self.initialized.then_some(self.x[SOC] as u32)
+    }
+
+    /// Returns the standard deviation of the state of charge in parts per
+    /// million.
+    #[must_use]
+    pub fn soc_uncertainty_ppm(&self) -> u32 {
+        (i64::from(self.p[SOC][SOC].max(0).isqrt()) * STEP_PPM) as u32
+    }
+
+    /// Returns the voltage across the RC pair in microvolts.
+    #[must_use]
+    pub const fn v1_uv(&self) -> i32 {
+        self.x[V1]
+    }
+
+    /// Returns the estimated state of health.
+    #[must_use]
+    pub fn health(&self) -> Health {
+        let relative = |value: i64, nominal: u32| {
+            mul_div(value, i64::from(FULL), i64::from(nominal.max(1))).clamp(0, u16::MAX.into())
+                as u16
+        };
+        Health {
+            capacity_mah: self.capacity_mah as u32,
+            capacity: relative(self.capacity_mah, self.config.capacity_mah),
+            r0_uohm: self.x[R0] as u32,
+            resistance: relative(self.x[R0].into(), self.config.r0_uohm),
+        }
+    }
+}
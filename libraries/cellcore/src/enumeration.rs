@@ -0,0 +1,192 @@
+//! Assignment of sequential addresses along the daisy chain.
+//!
+//! The procedure is described in the `addressing` module of the cellagent
+//! crate. The first cellagent after the cellcore gets
+//! [`Address::FIRST_AGENT`], the next one the following address and so on.
+
+use cellguard_protocol::{Address, ErrorCode, Request, Response};
+
+use crate::bus::{Bus, Master, Replies};
+
+/// Enumeration settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Number of cellagents in the pack.
+    pub expected: u8,
+    /// Number of times a request is repeated if it isn't answered.
+    pub retries: u8,
+}
+
+impl Config {
+    /// Creates a configuration for a pack with `expected` cellagents.
+    #[must_use]
+    pub const fn new(expected: u8) -> Self {
+        Self {
+            expected,
+            retries: 2,
+        }
+    }
+}
+
+/// Reason for a failed enumeration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error<E> {
+    /// The bus failed.
+    Bus(E),
+    /// The chain ended before all expected cellagents were found.
+    ///
+    /// The link after the last found cellagent or the cellagent following it
+    /// is broken.
+    Missing {
+        /// Number of cellagents found.
+        found: u8,
+    },
+    /// More cellagents than expected were found.
+    Unexpected {
+        /// Number of cellagents found.
+        found: u8,
+    },
+    /// More than one node answered at this address.
+    Duplicate(Address),
+    /// A cellagent answered with an unexpected or corrupted response.
+    Invalid(Address),
+    /// A cellagent rejected its address.
+    Rejected(Address, ErrorCode),
+    /// A cellagent that was assigned this address stopped answering.
+    Lost(Address),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Self::Bus(error)
+    }
+}
+
+/// Enumerates the cellagents of the daisy chain.
+///
+/// Returns the number of cellagents, which is equal to the expected number.
+/// After an error the already assigned cellagents keep their addresses.
+pub fn enumerate<B: Bus>(master: &mut Master<B>, config: Config) -> Result<u8, Error<B::Error>> {
+    master.request(Address::BROADCAST, &Request::ResetAddresses)?;
+
+    let mut found: u8 = 0;
+    while let Some(address) = Address::agent(found) {
+        if !assign(master, address, config.retries)? {
+            break;
+        }
+        found += 1;
+    }
+
+    for index in 0..found {
+        let Some(address) = Address::agent(index) else {
+            break;
+        };
+        check(master, address, config.retries)?;
+    }
+
+    if found < config.expected {
+        Err(Error::Missing { found })
+    } else if found > config.expected {
+        Err(Error::Unexpected { found })
+    } else {
+        Ok(found)
+    }
+}
+
+/// Assigns `address` to the first unassigned cellagent.
+///
+/// Every attempt sends the assignment and, if it isn't answered, one ping,
+/// so `retries` bounds both. Returns false if no cellagent answered.
+fn assign<B: Bus>(
+    master: &mut Master<B>,
+    address: Address,
+    retries: u8,
+) -> Result<bool, Error<B::Error>> {
+    let request = Request::AssignAddress(address);
+    for _ in 0..=retries {
+        let assigned = master.request(Address::UNASSIGNED, &request)?;
+        match assigned {
+            Replies {
+                first: Some((_, response)),
+                count: 1,
+                invalid: 0,
+            } => {
+                return match response {
+                    Response::Address(a) if a == address => Ok(true),
+                    Response::Error(code) => Err(Error::Rejected(address, code)),
+                    _ => Err(Error::Invalid(address)),
+                };
+            }
+            Replies { count: 0, .. } => {}
+            _ => return Err(Error::Duplicate(address)),
+        }
+
+        // The cellagent may have taken the address and only its response got
+        // lost. Repeating the assignment would then reach the next cellagent,
+        // so it's only repeated if nothing answers at the address.
+        match master.request(address, &Request::Ping)? {
+            Replies {
+                count: 1,
+                invalid: 0,
+                ..
+            } => return Ok(true),
+            Replies {
+                count: 0,
+                invalid: 0,
+                ..
+            } if assigned.invalid == 0 => {}
+            Replies { count: 0, .. } => return Err(Error::Invalid(address)),
+            _ => return Err(Error::Duplicate(address)),
+        }
+    }
+    Ok(false)
+}
+
+/// Checks that exactly one cellagent answers at `address`.
+///
+/// The ping is repeated up to `retries` times while it isn't answered. After
+/// an error the chain has to be enumerated again.
+pub fn check<B: Bus>(
+    master: &mut Master<B>,
+    address: Address,
+    retries: u8,
+) -> Result<(), Error<B::Error>> {
+    match query(master, address, &Request::Ping, retries)? {
+        Response::Pong(_) => Ok(()),
+        _ => Err(Error::Invalid(address)),
+    }
+}
+
+/// Sends `request` to the cellagent at `address` and returns its response.
+///
+/// The request is repeated up to `retries` times while it isn't answered, so
+/// it has to be safe to repeat. After an error the chain has to be enumerated
+/// again.
+pub fn query<B: Bus>(
+    master: &mut Master<B>,
+    address: Address,
+    request: &Request,
+    retries: u8,
+) -> Result<Response, Error<B::Error>> {
+    let mut replies = Replies::default();
+    for _ in 0..=retries {
+        replies = master.request(address, request)?;
+        if replies.count > 0 {
+            break;
+        }
+    }
+    match replies {
+        Replies {
+            first: Some((_, response)),
+            count: 1,
+            invalid: 0,
+        } => Ok(response),
+        Replies {
+            count: 0,
+            invalid: 0,
+            ..
+        } => Err(Error::Lost(address)),
+        Replies { count: 0, .. } => Err(Error::Invalid(address)),
+        _ => Err(Error::Duplicate(address)),
+    }
+}
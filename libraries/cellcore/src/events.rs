@@ -0,0 +1,94 @@
+//! Recording of events in the event log.
+//!
+//! The firmware keeps an [`EventLog`] in flash. Resets, crashes and setting
+//! changes are appended where they happen. A [`Monitor`] compares the
+//! protection and contactor states with the previous ones and reports what
+//! changed.
+//!
+//! A quantity reaching a higher level is recorded as [`EventKind::Warning`],
+//! [`EventKind::Alarm`] or [`EventKind::Trip`] with the index of the quantity
+//! in [`Quantity::ALL`], and clearing latched trips as
+//! [`EventKind::TripsCleared`]. The end of a contactor sequence is recorded
+//! as [`EventKind::ContactorsClosed`] or [`EventKind::ContactorsOpened`], a
+//! fault as [`EventKind::ContactorFault`] with [`Fault::code`].
+//!
+//! [`Fault::code`]: crate::contactor::Fault::code
+
+use cellguard_log::Log;
+use cellguard_protocol::EventKind;
+
+use crate::contactor::State;
+use crate::protection::{Level, Protection, Quantity};
+
+/// Event log in flash.
+pub type EventLog<F> = Log<F>;
+
+/// Turns state changes into events.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    /// Highest active level of each quantity.
+    levels: [Option<Level>; Quantity::ALL.len()],
+    contactors: State,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    /// Creates a monitor with no level active and the contactors open.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            levels: [None; Quantity::ALL.len()],
+            contactors: State::Open,
+        }
+    }
+
+    /// Reports the changes of the protection state since the previous call.
+    ///
+    /// Calls `record` with the kind and data of each event. A quantity
+    /// jumping several levels at once only reports the highest one.
+    pub fn protection(&mut self, protection: &Protection, mut record: impl FnMut(EventKind, u16)) {
+        let mut cleared = false;
+        for (index, quantity) in Quantity::ALL.into_iter().enumerate() {
+            let level = protection.level(quantity);
+            let previous = core::mem::replace(&mut self.levels[index], level);
+            if let Some(level) = level
+                && Some(level) > previous
+            {
+                let kind = match level {
+                    Level::Warning => EventKind::Warning,
+                    Level::Alarm => EventKind::Alarm,
+                    Level::Trip => EventKind::Trip,
+                };
+                record(kind, index as u16);
+            }
+            cleared |= previous == Some(Level::Trip) && level != Some(Level::Trip);
+        }
+        if cleared {
+            record(EventKind::TripsCleared, 0);
+        }
+    }
+
+    /// Reports the change of the contactor state since the previous call.
+    ///
+    /// Only the end of a sequence is reported. Opening after a fault was
+    /// cleared isn't, since the contactors already opened with the fault.
+    pub fn contactors(&mut self, state: State, mut record: impl FnMut(EventKind, u16)) {
+        let previous = core::mem::replace(&mut self.contactors, state);
+        if state == previous {
+            return;
+        }
+        match state {
+            State::Closed => record(EventKind::ContactorsClosed, 0),
+            State::Open if !matches!(previous, State::Fault(_)) => {
+                record(EventKind::ContactorsOpened, 0);
+            }
+            State::Fault(fault) => record(EventKind::ContactorFault, fault.code()),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Requests of a host tool connected to the cellcore.
+//!
+//! Host tools use the cellguard protocol on the debug serial port and send
+//! their requests to [`Address::CORE`]:
+//!
+//! | Request                      | Response                      |
+//! |------------------------------|-------------------------------|
+//! | [`Request::Ping`]            | [`Response::Pong`]            |
+//! | [`Request::ReadPack`]        | [`Response::Pack`]            |
+//! | [`Request::ReadCells`]       | [`Response::Cells`]           |
+//! | [`Request::ReadSetting`]     | [`Response::Setting`]         |
+//! | [`Request::WriteSetting`]    | [`Response::SettingWritten`]  |
+//! | [`Request::ReadEvent`]       | [`Response::Event`]           |
+//! | [`Request::ReadCrash`]       | [`Response::Crash`]           |
+//!
+//! Settings are identified by [`Setting::id`] and use the units of the
+//! console, temperatures in 1/16 °C. Requests meant for cellagents are
+//! answered with [`ErrorCode::Unsupported`].
+
+use cellguard_protocol::{
+    Address, CellBlock, CellReading, CrashReport, ErrorCode, Event, Frame, MAX_FRAME_LEN, NodeInfo,
+    NodeKind, PackStatus, Request, Response, SettingValue, Version,
+};
+
+use crate::balancing::{self, Cell};
+use crate::config::PackConfig;
+use crate::console::Setting;
+use crate::limits::Limits;
+use crate::protection::{Level, Protection, Quantity};
+
+/// The firmware state a host tool operates on.
+pub trait Pack {
+    /// Returns the firmware version.
+    fn firmware(&self) -> Version;
+
+    /// Returns the state of charge in 0.01 %.
+    fn soc(&self) -> Option<u16>;
+
+    /// Returns the pack voltage in millivolts.
+    fn voltage_mv(&self) -> Option<u32>;
+
+    /// Returns the pack current in milliamperes, positive while charging.
+    fn current_ma(&self) -> Option<i32>;
+
+    /// Returns the limits for the inverter.
+    fn limits(&self) -> Limits;
+
+    /// Returns the latest readings of all cells.
+    fn cells(&self) -> &[Cell];
+
+    /// Returns true if the bleed resistor of a cell is switched on.
+    fn is_bleeding(&self, cell: usize) -> bool;
+
+    /// Returns the protection state.
+    fn protection(&mut self) -> &mut Protection;
+
+    /// Returns the balancing settings.
+    fn balancing_config(&mut self) -> &mut balancing::Config;
+
+    /// Returns the pack settings.
+    fn pack_config(&mut self) -> &mut PackConfig;
+
+    /// Called after a setting was changed.
+    ///
+    /// Protection settings are already applied at this point.
+    fn config_changed(&mut self, setting: Setting);
+
+    /// Returns an entry of the event log, index 0 being the oldest.
+    fn event(&self, index: u16) -> Option<Event>;
+
+    /// Returns the report of the panic that caused the last reset.
+    fn crash(&self) -> Option<CrashReport>;
+}
+
+/// Answers a frame received from a host tool.
+///
+/// Returns the length of the response written to `buf`, or `None` if the
+/// frame isn't addressed to the cellcore.
+pub fn handle<P: Pack>(
+    frame: &Frame<'_>,
+    pack: &mut P,
+    buf: &mut [u8; MAX_FRAME_LEN],
+) -> Option<usize> {
+    if frame.header.destination != Address::CORE {
+        return None;
+    }
+    let response = match Request::decode(frame) {
+        Ok(request) => respond(request, pack).unwrap_or_else(Response::Error),
+        Err(code) => Response::Error(code),
+    };
+    response.encode(frame.header.reply(Address::CORE), buf).ok()
+}
+
+fn respond<P: Pack>(request: Request, pack: &mut P) -> Result<Response, ErrorCode> {
+    Ok(match request {
+        Request::Ping => Response::Pong(NodeInfo::new(NodeKind::Core, pack.firmware())),
+        Request::ReadPack => Response::Pack(status(pack)),
+        Request::ReadCells(first) => Response::Cells(cells(pack, first)?),
+        Request::ReadSetting(id) => {
+            let setting = Setting::from_id(id).ok_or(ErrorCode::OutOfRange)?;
+            let protection = *pack.protection().config();
+            let balancing = *pack.balancing_config();
+            let value = setting.get(&protection, &balancing, pack.pack_config());
+            Response::Setting(SettingValue { id, value })
+        }
+        Request::WriteSetting(SettingValue { id, value }) => {
+            let setting = Setting::from_id(id).ok_or(ErrorCode::OutOfRange)?;
+            let mut protection = *pack.protection().config();
+            let mut balancing = *pack.balancing_config();
+            let mut config = *pack.pack_config();
+            setting
+                .set(value, &mut protection, &mut balancing, &mut config)
+                .ok_or(ErrorCode::OutOfRange)?;
+            pack.protection().set_config(protection);
+            *pack.balancing_config() = balancing;
+            *pack.pack_config() = config;
+            pack.config_changed(setting);
+            let value = setting.get(&protection, &balancing, &config);
+            Response::SettingWritten(SettingValue { id, value })
+        }
+        Request::ReadEvent(index) => {
+            Response::Event(pack.event(index).ok_or(ErrorCode::OutOfRange)?)
+        }
+        Request::ReadCrash => Response::Crash(pack.crash()),
+        Request::ReadVoltage
+        | Request::ReadTemperature
+        | Request::SetBalancing(_)
+        | Request::ReadStatus
+        | Request::ResetAddresses
+        | Request::AssignAddress(_)
+        | Request::ReadBalancing => return Err(ErrorCode::Unsupported),
+    })
+}
+
+fn status<P: Pack>(pack: &mut P) -> PackStatus {
+    let protection = pack.protection();
+    let [warnings, alarms, trips] = Level::ALL.map(|level| flags(protection, level));
+    let limits = pack.limits();
+    PackStatus {
+        soc: pack.soc(),
+        voltage_mv: pack.voltage_mv(),
+        current_ma: pack.current_ma(),
+        cells: pack.cells().len().try_into().unwrap_or(u8::MAX),
+        warnings,
+        alarms,
+        trips,
+        charge_current_ma: limits.charge_ma,
+        discharge_current_ma: limits.discharge_ma,
+        charge_voltage_mv: limits.charge_voltage_mv,
+        discharge_voltage_mv: limits.discharge_voltage_mv,
+    }
+}
+
+/// Returns the flags of the quantities at or above `level`.
+fn flags(protection: &Protection, level: Level) -> u8 {
+    let mut flags = 0;
+    for (bit, quantity) in Quantity::ALL.into_iter().enumerate() {
+        if protection.level(quantity) >= Some(level) {
+            flags |= 1 << bit;
+        }
+    }
+    flags
+}
+
+fn cells<P: Pack>(pack: &P, first: u8) -> Result<CellBlock, ErrorCode> {
+    let cells = pack.cells();
+    let total = cells.len().try_into().unwrap_or(u8::MAX);
+    let rest = cells
+        .get(usize::from(first)..usize::from(total))
+        .ok_or(ErrorCode::OutOfRange)?;
+    let mut block = CellBlock::new(first, total);
+    for (index, cell) in (usize::from(first)..).zip(rest) {
+        let reading = CellReading {
+            voltage_mv: cell.voltage_mv,
+            temperature: cell.temperature.map(|t| t.raw()),
+            balancing: pack.is_bleeding(index),
+        };
+        if !block.push(reading) {
+            break;
+        }
+    }
+    Ok(block)
+}
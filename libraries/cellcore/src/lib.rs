@@ -0,0 +1,20 @@
+//! Hardware independent logic of the cellcore firmware.
+//!
+//! Everything in here is free of register access so it can be tested on the
+//! host. The firmware binds it to the peripherals of the AVR128DB48.
+
+#![no_std]
+
+pub mod balancing;
+pub mod bus;
+pub mod config;
+pub mod console;
+pub mod contactor;
+pub mod ekf;
+pub mod enumeration;
+pub mod events;
+pub mod host;
+pub mod limits;
+pub mod polling;
+pub mod protection;
+pub mod soc;
@@ -0,0 +1,275 @@
+//! Charge and discharge limits for inverters and chargers.
+//!
+//! The allowed charge and discharge currents start from the maximum of the
+//! pack and are derated by piecewise linear [`Table`]s over the highest and
+//! lowest cell voltage, the state of charge and the highest and lowest
+//! temperature. The lowest factor of all tables applies. Limits are zero
+//! while any of these measurements is missing or [`Decision`] inhibits the
+//! direction.
+//!
+//! Reductions apply at once, increases are limited to
+//! [`Config::ramp_ma_per_s`] so the inverter doesn't jump back to full power
+//! after a derating has ended.
+//!
+//! The charge and discharge voltage limits are the configured cell voltages
+//! times the number of cells.
+
+use p3t1755::Temperature;
+
+use crate::protection::Decision;
+use crate::soc::FULL;
+
+/// Point of a derating table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Point {
+    /// Input value in the unit of the table.
+    pub input: i32,
+    /// Allowed current in 0.01 % of the maximum.
+    pub factor: u16,
+}
+
+impl Point {
+    /// Creates a point from an input and a factor in percent.
+    #[must_use]
+    pub const fn new(input: i32, percent: u8) -> Self {
+        Self {
+            input,
+            factor: percent as u16 * 100,
+        }
+    }
+
+    /// Creates a point of a temperature table.
+    #[must_use]
+    pub const fn temperature(deg_c: i8, percent: u8) -> Self {
+        Self::new(
+            Temperature::from_degrees_celsius(deg_c).raw() as i32,
+            percent,
+        )
+    }
+}
+
+/// Piecewise linear derating over an input, sorted by ascending input.
+///
+/// Inputs outside the table use the factor of the nearest end. An empty table
+/// doesn't derate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Table<'a>(pub &'a [Point]);
+
+impl Table<'_> {
+    /// Table without derating.
+    pub const NONE: Table<'static> = Table(&[]);
+
+    /// Returns the factor at an input in 0.01 %.
+    #[must_use]
+    pub fn factor(&self, input: i32) -> u16 {
+        let (Some(first), Some(last)) = (self.0.first(), self.0.last()) else {
+            return FULL;
+        };
+        if input <= first.input {
+            return first.factor;
+        }
+        let Some(pair) = self.0.windows(2).find(|pair| input <= pair[1].input) else {
+            return last.factor;
+        };
+        let (low, high) = (pair[0], pair[1]);
+        let span = (i64::from(high.input) - i64::from(low.input)).max(1);
+        let offset = i64::from(input) - i64::from(low.input);
+        let factor = i64::from(low.factor)
+            + offset * (i64::from(high.factor) - i64::from(low.factor)) / span;
+        factor.clamp(0, i64::from(FULL)) as u16
+    }
+}
+
+/// Derating tables of one direction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Derating<'a> {
+    /// Over the highest cell voltage in millivolts.
+    pub max_cell: Table<'a>,
+    /// Over the lowest cell voltage in millivolts.
+    pub min_cell: Table<'a>,
+    /// Over the state of charge in 0.01 %.
+    pub soc: Table<'a>,
+    /// Over the highest temperature in 1/16 °C.
+    pub max_temperature: Table<'a>,
+    /// Over the lowest temperature in 1/16 °C.
+    pub min_temperature: Table<'a>,
+}
+
+impl Derating<'_> {
+    /// Returns the lowest factor of all tables in 0.01 %.
+    fn factor(&self, inputs: &Inputs) -> Option<u16> {
+        let temperature = |t: Option<Temperature>| t.map(|t| i32::from(t.raw()));
+        let factors = [
+            self.max_cell.factor(inputs.max_cell_mv?.into()),
+            self.min_cell.factor(inputs.min_cell_mv?.into()),
+            self.soc.factor(inputs.soc?.into()),
+            self.max_temperature
+                .factor(temperature(inputs.max_temperature)?),
+            self.min_temperature
+                .factor(temperature(inputs.min_temperature)?),
+        ];
+        factors.into_iter().min()
+    }
+}
+
+/// Limit settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config<'a> {
+    /// Number of cells in series.
+    pub cells: u8,
+    /// Maximum charge current in milliamperes.
+    pub max_charge_ma: u32,
+    /// Maximum discharge current in milliamperes.
+    pub max_discharge_ma: u32,
+    /// Cell voltage to charge to in millivolts.
+    pub charge_voltage_mv: u16,
+    /// Cell voltage to discharge to in millivolts.
+    pub discharge_voltage_mv: u16,
+    /// Derating of the charge current.
+    pub charge: Derating<'a>,
+    /// Derating of the discharge current.
+    pub discharge: Derating<'a>,
+    /// Increase of the limits per second in milliamperes.
+    pub ramp_ma_per_s: u32,
+}
+
+impl Default for Config<'static> {
+    /// Limits for 16 LiFePO4 cells in series, matching the default
+    /// protection limits.
+    fn default() -> Self {
+        const CHARGE: Derating<'static> = Derating {
+            max_cell: Table(&[
+                Point::new(3400, 100),
+                Point::new(3450, 50),
+                Point::new(3500, 10),
+                Point::new(3550, 0),
+            ]),
+            min_cell: Table::NONE,
+            soc: Table(&[Point::new(9000, 100), Point::new(10_000, 20)]),
+            max_temperature: Table(&[Point::temperature(45, 100), Point::temperature(50, 0)]),
+            // Charging below freezing plates lithium.
+            min_temperature: Table(&[
+                Point::temperature(5, 0),
+                Point::temperature(10, 30),
+                Point::temperature(15, 100),
+            ]),
+        };
+        const DISCHARGE: Derating<'static> = Derating {
+            max_cell: Table::NONE,
+            min_cell: Table(&[
+                Point::new(2900, 0),
+                Point::new(3000, 20),
+                Point::new(3100, 100),
+            ]),
+            soc: Table(&[Point::new(500, 0), Point::new(1500, 100)]),
+            max_temperature: Table(&[Point::temperature(45, 100), Point::temperature(50, 0)]),
+            min_temperature: Table(&[Point::temperature(0, 0), Point::temperature(10, 100)]),
+        };
+
+        Self {
+            cells: 16,
+            max_charge_ma: 50_000,
+            max_discharge_ma: 100_000,
+            charge_voltage_mv: 3450,
+            discharge_voltage_mv: 2900,
+            charge: CHARGE,
+            discharge: DISCHARGE,
+            ramp_ma_per_s: 5000,
+        }
+    }
+}
+
+/// Inputs of the limits.
+///
+/// `None` marks a measurement that is missing or invalid.
+#[derive(Clone, Copy, Default)]
+pub struct Inputs {
+    /// Highest cell voltage in millivolts.
+    pub max_cell_mv: Option<u16>,
+    /// Lowest cell voltage in millivolts.
+    pub min_cell_mv: Option<u16>,
+    /// State of charge in 0.01 %.
+    pub soc: Option<u16>,
+    /// Highest temperature.
+    pub max_temperature: Option<Temperature>,
+    /// Lowest temperature.
+    pub min_temperature: Option<Temperature>,
+}
+
+/// Limits for the inverter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Limits {
+    /// Charge current limit (CCL) in milliamperes.
+    pub charge_ma: u32,
+    /// Discharge current limit (DCL) in milliamperes.
+    pub discharge_ma: u32,
+    /// Charge voltage limit (CVL) in millivolts.
+    pub charge_voltage_mv: u32,
+    /// Discharge voltage limit (DVL) in millivolts.
+    pub discharge_voltage_mv: u32,
+}
+
+/// Rate limited computation of the limits.
+#[derive(Clone, Debug)]
+pub struct Limiter<'a> {
+    config: Config<'a>,
+    limits: Limits,
+    /// Fraction of a milliampere of the ramp not yet applied, in
+    /// milliampere-milliseconds per second.
+    remainder: u64,
+}
+
+impl<'a> Limiter<'a> {
+    /// Creates a limiter whose current limits start at zero.
+    #[must_use]
+    pub const fn new(config: Config<'a>) -> Self {
+        Self {
+            limits: Limits {
+                charge_ma: 0,
+                discharge_ma: 0,
+                charge_voltage_mv: config.cells as u32 * config.charge_voltage_mv as u32,
+                discharge_voltage_mv: config.cells as u32 * config.discharge_voltage_mv as u32,
+            },
+            config,
+            remainder: 0,
+        }
+    }
+
+    /// Returns the settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config<'a> {
+        &self.config
+    }
+
+    /// Returns the last limits.
+    #[must_use]
+    pub const fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Computes the limits after `dt_ms`.
+    pub fn update(&mut self, dt_ms: u32, inputs: &Inputs, decision: &Decision) -> Limits {
+        let cfg = &self.config;
+        let target = |max_ma: u32, derating: &Derating<'_>, inhibit: bool| {
+            if inhibit {
+                return 0;
+            }
+            let factor = derating.factor(inputs).unwrap_or(0);
+            (u64::from(max_ma) * u64::from(factor) / u64::from(FULL)) as u32
+        };
+        let charge = target(cfg.max_charge_ma, &cfg.charge, decision.inhibit_charge);
+        let discharge = target(
+            cfg.max_discharge_ma,
+            &cfg.discharge,
+            decision.inhibit_discharge,
+        );
+
+        let total = u64::from(cfg.ramp_ma_per_s) * u64::from(dt_ms) + self.remainder;
+        self.remainder = total % 1000;
+        let step = (total / 1000).try_into().unwrap_or(u32::MAX);
+        let ramp = |current: u32, target: u32| target.min(current.saturating_add(step));
+        self.limits.charge_ma = ramp(self.limits.charge_ma, charge);
+        self.limits.discharge_ma = ramp(self.limits.discharge_ma, discharge);
+        self.limits
+    }
+}
@@ -0,0 +1,132 @@
+//! Periodic requests to the cellagents.
+//!
+//! The [`Poller`] enumerates the daisy chain on its first call and then
+//! visits one cellagent per call, so a poll takes only a few requests and
+//! the main loop stays responsive. A visit reads the cell voltage and the
+//! temperature and sends the latest [`Balancing`] request, which also keeps
+//! the command timeout of the cellagent from expiring.
+//!
+//! If a cellagent is lost or another node answers at its address, its
+//! readings are cleared and the chain is enumerated again by the next call.
+
+use cellguard_protocol::{Address, Balancing, Request, Response};
+use p3t1755::Temperature;
+
+use crate::balancing::Cell;
+use crate::bus::{Bus, Master};
+use crate::enumeration::{self, Config, Error};
+
+/// Round robin over the cellagents of the daisy chain.
+#[derive(Clone, Debug)]
+pub struct Poller {
+    config: Config,
+    enumerated: bool,
+    /// Index of the cellagent visited next.
+    next: u8,
+}
+
+impl Poller {
+    /// Creates a poller that enumerates the chain on the first poll.
+    #[must_use]
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            enumerated: false,
+            next: 0,
+        }
+    }
+
+    /// Returns the enumeration settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Changes the enumeration settings and enumerates the chain again.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.enumerated = false;
+    }
+
+    /// Returns true once the chain was enumerated without an error.
+    #[must_use]
+    pub const fn is_enumerated(&self) -> bool {
+        self.enumerated
+    }
+
+    /// Enumerates the chain if needed and otherwise visits the next
+    /// cellagent.
+    ///
+    /// `cells` holds the readings of the cellagents in chain order and
+    /// `requests` their balancing requests. Cellagents beyond either slice
+    /// are only checked. Returns the error of the enumeration or of the
+    /// visit, after which the next poll enumerates the chain again.
+    pub fn poll<B: Bus>(
+        &mut self,
+        master: &mut Master<B>,
+        cells: &mut [Cell],
+        requests: &[Balancing],
+    ) -> Result<(), Error<B::Error>> {
+        if !self.enumerated {
+            if let Err(error) = enumeration::enumerate(master, self.config) {
+                if let Error::Missing { found } = error {
+                    cells
+                        .iter_mut()
+                        .skip(found.into())
+                        .for_each(|cell| *cell = Cell::default());
+                }
+                return Err(error);
+            }
+            self.enumerated = true;
+            self.next = 0;
+            return Ok(());
+        }
+
+        let index = self.next;
+        self.next = (index + 1) % self.config.expected.max(1);
+        let Some(address) = Address::agent(index) else {
+            return Ok(());
+        };
+        let index = usize::from(index);
+        let result = match (cells.get_mut(index), requests.get(index)) {
+            (Some(cell), Some(&request)) => self.visit(master, address, cell, request),
+            _ => enumeration::check(master, address, self.config.retries),
+        };
+        if result.is_err() {
+            if let Some(cell) = cells.get_mut(index) {
+                *cell = Cell::default();
+            }
+            self.enumerated = false;
+        }
+        result
+    }
+
+    /// Reads the cell and sends the balancing request.
+    fn visit<B: Bus>(
+        &self,
+        master: &mut Master<B>,
+        address: Address,
+        cell: &mut Cell,
+        request: Balancing,
+    ) -> Result<(), Error<B::Error>> {
+        let retries = self.config.retries;
+        // Readings the cellagent doesn't have are answered with an error.
+        cell.voltage_mv = match enumeration::query(master, address, &Request::ReadVoltage, retries)?
+        {
+            Response::Voltage(mv) => Some(mv),
+            Response::Error(_) => None,
+            _ => return Err(Error::Invalid(address)),
+        };
+        let temperature = enumeration::query(master, address, &Request::ReadTemperature, retries)?;
+        cell.temperature = match temperature {
+            Response::Temperature(raw) => Temperature::from_raw(raw),
+            Response::Error(_) => None,
+            _ => return Err(Error::Invalid(address)),
+        };
+        // A cellagent in safe mode rejects balancing as busy.
+        match enumeration::query(master, address, &Request::SetBalancing(request), retries)? {
+            Response::Balancing(_) | Response::Error(_) => Ok(()),
+            _ => Err(Error::Invalid(address)),
+        }
+    }
+}
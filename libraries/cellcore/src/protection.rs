@@ -0,0 +1,525 @@
+//! Enforcement of the safe operating area of the pack.
+//!
+//! Every [`Quantity`] is checked against three [`Level`]s. A level becomes
+//! active once its threshold has been exceeded for the delay of the level and
+//! becomes inactive again once the value has returned past the threshold by
+//! the hysteresis. Going back within the hysteresis band keeps the state but
+//! restarts the delay.
+//!
+//! Warnings and alarms clear on their own. Trips latch and are only cleared
+//! by [`Protection::reset`], which is refused while the condition persists.
+//! A missing measurement counts as exceeding all levels, so it trips after
+//! the trip delay.
+//!
+//! Settings changed at runtime are checked with [`Limit::is_valid`] before
+//! they are applied.
+
+use core::ops::RangeInclusive;
+
+use p3t1755::Temperature;
+
+use crate::balancing::Cell;
+
+/// Number of quantities.
+const QUANTITIES: usize = 6;
+/// Number of levels.
+const LEVELS: usize = 3;
+
+/// Monitored quantity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Quantity {
+    /// Highest cell voltage in millivolts.
+    OverVoltage,
+    /// Lowest cell voltage in millivolts.
+    UnderVoltage,
+    /// Highest temperature in 1/16 °C.
+    OverTemperature,
+    /// Lowest temperature in 1/16 °C.
+    UnderTemperature,
+    /// Charge current in milliamperes.
+    ChargeOverCurrent,
+    /// Discharge current in milliamperes.
+    DischargeOverCurrent,
+}
+
+impl Quantity {
+    /// All quantities.
+    pub const ALL: [Self; QUANTITIES] = [
+        Self::OverVoltage,
+        Self::UnderVoltage,
+        Self::OverTemperature,
+        Self::UnderTemperature,
+        Self::ChargeOverCurrent,
+        Self::DischargeOverCurrent,
+    ];
+
+    /// Returns the short name used in settings, like `ov` for
+    /// [`Quantity::OverVoltage`].
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::OverVoltage => "ov",
+            Self::UnderVoltage => "uv",
+            Self::OverTemperature => "ot",
+            Self::UnderTemperature => "ut",
+            Self::ChargeOverCurrent => "occ",
+            Self::DischargeOverCurrent => "ocd",
+        }
+    }
+
+    /// Returns true if values below the threshold violate the limit.
+    const fn is_lower_limit(self) -> bool {
+        matches!(self, Self::UnderVoltage | Self::UnderTemperature)
+    }
+
+    /// Returns the threshold values within the ratings of lithium cells of
+    /// any chemistry.
+    #[must_use]
+    pub const fn range(self) -> RangeInclusive<i32> {
+        const fn celsius(deg_c: i8) -> i32 {
+            Temperature::from_degrees_celsius(deg_c).raw() as i32
+        }
+
+        match self {
+            Self::OverVoltage | Self::UnderVoltage => 1000..=4500,
+            Self::OverTemperature | Self::UnderTemperature => celsius(-40)..=celsius(85),
+            Self::ChargeOverCurrent | Self::DischargeOverCurrent => 0..=1_000_000,
+        }
+    }
+}
+
+/// Severity of a limit violation.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    /// Only reported.
+    Warning,
+    /// Charging, discharging or both are inhibited.
+    Alarm,
+    /// The contactors are opened until the fault is reset.
+    Trip,
+}
+
+impl Level {
+    /// All levels in ascending severity.
+    pub const ALL: [Self; LEVELS] = [Self::Warning, Self::Alarm, Self::Trip];
+
+    /// Returns the name used in settings.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Alarm => "alarm",
+            Self::Trip => "trip",
+        }
+    }
+}
+
+/// Threshold of a single level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Threshold {
+    /// Value at which the level is exceeded.
+    pub value: i32,
+    /// Distance the value has to return past `value` to clear the level.
+    pub hysteresis: i32,
+    /// Time the value has to exceed the threshold before the level becomes
+    /// active.
+    pub delay_ms: u32,
+}
+
+impl Threshold {
+    /// Creates a threshold.
+    #[must_use]
+    pub const fn new(value: i32, hysteresis: i32, delay_ms: u32) -> Self {
+        Self {
+            value,
+            hysteresis,
+            delay_ms,
+        }
+    }
+
+    /// Creates a temperature threshold with the hysteresis in whole degrees.
+    #[must_use]
+    pub const fn temperature(value: Temperature, hysteresis_deg_c: i8, delay_ms: u32) -> Self {
+        Self::new(
+            value.raw() as i32,
+            Temperature::from_degrees_celsius(hysteresis_deg_c).raw() as i32,
+            delay_ms,
+        )
+    }
+}
+
+/// Thresholds of all levels of a quantity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Limit {
+    /// Warning threshold.
+    pub warning: Threshold,
+    /// Alarm threshold.
+    pub alarm: Threshold,
+    /// Trip threshold.
+    pub trip: Threshold,
+}
+
+impl Limit {
+    /// Returns the threshold of a level.
+    #[must_use]
+    pub const fn threshold(&self, level: Level) -> &Threshold {
+        match level {
+            Level::Warning => &self.warning,
+            Level::Alarm => &self.alarm,
+            Level::Trip => &self.trip,
+        }
+    }
+
+    /// Returns the threshold of a level for changing it.
+    pub const fn threshold_mut(&mut self, level: Level) -> &mut Threshold {
+        match level {
+            Level::Warning => &mut self.warning,
+            Level::Alarm => &mut self.alarm,
+            Level::Trip => &mut self.trip,
+        }
+    }
+
+    /// Returns true if the thresholds suit the quantity.
+    ///
+    /// The values have to be within [`Quantity::range`] and must not
+    /// decrease in severity, so a trip is never reached before the alarm.
+    /// The hysteresis must not be negative.
+    #[must_use]
+    pub fn is_valid(&self, quantity: Quantity) -> bool {
+        let range = quantity.range();
+        let levels = [self.warning, self.alarm, self.trip];
+        let in_range = levels
+            .iter()
+            .all(|threshold| range.contains(&threshold.value) && threshold.hysteresis >= 0);
+        let ordered = levels.windows(2).all(|pair| {
+            if quantity.is_lower_limit() {
+                pair[0].value >= pair[1].value
+            } else {
+                pair[0].value <= pair[1].value
+            }
+        });
+        in_range && ordered
+    }
+}
+
+/// Limits of all quantities.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Highest cell voltage.
+    pub over_voltage: Limit,
+    /// Lowest cell voltage.
+    pub under_voltage: Limit,
+    /// Highest temperature.
+    pub over_temperature: Limit,
+    /// Lowest temperature.
+    pub under_temperature: Limit,
+    /// Charge current.
+    pub charge_over_current: Limit,
+    /// Discharge current.
+    pub discharge_over_current: Limit,
+}
+
+impl Config {
+    /// Returns the limit of a quantity.
+    #[must_use]
+    pub const fn limit(&self, quantity: Quantity) -> &Limit {
+        match quantity {
+            Quantity::OverVoltage => &self.over_voltage,
+            Quantity::UnderVoltage => &self.under_voltage,
+            Quantity::OverTemperature => &self.over_temperature,
+            Quantity::UnderTemperature => &self.under_temperature,
+            Quantity::ChargeOverCurrent => &self.charge_over_current,
+            Quantity::DischargeOverCurrent => &self.discharge_over_current,
+        }
+    }
+
+    /// Returns the limit of a quantity for changing it.
+    pub const fn limit_mut(&mut self, quantity: Quantity) -> &mut Limit {
+        match quantity {
+            Quantity::OverVoltage => &mut self.over_voltage,
+            Quantity::UnderVoltage => &mut self.under_voltage,
+            Quantity::OverTemperature => &mut self.over_temperature,
+            Quantity::UnderTemperature => &mut self.under_temperature,
+            Quantity::ChargeOverCurrent => &mut self.charge_over_current,
+            Quantity::DischargeOverCurrent => &mut self.discharge_over_current,
+        }
+    }
+}
+
+impl Default for Config {
+    /// Limits for LiFePO4 cells.
+    fn default() -> Self {
+        const fn celsius(deg_c: i8) -> Temperature {
+            Temperature::from_degrees_celsius(deg_c)
+        }
+
+        Self {
+            over_voltage: Limit {
+                warning: Threshold::new(3550, 50, 5000),
+                alarm: Threshold::new(3600, 100, 1000),
+                trip: Threshold::new(3650, 150, 500),
+            },
+            under_voltage: Limit {
+                warning: Threshold::new(2900, 100, 5000),
+                alarm: Threshold::new(2800, 200, 1000),
+                trip: Threshold::new(2500, 300, 500),
+            },
+            over_temperature: Limit {
+                warning: Threshold::temperature(celsius(50), 3, 5000),
+                alarm: Threshold::temperature(celsius(55), 5, 2000),
+                trip: Threshold::temperature(celsius(60), 10, 1000),
+            },
+            under_temperature: Limit {
+                warning: Threshold::temperature(celsius(5), 3, 5000),
+                alarm: Threshold::temperature(celsius(0), 3, 2000),
+                trip: Threshold::temperature(celsius(-20), 5, 1000),
+            },
+            charge_over_current: Limit {
+                warning: Threshold::new(50_000, 5000, 5000),
+                alarm: Threshold::new(60_000, 10_000, 2000),
+                trip: Threshold::new(80_000, 20_000, 200),
+            },
+            discharge_over_current: Limit {
+                warning: Threshold::new(100_000, 10_000, 5000),
+                alarm: Threshold::new(120_000, 20_000, 2000),
+                trip: Threshold::new(150_000, 30_000, 200),
+            },
+        }
+    }
+}
+
+/// Measurements of the pack.
+///
+/// `None` marks a measurement that is missing or invalid.
+#[derive(Clone, Copy, Default)]
+pub struct Measurements {
+    /// Highest cell voltage in millivolts.
+    pub max_cell_mv: Option<u16>,
+    /// Lowest cell voltage in millivolts.
+    pub min_cell_mv: Option<u16>,
+    /// Highest temperature.
+    pub max_temperature: Option<Temperature>,
+    /// Lowest temperature.
+    pub min_temperature: Option<Temperature>,
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+}
+
+impl Measurements {
+    /// Takes the extremes of the readings of `cells`.
+    ///
+    /// A reading missing for any cell makes the extreme missing, as that cell
+    /// could be the one beyond the limit. Without cells all are missing.
+    #[must_use]
+    pub fn from_cells(cells: &[Cell], current_ma: Option<i32>) -> Self {
+        let (min_cell_mv, max_cell_mv) = range(cells.iter().map(|cell| cell.voltage_mv));
+        let temperatures = cells
+            .iter()
+            .map(|cell| cell.temperature.map(Temperature::raw));
+        let (min_temperature, max_temperature) = range(temperatures);
+        Self {
+            max_cell_mv,
+            min_cell_mv,
+            max_temperature: max_temperature.and_then(Temperature::from_raw),
+            min_temperature: min_temperature.and_then(Temperature::from_raw),
+            current_ma,
+        }
+    }
+
+    /// Returns the value checked against the limit of a quantity.
+    fn value(&self, quantity: Quantity) -> Option<i32> {
+        match quantity {
+            Quantity::OverVoltage => self.max_cell_mv.map(i32::from),
+            Quantity::UnderVoltage => self.min_cell_mv.map(i32::from),
+            Quantity::OverTemperature => self.max_temperature.map(|t| i32::from(t.raw())),
+            Quantity::UnderTemperature => self.min_temperature.map(|t| i32::from(t.raw())),
+            Quantity::ChargeOverCurrent => self.current_ma,
+            Quantity::DischargeOverCurrent => self.current_ma.map(i32::saturating_neg),
+        }
+    }
+}
+
+/// Returns the lowest and the highest value, both `None` if any is missing.
+fn range<T: Copy + Ord>(values: impl Iterator<Item = Option<T>>) -> (Option<T>, Option<T>) {
+    let mut range = None;
+    for value in values {
+        let Some(value) = value else {
+            return (None, None);
+        };
+        range = Some(match range {
+            Some((min, max)) => (value.min(min), value.max(max)),
+            None => (value, value),
+        });
+    }
+    range.unzip()
+}
+
+/// Action required to protect the pack.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Decision {
+    /// The contactors have to be opened.
+    pub open_contactors: bool,
+    /// Charging is not allowed.
+    pub inhibit_charge: bool,
+    /// Discharging is not allowed.
+    pub inhibit_discharge: bool,
+    /// At least one warning is active.
+    pub warning: bool,
+}
+
+impl Decision {
+    /// Decision without any restriction.
+    pub const NONE: Self = Self {
+        open_contactors: false,
+        inhibit_charge: false,
+        inhibit_discharge: false,
+        warning: false,
+    };
+}
+
+/// State of a single level of a quantity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct LevelState {
+    active: bool,
+    /// The value is past the clear point.
+    clear: bool,
+    timer_ms: u32,
+}
+
+/// Protection state machine.
+#[derive(Clone, Debug)]
+pub struct Protection {
+    config: Config,
+    states: [[LevelState; LEVELS]; QUANTITIES],
+}
+
+impl Protection {
+    /// Creates the state machine with no level active.
+    #[must_use]
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config,
+            states: [[LevelState {
+                active: false,
+                clear: true,
+                timer_ms: 0,
+            }; LEVELS]; QUANTITIES],
+        }
+    }
+
+    /// Returns the limits.
+    #[must_use]
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Replaces the limits.
+    ///
+    /// Active levels and latched trips are kept and evaluated against the new
+    /// limits by the next update.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Evaluates the measurements after `dt_ms` and returns the decision.
+    pub fn update(&mut self, dt_ms: u32, measurements: &Measurements) -> Decision {
+        for (q, quantity) in Quantity::ALL.into_iter().enumerate() {
+            let value = measurements.value(quantity);
+            let limit = *self.config.limit(quantity);
+            for (l, level) in Level::ALL.into_iter().enumerate() {
+                let threshold = limit.threshold(level);
+                let (exceeded, clear) = match value {
+                    None => (true, false),
+                    Some(v) if quantity.is_lower_limit() => (
+                        v <= threshold.value,
+                        v > threshold.value.saturating_add(threshold.hysteresis),
+                    ),
+                    Some(v) => (
+                        v >= threshold.value,
+                        v < threshold.value.saturating_sub(threshold.hysteresis),
+                    ),
+                };
+
+                let state = &mut self.states[q][l];
+                state.clear = clear;
+                if exceeded {
+                    state.timer_ms = state.timer_ms.saturating_add(dt_ms);
+                    if state.timer_ms >= threshold.delay_ms {
+                        state.active = true;
+                    }
+                } else {
+                    state.timer_ms = 0;
+                    if clear && level != Level::Trip {
+                        state.active = false;
+                    }
+                }
+            }
+        }
+        self.decision()
+    }
+
+    /// Clears latched trips whose condition is gone.
+    ///
+    /// Returns true if no trip is latched anymore.
+    pub fn reset(&mut self) -> bool {
+        let trip = Level::Trip as usize;
+        let mut latched = false;
+        for states in &mut self.states {
+            let state = &mut states[trip];
+            if state.clear {
+                state.active = false;
+            }
+            latched |= state.active;
+        }
+        !latched
+    }
+
+    /// Returns the highest active level of a quantity.
+    #[must_use]
+    pub fn level(&self, quantity: Quantity) -> Option<Level> {
+        let states = &self.states[quantity as usize];
+        Level::ALL
+            .into_iter()
+            .rev()
+            .find(|&level| states[level as usize].active)
+    }
+
+    /// Returns true if any trip is latched.
+    #[must_use]
+    pub fn is_tripped(&self) -> bool {
+        self.states
+            .iter()
+            .any(|states| states[Level::Trip as usize].active)
+    }
+
+    /// Returns the decision for the current state.
+    #[must_use]
+    pub fn decision(&self) -> Decision {
+        let mut decision = Decision::NONE;
+        for quantity in Quantity::ALL {
+            match self.level(quantity) {
+                None | Some(Level::Warning) => {}
+                Some(Level::Alarm) => {
+                    let (charge, discharge) = match quantity {
+                        Quantity::OverVoltage | Quantity::ChargeOverCurrent => (true, false),
+                        Quantity::UnderVoltage | Quantity::DischargeOverCurrent => (false, true),
+                        // Charging below freezing plates lithium, discharging
+                        // is still fine.
+                        Quantity::UnderTemperature => (true, false),
+                        Quantity::OverTemperature => (true, true),
+                    };
+                    decision.inhibit_charge |= charge;
+                    decision.inhibit_discharge |= discharge;
+                }
+                Some(Level::Trip) => {
+                    decision.open_contactors = true;
+                    decision.inhibit_charge = true;
+                    decision.inhibit_discharge = true;
+                }
+            }
+            // Warnings are reported even beneath a higher level.
+            decision.warning |= self.states[quantity as usize][Level::Warning as usize].active;
+        }
+        decision
+    }
+}
@@ -0,0 +1,451 @@
+//! State of charge estimation.
+//!
+//! The [`Estimator`] integrates the pack current over time and corrects the
+//! result with the open circuit voltage (OCV) of the cells whenever the pack
+//! has rested long enough for the cell voltages to settle. It starts from the
+//! OCV of the first cell voltage it sees, since the contactors are open at
+//! power-up.
+//!
+//! The counted charge refers to the nominal capacity. At other temperatures
+//! the usable capacity differs, which is applied to the reported value: the
+//! charge already removed from a full pack is subtracted from the usable
+//! capacity at the current temperature.
+//!
+//! All math is done in integers. The state of charge is expressed in 0.01 %,
+//! so [`FULL`] is 100 %.
+
+use p3t1755::Temperature;
+
+/// State of charge of a full pack in 0.01 %.
+pub const FULL: u16 = 10_000;
+
+/// Milliampere-milliseconds per milliampere-second.
+const MAMS_PER_MAS: i64 = 1000;
+/// Temperature the nominal values refer to.
+const REFERENCE_TEMPERATURE: Temperature = Temperature::from_degrees_celsius(25);
+
+/// Point of an open circuit voltage curve.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OcvPoint {
+    /// Open circuit voltage of a cell in millivolts.
+    pub mv: u16,
+    /// State of charge at that voltage in 0.01 %.
+    pub soc: u16,
+}
+
+impl OcvPoint {
+    /// Creates a point from a voltage and a state of charge in percent.
+    #[must_use]
+    pub const fn new(mv: u16, percent: u8) -> Self {
+        Self {
+            mv,
+            soc: percent as u16 * 100,
+        }
+    }
+}
+
+/// Open circuit voltage over state of charge, sorted by ascending voltage
+/// and state of charge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OcvCurve<'a>(pub &'a [OcvPoint]);
+
+impl OcvCurve<'_> {
+    /// Curve of LiFePO4 cells at 25 °C.
+    pub const LFP: OcvCurve<'static> = OcvCurve(&[
+        OcvPoint::new(2500, 0),
+        OcvPoint::new(3000, 3),
+        OcvPoint::new(3150, 7),
+        OcvPoint::new(3200, 10),
+        OcvPoint::new(3250, 20),
+        OcvPoint::new(3280, 30),
+        OcvPoint::new(3300, 40),
+        OcvPoint::new(3310, 50),
+        OcvPoint::new(3320, 60),
+        OcvPoint::new(3330, 70),
+        OcvPoint::new(3340, 80),
+        OcvPoint::new(3360, 90),
+        OcvPoint::new(3400, 95),
+        OcvPoint::new(3450, 98),
+        OcvPoint::new(3600, 100),
+    ]);
+    /// Curve of NMC cells at 25 °C.
+    pub const NMC: OcvCurve<'static> = OcvCurve(&[
+        OcvPoint::new(3000, 0),
+        OcvPoint::new(3450, 5),
+        OcvPoint::new(3550, 10),
+        OcvPoint::new(3650, 20),
+        OcvPoint::new(3700, 30),
+        OcvPoint::new(3750, 40),
+        OcvPoint::new(3800, 50),
+        OcvPoint::new(3870, 60),
+        OcvPoint::new(3950, 70),
+        OcvPoint::new(4030, 80),
+        OcvPoint::new(4100, 90),
+        OcvPoint::new(4200, 100),
+    ]);
+
+    /// Returns the state of charge at an open circuit voltage.
+    ///
+    /// Voltages outside the curve are clamped to its ends. Returns `None` for
+    /// an empty or unsorted curve.
+    #[must_use]
+    pub fn soc(&self, mv: u16) -> Option<u16> {
+        let (low, high) = self.segment(mv)?;
+        if mv <= low.mv {
+            return Some(low.soc);
+        }
+        if mv >= high.mv {
+            return Some(high.soc);
+        }
+        let span = u32::from(high.soc - low.soc);
+        let offset = u32::from(mv - low.mv) * span / u32::from(high.mv - low.mv);
+        Some(low.soc + offset as u16)
+    }
+
+    /// Returns the slope of the curve at a voltage in millivolts per 10 %.
+    ///
+    /// Returns `None` outside the curve or for an unsorted curve.
+    #[must_use]
+    pub fn slope(&self, mv: u16) -> Option<u16> {
+        let (low, high) = self.segment(mv)?;
+        if mv < low.mv || mv > high.mv || high.soc == low.soc {
+            return None;
+        }
+        let slope = u32::from(high.mv - low.mv) * 1000 / u32::from(high.soc - low.soc);
+        Some(slope.min(u32::from(u16::MAX)) as u16)
+    }
+
+    /// Returns true if the points are sorted by ascending voltage and state
+    /// of charge, which the interpolation relies on.
+    #[must_use]
+    pub fn is_sorted(&self) -> bool {
+        self.0
+            .windows(2)
+            .all(|pair| pair[0].mv <= pair[1].mv && pair[0].soc <= pair[1].soc)
+    }
+
+    /// Returns the points enclosing a voltage, or the end points for voltages
+    /// outside the curve.
+    fn segment(&self, mv: u16) -> Option<(OcvPoint, OcvPoint)> {
+        if !self.is_sorted() {
+            return None;
+        }
+        let points = self.0;
+        let first = *points.first()?;
+        let last = *points.last()?;
+        if mv < first.mv {
+            return Some((first, first));
+        }
+        let segment = points
+            .windows(2)
+            .find(|pair| mv <= pair[1].mv)
+            .map_or((last, last), |pair| (pair[0], pair[1]));
+        Some(segment)
+    }
+}
+
+/// Usable capacity at a temperature.
+#[derive(Clone, Copy)]
+pub struct CapacityPoint {
+    /// Cell temperature.
+    pub temperature: Temperature,
+    /// Usable capacity relative to the nominal capacity in 0.01 %.
+    pub capacity: u16,
+}
+
+impl CapacityPoint {
+    /// Creates a point from a temperature and a capacity in percent.
+    #[must_use]
+    pub const fn new(deg_c: i8, percent: u8) -> Self {
+        Self {
+            temperature: Temperature::from_degrees_celsius(deg_c),
+            capacity: percent as u16 * 100,
+        }
+    }
+}
+
+/// Estimator settings.
+#[derive(Clone, Copy)]
+pub struct Config<'a> {
+    /// Nominal capacity at 25 °C in milliampere-hours.
+    pub capacity_mah: u32,
+    /// Open circuit voltage curve of a cell at 25 °C.
+    pub ocv: OcvCurve<'a>,
+    /// Change of the open circuit voltage in microvolts per °C above 25 °C.
+    pub ocv_temperature_uv: i16,
+    /// Usable capacity over temperature, sorted by ascending temperature.
+    ///
+    /// Empty to always use the nominal capacity.
+    pub capacity: &'a [CapacityPoint],
+    /// Share of the charge current that is stored in 0.01 %.
+    pub charge_efficiency: u16,
+    /// Current below which the pack is considered resting in milliamperes.
+    pub rest_current_ma: u16,
+    /// Time of rest after which the open circuit voltage is used.
+    pub rest_ms: u32,
+    /// Minimum slope of the curve for a correction in millivolts per 10 %.
+    ///
+    /// On flat parts of the curve a small voltage error results in a large
+    /// error of the state of charge, so the counted value is kept there.
+    pub min_ocv_slope_mv: u16,
+}
+
+impl<'a> Config<'a> {
+    /// Capacity of LiFePO4 cells over temperature.
+    pub const LFP_CAPACITY: &'static [CapacityPoint] = &[
+        CapacityPoint::new(-20, 60),
+        CapacityPoint::new(-10, 75),
+        CapacityPoint::new(0, 85),
+        CapacityPoint::new(10, 95),
+        CapacityPoint::new(25, 100),
+        CapacityPoint::new(45, 100),
+    ];
+    /// Capacity of NMC cells over temperature.
+    pub const NMC_CAPACITY: &'static [CapacityPoint] = &[
+        CapacityPoint::new(-20, 70),
+        CapacityPoint::new(-10, 80),
+        CapacityPoint::new(0, 90),
+        CapacityPoint::new(10, 96),
+        CapacityPoint::new(25, 100),
+        CapacityPoint::new(45, 100),
+    ];
+
+    /// Settings for LiFePO4 cells.
+    #[must_use]
+    pub const fn lfp(capacity_mah: u32) -> Self {
+        Self {
+            capacity_mah,
+            ocv: OcvCurve::LFP,
+            ocv_temperature_uv: -300,
+            capacity: Self::LFP_CAPACITY,
+            charge_efficiency: 9990,
+            rest_current_ma: 500,
+            rest_ms: 1_800_000,
+            min_ocv_slope_mv: 20,
+        }
+    }
+
+    /// Settings for NMC cells.
+    #[must_use]
+    pub const fn nmc(capacity_mah: u32) -> Self {
+        Self {
+            capacity_mah,
+            ocv: OcvCurve::NMC,
+            ocv_temperature_uv: -100,
+            capacity: Self::NMC_CAPACITY,
+            charge_efficiency: 9980,
+            rest_current_ma: 500,
+            rest_ms: 1_800_000,
+            min_ocv_slope_mv: 20,
+        }
+    }
+
+    /// Returns the nominal capacity in milliampere-seconds.
+    const fn capacity_mas(&self) -> i64 {
+        self.capacity_mah as i64 * 3600
+    }
+
+    /// Returns the usable capacity at a temperature in 0.01 % of the nominal
+    /// capacity.
+    fn usable_capacity(&self, temperature: Temperature) -> u16 {
+        let t = i32::from(temperature.raw());
+        let raw = |point: &CapacityPoint| i32::from(point.temperature.raw());
+        let (Some(first), Some(last)) = (self.capacity.first(), self.capacity.last()) else {
+            return FULL;
+        };
+        if t <= raw(first) {
+            return first.capacity;
+        }
+        let Some(pair) = self.capacity.windows(2).find(|pair| t <= raw(&pair[1])) else {
+            return last.capacity;
+        };
+        let (low, high) = (&pair[0], &pair[1]);
+        let span = (raw(high) - raw(low)).max(1);
+        let capacity = i32::from(low.capacity)
+            + (t - raw(low)) * (i32::from(high.capacity) - i32::from(low.capacity)) / span;
+        capacity.clamp(1, i32::from(u16::MAX)) as u16
+    }
+
+    /// Returns the voltage a cell would have at 25 °C.
+    fn compensate(&self, mv: u16, temperature: Temperature) -> u16 {
+        let delta = i32::from(temperature.raw()) - i32::from(REFERENCE_TEMPERATURE.raw());
+        // Temperatures are in 1/16 °C.
+        let offset_mv = i32::from(self.ocv_temperature_uv) * delta / 16 / 1000;
+        (i32::from(mv) - offset_mv).clamp(0, i32::from(u16::MAX)) as u16
+    }
+}
+
+/// Inputs of the estimator.
+///
+/// `None` marks a measurement that is missing or invalid.
+#[derive(Clone, Copy, Default)]
+pub struct Measurements {
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+    /// Average cell voltage in millivolts.
+    pub cell_mv: Option<u16>,
+    /// Cell temperature.
+    pub temperature: Option<Temperature>,
+}
+
+/// Source of the last change of the estimate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    /// No estimate yet.
+    None,
+    /// Open circuit voltage.
+    Ocv,
+    /// Coulomb counting.
+    Counting,
+    /// Value set by [`Estimator::set`].
+    Restored,
+}
+
+/// State of charge estimator.
+#[derive(Clone)]
+pub struct Estimator<'a> {
+    config: Config<'a>,
+    /// Stored charge at the nominal capacity in milliampere-seconds.
+    charge_mas: i64,
+    /// Fraction of a milliampere-second not yet added to `charge_mas`.
+    remainder_mams: i32,
+    rest_ms: u32,
+    /// The open circuit voltage was already applied during this rest.
+    corrected: bool,
+    source: Source,
+    temperature: Temperature,
+}
+
+impl<'a> Estimator<'a> {
+    /// Creates an estimator without an estimate.
+    #[must_use]
+    pub const fn new(config: Config<'a>) -> Self {
+        Self {
+            config,
+            charge_mas: 0,
+            remainder_mams: 0,
+            rest_ms: 0,
+            corrected: false,
+            source: Source::None,
+            temperature: REFERENCE_TEMPERATURE,
+        }
+    }
+
+    /// Returns the settings.
+    #[must_use]
+    pub const fn config(&self) -> &Config<'a> {
+        &self.config
+    }
+
+    /// Sets the state of charge in 0.01 % at 25 °C, for example from a value
+    /// saved before a reset.
+    pub fn set(&mut self, soc: u16) {
+        let soc = i64::from(soc.min(FULL));
+        self.charge_mas = self.config.capacity_mas() * soc / i64::from(FULL);
+        self.remainder_mams = 0;
+        self.source = Source::Restored;
+    }
+
+    /// Advances the estimator by `dt_ms`.
+    ///
+    /// `measurements` are the values during the elapsed time. Without a
+    /// current the charge is kept, without a temperature 25 °C is assumed.
+    /// Returns the new state of charge.
+    pub fn update(&mut self, dt_ms: u32, measurements: &Measurements) -> Option<u16> {
+        self.temperature = measurements.temperature.unwrap_or(REFERENCE_TEMPERATURE);
+
+        if let Some(current_ma) = measurements.current_ma {
+            if self.source != Source::None {
+                self.count(dt_ms, current_ma);
+            }
+            if current_ma.unsigned_abs() <= u32::from(self.config.rest_current_ma) {
+                self.rest_ms = self.rest_ms.saturating_add(dt_ms);
+            } else {
+                self.rest_ms = 0;
+                self.corrected = false;
+            }
+        }
+
+        let rested = self.rest_ms >= self.config.rest_ms && !self.corrected;
+        if let Some(mv) = measurements.cell_mv
+            && (self.source == Source::None || rested)
+        {
+            self.correct(mv);
+        }
+        self.soc()
+    }
+
+    /// Integrates the current.
+    fn count(&mut self, dt_ms: u32, current_ma: i32) {
+        let mut mams = i64::from(current_ma) * i64::from(dt_ms);
+        if mams > 0 {
+            mams = mams * i64::from(self.config.charge_efficiency) / i64::from(FULL);
+        }
+        let total = mams + i64::from(self.remainder_mams);
+        let mas = total / MAMS_PER_MAS;
+        self.remainder_mams = (total % MAMS_PER_MAS) as i32;
+        let capacity = self.config.capacity_mas();
+        self.charge_mas = (self.charge_mas + mas).clamp(0, capacity);
+        if self.charge_mas == 0 || self.charge_mas == capacity {
+            self.remainder_mams = 0;
+        }
+        self.source = Source::Counting;
+    }
+
+    /// Replaces the counted charge with the one from the open circuit voltage
+    /// unless the curve is too flat there.
+    fn correct(&mut self, mv: u16) {
+        let ocv = self.config.compensate(mv, self.temperature);
+        let Some(soc) = self.config.ocv.soc(ocv) else {
+            return;
+        };
+        let flat = self
+            .config
+            .ocv
+            .slope(ocv)
+            .is_some_and(|slope| slope < self.config.min_ocv_slope_mv);
+        self.corrected = true;
+        if flat && self.source != Source::None {
+            return;
+        }
+        self.set(soc);
+        self.source = Source::Ocv;
+    }
+
+    /// Returns the state of charge in 0.01 % at the last temperature.
+    #[must_use]
+    pub fn soc(&self) -> Option<u16> {
+        if self.source == Source::None {
+            return None;
+        }
+        let capacity = self.config.capacity_mas();
+        let usable =
+            capacity * i64::from(self.config.usable_capacity(self.temperature)) / i64::from(FULL);
+        let removed = capacity - self.charge_mas;
+        let available = (usable - removed).max(0);
+        Some((available * i64::from(FULL) / usable.max(1)).min(i64::from(FULL)) as u16)
+    }
+
+    /// Returns the remaining charge at the last temperature in
+    /// milliampere-hours.
+    #[must_use]
+    pub fn remaining_mah(&self) -> Option<u32> {
+        let soc = self.soc()?;
+        let usable = u64::from(self.config.capacity_mah) * u64::from(self.usable_capacity())
+            / u64::from(FULL);
+        Some((usable * u64::from(soc) / u64::from(FULL)) as u32)
+    }
+
+    /// Returns the usable capacity at the last temperature in 0.01 % of the
+    /// nominal capacity.
+    #[must_use]
+    pub fn usable_capacity(&self) -> u16 {
+        self.config.usable_capacity(self.temperature)
+    }
+
+    /// Returns the source of the last change of the estimate.
+    #[must_use]
+    pub const fn source(&self) -> Source {
+        self.source
+    }
+}
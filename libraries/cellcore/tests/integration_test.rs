@@ -0,0 +1,3331 @@
+//! Integration tests for the cellcore logic.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use cellagent::addressing::{Addressing, RamStore};
+use cellagent::voltage::Millivolts;
+use cellagent::{balancing as agent_balancing, bus as agent};
+use cellcore::balancing::{self, Cell, Phase, Plan, Planner, Strategy};
+use cellcore::bus::{Bus, Master};
+use cellcore::config::{self, ConfigStore, PackConfig, SLOT_LEN};
+use cellcore::console::{
+    self, BalancingSetting, Command, Console, Field, PackSetting, ParseError, ResetCause, Setting,
+    Target,
+};
+use cellcore::contactor::{self, Contactor, Contactors, Fault, PinMap, State};
+use cellcore::ekf::{self, Ekf};
+use cellcore::enumeration::{self, Config, Error};
+use cellcore::events::{EventLog, Monitor};
+use cellcore::host::{self, Pack};
+use cellcore::limits::{self, Inputs, Limiter, Limits, Point, Table};
+use cellcore::polling::Poller;
+use cellcore::protection::{
+    self, Decision, Level, Limit, Measurements, Protection, Quantity, Threshold,
+};
+use cellcore::soc::{self, CapacityPoint, Estimator, FULL, OcvCurve, OcvPoint, Source};
+use cellguard_config::mem::MemNvm;
+use cellguard_config::{Origin, Schema};
+use cellguard_log::mem::MemFlash;
+use cellguard_protocol::{
+    Address, Balancing, CellReading, CrashReport, DecodeError, ErrorCode, Event, EventKind, Frame,
+    Header, MAX_FRAME_LEN, NodeInfo, NodeKind, Request, Response, SettingValue, Status,
+    StatusFlags, Version,
+};
+use embedded_hal::i2c::{self, I2c, Operation};
+use p3t1755::Temperature;
+use tca9535::{PinIndex, Tca9535};
+
+/// Cellagent on the simulated daisy chain.
+#[derive(Default)]
+struct SimAgent {
+    addressing: Option<Addressing>,
+    store: RamStore,
+    readings: SimReadings,
+    /// The agent doesn't receive anything and its enable output stays low.
+    dead: bool,
+    /// The agent keeps its address on a reset, like an old firmware would.
+    ignores_reset: bool,
+    /// The agent enables the next one even without an address.
+    always_enables: bool,
+    /// Number of responses that are lost.
+    drop_responses: u8,
+    /// Number of responses that are corrupted.
+    corrupt_responses: u8,
+}
+
+/// Measurements and balancing of a simulated cellagent.
+#[derive(Default)]
+struct SimReadings {
+    voltage_mv: u16,
+    temperature: Option<Temperature>,
+    balancing: Option<agent_balancing::Controller>,
+}
+
+impl agent::Agent for SimReadings {
+    fn firmware(&self) -> Version {
+        Version::new(0, 1, 0)
+    }
+
+    fn voltage(&self) -> Option<Millivolts> {
+        Some(Millivolts(self.voltage_mv))
+    }
+
+    fn temperature(&self) -> Option<Temperature> {
+        self.temperature
+    }
+
+    fn balancing(&mut self) -> Option<&mut agent_balancing::Controller> {
+        self.balancing.as_mut()
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            flags: StatusFlags::NONE,
+            uptime_s: 0,
+        }
+    }
+}
+
+impl SimAgent {
+    fn with_address(address: u8) -> Self {
+        Self {
+            store: RamStore {
+                address: Some(Address(address)),
+                writes: 0,
+            },
+            ..Self::default()
+        }
+    }
+
+    fn addressing(&mut self) -> &mut Addressing {
+        self.addressing
+            .get_or_insert_with(|| Addressing::load(&mut self.store))
+    }
+
+    /// Returns the state of the enable output.
+    fn enables_next(&mut self) -> bool {
+        !self.dead && (self.always_enables || self.addressing().enables_next())
+    }
+
+    /// Processes a request with the firmware bus code.
+    fn process(&mut self, frame: &Frame<'_>, enabled: bool, responses: &mut Vec<Vec<u8>>) {
+        if self.dead || self.ignores_reset && Request::decode(frame) == Ok(Request::ResetAddresses)
+        {
+            return;
+        }
+        let mut addressing = *self.addressing();
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = agent::handle(
+            frame,
+            &mut addressing,
+            enabled,
+            &mut self.store,
+            &mut self.readings,
+            &mut buf,
+        );
+        self.addressing = Some(addressing);
+        let Some(len) = len else {
+            return;
+        };
+        if self.drop_responses > 0 {
+            self.drop_responses -= 1;
+            return;
+        }
+        if self.corrupt_responses > 0 {
+            self.corrupt_responses -= 1;
+            buf[len - 1] ^= 0xFF;
+        }
+        responses.push(buf[..len].to_vec());
+    }
+}
+
+/// Cellagents sharing the cell bus, with the enable lines along the daisy
+/// chain.
+#[derive(Default)]
+struct SimChain {
+    agents: Vec<SimAgent>,
+    /// Number of requests sent.
+    requests: usize,
+}
+
+impl SimChain {
+    fn new(count: usize) -> Self {
+        Self {
+            agents: (0..count).map(|_| SimAgent::default()).collect(),
+            requests: 0,
+        }
+    }
+
+    fn addresses(&mut self) -> Vec<Address> {
+        self.agents
+            .iter_mut()
+            .map(|agent| agent.addressing().address())
+            .collect()
+    }
+}
+
+impl Bus for SimChain {
+    type Error = Infallible;
+
+    fn exchange(
+        &mut self,
+        frame: &[u8],
+        receive: &mut dyn FnMut(Result<Frame<'_>, DecodeError>),
+    ) -> Result<(), Self::Error> {
+        let (request, _) = Frame::decode(frame).unwrap();
+        self.requests += 1;
+        let mut responses = Vec::new();
+        // The first agent is always enabled. Every agent sees the enable
+        // output of the previous one as it was before the request.
+        let mut enabled = true;
+        for agent in &mut self.agents {
+            let enables_next = agent.enables_next();
+            agent.process(&request, enabled, &mut responses);
+            enabled = enables_next;
+        }
+        for response in &responses {
+            receive(Frame::decode(response).map(|(frame, _)| frame));
+        }
+        Ok(())
+    }
+}
+
+fn agents(count: u8) -> Vec<Address> {
+    (0..count).map(|i| Address::agent(i).unwrap()).collect()
+}
+
+#[test]
+fn test_enumerate_chain() {
+    let mut master = Master::new(SimChain::new(5));
+    assert_eq!(enumeration::enumerate(&mut master, Config::new(5)), Ok(5));
+
+    let chain = master.bus_mut();
+    assert_eq!(chain.addresses(), agents(5));
+    for (agent, address) in chain.agents.iter().zip(agents(5)) {
+        assert_eq!(agent.store.address, Some(address));
+        assert_eq!(agent.store.writes, 1);
+    }
+}
+
+#[test]
+fn test_enumerate_replaces_stale_addresses() {
+    let mut chain = SimChain::default();
+    chain.agents.push(SimAgent::with_address(0x03));
+    chain.agents.push(SimAgent::with_address(0x01));
+    chain.agents.push(SimAgent::with_address(0x03));
+    let mut master = Master::new(chain);
+    assert_eq!(enumeration::enumerate(&mut master, Config::new(3)), Ok(3));
+    assert_eq!(master.bus_mut().addresses(), agents(3));
+}
+
+#[test]
+fn test_enumerate_keeps_unchanged_address() {
+    let mut master = Master::new(SimChain::new(2));
+    enumeration::enumerate(&mut master, Config::new(2)).unwrap();
+    enumeration::enumerate(&mut master, Config::new(2)).unwrap();
+    let chain = master.bus_mut();
+    assert!(chain.agents.iter().all(|agent| agent.store.writes == 1));
+}
+
+#[test]
+fn test_enumerate_missing_node() {
+    let mut chain = SimChain::new(6);
+    chain.agents[4].dead = true;
+    let mut master = Master::new(chain);
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(6)),
+        Err(Error::Missing { found: 4 })
+    );
+    let addresses = master.bus_mut().addresses();
+    assert_eq!(addresses[..4], agents(4));
+    assert_eq!(addresses[5], Address::UNASSIGNED);
+}
+
+#[test]
+fn test_enumerate_unexpected_node() {
+    let mut master = Master::new(SimChain::new(4));
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(3)),
+        Err(Error::Unexpected { found: 4 })
+    );
+}
+
+#[test]
+fn test_enumerate_empty_chain() {
+    let mut master = Master::new(SimChain::new(0));
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(1)),
+        Err(Error::Missing { found: 0 })
+    );
+    // The reset, then an assignment and a ping for each of the three
+    // attempts.
+    assert_eq!(master.bus_mut().requests, 7);
+    assert_eq!(enumeration::enumerate(&mut master, Config::new(0)), Ok(0));
+}
+
+#[test]
+fn test_enumerate_duplicate_on_assignment() {
+    // Both agents take the assignment because the first one enables the
+    // second while unassigned.
+    let mut chain = SimChain::new(3);
+    chain.agents[0].always_enables = true;
+    let mut master = Master::new(chain);
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(3)),
+        Err(Error::Duplicate(Address(0x01)))
+    );
+}
+
+#[test]
+fn test_enumerate_duplicate_stale_address() {
+    let mut chain = SimChain::new(2);
+    chain.agents.push(SimAgent {
+        ignores_reset: true,
+        ..SimAgent::with_address(0x01)
+    });
+    let mut master = Master::new(chain);
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(3)),
+        Err(Error::Duplicate(Address(0x01)))
+    );
+}
+
+#[test]
+fn test_enumerate_lost_response() {
+    let mut chain = SimChain::new(3);
+    chain.agents[1].drop_responses = 1;
+    let mut master = Master::new(chain);
+    assert_eq!(enumeration::enumerate(&mut master, Config::new(3)), Ok(3));
+    assert_eq!(master.bus_mut().addresses(), agents(3));
+}
+
+#[test]
+fn test_enumerate_corrupted_response() {
+    let mut chain = SimChain::new(2);
+    chain.agents[0].corrupt_responses = 1;
+    let mut master = Master::new(chain);
+    assert_eq!(enumeration::enumerate(&mut master, Config::new(2)), Ok(2));
+
+    let mut chain = SimChain::new(2);
+    chain.agents[0].corrupt_responses = 10;
+    let mut master = Master::new(chain);
+    assert_eq!(
+        enumeration::enumerate(&mut master, Config::new(2)),
+        Err(Error::Invalid(Address(0x01)))
+    );
+}
+
+#[test]
+fn test_enumerated_chain_answers() {
+    let mut chain = SimChain::new(3);
+    for (agent, mv) in chain.agents.iter_mut().zip([3300, 3310, 3320]) {
+        agent.readings.voltage_mv = mv;
+    }
+    let mut master = Master::new(chain);
+    enumeration::enumerate(&mut master, Config::new(3)).unwrap();
+    for (address, mv) in agents(3).into_iter().zip([3300, 3310, 3320]) {
+        let replies = master.request(address, &Request::ReadVoltage).unwrap();
+        assert_eq!(replies.single(), Some((address, Response::Voltage(mv))));
+    }
+}
+
+#[test]
+fn test_poller_visits_round_robin() {
+    let mut chain = SimChain::new(3);
+    for (agent, mv) in chain.agents.iter_mut().zip([3300, 3310, 3320]) {
+        agent.readings.voltage_mv = mv;
+        agent.readings.balancing = Some(agent_balancing::Controller::new(
+            agent_balancing::Config::default(),
+        ));
+    }
+    chain.agents[2].readings.temperature = Some(Temperature::from_degrees_celsius(25));
+    let mut master = Master::new(chain);
+    let mut poller = Poller::new(Config::new(3));
+    let mut cells = [Cell::default(); 3];
+    let requests = [Balancing::OFF, Balancing::on(10), Balancing::OFF];
+    assert!(!poller.is_enumerated());
+    assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    assert!(poller.is_enumerated());
+    assert_eq!(master.bus_mut().addresses(), agents(3));
+    assert!(cells.iter().all(|cell| cell.voltage_mv.is_none()));
+
+    // Three requests per visit.
+    let sent = master.bus_mut().requests;
+    for _ in 0..3 {
+        assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    }
+    assert_eq!(master.bus_mut().requests, sent + 9);
+    let voltages: Vec<_> = cells.iter().map(|cell| cell.voltage_mv).collect();
+    assert_eq!(voltages, [Some(3300), Some(3310), Some(3320)]);
+    let temperatures: Vec<_> = cells
+        .iter()
+        .map(|cell| cell.temperature.map(Temperature::raw))
+        .collect();
+    assert_eq!(temperatures, [None, None, Some(25 * 16)]);
+
+    let bleeding: Vec<_> = master
+        .bus_mut()
+        .agents
+        .iter_mut()
+        .map(|agent| {
+            let controller = agent.readings.balancing.as_mut().unwrap();
+            controller.update(
+                100,
+                Some(Temperature::from_degrees_celsius(25)),
+                Millivolts(3400),
+            )
+        })
+        .collect();
+    assert_eq!(bleeding, [false, true, false]);
+}
+
+#[test]
+fn test_poller_only_checks_agents_without_cells() {
+    let mut master = Master::new(SimChain::new(3));
+    let mut poller = Poller::new(Config::new(3));
+    let mut cells = [Cell::default(); 2];
+    let requests = [Balancing::OFF; 2];
+    for _ in 0..4 {
+        assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    }
+    // The enumeration, two visits and a ping.
+    assert_eq!(master.bus_mut().requests, 13 + 2 * 3 + 1);
+}
+
+#[test]
+fn test_poller_reenumerates_lost_agent() {
+    let mut chain = SimChain::new(3);
+    for agent in &mut chain.agents {
+        agent.readings.voltage_mv = 3300;
+    }
+    let mut master = Master::new(chain);
+    let mut poller = Poller::new(Config::new(3));
+    let mut cells = [Cell::default(); 3];
+    let requests = [Balancing::OFF; 3];
+    for _ in 0..4 {
+        poller.poll(&mut master, &mut cells, &requests).unwrap();
+    }
+    assert!(cells.iter().all(|cell| cell.voltage_mv == Some(3300)));
+
+    master.bus_mut().agents[1].dead = true;
+    assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    assert_eq!(
+        poller.poll(&mut master, &mut cells, &requests),
+        Err(Error::Lost(Address(0x02)))
+    );
+    assert!(!poller.is_enumerated());
+    assert_eq!(cells[1].voltage_mv, None);
+    assert_eq!(
+        poller.poll(&mut master, &mut cells, &requests),
+        Err(Error::Missing { found: 1 })
+    );
+    assert_eq!(cells[0].voltage_mv, Some(3300));
+    assert_eq!(cells[2].voltage_mv, None);
+
+    master.bus_mut().agents[1].dead = false;
+    assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    assert!(poller.is_enumerated());
+    assert_eq!(master.bus_mut().addresses(), agents(3));
+}
+
+#[test]
+fn test_poller_reenumerates_duplicate() {
+    let mut master = Master::new(SimChain::new(2));
+    let mut poller = Poller::new(Config::new(2));
+    let mut cells = [Cell::default(); 2];
+    let requests = [Balancing::OFF; 2];
+    poller.poll(&mut master, &mut cells, &requests).unwrap();
+
+    // The second agent comes back with the address of the first one.
+    let agent = &mut master.bus_mut().agents[1];
+    agent.store.address = Some(Address(0x01));
+    agent.addressing = None;
+    assert_eq!(
+        poller.poll(&mut master, &mut cells, &requests),
+        Err(Error::Duplicate(Address(0x01)))
+    );
+    assert_eq!(poller.poll(&mut master, &mut cells, &requests), Ok(()));
+    assert_eq!(master.bus_mut().addresses(), agents(2));
+}
+
+#[test]
+fn test_poller_config_change() {
+    let mut master = Master::new(SimChain::new(2));
+    let mut poller = Poller::new(Config::new(2));
+    let mut cells = [Cell::default(); 3];
+    let requests = [Balancing::OFF; 3];
+    poller.poll(&mut master, &mut cells, &requests).unwrap();
+    poller.set_config(Config::new(3));
+    assert!(!poller.is_enumerated());
+    assert_eq!(
+        poller.poll(&mut master, &mut cells, &requests),
+        Err(Error::Missing { found: 2 })
+    );
+}
+
+#[test]
+fn test_master_sequence_and_filter() {
+    struct Echo(Vec<u8>);
+
+    impl Bus for Echo {
+        type Error = Infallible;
+
+        fn exchange(
+            &mut self,
+            frame: &[u8],
+            receive: &mut dyn FnMut(Result<Frame<'_>, DecodeError>),
+        ) -> Result<(), Self::Error> {
+            let (request, _) = Frame::decode(frame).unwrap();
+            self.0.push(request.header.sequence);
+            // The echo of the request is not a reply.
+            receive(Ok(request));
+            receive(Err(DecodeError::Crc));
+            Ok(())
+        }
+    }
+
+    let mut master = Master::new(Echo(Vec::new()));
+    for _ in 0..3 {
+        let replies = master.request(Address(0x01), &Request::Ping).unwrap();
+        assert_eq!(replies.count, 0);
+        assert_eq!(replies.invalid, 1);
+        assert_eq!(replies.single(), None);
+    }
+    assert_eq!(master.release().0, [0, 1, 2]);
+}
+
+const WARM: Option<Temperature> = Some(Temperature::from_degrees_celsius(30));
+
+fn cells(voltages: &[u16]) -> Vec<Cell> {
+    voltages
+        .iter()
+        .map(|&mv| Cell {
+            voltage_mv: Some(mv),
+            temperature: WARM,
+        })
+        .collect()
+}
+
+fn continuous() -> balancing::Config {
+    balancing::Config {
+        strategy: Strategy::Continuous,
+        balance_ms: 0,
+        ..balancing::Config::default()
+    }
+}
+
+/// Runs one planning step and returns the plan and the indices of the cells
+/// to bleed.
+fn plan<const N: usize>(
+    planner: &mut Planner<N>,
+    dt_ms: u32,
+    cells: &[Cell],
+) -> (Plan, Vec<usize>) {
+    let mut requests = vec![Balancing::OFF; cells.len()];
+    let plan = planner.update(dt_ms, cells, &mut requests);
+    let duration_s = planner.config().duration_s;
+    let bleeding = requests
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| {
+            assert!(!r.enable || r.duration_s == duration_s);
+            r.enable
+        })
+        .map(|(i, _)| i)
+        .collect();
+    (plan, bleeding)
+}
+
+#[test]
+fn test_planner_threshold_and_hysteresis() {
+    let mut planner = Planner::<4>::new(continuous());
+    // 15 mV above the lowest cell is not enough.
+    let (p, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3315, 3310, 3305]));
+    assert_eq!(p.phase, Phase::Idle);
+    assert!(bleeding.is_empty());
+
+    let (p, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3316, 3320, 3305]));
+    assert_eq!(
+        p,
+        Plan {
+            phase: Phase::Balancing,
+            bleeding: 2
+        }
+    );
+    assert_eq!(bleeding, [1, 2]);
+
+    // Bleeding cells continue down to 10 mV above the lowest cell.
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3311, 3316, 3305]));
+    assert_eq!(bleeding, [1, 2]);
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3310, 3312, 3305]));
+    assert_eq!(bleeding, [2]);
+    // A cell that stopped needs the full threshold again.
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3314, 3312, 3305]));
+    assert_eq!(bleeding, [2]);
+}
+
+#[test]
+fn test_planner_top_balancing() {
+    let config = balancing::Config {
+        strategy: Strategy::Top { start_mv: 3400 },
+        balance_ms: 0,
+        ..balancing::Config::default()
+    };
+    let mut planner = Planner::<3>::new(config);
+    let (p, _) = plan(&mut planner, 1000, &cells(&[3300, 3390, 3320]));
+    assert_eq!(p.phase, Phase::Idle);
+
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3400, 3320]));
+    assert_eq!(bleeding, [1, 2]);
+    // Continues within the hysteresis of the start voltage.
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3396, 3320]));
+    assert_eq!(bleeding, [1, 2]);
+    let (p, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3394, 3320]));
+    assert_eq!(p.phase, Phase::Idle);
+    assert!(bleeding.is_empty());
+}
+
+#[test]
+fn test_planner_min_voltage_guard() {
+    let mut planner = Planner::<3>::new(continuous());
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[2900, 3000, 3100]));
+    assert_eq!(bleeding, [2]);
+}
+
+#[test]
+fn test_planner_limits_bleeding_cells() {
+    let config = balancing::Config {
+        max_bleeding: 2,
+        ..continuous()
+    };
+    let mut planner = Planner::<6>::new(config);
+    let (p, bleeding) = plan(
+        &mut planner,
+        1000,
+        &cells(&[3300, 3350, 3380, 3340, 3380, 3360]),
+    );
+    assert_eq!(p.bleeding, 2);
+    // The highest cells are chosen, ties by position.
+    assert_eq!(bleeding, [2, 4]);
+    assert_eq!(
+        planner.bleeding(),
+        &[false, false, true, false, true, false]
+    );
+}
+
+#[test]
+fn test_planner_config_change() {
+    let mut planner = Planner::<6>::new(continuous());
+    let voltages = cells(&[3300, 3350, 3380, 3340, 3380, 3360]);
+    let (p, _) = plan(&mut planner, 1000, &voltages);
+    assert_eq!(p.bleeding, 4);
+    planner.set_config(balancing::Config {
+        max_bleeding: 1,
+        ..continuous()
+    });
+    let (_, bleeding) = plan(&mut planner, 1000, &voltages);
+    assert_eq!(bleeding, [2]);
+}
+
+#[test]
+fn test_planner_skips_hot_cells() {
+    let mut planner = Planner::<3>::new(continuous());
+    let mut readings = cells(&[3300, 3350, 3350]);
+    readings[1].temperature = Some(Temperature::from_degrees_celsius(50));
+    readings[2].temperature = None;
+    let (p, bleeding) = plan(&mut planner, 1000, &readings);
+    assert_eq!(p.phase, Phase::Idle);
+    assert!(bleeding.is_empty());
+}
+
+#[test]
+fn test_planner_inhibited_without_voltage() {
+    let mut planner = Planner::<3>::new(continuous());
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3350, 3350]));
+    assert_eq!(bleeding, [1, 2]);
+
+    let mut readings = cells(&[3300, 3350, 3350]);
+    readings[0].voltage_mv = None;
+    let (p, bleeding) = plan(&mut planner, 1000, &readings);
+    assert_eq!(
+        p,
+        Plan {
+            phase: Phase::Inhibited,
+            bleeding: 0
+        }
+    );
+    assert!(bleeding.is_empty());
+}
+
+#[test]
+fn test_planner_rest_period() {
+    let config = balancing::Config {
+        balance_ms: 3000,
+        rest_ms: 2000,
+        ..continuous()
+    };
+    let mut planner = Planner::<2>::new(config);
+    let readings = cells(&[3300, 3350]);
+    // Three steps of 1 s balancing are followed by two steps of rest.
+    let phases: Vec<_> = (0..10)
+        .map(|_| plan(&mut planner, 1000, &readings).0.phase)
+        .collect();
+    assert_eq!(
+        phases,
+        [
+            Phase::Balancing,
+            Phase::Balancing,
+            Phase::Balancing,
+            Phase::Resting,
+            Phase::Resting,
+            Phase::Balancing,
+            Phase::Balancing,
+            Phase::Balancing,
+            Phase::Resting,
+            Phase::Resting,
+        ]
+    );
+}
+
+#[test]
+fn test_planner_ignores_cells_beyond_capacity() {
+    let mut planner = Planner::<2>::new(continuous());
+    let (_, bleeding) = plan(&mut planner, 1000, &cells(&[3300, 3350, 3400]));
+    assert_eq!(bleeding, [1]);
+}
+
+/// Measurements well within the default limits.
+fn nominal() -> Measurements {
+    Measurements {
+        max_cell_mv: Some(3350),
+        min_cell_mv: Some(3300),
+        max_temperature: Some(Temperature::from_degrees_celsius(30)),
+        min_temperature: Some(Temperature::from_degrees_celsius(20)),
+        current_ma: Some(0),
+    }
+}
+
+/// Limits with a single over-voltage configuration used by the transition
+/// tests.
+fn over_voltage_config() -> protection::Config {
+    protection::Config {
+        over_voltage: Limit {
+            warning: Threshold::new(3550, 50, 1000),
+            alarm: Threshold::new(3600, 100, 500),
+            trip: Threshold::new(3650, 150, 200),
+        },
+        ..protection::Config::default()
+    }
+}
+
+fn with_max_cell(mv: u16) -> Measurements {
+    Measurements {
+        max_cell_mv: Some(mv),
+        ..nominal()
+    }
+}
+
+#[test]
+fn test_protection_nominal() {
+    let mut protection = Protection::new(protection::Config::default());
+    assert_eq!(protection.update(100, &nominal()), Decision::NONE);
+    for quantity in Quantity::ALL {
+        assert_eq!(protection.level(quantity), None);
+    }
+}
+
+#[test]
+fn test_protection_measurements_from_cells() {
+    let cell = |mv, celsius| Cell {
+        voltage_mv: Some(mv),
+        temperature: Some(Temperature::from_degrees_celsius(celsius)),
+    };
+    let mut cells = [cell(3300, 20), cell(3350, 30), cell(3320, 25)];
+    let measurements = Measurements::from_cells(&cells, Some(0));
+    assert_eq!(measurements.max_cell_mv, Some(3350));
+    assert_eq!(measurements.min_cell_mv, Some(3300));
+    assert_eq!(
+        measurements.max_temperature.map(Temperature::raw),
+        Some(Temperature::from_degrees_celsius(30).raw())
+    );
+    assert_eq!(
+        measurements.min_temperature.map(Temperature::raw),
+        Some(Temperature::from_degrees_celsius(20).raw())
+    );
+    assert_eq!(measurements.current_ma, Some(0));
+
+    // A single missing reading could hide the extreme.
+    cells[2].temperature = None;
+    let measurements = Measurements::from_cells(&cells, Some(0));
+    assert_eq!(measurements.max_cell_mv, Some(3350));
+    assert!(measurements.max_temperature.is_none());
+    assert!(measurements.min_temperature.is_none());
+
+    let measurements = Measurements::from_cells(&[], None);
+    assert_eq!(measurements.max_cell_mv, None);
+    assert_eq!(measurements.min_cell_mv, None);
+}
+
+#[test]
+fn test_protection_warning_delay_and_hysteresis() {
+    let mut protection = Protection::new(over_voltage_config());
+    for _ in 0..9 {
+        assert_eq!(protection.update(100, &with_max_cell(3560)), Decision::NONE);
+    }
+    let decision = protection.update(100, &with_max_cell(3560));
+    assert!(decision.warning);
+    assert!(!decision.inhibit_charge);
+    assert_eq!(
+        protection.level(Quantity::OverVoltage),
+        Some(Level::Warning)
+    );
+
+    // Within the hysteresis band the warning stays.
+    assert!(protection.update(100, &with_max_cell(3501)).warning);
+    // Past it the warning clears immediately.
+    assert_eq!(protection.update(100, &with_max_cell(3499)), Decision::NONE);
+}
+
+#[test]
+fn test_protection_delay_restarts() {
+    let mut protection = Protection::new(over_voltage_config());
+    for _ in 0..5 {
+        protection.update(100, &with_max_cell(3560));
+    }
+    // A single sample below the threshold restarts the delay.
+    protection.update(100, &with_max_cell(3540));
+    for _ in 0..9 {
+        assert!(!protection.update(100, &with_max_cell(3560)).warning);
+    }
+    assert!(protection.update(100, &with_max_cell(3560)).warning);
+}
+
+#[test]
+fn test_protection_alarm_decisions() {
+    let cases = [
+        (
+            Measurements {
+                max_cell_mv: Some(3620),
+                ..nominal()
+            },
+            Quantity::OverVoltage,
+            (true, false),
+        ),
+        (
+            Measurements {
+                min_cell_mv: Some(2750),
+                ..nominal()
+            },
+            Quantity::UnderVoltage,
+            (false, true),
+        ),
+        (
+            Measurements {
+                max_temperature: Some(Temperature::from_degrees_celsius(56)),
+                ..nominal()
+            },
+            Quantity::OverTemperature,
+            (true, true),
+        ),
+        (
+            Measurements {
+                min_temperature: Some(Temperature::from_degrees_celsius(-1)),
+                ..nominal()
+            },
+            Quantity::UnderTemperature,
+            (true, false),
+        ),
+        (
+            Measurements {
+                current_ma: Some(65_000),
+                ..nominal()
+            },
+            Quantity::ChargeOverCurrent,
+            (true, false),
+        ),
+        (
+            Measurements {
+                current_ma: Some(-125_000),
+                ..nominal()
+            },
+            Quantity::DischargeOverCurrent,
+            (false, true),
+        ),
+    ];
+    for (measurements, quantity, (charge, discharge)) in cases {
+        let mut protection = Protection::new(protection::Config::default());
+        let decision = protection.update(5000, &measurements);
+        assert_eq!(
+            protection.level(quantity),
+            Some(Level::Alarm),
+            "{quantity:?}"
+        );
+        assert_eq!(
+            decision,
+            Decision {
+                open_contactors: false,
+                inhibit_charge: charge,
+                inhibit_discharge: discharge,
+                warning: true,
+            },
+            "{quantity:?}"
+        );
+
+        // Alarms clear on their own.
+        assert_eq!(protection.update(100, &nominal()), Decision::NONE);
+    }
+}
+
+#[test]
+fn test_protection_trip_latches() {
+    let mut protection = Protection::new(over_voltage_config());
+    protection.update(100, &with_max_cell(3660));
+    assert!(!protection.is_tripped());
+    let decision = protection.update(100, &with_max_cell(3660));
+    assert!(protection.is_tripped());
+    assert_eq!(
+        decision,
+        Decision {
+            open_contactors: true,
+            inhibit_charge: true,
+            inhibit_discharge: true,
+            warning: false,
+        }
+    );
+    assert_eq!(protection.level(Quantity::OverVoltage), Some(Level::Trip));
+
+    // The trip stays after the voltage has recovered.
+    let decision = protection.update(100, &nominal());
+    assert!(decision.open_contactors);
+    assert_eq!(protection.level(Quantity::OverVoltage), Some(Level::Trip));
+}
+
+#[test]
+fn test_protection_reset_rules() {
+    let mut protection = Protection::new(over_voltage_config());
+    protection.update(1000, &with_max_cell(3660));
+    assert!(protection.is_tripped());
+
+    // Refused while the voltage is above the threshold or within the
+    // hysteresis.
+    assert!(!protection.reset());
+    protection.update(100, &with_max_cell(3600));
+    assert!(!protection.reset());
+    assert!(protection.update(100, &with_max_cell(3600)).open_contactors);
+
+    // Accepted once it is past the hysteresis. The alarm is still active.
+    let decision = protection.update(100, &with_max_cell(3490));
+    assert!(decision.open_contactors);
+    assert!(protection.reset());
+    assert!(!protection.is_tripped());
+    assert_eq!(protection.decision(), Decision::NONE);
+}
+
+#[test]
+fn test_protection_set_config_keeps_trip() {
+    let mut protection = Protection::new(over_voltage_config());
+    protection.update(1000, &with_max_cell(3660));
+    let mut config = over_voltage_config();
+    config.over_voltage.trip.value = 3700;
+    protection.set_config(config);
+    assert!(protection.is_tripped());
+    assert_eq!(protection.config().over_voltage.trip.value, 3700);
+
+    // The trip clears against the new limit.
+    protection.update(100, &with_max_cell(3540));
+    assert!(protection.reset());
+}
+
+#[test]
+fn test_protection_limit_validity() {
+    let config = protection::Config::default();
+    for quantity in Quantity::ALL {
+        assert!(config.limit(quantity).is_valid(quantity));
+    }
+
+    // Lower limits decrease towards the trip, upper limits increase.
+    let mut over_voltage = config.over_voltage;
+    over_voltage.alarm.value = over_voltage.trip.value;
+    assert!(over_voltage.is_valid(Quantity::OverVoltage));
+    over_voltage.alarm.value = 3700;
+    assert!(!over_voltage.is_valid(Quantity::OverVoltage));
+    let mut under_voltage = config.under_voltage;
+    under_voltage.trip.value = 2850;
+    assert!(!under_voltage.is_valid(Quantity::UnderVoltage));
+
+    // Values outside of the cell ratings and negative hysteresis.
+    let mut over_voltage = config.over_voltage;
+    over_voltage.trip.value = 5000;
+    assert!(!over_voltage.is_valid(Quantity::OverVoltage));
+    let mut over_temperature = config.over_temperature;
+    over_temperature.trip = Threshold::temperature(Temperature::from_degrees_celsius(90), 10, 1000);
+    assert!(!over_temperature.is_valid(Quantity::OverTemperature));
+    let mut charge_over_current = config.charge_over_current;
+    charge_over_current.warning.hysteresis = -1;
+    assert!(!charge_over_current.is_valid(Quantity::ChargeOverCurrent));
+}
+
+#[test]
+fn test_protection_missing_measurement_trips() {
+    let mut protection = Protection::new(protection::Config::default());
+    let missing = Measurements {
+        max_temperature: None,
+        ..nominal()
+    };
+    assert!(!protection.update(500, &missing).open_contactors);
+    assert!(protection.update(500, &missing).open_contactors);
+    assert_eq!(
+        protection.level(Quantity::OverTemperature),
+        Some(Level::Trip)
+    );
+    assert!(!protection.reset());
+
+    protection.update(100, &nominal());
+    assert!(protection.reset());
+}
+
+#[test]
+fn test_protection_under_voltage_hysteresis() {
+    let mut protection = Protection::new(protection::Config::default());
+    let low = Measurements {
+        min_cell_mv: Some(2790),
+        ..nominal()
+    };
+    assert!(protection.update(1000, &low).inhibit_discharge);
+    // The alarm needs 200 mV of recovery.
+    let recovering = Measurements {
+        min_cell_mv: Some(2950),
+        ..nominal()
+    };
+    assert!(protection.update(100, &recovering).inhibit_discharge);
+    let recovered = Measurements {
+        min_cell_mv: Some(3010),
+        ..nominal()
+    };
+    assert!(!protection.update(100, &recovered).inhibit_discharge);
+}
+
+const PINS: PinMap = PinMap {
+    precharge: PinIndex::P0,
+    main_positive: PinIndex::P1,
+    main_negative: PinIndex::P2,
+    positive_aux: PinIndex::P8,
+    negative_aux: PinIndex::P9,
+    aux_active_low: true,
+};
+const PACK_MV: u32 = 51_200;
+
+/// Faults of the simulated contactors, shared with the test.
+#[derive(Debug, Default)]
+struct Plant {
+    /// Contactor whose contacts never open.
+    welded: Option<PinIndex>,
+    /// Contactor whose contacts never close.
+    stuck_open: Option<PinIndex>,
+    /// Every transaction fails.
+    offline: bool,
+    /// Reads of the input registers fail, other transactions succeed.
+    input_fails: bool,
+}
+
+/// TCA9535 with the contactors connected to it.
+#[derive(Debug, Default)]
+struct SimExpander {
+    /// Output, polarity inversion and configuration registers.
+    regs: [u8; 8],
+    pointer: u8,
+    plant: Rc<RefCell<Plant>>,
+}
+
+impl SimExpander {
+    fn register_pair(&self, index: usize) -> u16 {
+        u16::from_le_bytes([self.regs[index], self.regs[index + 1]])
+    }
+
+    fn coil(&self, pin: PinIndex) -> bool {
+        let output = self.register_pair(2) & !self.register_pair(6);
+        output & pin.mask() != 0
+    }
+
+    /// Returns the input registers as the pins read them.
+    fn input(&self) -> u16 {
+        let plant = self.plant.borrow();
+        let mut levels = u16::MAX;
+        for (coil, aux) in [
+            (PINS.main_positive, PINS.positive_aux),
+            (PINS.main_negative, PINS.negative_aux),
+        ] {
+            let closed = if plant.welded == Some(coil) {
+                true
+            } else if plant.stuck_open == Some(coil) {
+                false
+            } else {
+                self.coil(coil)
+            };
+            // The auxiliary contact pulls the input low when closed.
+            if closed {
+                levels &= !aux.mask();
+            }
+        }
+        levels ^ self.register_pair(4)
+    }
+}
+
+impl i2c::ErrorType for SimExpander {
+    type Error = i2c::ErrorKind;
+}
+
+impl I2c for SimExpander {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        assert_eq!(address, tca9535::Address::Lll.get());
+        if self.plant.borrow().offline {
+            return Err(i2c::ErrorKind::NoAcknowledge(
+                i2c::NoAcknowledgeSource::Address,
+            ));
+        }
+        let mut first = true;
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    let mut data = &bytes[..];
+                    if first {
+                        let (&pointer, rest) = data.split_first().unwrap();
+                        if pointer < 2 && self.plant.borrow().input_fails {
+                            return Err(i2c::ErrorKind::Bus);
+                        }
+                        self.pointer = pointer;
+                        data = rest;
+                    }
+                    for &byte in data {
+                        // The input registers are read-only.
+                        if self.pointer >= 2 {
+                            self.regs[usize::from(self.pointer)] = byte;
+                        }
+                        self.pointer ^= 1;
+                    }
+                }
+                Operation::Read(buf) => {
+                    let input = self.input().to_le_bytes();
+                    for byte in buf.iter_mut() {
+                        let p = usize::from(self.pointer);
+                        *byte = if p < 2 { input[p] } else { self.regs[p] };
+                        self.pointer ^= 1;
+                    }
+                }
+            }
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the state machine and returns the shared plant faults.
+fn contactors() -> (Contactors<SimExpander>, Rc<RefCell<Plant>>) {
+    let sim = SimExpander::default();
+    let plant = Rc::clone(&sim.plant);
+    let expander = Tca9535::new(sim, tca9535::Address::Lll);
+    let contactors = Contactors::new(expander, PINS, contactor::Config::default()).unwrap();
+    (contactors, plant)
+}
+
+/// Runs the state machine in 10 ms steps with the bus voltage following the
+/// pack through the precharge resistor or the main contactor.
+fn run_contactors(
+    contactors: &mut Contactors<SimExpander>,
+    bus_mv: &mut u32,
+    ms: u32,
+    converges: bool,
+) -> State {
+    let mut state = contactors.state();
+    for _ in 0..ms / 10 {
+        state = contactors.update(10, PACK_MV, *bus_mv).unwrap();
+        let connected = contactors.is_energized(Contactor::MainNegative)
+            && (contactors.is_energized(Contactor::Precharge)
+                || contactors.is_energized(Contactor::MainPositive));
+        if connected && converges {
+            // Time constant of 100 ms
+            *bus_mv += (PACK_MV - *bus_mv) / 10;
+        }
+    }
+    state
+}
+
+/// Closes the contactors and returns the bus voltage.
+fn close_contactors(contactors: &mut Contactors<SimExpander>) -> u32 {
+    let mut bus_mv = 0;
+    contactors.close();
+    assert_eq!(
+        run_contactors(contactors, &mut bus_mv, 1000, true),
+        State::Closed
+    );
+    bus_mv
+}
+
+#[test]
+fn test_contactor_init() {
+    let (contactors, _) = contactors();
+    assert_eq!(contactors.state(), State::Open);
+    let sim = contactors.release().into_inner();
+    // Coils are released outputs, the auxiliary contacts inverted inputs.
+    assert_eq!(sim.register_pair(6), !0b111);
+    assert_eq!(sim.register_pair(2), 0);
+    assert_eq!(
+        sim.register_pair(4),
+        PINS.positive_aux.mask() | PINS.negative_aux.mask()
+    );
+}
+
+#[test]
+fn test_contactor_close_sequence() {
+    let (mut contactors, _) = contactors();
+    let mut bus_mv = 0;
+    contactors.close();
+    assert_eq!(contactors.state(), State::ClosingNegative);
+
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::ClosingNegative)
+    );
+    assert!(contactors.is_energized(Contactor::MainNegative));
+    assert!(!contactors.is_energized(Contactor::Precharge));
+
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::Precharging)
+    );
+    assert!(contactors.is_energized(Contactor::Precharge));
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+
+    while contactors.state() == State::Precharging {
+        run_contactors(&mut contactors, &mut bus_mv, 10, true);
+    }
+    assert_eq!(contactors.state(), State::ClosingPositive);
+    assert!(PACK_MV - bus_mv <= 2000);
+    assert!(contactors.is_energized(Contactor::Precharge));
+    assert!(contactors.is_energized(Contactor::MainPositive));
+
+    assert_eq!(contactors.update(10, PACK_MV, bus_mv), Ok(State::Closed));
+    assert!(!contactors.is_energized(Contactor::Precharge));
+    assert!(contactors.is_energized(Contactor::MainPositive));
+    assert!(contactors.is_energized(Contactor::MainNegative));
+
+    // Closing again has no effect.
+    contactors.close();
+    assert_eq!(
+        run_contactors(&mut contactors, &mut bus_mv, 1000, true),
+        State::Closed
+    );
+}
+
+#[test]
+fn test_contactor_open_sequence() {
+    let (mut contactors, _) = contactors();
+    let bus_mv = close_contactors(&mut contactors);
+
+    contactors.open();
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::OpeningPositive)
+    );
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+    assert!(contactors.is_energized(Contactor::MainNegative));
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::OpeningNegative)
+    );
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+    assert_eq!(contactors.update(10, PACK_MV, bus_mv), Ok(State::Open));
+}
+
+#[test]
+fn test_contactor_abort_close() {
+    let (mut contactors, _) = contactors();
+    let mut bus_mv = 0;
+    contactors.close();
+    assert_eq!(
+        run_contactors(&mut contactors, &mut bus_mv, 20, false),
+        State::Precharging
+    );
+    contactors.open();
+    assert_eq!(
+        run_contactors(&mut contactors, &mut bus_mv, 50, false),
+        State::Open
+    );
+    assert!(!contactors.is_energized(Contactor::Precharge));
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+}
+
+#[test]
+fn test_contactor_precharge_timeout() {
+    let (mut contactors, _) = contactors();
+    let mut bus_mv = 0;
+    contactors.close();
+    assert_eq!(
+        run_contactors(&mut contactors, &mut bus_mv, 3010, false),
+        State::Precharging
+    );
+    assert_eq!(
+        run_contactors(&mut contactors, &mut bus_mv, 10, false),
+        State::Fault(Fault::PrechargeTimeout)
+    );
+    assert!(!contactors.is_energized(Contactor::Precharge));
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+}
+
+#[test]
+fn test_contactor_failed_to_close() {
+    for (pin, contactor) in [
+        (PINS.main_negative, Contactor::MainNegative),
+        (PINS.main_positive, Contactor::MainPositive),
+    ] {
+        let (mut contactors, plant) = contactors();
+        plant.borrow_mut().stuck_open = Some(pin);
+        let mut bus_mv = 0;
+        contactors.close();
+        assert_eq!(
+            run_contactors(&mut contactors, &mut bus_mv, 1000, true),
+            State::Fault(Fault::FailedToClose(contactor))
+        );
+        assert!(!contactors.is_energized(Contactor::MainPositive));
+        assert!(!contactors.is_energized(Contactor::MainNegative));
+    }
+}
+
+#[test]
+fn test_contactor_welded_on_open() {
+    let (mut contactors, plant) = contactors();
+    let bus_mv = close_contactors(&mut contactors);
+    plant.borrow_mut().welded = Some(PINS.main_positive);
+
+    contactors.open();
+    assert_eq!(
+        contactors.update(90, PACK_MV, bus_mv),
+        Ok(State::OpeningPositive)
+    );
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::Fault(Fault::Welded(Contactor::MainPositive)))
+    );
+    // The negative side is released as well.
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+}
+
+#[test]
+fn test_contactor_welded_while_open() {
+    let (mut contactors, plant) = contactors();
+    plant.borrow_mut().welded = Some(PINS.main_negative);
+    assert_eq!(
+        contactors.update(10, PACK_MV, 0),
+        Ok(State::Fault(Fault::Welded(Contactor::MainNegative)))
+    );
+    contactors.close();
+    assert_eq!(
+        contactors.state(),
+        State::Fault(Fault::Welded(Contactor::MainNegative))
+    );
+}
+
+#[test]
+fn test_contactor_welded_while_closing() {
+    for (ms, state) in [(0, State::ClosingNegative), (20, State::Precharging)] {
+        let (mut contactors, plant) = contactors();
+        let mut bus_mv = 0;
+        contactors.close();
+        assert_eq!(
+            run_contactors(&mut contactors, &mut bus_mv, ms, false),
+            state
+        );
+        plant.borrow_mut().welded = Some(PINS.main_positive);
+        assert_eq!(
+            contactors.update(10, PACK_MV, bus_mv),
+            Ok(State::Fault(Fault::Welded(Contactor::MainPositive)))
+        );
+        assert!(!contactors.is_energized(Contactor::Precharge));
+        assert!(!contactors.is_energized(Contactor::MainNegative));
+    }
+}
+
+#[test]
+fn test_contactor_dropped() {
+    let (mut contactors, plant) = contactors();
+    let bus_mv = close_contactors(&mut contactors);
+    plant.borrow_mut().stuck_open = Some(PINS.main_negative);
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::Fault(Fault::Dropped(Contactor::MainNegative)))
+    );
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+}
+
+#[test]
+fn test_contactor_emergency_open() {
+    let (mut contactors, _) = contactors();
+    let bus_mv = close_contactors(&mut contactors);
+
+    contactors.emergency_open().unwrap();
+    assert_eq!(contactors.state(), State::Fault(Fault::Emergency));
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+
+    // Latched until reset.
+    contactors.close();
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::Fault(Fault::Emergency))
+    );
+}
+
+#[test]
+fn test_contactor_reset_rules() {
+    let (mut contactors, plant) = contactors();
+    assert_eq!(contactors.reset(), Ok(true));
+
+    plant.borrow_mut().welded = Some(PINS.main_positive);
+    contactors.update(10, PACK_MV, 0).unwrap();
+    // Refused while the auxiliary contact reports closed.
+    assert_eq!(contactors.reset(), Ok(false));
+    assert_eq!(
+        contactors.state(),
+        State::Fault(Fault::Welded(Contactor::MainPositive))
+    );
+
+    plant.borrow_mut().welded = None;
+    assert_eq!(contactors.reset(), Ok(true));
+    assert_eq!(contactors.state(), State::Open);
+    contactors.close();
+    assert_eq!(contactors.state(), State::ClosingNegative);
+}
+
+#[test]
+fn test_contactor_communication_fault() {
+    let (mut contactors, plant) = contactors();
+    let bus_mv = close_contactors(&mut contactors);
+    plant.borrow_mut().input_fails = true;
+    assert!(contactors.update(10, PACK_MV, bus_mv).is_err());
+    assert_eq!(contactors.state(), State::Fault(Fault::Communication));
+    // The coils are released by the failing update.
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+    assert!(!contactors.is_energized(Contactor::MainNegative));
+    assert!(contactors.reset().is_err());
+    plant.borrow_mut().input_fails = false;
+    assert_eq!(contactors.reset(), Ok(true));
+
+    let bus_mv = close_contactors(&mut contactors);
+    plant.borrow_mut().offline = true;
+    assert!(contactors.update(10, PACK_MV, bus_mv).is_err());
+    assert_eq!(contactors.state(), State::Fault(Fault::Communication));
+    assert!(contactors.reset().is_err());
+
+    plant.borrow_mut().offline = false;
+    // Without any response the coils are released on the next update.
+    assert_eq!(
+        contactors.update(10, PACK_MV, bus_mv),
+        Ok(State::Fault(Fault::Communication))
+    );
+    assert!(!contactors.is_energized(Contactor::MainPositive));
+    assert!(contactors.reset().is_ok());
+}
+
+#[test]
+fn test_contactor_fault_codes() {
+    let contactors = [
+        Contactor::Precharge,
+        Contactor::MainPositive,
+        Contactor::MainNegative,
+    ];
+    let faults = contactors
+        .into_iter()
+        .flat_map(|c| [Fault::FailedToClose(c), Fault::Welded(c), Fault::Dropped(c)])
+        .chain([
+            Fault::PrechargeTimeout,
+            Fault::Emergency,
+            Fault::Communication,
+        ]);
+    for fault in faults {
+        assert_eq!(Fault::from_code(fault.code()), Some(fault), "{fault}");
+    }
+    assert_eq!(Fault::Welded(Contactor::MainNegative).code(), 0x0202);
+    assert_eq!(Fault::from_code(0x0203), None);
+    assert_eq!(Fault::from_code(0x0401), None);
+    assert_eq!(Fault::from_code(0x0700), None);
+}
+
+/// Runs the monitor on the protection state and returns the events.
+fn protection_events(monitor: &mut Monitor, protection: &Protection) -> Vec<(EventKind, u16)> {
+    let mut events = Vec::new();
+    monitor.protection(protection, |kind, data| events.push((kind, data)));
+    events
+}
+
+#[test]
+fn test_events_protection() {
+    let mut monitor = Monitor::new();
+    let mut protection = Protection::new(over_voltage_config());
+    assert!(protection_events(&mut monitor, &protection).is_empty());
+
+    protection.update(1000, &with_max_cell(3560));
+    assert_eq!(
+        protection_events(&mut monitor, &protection),
+        [(EventKind::Warning, 0)]
+    );
+    // Nothing changed.
+    protection.update(100, &with_max_cell(3560));
+    assert!(protection_events(&mut monitor, &protection).is_empty());
+
+    // Alarm and trip at once only report the trip.
+    protection.update(1000, &with_max_cell(3660));
+    assert_eq!(
+        protection_events(&mut monitor, &protection),
+        [(EventKind::Trip, 0)]
+    );
+
+    // Falling back is only reported for trips.
+    protection.update(100, &with_max_cell(3450));
+    assert!(protection_events(&mut monitor, &protection).is_empty());
+    assert!(protection.reset());
+    assert_eq!(
+        protection_events(&mut monitor, &protection),
+        [(EventKind::TripsCleared, 0)]
+    );
+
+    // A missing current trips both current limits.
+    protection.update(
+        10_000,
+        &Measurements {
+            current_ma: None,
+            ..nominal()
+        },
+    );
+    assert_eq!(
+        protection_events(&mut monitor, &protection),
+        [(EventKind::Trip, 4), (EventKind::Trip, 5)]
+    );
+}
+
+#[test]
+fn test_events_contactors() {
+    let mut monitor = Monitor::new();
+    let mut events = Vec::new();
+    for state in [
+        State::Open,
+        State::ClosingNegative,
+        State::Precharging,
+        State::ClosingPositive,
+        State::Closed,
+        State::Closed,
+        State::OpeningPositive,
+        State::OpeningNegative,
+        State::Open,
+        State::ClosingNegative,
+        State::Fault(Fault::FailedToClose(Contactor::MainNegative)),
+        State::Open,
+    ] {
+        monitor.contactors(state, |kind, data| events.push((kind, data)));
+    }
+    assert_eq!(
+        events,
+        [
+            (EventKind::ContactorsClosed, 0),
+            (EventKind::ContactorsOpened, 0),
+            (EventKind::ContactorFault, 0x0102),
+        ]
+    );
+}
+
+#[test]
+fn test_event_log() {
+    let mut log = EventLog::mount(MemFlash::<2, 64>::new());
+    let mut monitor = Monitor::new();
+    let mut protection = Protection::new(over_voltage_config());
+    protection.update(1000, &with_max_cell(3610));
+    monitor.protection(&protection, |kind, data| {
+        log.append(12, kind, data).unwrap();
+    });
+
+    let log = EventLog::mount(log.release());
+    assert_eq!(
+        log.iter().collect::<Vec<_>>(),
+        [Event {
+            sequence: 0,
+            uptime_s: 12,
+            kind: EventKind::Alarm,
+            data: 0,
+        }]
+    );
+}
+
+/// Returns the open circuit voltage at a state of charge in 0.01 %.
+fn ocv_mv(curve: OcvCurve<'_>, soc: u16) -> u16 {
+    let pair = curve.0.windows(2).find(|pair| soc <= pair[1].soc).unwrap();
+    let (low, high) = (pair[0], pair[1]);
+    low.mv
+        + ((soc - low.soc) as u32 * u32::from(high.mv - low.mv) / u32::from(high.soc - low.soc))
+            as u16
+}
+
+fn at_rest(cell_mv: u16) -> soc::Measurements {
+    soc::Measurements {
+        current_ma: Some(0),
+        cell_mv: Some(cell_mv),
+        temperature: Some(Temperature::from_degrees_celsius(25)),
+    }
+}
+
+fn with_current(current_ma: i32) -> soc::Measurements {
+    soc::Measurements {
+        current_ma: Some(current_ma),
+        cell_mv: Some(3700),
+        temperature: Some(Temperature::from_degrees_celsius(25)),
+    }
+}
+
+/// Runs the estimator in one second steps.
+fn run_soc(estimator: &mut Estimator<'_>, s: u32, measurements: &soc::Measurements) -> u16 {
+    for _ in 0..s {
+        estimator.update(1000, measurements);
+    }
+    estimator.soc().unwrap()
+}
+
+#[test]
+fn test_ocv_curve() {
+    let curve = OcvCurve::NMC;
+    assert_eq!(curve.soc(2900), Some(0));
+    assert_eq!(curve.soc(3000), Some(0));
+    assert_eq!(curve.soc(3800), Some(5000));
+    assert_eq!(curve.soc(3835), Some(5500));
+    assert_eq!(curve.soc(4200), Some(FULL));
+    assert_eq!(curve.soc(4300), Some(FULL));
+    assert_eq!(OcvCurve(&[]).soc(3800), None);
+
+    assert_eq!(curve.slope(3775), Some(50));
+    assert_eq!(curve.slope(3000), Some(900));
+    assert_eq!(curve.slope(2900), None);
+    assert_eq!(curve.slope(4300), None);
+
+    for curve in [OcvCurve::LFP, OcvCurve::NMC] {
+        assert!(curve.is_sorted());
+        for pair in curve.0.windows(2) {
+            assert!(pair[0].mv < pair[1].mv && pair[0].soc < pair[1].soc);
+        }
+    }
+    assert_eq!(OcvPoint::new(3300, 40).soc, 4000);
+
+    // Unsorted points would underflow the interpolation.
+    let unsorted = OcvCurve(&[
+        OcvPoint::new(3000, 0),
+        OcvPoint::new(3700, 50),
+        OcvPoint::new(3600, 100),
+    ]);
+    assert!(!unsorted.is_sorted());
+    assert_eq!(unsorted.soc(3650), None);
+    assert_eq!(unsorted.slope(3650), None);
+    let unsorted = OcvCurve(&[OcvPoint::new(3000, 50), OcvPoint::new(3600, 0)]);
+    assert_eq!(unsorted.soc(3300), None);
+}
+
+#[test]
+fn test_soc_initialized_from_ocv() {
+    let mut estimator = Estimator::new(soc::Config::nmc(100_000));
+    assert_eq!(estimator.soc(), None);
+    assert_eq!(estimator.source(), Source::None);
+
+    // Without a cell voltage there is nothing to start from.
+    let no_voltage = soc::Measurements {
+        cell_mv: None,
+        ..with_current(-10_000)
+    };
+    assert_eq!(estimator.update(1000, &no_voltage), None);
+
+    assert_eq!(estimator.update(1000, &at_rest(3800)), Some(5000));
+    assert_eq!(estimator.source(), Source::Ocv);
+}
+
+#[test]
+fn test_soc_coulomb_counting() {
+    let mut estimator = Estimator::new(soc::Config::nmc(100_000));
+    estimator.set(5000);
+    assert_eq!(estimator.source(), Source::Restored);
+
+    // 50 A for half an hour removes a quarter of 100 Ah.
+    assert_eq!(run_soc(&mut estimator, 1800, &with_current(-50_000)), 2500);
+    assert_eq!(estimator.source(), Source::Counting);
+    assert_eq!(estimator.remaining_mah(), Some(25_000));
+    // Clamped when empty.
+    assert_eq!(run_soc(&mut estimator, 3600, &with_current(-50_000)), 0);
+
+    // Charging stores 99.8 % of the charge.
+    estimator.set(5000);
+    assert_eq!(run_soc(&mut estimator, 3600, &with_current(10_000)), 5998);
+    assert_eq!(run_soc(&mut estimator, 36_000, &with_current(10_000)), FULL);
+}
+
+#[test]
+fn test_soc_large_pack() {
+    // 1000 Ah exceed 32 bits in milliampere-seconds.
+    let mut estimator = Estimator::new(soc::Config::lfp(1_000_000));
+    estimator.set(5000);
+    assert_eq!(estimator.remaining_mah(), Some(500_000));
+    assert_eq!(run_soc(&mut estimator, 1800, &with_current(-500_000)), 2500);
+    assert_eq!(estimator.remaining_mah(), Some(250_000));
+    assert_eq!(run_soc(&mut estimator, 3600, &with_current(-500_000)), 0);
+}
+
+#[test]
+fn test_soc_counts_small_currents() {
+    let config = soc::Config {
+        capacity_mah: 1,
+        ..soc::Config::nmc(0)
+    };
+    let mut estimator = Estimator::new(config);
+    estimator.set(5000);
+    // 1 mA for 36 s in 10 ms steps is 1 % of 1 mAh.
+    for _ in 0..3600 {
+        estimator.update(10, &with_current(-1));
+    }
+    assert_eq!(estimator.soc(), Some(4900));
+}
+
+#[test]
+fn test_soc_missing_current_holds() {
+    let mut estimator = Estimator::new(soc::Config::nmc(100_000));
+    estimator.set(5000);
+    let missing = soc::Measurements {
+        current_ma: None,
+        ..at_rest(4200)
+    };
+    assert_eq!(run_soc(&mut estimator, 3600, &missing), 5000);
+}
+
+#[test]
+fn test_soc_rest_correction() {
+    let config = soc::Config::nmc(100_000);
+    let mut estimator = Estimator::new(config);
+    estimator.set(5000);
+
+    // 70 % after the cell voltages have settled.
+    assert_eq!(run_soc(&mut estimator, 1799, &at_rest(3950)), 5000);
+    assert_eq!(run_soc(&mut estimator, 1, &at_rest(3950)), 7000);
+    assert_eq!(estimator.source(), Source::Ocv);
+
+    // Only once per rest.
+    estimator.set(6000);
+    assert_eq!(run_soc(&mut estimator, 3600, &at_rest(3950)), 6000);
+
+    // Small currents don't end the rest, large ones do.
+    run_soc(&mut estimator, 10, &with_current(-1000));
+    assert!(run_soc(&mut estimator, 1799, &with_current(-200)) < 6000);
+    assert_eq!(run_soc(&mut estimator, 1, &at_rest(3950)), 7000);
+}
+
+#[test]
+fn test_soc_skips_flat_ocv() {
+    let mut estimator = Estimator::new(soc::Config::lfp(100_000));
+    estimator.set(8000);
+    // The plateau is too flat for a correction.
+    assert_eq!(run_soc(&mut estimator, 1800, &at_rest(3305)), 8000);
+    assert_eq!(estimator.source(), Source::Counting);
+
+    // The knee at 20 % is steep enough.
+    run_soc(&mut estimator, 1, &with_current(-10_000));
+    estimator.set(8000);
+    assert_eq!(run_soc(&mut estimator, 1800, &at_rest(3235)), 1700);
+
+    // A fresh estimator uses whatever it gets.
+    let mut estimator = Estimator::new(soc::Config::lfp(100_000));
+    assert_eq!(estimator.update(1000, &at_rest(3305)), Some(4500));
+}
+
+#[test]
+fn test_soc_temperature_capacity() {
+    let mut estimator = Estimator::new(soc::Config::lfp(100_000));
+    estimator.set(FULL);
+    let cold = soc::Measurements {
+        temperature: Some(Temperature::from_degrees_celsius(0)),
+        ..with_current(0)
+    };
+    assert_eq!(estimator.update(0, &cold), Some(FULL));
+    assert_eq!(estimator.usable_capacity(), 8500);
+    assert_eq!(estimator.remaining_mah(), Some(85_000));
+
+    // Removing 42.5 Ah leaves half of the usable capacity.
+    let discharge = soc::Measurements {
+        current_ma: Some(-42_500),
+        ..cold
+    };
+    assert_eq!(run_soc(&mut estimator, 3600, &discharge), 5000);
+    // The charge is still there once the pack warms up.
+    assert_eq!(estimator.update(0, &with_current(0)), Some(5750));
+
+    // Interpolated between the points, clamped outside.
+    let config = soc::Config::lfp(100_000);
+    let at = |deg_c| {
+        let mut estimator = Estimator::new(config);
+        estimator.set(FULL);
+        estimator.update(
+            0,
+            &soc::Measurements {
+                temperature: Some(Temperature::from_degrees_celsius(deg_c)),
+                ..with_current(0)
+            },
+        );
+        estimator.usable_capacity()
+    };
+    assert_eq!(at(-15), 6750);
+    assert_eq!(at(-40), 6000);
+    assert_eq!(at(60), FULL);
+}
+
+#[test]
+fn test_soc_capacity_table() {
+    let config = soc::Config {
+        capacity: &[CapacityPoint::new(0, 50), CapacityPoint::new(20, 100)],
+        ..soc::Config::nmc(100_000)
+    };
+    let mut estimator = Estimator::new(config);
+    estimator.set(FULL);
+    let measurements = soc::Measurements {
+        temperature: Some(Temperature::from_degrees_celsius(10)),
+        ..with_current(0)
+    };
+    estimator.update(0, &measurements);
+    assert_eq!(estimator.usable_capacity(), 7500);
+
+    let config = soc::Config {
+        capacity: &[],
+        ..soc::Config::nmc(100_000)
+    };
+    let mut estimator = Estimator::new(config);
+    estimator.set(FULL);
+    estimator.update(0, &measurements);
+    assert_eq!(estimator.usable_capacity(), FULL);
+}
+
+#[test]
+fn test_soc_ocv_temperature_compensation() {
+    let config = soc::Config {
+        ocv_temperature_uv: -2500,
+        ..soc::Config::nmc(100_000)
+    };
+    // 20 °C above the reference lower the voltage by 50 mV.
+    let mut estimator = Estimator::new(config);
+    let hot = soc::Measurements {
+        temperature: Some(Temperature::from_degrees_celsius(45)),
+        ..at_rest(3750)
+    };
+    assert_eq!(estimator.update(0, &hot), Some(5000));
+
+    // Without a temperature the voltage is used as it is.
+    let mut estimator = Estimator::new(config);
+    let unknown = soc::Measurements {
+        temperature: None,
+        ..at_rest(3750)
+    };
+    assert_eq!(estimator.update(0, &unknown), Some(4000));
+}
+
+/// Follows a synthetic drive profile with a cell model and checks the error of
+/// the estimate.
+#[test]
+fn test_soc_synthetic_profile() {
+    const CAPACITY_MAS: i64 = 100_000 * 3600;
+    const RESISTANCE_MOHM: i64 = 2;
+    // Power-up, discharge, rest, charge and rest in minutes with the pack current.
+    const PROFILE: [(u32, i32); 9] = [
+        (1, 0),
+        (30, -80_000),
+        (45, 0),
+        (20, -120_000),
+        (10, -5_000),
+        (60, 0),
+        (90, 40_000),
+        (45, 0),
+        (30, -60_000),
+    ];
+
+    // The estimator believes in 2 % more capacity than there is and the
+    // current sensor has an offset of 200 mA.
+    let mut estimator = Estimator::new(soc::Config::nmc(102_000));
+    let mut charge_mas = CAPACITY_MAS * 95 / 100;
+    let true_soc = |charge_mas: i64| (charge_mas * i64::from(FULL) / CAPACITY_MAS) as u16;
+
+    let mut max_error = 0;
+    for (minutes, current_ma) in PROFILE {
+        for _ in 0..minutes * 60 {
+            let stored = if current_ma > 0 {
+                i64::from(current_ma) * 998 / 1000
+            } else {
+                i64::from(current_ma)
+            };
+            charge_mas = (charge_mas + stored).clamp(0, CAPACITY_MAS);
+            let soc = true_soc(charge_mas);
+            let cell_mv =
+                ocv_mv(OcvCurve::NMC, soc) as i64 + i64::from(current_ma) * RESISTANCE_MOHM / 1000;
+            let measurements = soc::Measurements {
+                current_ma: Some(current_ma + 200),
+                cell_mv: Some(cell_mv as u16),
+                temperature: Some(Temperature::from_degrees_celsius(25)),
+            };
+            let estimate = estimator.update(1000, &measurements).unwrap();
+            max_error = max_error.max(estimate.abs_diff(soc));
+        }
+        // A correction at the end of every long rest removes the drift.
+        if current_ma == 0 && minutes >= 30 {
+            let error = estimator.soc().unwrap().abs_diff(true_soc(charge_mas));
+            assert!(error <= 10, "error of {error} after rest");
+        }
+    }
+    assert!(max_error <= 200, "maximum error of {max_error}");
+}
+
+/// Cell following the equivalent circuit with exact time constants.
+struct SimCell {
+    /// State of charge between 0 and 1.
+    soc: f64,
+    v1: f64,
+    r0: f64,
+    r1: f64,
+    tau_s: f64,
+    capacity_as: f64,
+}
+
+impl SimCell {
+    fn nmc(soc: f64, capacity_ah: f64, r0_uohm: f64) -> Self {
+        Self {
+            soc,
+            v1: 0.0,
+            r0: r0_uohm * 1e-6,
+            r1: 500e-6,
+            tau_s: 30.0,
+            capacity_as: capacity_ah * 3600.0,
+        }
+    }
+
+    /// Draws `current_a` for a second and returns the terminal voltage.
+    fn step(&mut self, current_a: f64) -> u16 {
+        let stored = if current_a > 0.0 {
+            current_a * 0.998
+        } else {
+            current_a
+        };
+        self.soc = (self.soc + stored / self.capacity_as).clamp(0.0, 1.0);
+        let a = (-1.0 / self.tau_s).exp();
+        self.v1 = a * self.v1 + (1.0 - a) * self.r1 * current_a;
+        let ocv = f64::from(ocv_mv(OcvCurve::NMC, (self.soc * 10_000.0) as u16)) / 1000.0;
+        ((ocv + self.v1 + self.r0 * current_a) * 1000.0).round() as u16
+    }
+
+    fn soc(&self) -> u16 {
+        (self.soc * 10_000.0).round() as u16
+    }
+}
+
+/// Floating point reference of the filter in the same units.
+struct FloatEkf {
+    config: ekf::Config<'static>,
+    x: [f64; 3],
+    p: [[f64; 3]; 3],
+}
+
+impl FloatEkf {
+    fn new(config: ekf::Config<'static>, soc: u16) -> Self {
+        let soc_std = f64::from(config.initial_soc_ppm);
+        let r0_std = f64::from(config.initial_r0_uohm);
+        Self {
+            config,
+            x: [f64::from(soc) * 100.0, 0.0, f64::from(config.r0_uohm)],
+            p: [
+                [soc_std * soc_std, 0.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, r0_std * r0_std],
+            ],
+        }
+    }
+
+    /// Returns the open circuit voltage in microvolts and its slope.
+    fn ocv(&self, soc_ppm: f64) -> (f64, f64) {
+        let points = self.config.ocv.0;
+        let pair = points
+            .windows(2)
+            .find(|pair| soc_ppm <= f64::from(pair[1].soc) * 100.0)
+            .unwrap_or(&points[points.len() - 2..]);
+        let (x0, x1) = (
+            f64::from(pair[0].soc) * 100.0,
+            f64::from(pair[1].soc) * 100.0,
+        );
+        let (y0, y1) = (
+            f64::from(pair[0].mv) * 1000.0,
+            f64::from(pair[1].mv) * 1000.0,
+        );
+        let slope = (y1 - y0) / (x1 - x0);
+        (y0 + (soc_ppm.clamp(0.0, 1e6) - x0) * slope, slope)
+    }
+
+    fn update(&mut self, dt_ms: u32, current_ma: i32, cell_mv: u16) {
+        let config = self.config;
+        let dt = f64::from(dt_ms);
+        let i = f64::from(current_ma);
+        let stored = if i > 0.0 {
+            i * f64::from(config.charge_efficiency) / 10_000.0
+        } else {
+            i
+        };
+        self.x[0] += stored * dt * 10.0 / (f64::from(config.capacity_mah) * 36.0);
+        self.x[0] = self.x[0].clamp(0.0, 1e6);
+        let tau = f64::from(config.tau_ms);
+        let a = tau / (tau + dt);
+        self.x[1] = a * self.x[1] + (1.0 - a) * f64::from(config.r1_uohm) * i / 1000.0;
+
+        let f = [1.0, a, 1.0];
+        let noise = [
+            config.process_soc_ppm,
+            config.process_v1_uv,
+            config.process_r0_uohm,
+        ];
+        for r in 0..3 {
+            for c in 0..3 {
+                self.p[r][c] *= f[r] * f[c];
+            }
+            self.p[r][r] += f64::from(noise[r]).powi(2) * dt / 1000.0;
+        }
+
+        let (ocv, slope) = self.ocv(self.x[0]);
+        let h = [slope, 1.0, i / 1000.0];
+        let innovation = f64::from(cell_mv) * 1000.0 - (ocv + self.x[1] + self.x[2] * i / 1000.0);
+        let ph: Vec<f64> = (0..3)
+            .map(|r| (0..3).map(|c| self.p[r][c] * h[c]).sum())
+            .collect();
+        let s =
+            (0..3).map(|r| h[r] * ph[r]).sum::<f64>() + f64::from(config.measurement_uv).powi(2);
+        for r in 0..3 {
+            self.x[r] += ph[r] * innovation / s;
+            for c in 0..3 {
+                self.p[r][c] -= ph[r] * ph[c] / s;
+            }
+        }
+        self.x[0] = self.x[0].clamp(0.0, 1e6);
+        self.x[2] = self.x[2].max(0.0);
+    }
+}
+
+/// Current in amperes of a drive cycle with pulses and rests at second `t`.
+fn pulses(t: u32) -> f64 {
+    match t % 600 {
+        0..120 => -60.0,
+        120..180 => 0.0,
+        180..240 => -120.0,
+        240..300 => 20.0,
+        300..420 => -30.0,
+        _ => 0.0,
+    }
+}
+
+#[test]
+fn test_ekf_starts_from_ocv() {
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    assert_eq!(filter.soc(), None);
+    let no_voltage = ekf::Measurements {
+        current_ma: Some(0),
+        cell_mv: None,
+    };
+    assert_eq!(filter.update(1000, &no_voltage), None);
+    let at_rest = ekf::Measurements {
+        current_ma: Some(0),
+        cell_mv: Some(3800),
+    };
+    assert_eq!(filter.update(1000, &at_rest), Some(5000));
+    assert_eq!(filter.soc_ppm(), Some(500_000));
+    assert_eq!(filter.soc_uncertainty_ppm(), 50_000);
+}
+
+#[test]
+fn test_ekf_matches_float_reference() {
+    // Without capacity updates both filters run the same equations.
+    let config = ekf::Config {
+        capacity_window: u16::MAX,
+        ..ekf::Config::nmc(100_000)
+    };
+    // The resistance is off by a quarter.
+    let mut cell = SimCell::nmc(0.6, 100.0, 1000.0);
+    let mut filter = Ekf::new(config);
+    let at_rest = ekf::Measurements {
+        current_ma: Some(0),
+        cell_mv: Some(cell.step(0.0)),
+    };
+    let soc = filter.update(0, &at_rest).unwrap();
+    let mut reference = FloatEkf::new(config, soc);
+
+    for t in 0..4 * 3600 {
+        let current_ma = (pulses(t) * 1000.0) as i32;
+        let cell_mv = cell.step(pulses(t));
+        filter.update(
+            1000,
+            &ekf::Measurements {
+                current_ma: Some(current_ma),
+                cell_mv: Some(cell_mv),
+            },
+        );
+        reference.update(1000, current_ma, cell_mv);
+
+        let soc_ppm = filter.soc_ppm().unwrap();
+        assert!(
+            (f64::from(soc_ppm) - reference.x[0]).abs() <= 200.0,
+            "{soc_ppm} ppm against {} ppm after {t} s",
+            reference.x[0]
+        );
+        assert!((f64::from(filter.v1_uv()) - reference.x[1]).abs() <= 200.0);
+        let r0 = f64::from(filter.health().r0_uohm);
+        assert!(
+            (r0 - reference.x[2]).abs() <= 10.0,
+            "{r0} µΩ against {} µΩ after {t} s",
+            reference.x[2]
+        );
+    }
+    // Both converged to the cell.
+    assert!(filter.soc().unwrap().abs_diff(cell.soc()) <= 100);
+}
+
+#[test]
+fn test_ekf_corrects_wrong_initial_soc() {
+    let mut cell = SimCell::nmc(0.5, 100.0, 800.0);
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    filter.set(8000);
+    for t in 0..3600 {
+        let cell_mv = cell.step(pulses(t));
+        filter.update(
+            1000,
+            &ekf::Measurements {
+                current_ma: Some((pulses(t) * 1000.0) as i32),
+                cell_mv: Some(cell_mv),
+            },
+        );
+    }
+    let error = filter.soc().unwrap().abs_diff(cell.soc());
+    assert!(error <= 100, "error of {error}");
+    assert!(filter.soc_uncertainty_ppm() < 10_000);
+}
+
+#[test]
+fn test_ekf_tracks_resistance() {
+    let mut cell = SimCell::nmc(0.9, 100.0, 1200.0);
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    filter.set(9000);
+    for t in 0..3 * 3600 {
+        let cell_mv = cell.step(pulses(t));
+        filter.update(
+            1000,
+            &ekf::Measurements {
+                current_ma: Some((pulses(t) * 1000.0) as i32),
+                cell_mv: Some(cell_mv),
+            },
+        );
+    }
+    let health = filter.health();
+    assert!(health.r0_uohm.abs_diff(1200) <= 60, "{health:?}");
+    assert!(health.resistance.abs_diff(15_000) <= 750, "{health:?}");
+    assert!(filter.soc().unwrap().abs_diff(cell.soc()) <= 100);
+}
+
+#[test]
+fn test_ekf_estimates_capacity() {
+    // The cells have lost 10 % of their capacity.
+    let mut cell = SimCell::nmc(0.95, 90.0, 800.0);
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    filter.update(
+        0,
+        &ekf::Measurements {
+            current_ma: Some(0),
+            cell_mv: Some(cell.step(0.0)),
+        },
+    );
+    // Cycle between 10 % and 95 % with the drive cycle and a constant charge.
+    let mut charging = false;
+    for t in 0..40 * 3600 {
+        if cell.soc < 0.1 {
+            charging = true;
+        } else if cell.soc > 0.95 {
+            charging = false;
+        }
+        let current_a = if charging { 30.0 } else { pulses(t) };
+        let cell_mv = cell.step(current_a);
+        filter.update(
+            1000,
+            &ekf::Measurements {
+                current_ma: Some((current_a * 1000.0) as i32),
+                cell_mv: Some(cell_mv),
+            },
+        );
+    }
+    let health = filter.health();
+    assert!(health.capacity_mah.abs_diff(90_000) <= 2000, "{health:?}");
+    assert!(health.capacity.abs_diff(9000) <= 200, "{health:?}");
+    assert!(filter.soc().unwrap().abs_diff(cell.soc()) <= 150);
+}
+
+#[test]
+fn test_ekf_missing_measurements() {
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    filter.set(5000);
+    // Without a current nothing changes.
+    let no_current = ekf::Measurements {
+        current_ma: None,
+        cell_mv: Some(3000),
+    };
+    assert_eq!(filter.update(3_600_000, &no_current), Some(5000));
+
+    // Without a voltage the filter counts and gets less certain.
+    let uncertainty = filter.soc_uncertainty_ppm();
+    let no_voltage = ekf::Measurements {
+        current_ma: Some(-10_000),
+        cell_mv: None,
+    };
+    for _ in 0..3600 {
+        filter.update(1000, &no_voltage);
+    }
+    assert_eq!(filter.soc(), Some(4000));
+    assert!(filter.soc_uncertainty_ppm() > uncertainty);
+}
+
+#[test]
+fn test_ekf_restored_health() {
+    let mut filter = Ekf::new(ekf::Config::nmc(100_000));
+    filter.set_health(80_000, 1600);
+    assert_eq!(
+        filter.health(),
+        ekf::Health {
+            capacity_mah: 80_000,
+            capacity: 8000,
+            r0_uohm: 1600,
+            resistance: 20_000,
+        }
+    );
+    // The counted charge refers to the estimated capacity.
+    filter.set(5000);
+    let discharge = ekf::Measurements {
+        current_ma: Some(-8000),
+        cell_mv: None,
+    };
+    for _ in 0..3600 {
+        filter.update(1000, &discharge);
+    }
+    assert_eq!(filter.soc(), Some(4000));
+}
+
+fn nominal_inputs() -> Inputs {
+    Inputs {
+        max_cell_mv: Some(3310),
+        min_cell_mv: Some(3290),
+        soc: Some(5000),
+        max_temperature: Some(Temperature::from_degrees_celsius(30)),
+        min_temperature: Some(Temperature::from_degrees_celsius(20)),
+    }
+}
+
+/// Runs the limiter long enough to settle and returns the current limits.
+fn settled(limiter: &mut Limiter<'_>, inputs: &Inputs) -> (u32, u32) {
+    let limits = limiter.update(60_000, inputs, &Decision::NONE);
+    (limits.charge_ma, limits.discharge_ma)
+}
+
+#[test]
+fn test_limits_table() {
+    let table = Table(&[Point::new(10, 100), Point::new(20, 50), Point::new(30, 0)]);
+    assert_eq!(table.factor(i32::MIN), 10_000);
+    assert_eq!(table.factor(10), 10_000);
+    assert_eq!(table.factor(15), 7500);
+    assert_eq!(table.factor(20), 5000);
+    assert_eq!(table.factor(29), 500);
+    assert_eq!(table.factor(i32::MAX), 0);
+    assert_eq!(Table::NONE.factor(0), 10_000);
+    assert_eq!(Point::temperature(10, 50).input, 160);
+}
+
+#[test]
+fn test_limits_nominal() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    assert_eq!(
+        limiter.update(60_000, &nominal_inputs(), &Decision::NONE),
+        Limits {
+            charge_ma: 50_000,
+            discharge_ma: 100_000,
+            charge_voltage_mv: 55_200,
+            discharge_voltage_mv: 46_400,
+        }
+    );
+}
+
+#[test]
+fn test_limits_derating() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    let high_cell = Inputs {
+        max_cell_mv: Some(3425),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &high_cell), (37_500, 100_000));
+
+    let nearly_full = Inputs {
+        soc: Some(9500),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &nearly_full), (30_000, 100_000));
+
+    let low_cell = Inputs {
+        min_cell_mv: Some(2950),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &low_cell), (50_000, 10_000));
+
+    let nearly_empty = Inputs {
+        soc: Some(1000),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &nearly_empty), (50_000, 50_000));
+
+    let hot = Inputs {
+        max_temperature: Some(Temperature::from_degrees_celsius(48)),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &hot), (20_000, 40_000));
+
+    // Cold blocks charging before discharging.
+    let cold = Inputs {
+        min_temperature: Some(Temperature::from_degrees_celsius(5)),
+        ..nominal_inputs()
+    };
+    assert_eq!(settled(&mut limiter, &cold), (0, 50_000));
+}
+
+#[test]
+fn test_limits_lowest_factor_applies() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    let inputs = Inputs {
+        max_cell_mv: Some(3450),
+        soc: Some(9750),
+        max_temperature: Some(Temperature::from_degrees_celsius(46)),
+        ..nominal_inputs()
+    };
+    // 50 % from the cell voltage, 40 % from the state of charge and 80 % from
+    // the temperature.
+    assert_eq!(settled(&mut limiter, &inputs).0, 20_000);
+}
+
+#[test]
+fn test_limits_missing_inputs() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    settled(&mut limiter, &nominal_inputs());
+    for inputs in [
+        Inputs {
+            max_cell_mv: None,
+            ..nominal_inputs()
+        },
+        Inputs {
+            soc: None,
+            ..nominal_inputs()
+        },
+        Inputs {
+            min_temperature: None,
+            ..nominal_inputs()
+        },
+    ] {
+        let limits = limiter.update(100, &inputs, &Decision::NONE);
+        assert_eq!((limits.charge_ma, limits.discharge_ma), (0, 0));
+    }
+}
+
+#[test]
+fn test_limits_protection_decision() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    settled(&mut limiter, &nominal_inputs());
+    let decision = Decision {
+        inhibit_charge: true,
+        ..Decision::NONE
+    };
+    let limits = limiter.update(100, &nominal_inputs(), &decision);
+    assert_eq!((limits.charge_ma, limits.discharge_ma), (0, 100_000));
+    let decision = Decision {
+        inhibit_discharge: true,
+        ..Decision::NONE
+    };
+    let limits = limiter.update(100, &nominal_inputs(), &decision);
+    assert_eq!(limits.discharge_ma, 0);
+}
+
+#[test]
+fn test_limits_ramp() {
+    let mut limiter = Limiter::new(limits::Config::default());
+    assert_eq!(limiter.limits().charge_ma, 0);
+    // 5 A per second.
+    let limits = limiter.update(1000, &nominal_inputs(), &Decision::NONE);
+    assert_eq!((limits.charge_ma, limits.discharge_ma), (5000, 5000));
+    let limits = limiter.update(500, &nominal_inputs(), &Decision::NONE);
+    assert_eq!((limits.charge_ma, limits.discharge_ma), (7500, 7500));
+    assert_eq!(settled(&mut limiter, &nominal_inputs()), (50_000, 100_000));
+
+    // Reductions apply at once.
+    let low_cell = Inputs {
+        min_cell_mv: Some(2950),
+        ..nominal_inputs()
+    };
+    let limits = limiter.update(100, &low_cell, &Decision::NONE);
+    assert_eq!(limits.discharge_ma, 10_000);
+    let limits = limiter.update(1000, &nominal_inputs(), &Decision::NONE);
+    assert_eq!(limits.discharge_ma, 15_000);
+    assert_eq!(limiter.limits(), limits);
+}
+
+#[test]
+fn test_limits_slow_ramp() {
+    let config = limits::Config {
+        ramp_ma_per_s: 100,
+        ..limits::Config::default()
+    };
+    let mut limiter = Limiter::new(config);
+    // Each step is a tenth of a milliampere, which adds up over a second.
+    for _ in 0..1000 {
+        limiter.update(1, &nominal_inputs(), &Decision::NONE);
+    }
+    assert_eq!(limiter.limits().charge_ma, 100);
+    assert_eq!(limiter.limits().discharge_ma, 100);
+}
+
+/// I2C bus with devices whose registers are selected by a pointer byte.
+#[derive(Default)]
+struct SimBus {
+    /// Register contents by device address and pointer.
+    devices: HashMap<u8, HashMap<u8, Vec<u8>>>,
+}
+
+impl SimBus {
+    fn add(&mut self, address: u8, registers: &[(u8, &[u8])]) {
+        let registers = registers
+            .iter()
+            .map(|&(pointer, bytes)| (pointer, bytes.to_vec()))
+            .collect();
+        self.devices.insert(address, registers);
+    }
+
+    fn register(&self, address: u8, pointer: u8) -> Vec<u8> {
+        self.devices[&address][&pointer].clone()
+    }
+}
+
+impl i2c::ErrorType for SimBus {
+    type Error = i2c::ErrorKind;
+}
+
+impl I2c for SimBus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let registers = self
+            .devices
+            .get_mut(&address)
+            .ok_or(i2c::ErrorKind::NoAcknowledge(
+                i2c::NoAcknowledgeSource::Address,
+            ))?;
+        let mut pointer = 0;
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    if let Some((&first, data)) = bytes.split_first() {
+                        pointer = first;
+                        if !data.is_empty() {
+                            registers.insert(pointer, data.to_vec());
+                        }
+                    }
+                }
+                Operation::Read(buf) => {
+                    let value = registers.get(&pointer).cloned().unwrap_or_default();
+                    for (i, byte) in buf.iter_mut().enumerate() {
+                        *byte = value.get(i).copied().unwrap_or(0);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Firmware state on the bench.
+struct Bench {
+    bus: SimBus,
+    cells: Vec<Cell>,
+    protection: Protection,
+    balancing_config: balancing::Config,
+    pack_config: PackConfig,
+    /// Settings reported as changed.
+    changes: Vec<Setting>,
+    reset_cause: ResetCause,
+    crash: Option<CrashReport>,
+    bleeding: Vec<bool>,
+    events: Vec<Event>,
+    lost_events: u16,
+}
+
+impl Bench {
+    fn new() -> Self {
+        let mut bus = SimBus::default();
+        // 25 °C, default configuration, 75 °C and 80 °C.
+        bus.add(
+            0x48,
+            &[
+                (0, &[0x19, 0x00]),
+                (1, &[0x28]),
+                (2, &[0x4B, 0x00]),
+                (3, &[0x50, 0x00]),
+            ],
+        );
+        bus.add(0x20, &[(0, &[0xFF]), (6, &[0xFF])]);
+        Self {
+            bus,
+            cells: vec![
+                Cell {
+                    voltage_mv: Some(3310),
+                    temperature: Some(Temperature::from_degrees_celsius(25)),
+                },
+                Cell {
+                    voltage_mv: None,
+                    temperature: None,
+                },
+                Cell {
+                    voltage_mv: Some(3295),
+                    temperature: Some(Temperature::from_centi_degrees_celsius(-250)),
+                },
+            ],
+            protection: Protection::new(over_voltage_config()),
+            balancing_config: balancing::Config::default(),
+            pack_config: PackConfig::default(),
+            changes: Vec::new(),
+            reset_cause: ResetCause::POWER_ON,
+            crash: None,
+            bleeding: vec![false, false, true],
+            events: vec![
+                Event {
+                    sequence: 41,
+                    uptime_s: 0,
+                    kind: EventKind::Reset,
+                    data: ResetCause::POWER_ON.0.into(),
+                },
+                Event {
+                    sequence: 42,
+                    uptime_s: 125,
+                    kind: EventKind::Warning,
+                    data: 0,
+                },
+            ],
+            lost_events: 0,
+        }
+    }
+
+    /// Sends a host request and returns the response.
+    fn request(&mut self, destination: Address, request: Request) -> Option<Response> {
+        let header = Header::new(destination, Address::HOST, 9);
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = request.encode(header, &mut buf).unwrap();
+        let (frame, _) = Frame::decode(&buf[..len]).unwrap();
+        let mut reply = [0; MAX_FRAME_LEN];
+        let len = host::handle(&frame, self, &mut reply)?;
+        let (frame, _) = Frame::decode(&reply[..len]).unwrap();
+        assert!(frame.header.is_reply_to(&header));
+        Some(Response::decode(&frame).unwrap())
+    }
+
+    /// Runs a command line and returns the output.
+    fn run(&mut self, line: &str) -> String {
+        let mut out = String::new();
+        console::run(line, self, &mut out).unwrap();
+        out
+    }
+}
+
+impl Target for Bench {
+    type I2c = SimBus;
+
+    fn i2c(&mut self) -> &mut Self::I2c {
+        &mut self.bus
+    }
+
+    fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    fn protection(&mut self) -> &mut Protection {
+        &mut self.protection
+    }
+
+    fn balancing_config(&mut self) -> &mut balancing::Config {
+        &mut self.balancing_config
+    }
+
+    fn pack_config(&mut self) -> &mut PackConfig {
+        &mut self.pack_config
+    }
+
+    fn config_changed(&mut self, setting: Setting) {
+        self.changes.push(setting);
+    }
+
+    fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+
+    fn crash(&self) -> Option<CrashReport> {
+        self.crash
+    }
+
+    fn event_count(&self) -> u16 {
+        self.events.len() as u16
+    }
+
+    fn lost_events(&self) -> u16 {
+        self.lost_events
+    }
+
+    fn event(&self, index: u16) -> Option<Event> {
+        self.events.get(usize::from(index)).copied()
+    }
+}
+
+impl Pack for Bench {
+    fn firmware(&self) -> Version {
+        Version::new(0, 3, 1)
+    }
+
+    fn soc(&self) -> Option<u16> {
+        Some(6420)
+    }
+
+    fn voltage_mv(&self) -> Option<u32> {
+        Some(52_960)
+    }
+
+    fn current_ma(&self) -> Option<i32> {
+        None
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            charge_ma: 40_000,
+            discharge_ma: 80_000,
+            charge_voltage_mv: 56_800,
+            discharge_voltage_mv: 46_400,
+        }
+    }
+
+    fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    fn is_bleeding(&self, cell: usize) -> bool {
+        self.bleeding[cell]
+    }
+
+    fn protection(&mut self) -> &mut Protection {
+        &mut self.protection
+    }
+
+    fn balancing_config(&mut self) -> &mut balancing::Config {
+        &mut self.balancing_config
+    }
+
+    fn pack_config(&mut self) -> &mut PackConfig {
+        &mut self.pack_config
+    }
+
+    fn config_changed(&mut self, setting: Setting) {
+        self.changes.push(setting);
+    }
+
+    fn event(&self, index: u16) -> Option<Event> {
+        self.events.get(usize::from(index)).copied()
+    }
+
+    fn crash(&self) -> Option<CrashReport> {
+        self.crash
+    }
+}
+
+/// Report of a panic in the third run in a row.
+fn crash_report() -> CrashReport {
+    CrashReport {
+        file: *b"soc.rs\0\0\0\0\0\0",
+        line: 318,
+        message_hash: 0x4A7E,
+        reset_cause: ResetCause::WATCHDOG.0,
+        crashes: 3,
+        safe_mode: true,
+    }
+}
+
+#[test]
+fn test_console_parse() {
+    assert_eq!(console::parse("help"), Ok(Command::Help));
+    assert_eq!(console::parse("  scan  "), Ok(Command::Scan));
+    assert_eq!(
+        console::parse("p3t 0x48 2 0x4B00"),
+        Ok(Command::P3t1755 {
+            address: 0x48,
+            register: 2,
+            value: Some(0x4B00),
+        })
+    );
+    assert_eq!(
+        console::parse("tca 32 6"),
+        Ok(Command::Tca9535 {
+            address: 0x20,
+            register: 6,
+            value: None,
+        })
+    );
+    assert_eq!(console::parse("config"), Ok(Command::Config("")));
+    assert_eq!(
+        console::parse("config ov.trip"),
+        Ok(Command::Config("ov.trip"))
+    );
+    assert_eq!(
+        console::parse("set uv.alarm.delay 2000"),
+        Ok(Command::Set(
+            Setting::Threshold(Quantity::UnderVoltage, Level::Alarm, Field::Delay),
+            2000
+        ))
+    );
+    // Temperatures are entered in °C and stored in 1/16 °C.
+    assert_eq!(
+        console::parse("set bal.max_temperature 45.5"),
+        Ok(Command::Set(
+            Setting::Balancing(BalancingSetting::MaxTemperature),
+            728
+        ))
+    );
+    assert_eq!(
+        console::parse("set ut.trip.value -10"),
+        Ok(Command::Set(
+            Setting::Threshold(Quantity::UnderTemperature, Level::Trip, Field::Value),
+            -160
+        ))
+    );
+}
+
+#[test]
+fn test_console_parse_errors() {
+    assert_eq!(console::parse("blink"), Err(ParseError::UnknownCommand));
+    assert_eq!(console::parse("p3t 0x48"), Err(ParseError::MissingArgument));
+    // Not a P3T1755 or TCA9535 address.
+    assert_eq!(
+        console::parse("p3t 0x20 0"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("tca 0x48 0"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("p3t 0x48 4"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("tca 0x20 8"),
+        Err(ParseError::InvalidArgument)
+    );
+    // The configuration register has a single byte.
+    assert_eq!(
+        console::parse("p3t 0x48 1 0x100"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("tca 0x20 2 256"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("tca 0x20 zero"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("scan now"),
+        Err(ParseError::TooManyArguments)
+    );
+    assert_eq!(
+        console::parse("set ov.trip 1"),
+        Err(ParseError::UnknownSetting)
+    );
+    assert_eq!(
+        console::parse("set ov.trip.value.x 1"),
+        Err(ParseError::UnknownSetting)
+    );
+    assert_eq!(
+        console::parse("set bal.max_temperature 45.25"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        console::parse("set ov.trip.value"),
+        Err(ParseError::MissingArgument)
+    );
+}
+
+#[test]
+fn test_console_setting_names_round_trip() {
+    let settings: Vec<Setting> = Setting::all().collect();
+    assert_eq!(settings.len(), 6 * 3 * 3 + 6 + 2);
+    for setting in settings {
+        assert_eq!(Setting::from_name(&setting.to_string()), Some(setting));
+    }
+}
+
+#[test]
+fn test_setting_ids() {
+    let settings: Vec<Setting> = Setting::all().collect();
+    for &setting in &settings {
+        assert_eq!(Setting::from_id(setting.id()), Some(setting));
+    }
+    let mut ids: Vec<u16> = settings.iter().map(|setting| setting.id()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), settings.len());
+
+    // Thresholds follow the Modbus holding registers.
+    assert_eq!(Setting::from_name("ov.warning.value").unwrap().id(), 0);
+    assert_eq!(Setting::from_name("uv.alarm.hysteresis").unwrap().id(), 13);
+    assert_eq!(Setting::from_name("ocd.trip.delay").unwrap().id(), 53);
+    assert_eq!(
+        Setting::from_id(Setting::BALANCING_ID),
+        Some(Setting::Balancing(BalancingSetting::Threshold))
+    );
+    assert_eq!(Setting::from_id(54), None);
+    assert_eq!(Setting::from_id(Setting::BALANCING_ID + 6), None);
+    assert_eq!(
+        Setting::from_name("pack.modbus_unit").unwrap().id(),
+        Setting::PACK_ID + 1
+    );
+    assert_eq!(Setting::from_id(Setting::PACK_ID + 2), None);
+}
+
+#[test]
+fn test_console_help() {
+    let out = Bench::new().run("help");
+    for command in [
+        "help", "scan", "p3t", "tca", "cells", "config", "set", "clear", "reset", "events",
+    ] {
+        assert!(
+            out.lines().any(|line| line.starts_with(command)),
+            "{command} missing"
+        );
+    }
+    assert!(out.ends_with("\r\n"));
+}
+
+#[test]
+fn test_console_scan() {
+    let out = Bench::new().run("scan");
+    assert_eq!(out, "0x20 TCA9535\r\n0x48 P3T1755\r\n2 devices\r\n");
+}
+
+#[test]
+fn test_console_p3t1755_registers() {
+    let mut bench = Bench::new();
+    assert_eq!(bench.run("p3t 0x48 0"), "0x1900 (25.0 C)\r\n");
+    assert_eq!(bench.run("p3t 0x48 1"), "0x28\r\n");
+    assert_eq!(bench.run("p3t 0x48 1 0x29"), "ok\r\n");
+    assert_eq!(bench.bus.register(0x48, 1), [0x29]);
+    assert_eq!(bench.run("p3t 0x48 3 0x4B00"), "ok\r\n");
+    assert_eq!(bench.bus.register(0x48, 3), [0x4B, 0x00]);
+    bench.run("p3t 0x48 2 0xFF00");
+    assert_eq!(bench.run("p3t 0x48 2"), "0xFF00 (-1.0 C)\r\n");
+    // Nobody answers on another address.
+    assert!(bench.run("p3t 0x49 0").starts_with("error: "));
+}
+
+#[test]
+fn test_console_tca9535_registers() {
+    let mut bench = Bench::new();
+    assert_eq!(bench.run("tca 0x20 6"), "0xFF 0b11111111\r\n");
+    assert_eq!(bench.run("tca 0x20 6 0xF0"), "ok\r\n");
+    assert_eq!(bench.run("tca 0x20 6"), "0xF0 0b11110000\r\n");
+    assert!(bench.run("tca 0x21 6 1").starts_with("error: "));
+}
+
+#[test]
+fn test_console_cells() {
+    let out = Bench::new().run("cells");
+    let lines: Vec<&str> = out.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "  0  3310 mV   25.0 C",
+            "  1     - mV      - C",
+            "  2  3295 mV   -2.5 C",
+            "min 3295 mV (cell 2), max 3310 mV (cell 0), delta 15 mV",
+        ]
+    );
+}
+
+#[test]
+fn test_console_config() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.run("config ov.trip"),
+        "ov.trip.value = 3650 mV\r\nov.trip.hysteresis = 150 mV\r\nov.trip.delay = 200 ms\r\n"
+    );
+    let out = bench.run("config");
+    assert_eq!(out.lines().count(), 6 * 3 * 3 + 6 + 2);
+    assert!(out.contains("ot.trip.value = 60.0 C"));
+    assert!(out.contains("bal.max_temperature = 50.0 C"));
+    assert!(out.contains("bal.max_bleeding = 4\r\n"));
+    assert!(out.contains("pack.cells = 16\r\npack.modbus_unit = 1\r\n"));
+}
+
+#[test]
+fn test_console_set() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.run("set ov.trip.value 3700"),
+        "ov.trip.value = 3700 mV\r\n"
+    );
+    assert_eq!(bench.protection.config().over_voltage.trip.value, 3700);
+    let ov_trip = Setting::Threshold(Quantity::OverVoltage, Level::Trip, Field::Value);
+    assert_eq!(bench.changes, [ov_trip]);
+
+    assert_eq!(
+        bench.run("set bal.max_temperature 45.5"),
+        "bal.max_temperature = 45.5 C\r\n"
+    );
+    // 45.5 °C in 1/16 °C, truncated.
+    assert_eq!(bench.balancing_config.max_temperature.raw(), 728);
+
+    // Out of range values change nothing.
+    assert_eq!(
+        bench.run("set bal.max_bleeding 300"),
+        "error: out of range\r\n"
+    );
+    assert_eq!(
+        bench.run("set ov.trip.hysteresis -1"),
+        "error: out of range\r\n"
+    );
+    assert_eq!(bench.balancing_config.max_bleeding, 4);
+
+    // So do thresholds out of order or outside of the cell ratings.
+    for line in [
+        "set ov.alarm.value 3800",
+        "set uv.trip.value 3000",
+        "set ov.trip.value 5000",
+    ] {
+        assert_eq!(bench.run(line), "error: out of range\r\n");
+    }
+    assert_eq!(bench.protection.config().over_voltage.alarm.value, 3600);
+    assert_eq!(bench.protection.config().under_voltage.trip.value, 2500);
+    assert_eq!(bench.protection.config().over_voltage.trip.value, 3700);
+    assert_eq!(
+        bench.changes,
+        [
+            ov_trip,
+            Setting::Balancing(BalancingSetting::MaxTemperature)
+        ]
+    );
+    assert_eq!(bench.run("set ov.trip"), "error: unknown setting\r\n");
+}
+
+#[test]
+fn test_console_set_pack() {
+    let mut bench = Bench::new();
+    assert_eq!(bench.run("set pack.cells 8"), "pack.cells = 8\r\n");
+    assert_eq!(
+        bench.run("set pack.modbus_unit 247"),
+        "pack.modbus_unit = 247\r\n"
+    );
+    assert_eq!(
+        bench.pack_config,
+        PackConfig {
+            cells: 8,
+            modbus_unit: 247
+        }
+    );
+    assert_eq!(
+        bench.changes,
+        [
+            Setting::Pack(PackSetting::Cells),
+            Setting::Pack(PackSetting::ModbusUnit)
+        ]
+    );
+
+    for line in [
+        "set pack.cells 0",
+        "set pack.cells 240",
+        "set pack.modbus_unit 0",
+        "set pack.modbus_unit 248",
+    ] {
+        assert_eq!(bench.run(line), "error: out of range\r\n");
+    }
+    assert_eq!(bench.pack_config.cells, 8);
+    assert_eq!(bench.pack_config.modbus_unit, 247);
+    assert_eq!(bench.changes.len(), 2);
+}
+
+#[test]
+fn test_console_clear() {
+    let mut bench = Bench::new();
+    bench.protection.update(1000, &with_max_cell(3660));
+    assert_eq!(bench.run("clear"), "trips persist\r\nov trip\r\n");
+    bench.protection.update(100, &with_max_cell(3450));
+    assert_eq!(bench.run("clear"), "trips cleared\r\n");
+    assert!(!bench.protection.is_tripped());
+}
+
+#[test]
+fn test_console_reset_cause() {
+    let mut bench = Bench::new();
+    assert_eq!(bench.run("reset"), "power-on\r\n");
+    bench.reset_cause = ResetCause::EXTERNAL | ResetCause::WATCHDOG;
+    assert_eq!(bench.run("reset"), "external, watchdog\r\n");
+    bench.reset_cause = ResetCause::NONE;
+    assert_eq!(bench.run("reset"), "unknown\r\n");
+}
+
+#[test]
+fn test_console_crash() {
+    let mut bench = Bench::new();
+    bench.reset_cause = ResetCause::WATCHDOG;
+    bench.crash = Some(crash_report());
+    assert_eq!(
+        bench.run("reset"),
+        "watchdog\r\ncrash: soc.rs:318, message 0x4A7E\r\ncrashed run: watchdog\r\ncrashes in a \
+         row: 3, safe mode\r\n"
+    );
+    bench.crash = Some(CrashReport {
+        reset_cause: ResetCause::POWER_ON.0,
+        crashes: 1,
+        safe_mode: false,
+        ..crash_report()
+    });
+    assert!(
+        bench
+            .run("reset")
+            .ends_with("crashed run: power-on\r\ncrashes in a row: 1\r\n")
+    );
+
+    bench.events.push(Event {
+        sequence: 43,
+        uptime_s: 0,
+        kind: EventKind::Crash,
+        data: 318,
+    });
+    assert_eq!(
+        bench.run("events 1"),
+        "#43             0 s  crash: line 318\r\n3 events\r\n"
+    );
+}
+
+#[test]
+fn test_console_events() {
+    let mut bench = Bench::new();
+    bench.events.push(Event {
+        sequence: 43,
+        uptime_s: 130,
+        kind: EventKind::ContactorFault,
+        data: Fault::PrechargeTimeout.code(),
+    });
+    assert_eq!(
+        bench.run("events"),
+        "#41             0 s  reset: power-on\r\n\
+         #42           125 s  warning: ov\r\n\
+         #43           130 s  contactor fault: precharge timeout\r\n\
+         3 events\r\n"
+    );
+    assert_eq!(
+        bench.run("events 1"),
+        "#43           130 s  contactor fault: precharge timeout\r\n3 events\r\n"
+    );
+    assert_eq!(bench.run("events 0"), "3 events\r\n");
+    bench.lost_events = 2;
+    assert_eq!(bench.run("events 0"), "3 events, 2 lost\r\n");
+    assert_eq!(console::parse("events x"), Err(ParseError::InvalidArgument));
+    bench.events.clear();
+    assert_eq!(bench.run("events"), "0 events, 2 lost\r\n");
+}
+
+/// Feeds a string to the console and returns the output.
+fn feed<const N: usize>(console: &mut Console<N>, bench: &mut Bench, input: &[u8]) -> String {
+    let mut out = String::new();
+    for &byte in input {
+        console.feed(byte, bench, &mut out).unwrap();
+    }
+    out
+}
+
+#[test]
+fn test_console_line_editing() {
+    let mut bench = Bench::new();
+    let mut console = Console::<16>::new();
+    let mut out = String::new();
+    console.prompt(&mut out).unwrap();
+    assert_eq!(out, "> ");
+
+    // Echo, backspace and a CRLF that only runs the line once.
+    assert_eq!(
+        feed(&mut console, &mut bench, b"resex\x08t\r\n"),
+        "resex\x08 \x08t\r\npower-on\r\n> "
+    );
+    // Empty lines and control characters only show a new prompt.
+    assert_eq!(feed(&mut console, &mut bench, b"\x1B\n"), "\r\n> ");
+    // Backspace on an empty line does nothing.
+    assert_eq!(feed(&mut console, &mut bench, b"\x7F"), "");
+    assert_eq!(
+        feed(&mut console, &mut bench, b"blink\r"),
+        "blink\r\nerror: unknown command, try help\r\n> "
+    );
+}
+
+#[test]
+fn test_console_line_too_long() {
+    let mut bench = Bench::new();
+    let mut console = Console::<8>::new();
+    let out = feed(&mut console, &mut bench, b"set ov.trip.value 1\r");
+    assert_eq!(out, "set ov.t\r\nerror: line too long\r\n> ");
+    assert!(bench.changes.is_empty());
+    // The next line works again.
+    assert_eq!(
+        feed(&mut console, &mut bench, b"reset\r"),
+        "reset\r\npower-on\r\n> "
+    );
+}
+
+#[test]
+fn test_host_ping() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::CORE, Request::Ping),
+        Some(Response::Pong(NodeInfo::new(
+            NodeKind::Core,
+            Version::new(0, 3, 1)
+        )))
+    );
+    // Frames for other nodes and broadcasts aren't answered.
+    assert_eq!(bench.request(Address(0x01), Request::Ping), None);
+    assert_eq!(bench.request(Address::BROADCAST, Request::Ping), None);
+}
+
+#[test]
+fn test_host_read_pack() {
+    let mut bench = Bench::new();
+    bench.protection.update(1000, &with_max_cell(3610));
+    let Some(Response::Pack(status)) = bench.request(Address::CORE, Request::ReadPack) else {
+        panic!("no pack status");
+    };
+    assert_eq!(status.soc, Some(6420));
+    assert_eq!(status.voltage_mv, Some(52_960));
+    assert_eq!(status.current_ma, None);
+    assert_eq!(status.cells, 3);
+    assert_eq!(
+        (status.warnings, status.alarms, status.trips),
+        (0b1, 0b1, 0)
+    );
+    assert_eq!(status.charge_current_ma, 40_000);
+    assert_eq!(status.discharge_voltage_mv, 46_400);
+}
+
+#[test]
+fn test_host_read_cells() {
+    let mut bench = Bench::new();
+    let Some(Response::Cells(block)) = bench.request(Address::CORE, Request::ReadCells(1)) else {
+        panic!("no cells");
+    };
+    assert_eq!((block.first, block.total), (1, 3));
+    assert_eq!(
+        block.readings(),
+        [
+            CellReading::default(),
+            CellReading {
+                voltage_mv: Some(3295),
+                temperature: Some(-40),
+                balancing: true,
+            },
+        ]
+    );
+
+    // Reading right after the last cell returns an empty block.
+    let Some(Response::Cells(block)) = bench.request(Address::CORE, Request::ReadCells(3)) else {
+        panic!("no cells");
+    };
+    assert!(block.readings().is_empty());
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadCells(4)),
+        Some(Response::Error(ErrorCode::OutOfRange))
+    );
+}
+
+#[test]
+fn test_host_settings() {
+    let mut bench = Bench::new();
+    let ov_trip = Setting::from_name("ov.trip.value").unwrap().id();
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadSetting(ov_trip)),
+        Some(Response::Setting(SettingValue {
+            id: ov_trip,
+            value: 3650
+        }))
+    );
+
+    let write = SettingValue {
+        id: ov_trip,
+        value: 3700,
+    };
+    assert_eq!(
+        bench.request(Address::CORE, Request::WriteSetting(write)),
+        Some(Response::SettingWritten(write))
+    );
+    assert_eq!(bench.protection.config().over_voltage.trip.value, 3700);
+    assert_eq!(bench.changes.len(), 1);
+    assert_eq!(bench.changes[0].id(), ov_trip);
+
+    // Unknown settings and out of range values change nothing.
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadSetting(54)),
+        Some(Response::Error(ErrorCode::OutOfRange))
+    );
+    let max_bleeding = SettingValue {
+        id: Setting::Balancing(BalancingSetting::MaxBleeding).id(),
+        value: 300,
+    };
+    assert_eq!(
+        bench.request(Address::CORE, Request::WriteSetting(max_bleeding)),
+        Some(Response::Error(ErrorCode::OutOfRange))
+    );
+    assert_eq!(bench.balancing_config.max_bleeding, 4);
+    let ov_warning = SettingValue {
+        id: Setting::from_name("ov.warning.value").unwrap().id(),
+        value: 3750,
+    };
+    assert_eq!(
+        bench.request(Address::CORE, Request::WriteSetting(ov_warning)),
+        Some(Response::Error(ErrorCode::OutOfRange))
+    );
+    assert_eq!(bench.protection.config().over_voltage.warning.value, 3550);
+    assert_eq!(bench.changes.len(), 1);
+
+    let modbus_unit = SettingValue {
+        id: Setting::Pack(PackSetting::ModbusUnit).id(),
+        value: 12,
+    };
+    assert_eq!(
+        bench.request(Address::CORE, Request::WriteSetting(modbus_unit)),
+        Some(Response::SettingWritten(modbus_unit))
+    );
+    assert_eq!(bench.pack_config.modbus_unit, 12);
+}
+
+#[test]
+fn test_host_events() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadEvent(1)),
+        Some(Response::Event(bench.events[1]))
+    );
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadEvent(2)),
+        Some(Response::Error(ErrorCode::OutOfRange))
+    );
+}
+
+#[test]
+fn test_host_crash() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadCrash),
+        Some(Response::Crash(None))
+    );
+    bench.crash = Some(crash_report());
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadCrash),
+        Some(Response::Crash(Some(crash_report())))
+    );
+}
+
+#[test]
+fn test_host_rejects_agent_requests() {
+    let mut bench = Bench::new();
+    assert_eq!(
+        bench.request(Address::CORE, Request::ReadVoltage),
+        Some(Response::Error(ErrorCode::Unsupported))
+    );
+}
+
+type Eeprom = MemNvm<512>;
+
+#[test]
+fn test_config_defaults() {
+    let store = ConfigStore::load(Eeprom::new(), 0);
+    assert_eq!(store.origin(), Origin::Defaults);
+    let config = store.config();
+    assert_eq!(config.protection, protection::Config::default());
+    assert_eq!(config.balancing.strategy, Strategy::Top { start_mv: 3400 });
+    assert_eq!(config.cells, 16);
+    assert_eq!(config.modbus_unit, 1);
+}
+
+#[test]
+fn test_config_roundtrip() {
+    let mut config = config::Config::default();
+    config.protection.over_voltage.trip = Threshold::new(3700, 120, 250);
+    config.protection.discharge_over_current.alarm.value = 200_000;
+    config.protection.under_temperature.warning.value = -10 * 16;
+    config.balancing.strategy = Strategy::Continuous;
+    config.balancing.max_temperature = Temperature::from_raw(-40).unwrap();
+    config.balancing.rest_ms = 0;
+    config.cells = 8;
+    config.modbus_unit = 247;
+
+    let mut store = ConfigStore::load(Eeprom::new(), 0);
+    store.save(config).unwrap();
+    let store = ConfigStore::load(store.release(), 0);
+    assert_eq!(store.origin(), Origin::Stored);
+    let loaded = store.config();
+    assert_eq!(loaded.protection, config.protection);
+    assert_eq!(loaded.balancing.strategy, Strategy::Continuous);
+    assert_eq!(loaded.balancing.threshold_mv, 15);
+    assert_eq!(loaded.balancing.max_temperature.raw(), -40);
+    assert_eq!(loaded.balancing.rest_ms, 0);
+    assert_eq!(loaded.cells, 8);
+    assert_eq!(loaded.modbus_unit, 247);
+
+    // The second save goes to the other half of the EEPROM.
+    let mut store = store;
+    store.save(config::Config::default()).unwrap();
+    let eeprom = store.release();
+    assert_eq!(eeprom.wear[0], 1);
+    assert_eq!(eeprom.wear[SLOT_LEN], 1);
+    assert_eq!(eeprom.wear[SLOT_LEN + 5 + config::Config::LEN + 1], 1);
+    let store = ConfigStore::load(eeprom, 0);
+    assert_eq!(store.config().cells, 16);
+}
+
+#[test]
+fn test_config_rejects_invalid_values() {
+    let mut negative_hysteresis = config::Config::default();
+    negative_hysteresis
+        .protection
+        .over_temperature
+        .alarm
+        .hysteresis = -1;
+    let no_cells = config::Config {
+        cells: 0,
+        ..Default::default()
+    };
+    let bad_unit = config::Config {
+        modbus_unit: 248,
+        ..Default::default()
+    };
+
+    for config in [negative_hysteresis, no_cells, bad_unit] {
+        let mut store = ConfigStore::load(Eeprom::new(), 0);
+        store.save(config).unwrap();
+        let store = ConfigStore::load(store.release(), 0);
+        assert_eq!(store.origin(), Origin::Defaults);
+    }
+}
@@ -0,0 +1,12 @@
+//! Application logic shared by both firmware binaries.
+
+use crate::Board;
+
+/// Mirrors the user switch onto the status LED.
+///
+/// Returns whether the switch is pressed.
+pub fn mirror_switch<B: Board>(board: &mut B) -> bool {
+    let pressed = board.user_switch_pressed();
+    board.set_status_led(pressed);
+    pressed
+}
@@ -0,0 +1,41 @@
+//! Writes to the registers protected by the configuration change protection.
+//!
+//! Writing a key to CPU.CCP unlocks the protected registers for the next four
+//! instructions. [`CCP_SPM`] unlocks the command register of NVMCTRL,
+//! [`CCP_IOREG`] the other protected registers like the watchdog and the
+//! interrupt vector select. The sequence has to be exact, so it's written in
+//! assembly once for both microcontrollers.
+
+/// Key unlocking NVMCTRL.CTRLA.
+pub const CCP_SPM: u8 = 0x9D;
+/// Key unlocking the protected I/O registers.
+pub const CCP_IOREG: u8 = 0xD8;
+
+/// I/O address of CPU.CCP.
+#[cfg(target_arch = "avr")]
+const CCP: u8 = 0x34;
+
+/// Writes `value` to the protected register at data space `address`, unlocked
+/// with `key`.
+///
+/// # Safety
+///
+/// The value has to be valid for the register according to the datasheet.
+/// Commands started this way, like writing the flash, are up to the caller.
+#[cfg(target_arch = "avr")]
+pub unsafe fn ccp_write(address: u16, key: u8, value: u8) {
+    // SAFETY: The store follows the key right away, within the four
+    // instructions the register stays unlocked. The caller guarantees that
+    // the value is valid.
+    unsafe {
+        core::arch::asm!(
+            "out {ccp}, {key}",
+            "st Z, {value}",
+            ccp = const CCP,
+            key = in(reg) key,
+            value = in(reg) value,
+            in("Z") address,
+            options(nostack, preserves_flags),
+        );
+    }
+}
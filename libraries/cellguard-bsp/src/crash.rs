@@ -0,0 +1,247 @@
+//! Crash reports kept in RAM across the watchdog reset.
+//!
+//! [`retained`] returns the [`Retained`] block in the `.noinit` section,
+//! which the startup code neither clears nor initializes. The panic handler
+//! stores where it panicked with [`record`] and resets through the watchdog
+//! with `reboot`. After the reset [`Retained::boot`] returns the report.
+//!
+//! A power failure loses the block, which the CRC detects. Crashes are
+//! counted until a reset that isn't caused by a crash or until
+//! [`Retained::stable`]. From [`SAFE_MODE_CRASHES`] crashes in a row on the
+//! firmware should run in safe mode, which only reports the crash.
+//!
+//! | Offset | Size | Content                                   |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 1    | [`MAGIC`]                                 |
+//! | 1      | 1    | Reset flags of the current run            |
+//! | 2      | 1    | Crashes in a row                          |
+//! | 3      | 1    | 1 if a panic was recorded in this run     |
+//! | 4      | 12   | File name, padded with zeros              |
+//! | 16     | 2    | Line                                      |
+//! | 18     | 2    | Message hash                              |
+//! | 20     | 1    | Reset flags of the run that crashed       |
+//! | 21     | 2    | CRC-16 of offsets 0 to 20, big endian     |
+
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+
+use cellguard_protocol::{CrashReport, StatusFlags, crc16, crc16_update};
+
+/// First byte of a valid block.
+pub const MAGIC: u8 = 0xC5;
+/// Size of the [`Retained`] block.
+pub const RETAINED_LEN: usize = 23;
+/// Number of crashes in a row that start the safe mode.
+pub const SAFE_MODE_CRASHES: u8 = 3;
+
+const CAUSE: usize = 1;
+const CRASHES: usize = 2;
+const CRASHED: usize = 3;
+const FILE: usize = 4;
+const LINE: usize = FILE + CrashReport::FILE_LEN;
+const HASH: usize = LINE + 2;
+const CRASH_CAUSE: usize = HASH + 2;
+const CRC: usize = CRASH_CAUSE + 1;
+
+/// Data space address of RSTCTRL.RSTFR.
+#[cfg(target_arch = "avr")]
+const RSTCTRL_RSTFR: usize = 0x0040;
+/// Data space address of WDT.CTRLA.
+#[cfg(target_arch = "avr")]
+const WDT_CTRLA: u16 = 0x0100;
+/// WDT.CTRLA: Time-out after 8 cycles of the 1.024 kHz oscillator.
+#[cfg(target_arch = "avr")]
+const WDT_PERIOD_8CLK: u8 = 0x01;
+
+#[cfg_attr(target_arch = "avr", unsafe(link_section = ".noinit"))]
+static mut RETAINED: MaybeUninit<Retained> = MaybeUninit::uninit();
+
+/// Memory that keeps its content across a watchdog reset.
+///
+/// Every byte pattern is valid, so the block can be read before it was ever
+/// written.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct Retained(pub [u8; RETAINED_LEN]);
+
+impl Default for Retained {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Retained {
+    /// Creates an invalid block, as found after power-on.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self([0; RETAINED_LEN])
+    }
+
+    /// Starts a run after a reset with the given reset flags.
+    ///
+    /// Returns the report of the panic that caused the reset, if any.
+    pub fn boot(&mut self, reset_cause: u8) -> Option<CrashReport> {
+        let report = if self.is_valid() && self.0[CRASHED] == 1 {
+            Some(self.report())
+        } else {
+            self.0 = [0; RETAINED_LEN];
+            self.0[0] = MAGIC;
+            None
+        };
+        self.0[CAUSE] = reset_cause;
+        self.0[CRASHED] = 0;
+        self.seal();
+        report
+    }
+
+    /// Records a panic and counts it.
+    ///
+    /// Meant to be called from the panic handler, which then resets. The hash
+    /// is usually calculated with [`message_hash`].
+    pub fn record_panic(&mut self, file: &str, line: u32, message_hash: u16) {
+        if !self.is_valid() {
+            // A panic before `boot`, the reset cause is unknown.
+            self.0 = [0; RETAINED_LEN];
+            self.0[0] = MAGIC;
+        }
+        self.0[CRASHES] = self.0[CRASHES].saturating_add(1);
+        self.0[CRASHED] = 1;
+        let name = file.rsplit(['/', '\\']).next().unwrap_or(file).as_bytes();
+        let len = name.len().min(CrashReport::FILE_LEN);
+        let field = &mut self.0[FILE..LINE];
+        field.fill(0);
+        field[..len].copy_from_slice(&name[..len]);
+        let line = u16::try_from(line).unwrap_or(u16::MAX);
+        self.0[LINE..HASH].copy_from_slice(&line.to_le_bytes());
+        self.0[HASH..CRASH_CAUSE].copy_from_slice(&message_hash.to_le_bytes());
+        self.0[CRASH_CAUSE] = self.0[CAUSE];
+        self.seal();
+    }
+
+    /// Ends the series of crashes once the firmware ran long enough.
+    ///
+    /// Has no effect in safe mode, which only ends with a reset that isn't
+    /// caused by a crash.
+    pub fn stable(&mut self) {
+        if self.is_valid() && self.0[CRASHES] < SAFE_MODE_CRASHES {
+            self.0[CRASHES] = 0;
+            self.seal();
+        }
+    }
+
+    /// Returns the number of crashes in a row.
+    #[must_use]
+    pub fn crashes(&self) -> u8 {
+        if self.is_valid() { self.0[CRASHES] } else { 0 }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.0[0] == MAGIC && crc16(&self.0[..CRC]).to_be_bytes() == self.0[CRC..]
+    }
+
+    fn seal(&mut self) {
+        let crc = crc16(&self.0[..CRC]);
+        self.0[CRC..].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    fn report(&self) -> CrashReport {
+        let mut file = [0; CrashReport::FILE_LEN];
+        file.copy_from_slice(&self.0[FILE..LINE]);
+        CrashReport {
+            file,
+            line: u16::from_le_bytes([self.0[LINE], self.0[LINE + 1]]),
+            message_hash: u16::from_le_bytes([self.0[HASH], self.0[HASH + 1]]),
+            reset_cause: self.0[CRASH_CAUSE],
+            crashes: self.0[CRASHES],
+            safe_mode: self.0[CRASHES] >= SAFE_MODE_CRASHES,
+        }
+    }
+}
+
+/// Returns the status flags reporting a crash.
+#[must_use]
+pub fn status_flags(report: Option<&CrashReport>) -> StatusFlags {
+    let mut flags = StatusFlags::NONE;
+    flags.set(StatusFlags::CRASHED, report.is_some());
+    flags.set(StatusFlags::SAFE_MODE, report.is_some_and(|r| r.safe_mode));
+    flags
+}
+
+/// Returns the crash report block of the firmware.
+///
+/// # Safety
+///
+/// No other reference to the block may be used afterwards. `main` takes it
+/// once and the panic handler again, which never returns to `main`.
+pub unsafe fn retained() -> &'static mut Retained {
+    // SAFETY: The RAM of the block holds some byte pattern after any reset,
+    // and every byte pattern is a valid `Retained`. The caller guarantees
+    // exclusive access.
+    unsafe { &mut *(&raw mut RETAINED).cast::<Retained>() }
+}
+
+/// Records the location of a panic and the hash of its message.
+///
+/// The firmware picks [`message_hash`] or [`static_message_hash`] depending
+/// on whether formatting fits into its flash.
+pub fn record(retained: &mut Retained, info: &PanicInfo, message_hash: u16) {
+    let (file, line) = info
+        .location()
+        .map_or(("", 0), |location| (location.file(), location.line()));
+    retained.record_panic(file, line, message_hash);
+}
+
+/// Reads and clears the reset flags.
+#[cfg(target_arch = "avr")]
+#[must_use]
+pub fn reset_flags() -> u8 {
+    let rstfr = RSTCTRL_RSTFR as *mut u8;
+    // SAFETY: RSTFR is mapped into the data space on both microcontrollers.
+    // Writing ones clears the flags.
+    unsafe {
+        let flags = core::ptr::read_volatile(rstfr);
+        core::ptr::write_volatile(rstfr, flags);
+        flags
+    }
+}
+
+/// Resets the microcontroller through the watchdog.
+///
+/// Must be called with interrupts disabled.
+#[cfg(target_arch = "avr")]
+pub fn reboot() -> ! {
+    // SAFETY: A valid period for WDT.CTRLA, which resets the microcontroller
+    // 8 ms later.
+    unsafe { crate::cpu::ccp_write(WDT_CTRLA, crate::cpu::CCP_IOREG, WDT_PERIOD_8CLK) };
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the CRC-16 of a formatted panic message.
+#[must_use]
+pub fn message_hash(message: impl fmt::Display) -> u16 {
+    let mut hasher = Hasher(0xFFFF);
+    // Hashing never fails.
+    let _ = fmt::write(&mut hasher, format_args!("{message}"));
+    hasher.0
+}
+
+/// Returns the CRC-16 of a panic message without arguments.
+///
+/// Equal to [`message_hash`] of the same text, without the code size of the
+/// formatting machinery.
+#[must_use]
+pub const fn static_message_hash(message: &str) -> u16 {
+    crc16(message.as_bytes())
+}
+
+struct Hasher(u16);
+
+impl fmt::Write for Hasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 = crc16_update(self.0, s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Busy-wait delay based on the CPU clock.
+//!
+//! On the AVR every iteration of the wait loop takes [`LOOP_CYCLES`] cycles.
+//! Other targets only spin, so the application logic builds for host tests.
+
+use embedded_hal::delay::DelayNs;
+
+/// Cycles of one iteration of the wait loop: four subtractions and a taken
+/// branch.
+pub const LOOP_CYCLES: u32 = 6;
+
+/// Busy-wait delay of a CPU running at `CLK_PER_HZ`.
+///
+/// Delays are at least as long as requested. Interrupts extend them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Delay<const CLK_PER_HZ: u32>;
+
+impl<const CLK_PER_HZ: u32> Delay<CLK_PER_HZ> {
+    /// Cycles per microsecond, rounded up so delays are never too short.
+    const CYCLES_PER_US: u32 = CLK_PER_HZ.div_ceil(1_000_000);
+}
+
+impl<const CLK_PER_HZ: u32> DelayNs for Delay<CLK_PER_HZ> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay_us(ns.div_ceil(1_000));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        wait(us.saturating_mul(Self::CYCLES_PER_US).div_ceil(LOOP_CYCLES));
+    }
+}
+
+/// Runs the wait loop `iterations` times.
+#[cfg(target_arch = "avr")]
+fn wait(iterations: u32) {
+    if iterations == 0 {
+        return;
+    }
+    let [b0, b1, b2, b3] = iterations.to_le_bytes();
+    // SAFETY: The loop only counts down its own registers. SBCI keeps the
+    // zero flag of the previous bytes, so the branch tests all 32 bits.
+    unsafe {
+        core::arch::asm!(
+            "1:",
+            "subi {b0}, 1",
+            "sbci {b1}, 0",
+            "sbci {b2}, 0",
+            "sbci {b3}, 0",
+            "brne 1b",
+            b0 = inout(reg_upper) b0 => _,
+            b1 = inout(reg_upper) b1 => _,
+            b2 = inout(reg_upper) b2 => _,
+            b3 = inout(reg_upper) b3 => _,
+            options(nomem, nostack),
+        );
+    }
+}
+
+/// Runs the wait loop `iterations` times.
+#[cfg(not(target_arch = "avr"))]
+fn wait(iterations: u32) {
+    for _ in 0..iterations {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,37 @@
+//! Board support abstraction shared by the cellagent and cellcore firmware.
+//!
+//! Both boards have a status LED and a user switch. The firmware implements
+//! [`Board`] for its microcontroller, which lets the application logic in
+//! [`app`] be written once and tested on the host using [`mock::MockBoard`].
+//! The panic handlers of both keep a report in [`crash::Retained`], and both
+//! wait with the [`delay::Delay`] of their clock.
+
+#![no_std]
+#![cfg_attr(target_arch = "avr", feature(asm_experimental_arch))]
+
+use embedded_hal::delay::DelayNs;
+
+pub mod app;
+pub mod cpu;
+pub mod crash;
+pub mod delay;
+pub mod mock;
+
+/// Peripherals common to all boards.
+pub trait Board {
+    /// Busy-wait delay of the board.
+    type Delay: DelayNs;
+
+    /// Switches the status LED on or off.
+    ///
+    /// Implementations take care of the polarity of the LED.
+    fn set_status_led(&mut self, on: bool);
+
+    /// Returns true while the user switch is pressed.
+    ///
+    /// Implementations take care of the polarity of the switch.
+    fn user_switch_pressed(&mut self) -> bool;
+
+    /// Returns the delay of the board.
+    fn delay(&mut self) -> &mut Self::Delay;
+}
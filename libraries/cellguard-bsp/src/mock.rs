@@ -0,0 +1,63 @@
+//! Board model for host tests.
+
+use embedded_hal::delay::DelayNs;
+
+use crate::Board;
+
+/// Delay that only accumulates the requested time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MockDelay {
+    /// Total requested delay in nanoseconds.
+    pub elapsed_ns: u64,
+}
+
+impl DelayNs for MockDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.elapsed_ns += u64::from(ns);
+    }
+}
+
+/// Board model recording the state of its outputs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MockBoard {
+    /// Current state of the status LED.
+    pub status_led: bool,
+    /// Number of times the status LED changed its state.
+    pub status_led_toggles: u32,
+    /// State of the user switch returned to the application.
+    pub user_switch: bool,
+    /// Delay of the board.
+    pub delay: MockDelay,
+}
+
+impl MockBoard {
+    /// Creates a board with the LED off and the switch released.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            status_led: false,
+            status_led_toggles: 0,
+            user_switch: false,
+            delay: MockDelay { elapsed_ns: 0 },
+        }
+    }
+}
+
+impl Board for MockBoard {
+    type Delay = MockDelay;
+
+    fn set_status_led(&mut self, on: bool) {
+        if self.status_led != on {
+            self.status_led_toggles += 1;
+        }
+        self.status_led = on;
+    }
+
+    fn user_switch_pressed(&mut self) -> bool {
+        self.user_switch
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        &mut self.delay
+    }
+}
@@ -0,0 +1,144 @@
+//! Integration tests for the board support abstraction.
+
+use cellguard_bsp::crash::{self, RETAINED_LEN, Retained, SAFE_MODE_CRASHES};
+use cellguard_bsp::mock::MockBoard;
+use cellguard_bsp::{Board, app};
+use cellguard_protocol::{StatusFlags, crc16};
+
+#[test]
+fn test_mirror_switch() {
+    let mut board = MockBoard::new();
+    assert!(!app::mirror_switch(&mut board));
+    assert!(!board.status_led);
+
+    board.user_switch = true;
+    assert!(app::mirror_switch(&mut board));
+    assert!(board.status_led);
+
+    board.user_switch = false;
+    assert!(!app::mirror_switch(&mut board));
+    assert!(!board.status_led);
+    assert_eq!(board.status_led_toggles, 2);
+}
+
+#[test]
+fn test_mock_led_toggles_only_count_changes() {
+    let mut board = MockBoard::new();
+    board.set_status_led(false);
+    board.set_status_led(true);
+    board.set_status_led(true);
+    assert_eq!(board.status_led_toggles, 1);
+}
+
+/// Reset flags of a power-on and a watchdog reset.
+const POWER_ON: u8 = 0x01;
+const WATCHDOG: u8 = 0x08;
+
+#[test]
+fn test_crash_report_survives_reset() {
+    let mut retained = Retained::new();
+    assert_eq!(retained.boot(POWER_ON), None);
+    retained.record_panic("src/balancing.rs", 214, 0xB1C3);
+
+    let report = retained.boot(WATCHDOG).unwrap();
+    assert_eq!(report.file(), "balancing.rs");
+    assert_eq!(report.line, 214);
+    assert_eq!(report.message_hash, 0xB1C3);
+    // The run that crashed started at power-on.
+    assert_eq!(report.reset_cause, POWER_ON);
+    assert_eq!(report.crashes, 1);
+    assert!(!report.safe_mode);
+    assert_eq!(crash::status_flags(Some(&report)), StatusFlags::CRASHED);
+
+    // A later reset without a crash has no report and starts counting anew.
+    assert_eq!(retained.boot(0x04), None);
+    assert_eq!(retained.crashes(), 0);
+    assert_eq!(crash::status_flags(None), StatusFlags::NONE);
+}
+
+#[test]
+fn test_crash_file_name() {
+    let mut retained = Retained::new();
+    retained.boot(POWER_ON);
+    retained.record_panic("C:\\cellguard\\src\\main.rs", 7, 0);
+    assert_eq!(retained.boot(WATCHDOG).unwrap().file(), "main.rs");
+
+    retained.record_panic("/rustc/library/core/src/num/integer.rs", 70_000, 0);
+    let report = retained.boot(WATCHDOG).unwrap();
+    assert_eq!(report.file(), "integer.rs");
+    assert_eq!(report.line, u16::MAX);
+    // The previous crash was after a watchdog reset.
+    assert_eq!(report.reset_cause, WATCHDOG);
+
+    retained.record_panic("src/contactor_sequencer.rs", 1, 0);
+    assert_eq!(retained.boot(WATCHDOG).unwrap().file(), "contactor_se");
+}
+
+#[test]
+fn test_safe_mode_after_repeated_crashes() {
+    let mut retained = Retained::new();
+    retained.boot(POWER_ON);
+    for crashes in 1..SAFE_MODE_CRASHES {
+        retained.record_panic("main.rs", 1, 0);
+        let report = retained.boot(WATCHDOG).unwrap();
+        assert_eq!(report.crashes, crashes);
+        assert!(!report.safe_mode);
+    }
+    retained.record_panic("main.rs", 1, 0);
+    let report = retained.boot(WATCHDOG).unwrap();
+    assert!(report.safe_mode);
+    assert_eq!(
+        crash::status_flags(Some(&report)),
+        StatusFlags::CRASHED | StatusFlags::SAFE_MODE
+    );
+
+    // A stable run doesn't end the safe mode, a reset without a crash does.
+    retained.stable();
+    assert_eq!(retained.crashes(), SAFE_MODE_CRASHES);
+    assert_eq!(retained.boot(0x04), None);
+    assert_eq!(retained.crashes(), 0);
+}
+
+#[test]
+fn test_stable_run_ends_crash_series() {
+    let mut retained = Retained::new();
+    retained.boot(POWER_ON);
+    for _ in 1..SAFE_MODE_CRASHES {
+        retained.record_panic("main.rs", 1, 0);
+        retained.boot(WATCHDOG).unwrap();
+    }
+    retained.stable();
+    assert_eq!(retained.crashes(), 0);
+    retained.record_panic("main.rs", 1, 0);
+    assert_eq!(retained.boot(WATCHDOG).unwrap().crashes, 1);
+}
+
+#[test]
+fn test_corrupt_block_is_discarded() {
+    // Random content after power-on.
+    let mut retained = Retained([0x5A; RETAINED_LEN]);
+    assert_eq!(retained.crashes(), 0);
+    assert_eq!(retained.boot(POWER_ON), None);
+
+    retained.record_panic("main.rs", 1, 0);
+    retained.0[10] ^= 0x01;
+    assert_eq!(retained.boot(WATCHDOG), None);
+    assert_eq!(retained.crashes(), 0);
+
+    // A panic before the block was set up is still reported.
+    let mut retained = Retained::new();
+    retained.record_panic("main.rs", 3, 0);
+    let report = retained.boot(WATCHDOG).unwrap();
+    assert_eq!(report.line, 3);
+    assert_eq!(report.reset_cause, 0);
+}
+
+#[test]
+fn test_message_hash() {
+    let hash = crash::message_hash(format_args!("index {} out of range", 12));
+    assert_eq!(hash, crc16(b"index 12 out of range"));
+    assert_eq!(
+        crash::static_message_hash("attempt to divide by zero"),
+        crash::message_hash("attempt to divide by zero")
+    );
+}
@@ -0,0 +1,463 @@
+//! Terminal dashboard showing all cells of the pack at once.
+//!
+//! The dashboard shows the state of charge, the pack voltage and current,
+//! the limits for the inverter and the active warnings, alarms and trips.
+//! Below, every cell gets a voltage bar with the lowest and highest cell
+//! highlighted, and the temperatures of the P3T1755 sensors on the cellagents
+//! form a heat-map.
+//!
+//! Samples come from a [`Source`]: [`Live`] polls the cellcore and
+//! [`Replay`] plays back a recording. Recordings are the JSON Lines written
+//! by `tail --format json`, so both tools produce files for a replay.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::link::Link;
+use crate::output::{CellSample, Sample, SampleWriter, optional};
+
+/// Time between two redraws while waiting for keys.
+const FRAME: Duration = Duration::from_millis(50);
+
+/// Width of a tile in the heat-map.
+const TILE_WIDTH: u16 = 7;
+
+/// Where the samples come from.
+pub trait Source {
+    /// Advances the source by `elapsed` and returns a sample if one is due.
+    ///
+    /// A paused dashboard advances by zero.
+    fn advance(&mut self, elapsed: Duration) -> Result<Option<Sample>>;
+
+    /// Describes the source and its progress for the status line.
+    fn status(&self) -> String;
+}
+
+/// Samples polled from the cellcore.
+pub struct Live<P> {
+    link: Link<P>,
+    name: String,
+    interval: Duration,
+    waited: Duration,
+    start: Instant,
+}
+
+impl<P: Read + Write> Live<P> {
+    /// Polls the cellcore on `link` every `interval`, starting right away.
+    pub fn new(link: Link<P>, name: String, interval: Duration) -> Self {
+        Self {
+            link,
+            name,
+            interval,
+            waited: interval,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<P: Read + Write> Source for Live<P> {
+    fn advance(&mut self, elapsed: Duration) -> Result<Option<Sample>> {
+        self.waited += elapsed;
+        if self.waited < self.interval {
+            return Ok(None);
+        }
+        self.waited = Duration::ZERO;
+        let status = self.link.pack()?;
+        let cells = self.link.cells()?;
+        Ok(Some(Sample::new(self.start.elapsed(), &status, &cells)))
+    }
+
+    fn status(&self) -> String {
+        format!("live {}", self.name)
+    }
+}
+
+/// Samples played back from a recording.
+pub struct Replay {
+    name: String,
+    samples: Vec<Sample>,
+    next: usize,
+    speed: f64,
+    clock_ms: f64,
+}
+
+impl Replay {
+    /// Plays back `samples` at `speed` times the recorded pace.
+    ///
+    /// The first sample is shown right away.
+    pub fn new(name: String, samples: Vec<Sample>, speed: f64) -> Self {
+        let clock_ms = samples.first().map_or(0.0, |sample| sample.time_ms as f64);
+        Self {
+            name,
+            samples,
+            next: 0,
+            speed,
+            clock_ms,
+        }
+    }
+
+    /// Returns true once all samples were played back.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.samples.len()
+    }
+}
+
+impl Source for Replay {
+    fn advance(&mut self, elapsed: Duration) -> Result<Option<Sample>> {
+        self.clock_ms += elapsed.as_secs_f64() * 1000.0 * self.speed;
+        // Skips samples that are already overdue, like at high speeds.
+        let due = self.samples[self.next..]
+            .iter()
+            .take_while(|sample| sample.time_ms as f64 <= self.clock_ms)
+            .count();
+        if due == 0 {
+            return Ok(None);
+        }
+        self.next += due;
+        Ok(Some(self.samples[self.next - 1].clone()))
+    }
+
+    fn status(&self) -> String {
+        if self.is_finished() {
+            format!("replay {} finished", self.name)
+        } else {
+            format!(
+                "replay {} {}/{} at {}x",
+                self.name,
+                self.next,
+                self.samples.len(),
+                self.speed
+            )
+        }
+    }
+}
+
+/// Reads a recording written by the dashboard or `tail --format json`.
+pub fn load(path: &Path) -> Result<Vec<Sample>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut samples = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let sample = serde_json::from_str(&line)
+            .with_context(|| format!("invalid sample on line {}", number + 1))?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+/// Runs the dashboard until the user quits.
+///
+/// Every sample of the source is also written to `record` if given.
+pub fn run(source: &mut dyn Source, mut record: Option<SampleWriter<File>>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, source, record.as_mut());
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    source: &mut dyn Source,
+    mut record: Option<&mut SampleWriter<File>>,
+) -> Result<()> {
+    let mut dashboard = Dashboard::new();
+    let mut last = Instant::now();
+    loop {
+        let now = Instant::now();
+        let elapsed = if dashboard.is_paused() {
+            Duration::ZERO
+        } else {
+            now - last
+        };
+        last = now;
+        match source.advance(elapsed) {
+            Ok(Some(sample)) => {
+                if let Some(record) = &mut record {
+                    record.write(&sample)?;
+                }
+                dashboard.update(sample);
+            }
+            Ok(None) => {}
+            Err(e) => dashboard.set_error(format!("{e:#}")),
+        }
+        dashboard.set_status(source.status());
+        terminal.draw(|frame| dashboard.render(frame, frame.area()))?;
+
+        if event::poll(FRAME)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char(' ') => dashboard.toggle_pause(),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// State shown by the dashboard.
+#[derive(Default)]
+pub struct Dashboard {
+    sample: Option<Sample>,
+    status: String,
+    error: Option<String>,
+    paused: bool,
+}
+
+impl Dashboard {
+    /// Creates a dashboard waiting for the first sample.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows a new sample and clears a previous error.
+    pub fn update(&mut self, sample: Sample) {
+        self.sample = Some(sample);
+        self.error = None;
+    }
+
+    /// Shows an error in the status line, keeping the last sample.
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    /// Sets the description of the source in the status line.
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// Stops or resumes taking samples.
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Returns true while no samples are taken.
+    pub const fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Draws the dashboard into `area`.
+    pub fn render(&self, frame: &mut Frame<'_>, area: Rect) {
+        let [top, protection, body, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(area);
+        frame.render_widget(self.footer(), footer);
+        let Some(sample) = &self.sample else {
+            frame.render_widget(
+                Paragraph::new("waiting for the cellcore...").block(Block::bordered()),
+                top.union(body),
+            );
+            return;
+        };
+
+        let [soc, pack, limits] = Layout::horizontal([Constraint::Fill(1); 3]).areas(top);
+        frame.render_widget(soc_gauge(sample), soc);
+        frame.render_widget(pack_values(sample), pack);
+        frame.render_widget(limit_values(sample), limits);
+        frame.render_widget(protection_flags(sample), protection);
+
+        let [cells, temperatures] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(body);
+        frame.render_widget(cell_bars(sample, cells.width), cells);
+        frame.render_widget(heat_map(sample, temperatures.width), temperatures);
+    }
+
+    fn footer(&self) -> Line<'_> {
+        let mut spans = vec![Span::raw(self.status.as_str())];
+        if let Some(sample) = &self.sample {
+            spans.push(Span::raw(format!(
+                "  t {:.1} s",
+                sample.time_ms as f64 / 1000.0
+            )));
+        }
+        if self.paused {
+            spans.push(" paused".bold());
+        }
+        if let Some(error) = &self.error {
+            spans.push(Span::styled(format!("  {error}"), Color::Red));
+        }
+        spans.push(Span::styled("  space pause  q quit", Color::DarkGray));
+        Line::from(spans)
+    }
+}
+
+fn soc_gauge(sample: &Sample) -> Gauge<'static> {
+    let block = Block::bordered().title("SOC");
+    match sample.soc_percent {
+        Some(soc) => Gauge::default()
+            .block(block)
+            .gauge_style(Color::Green)
+            .ratio((soc / 100.0).clamp(0.0, 1.0))
+            .label(format!("{soc:.2} %")),
+        None => Gauge::default().block(block).label("- %"),
+    }
+}
+
+fn pack_values(sample: &Sample) -> Paragraph<'static> {
+    Paragraph::new(vec![
+        Line::from(format!("voltage {} mV", optional(sample.voltage_mv))),
+        Line::from(format!("current {} mA", optional(sample.current_ma))),
+    ])
+    .block(Block::bordered().title("Pack"))
+}
+
+fn limit_values(sample: &Sample) -> Paragraph<'static> {
+    Paragraph::new(vec![
+        Line::from(format!(
+            "charge    {:>6} mA {:>6} mV",
+            sample.charge_current_ma, sample.charge_voltage_mv
+        )),
+        Line::from(format!(
+            "discharge {:>6} mA {:>6} mV",
+            sample.discharge_current_ma, sample.discharge_voltage_mv
+        )),
+    ])
+    .block(Block::bordered().title("Limits"))
+}
+
+fn protection_flags(sample: &Sample) -> Paragraph<'_> {
+    let mut spans = Vec::new();
+    for (name, quantities, color) in [
+        ("trips", &sample.trips, Color::Red),
+        ("alarms", &sample.alarms, Color::LightRed),
+        ("warnings", &sample.warnings, Color::Yellow),
+    ] {
+        spans.push(Span::raw(format!("{name}: ")));
+        if quantities.is_empty() {
+            spans.push(Span::styled("none", Color::DarkGray));
+        } else {
+            spans.push(Span::styled(
+                quantities.join(" "),
+                Style::new().fg(color).add_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::raw("   "));
+    }
+    Paragraph::new(Line::from(spans)).block(Block::bordered().title("Protection"))
+}
+
+/// Returns the indices of the lowest and the highest cell voltage.
+///
+/// Cells without a reading are left out.
+pub fn extremes(cells: &[CellSample]) -> Option<(usize, usize)> {
+    let voltages = cells
+        .iter()
+        .enumerate()
+        .filter_map(|(index, cell)| Some((index, cell.voltage_mv?)));
+    let min = voltages.clone().min_by_key(|&(_, mv)| mv)?;
+    let max = voltages.max_by_key(|&(_, mv)| mv)?;
+    Some((min.0, max.0))
+}
+
+fn cell_bars(sample: &Sample, width: u16) -> Paragraph<'_> {
+    let extremes = extremes(&sample.cells);
+    let Some((min, max)) = extremes.map(|(min, max)| {
+        (
+            sample.cells[min].voltage_mv.unwrap_or_default(),
+            sample.cells[max].voltage_mv.unwrap_or_default(),
+        )
+    }) else {
+        return Paragraph::new("no cell voltages").block(Block::bordered().title("Cells"));
+    };
+    // The bars start below the lowest cell so small differences show.
+    let low = min.saturating_sub((max - min).max(20));
+    let bar_width = usize::from(width.saturating_sub(2 + 4 + 8 + 4 + 4));
+
+    let mut lines = Vec::new();
+    for (index, cell) in sample.cells.iter().enumerate() {
+        let mut spans = vec![Span::raw(format!("{index:>3} "))];
+        let style = match extremes {
+            Some((min, _)) if min == index => Style::new().fg(Color::Cyan).bold(),
+            Some((_, max)) if max == index => Style::new().fg(Color::Red).bold(),
+            _ => Style::new(),
+        };
+        match cell.voltage_mv {
+            Some(mv) => {
+                let fill = usize::from(mv - low) * bar_width / usize::from(max - low);
+                spans.push(Span::styled("█".repeat(fill), style));
+                spans.push(Span::styled("░".repeat(bar_width - fill), Color::DarkGray));
+                spans.push(Span::styled(format!(" {mv:>4} mV"), style));
+            }
+            None => {
+                spans.push(Span::raw(" ".repeat(bar_width)));
+                spans.push(Span::styled("    - mV", Color::DarkGray));
+            }
+        }
+        spans.push(Span::styled(
+            match extremes {
+                Some((min, _)) if min == index => " MIN",
+                Some((_, max)) if max == index => " MAX",
+                _ => "    ",
+            },
+            style,
+        ));
+        if cell.balancing {
+            spans.push(Span::styled(" BAL", Color::Yellow));
+        }
+        lines.push(Line::from(spans));
+    }
+    let title = format!("Cells {min}..{max} mV, spread {} mV", max - min);
+    Paragraph::new(lines).block(Block::bordered().title(title))
+}
+
+/// Returns the color of a temperature in the heat-map.
+pub fn heat(deg_c: f64) -> Color {
+    match deg_c {
+        t if t < 0.0 => Color::Blue,
+        t if t < 15.0 => Color::Cyan,
+        t if t < 30.0 => Color::Green,
+        t if t < 40.0 => Color::Yellow,
+        t if t < 50.0 => Color::LightRed,
+        _ => Color::Red,
+    }
+}
+
+fn heat_map(sample: &Sample, width: u16) -> Paragraph<'_> {
+    let columns = usize::from((width.saturating_sub(2) / TILE_WIDTH).max(1));
+    let mut lines = Vec::new();
+    for (row, cells) in sample.cells.chunks(columns).enumerate() {
+        let mut labels = Vec::new();
+        let mut values = Vec::new();
+        for (column, cell) in cells.iter().enumerate() {
+            let style = match cell.temperature_c {
+                Some(t) => Style::new().fg(Color::Black).bg(heat(t)),
+                None => Style::new().fg(Color::DarkGray),
+            };
+            let label = format!("#{}", row * columns + column);
+            labels.push(Span::styled(format!("{label:^6}"), style));
+            labels.push(Span::raw(" "));
+            let value = optional(cell.temperature_c.map(|t| format!("{t:.1}")));
+            values.push(Span::styled(format!("{value:^6}"), style));
+            values.push(Span::raw(" "));
+        }
+        lines.push(Line::from(labels));
+        lines.push(Line::from(values));
+    }
+
+    let temperatures = sample.cells.iter().filter_map(|cell| cell.temperature_c);
+    let min = temperatures.clone().reduce(f64::min);
+    let title = match (min, temperatures.reduce(f64::max)) {
+        (Some(min), Some(max)) => format!("Temperatures {min:.1}..{max:.1} C"),
+        _ => "Temperatures".into(),
+    };
+    Paragraph::new(lines).block(Block::bordered().title(title))
+}
@@ -0,0 +1,8 @@
+//! Host side of the cellguard tools.
+//!
+//! The `cellguard` binary is built from these modules; they are a library so
+//! the tests can drive the dashboard without a terminal.
+
+pub mod dashboard;
+pub mod link;
+pub mod output;
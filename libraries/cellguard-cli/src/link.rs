@@ -0,0 +1,172 @@
+//! Requests to the cellcore over a serial port.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use cellguard_protocol::{
+    Address, CellReading, CrashReport, Decoder, ErrorCode, Event, Header, MAX_FRAME_LEN, NodeInfo,
+    PackStatus, Request, Response, SettingValue,
+};
+
+/// Number of times a request is repeated after a timeout.
+const RETRIES: u8 = 2;
+
+/// Connection to the cellcore.
+pub struct Link<P> {
+    port: P,
+    decoder: Decoder,
+    sequence: u8,
+    timeout: Duration,
+}
+
+impl Link<Box<dyn serialport::SerialPort>> {
+    /// Opens a serial port with 8N1 framing.
+    pub fn open(path: &str, baud: u32, timeout: Duration) -> Result<Self> {
+        let port = serialport::new(path, baud)
+            // Reads return early so the response timeout can be checked.
+            .timeout(Duration::from_millis(10))
+            .open()
+            .with_context(|| format!("failed to open {path}"))?;
+        // Drops a late response of a previous session.
+        port.clear(serialport::ClearBuffer::Input)?;
+        Ok(Self::new(port, timeout))
+    }
+}
+
+impl<P: Read + Write> Link<P> {
+    /// Creates a link on an open port.
+    pub fn new(port: P, timeout: Duration) -> Self {
+        Self {
+            port,
+            decoder: Decoder::new(),
+            sequence: 0,
+            timeout,
+        }
+    }
+
+    /// Sends a request to the cellcore and waits for the response.
+    ///
+    /// Rejected requests are returned as [`Response::Error`].
+    pub fn request(&mut self, request: &Request) -> Result<Response> {
+        for _ in 0..=RETRIES {
+            let header = Header::new(Address::CORE, Address::HOST, self.sequence);
+            self.sequence = self.sequence.wrapping_add(1);
+
+            let mut buf = [0; MAX_FRAME_LEN];
+            let len = request
+                .encode(header, &mut buf)
+                .expect("requests always fit into a frame");
+            self.port.write_all(&buf[..len])?;
+            self.port.flush()?;
+
+            if let Some(response) = self.receive(&header)? {
+                return Ok(response);
+            }
+        }
+        bail!("no response from the cellcore")
+    }
+
+    /// Waits for the response to the request with `header`.
+    ///
+    /// Returns `None` after the timeout.
+    fn receive(&mut self, header: &Header) -> Result<Option<Response>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0; 64];
+        while Instant::now() < deadline {
+            let len = match self.port.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for &byte in &buf[..len] {
+                let Some(Ok(frame)) = self.decoder.push(byte) else {
+                    continue;
+                };
+                if !frame.header.is_reply_to(header) {
+                    continue;
+                }
+                let response = Response::decode(&frame)
+                    .map_err(|code| anyhow::anyhow!("invalid response: {code:?}"))?;
+                return Ok(Some(response));
+            }
+        }
+        self.decoder.reset();
+        Ok(None)
+    }
+
+    /// Identifies the cellcore.
+    pub fn ping(&mut self) -> Result<NodeInfo> {
+        match self.request(&Request::Ping)? {
+            Response::Pong(info) => Ok(info),
+            response => unexpected(response),
+        }
+    }
+
+    /// Reads the pack state.
+    pub fn pack(&mut self) -> Result<PackStatus> {
+        match self.request(&Request::ReadPack)? {
+            Response::Pack(status) => Ok(status),
+            response => unexpected(response),
+        }
+    }
+
+    /// Reads the readings of all cells.
+    pub fn cells(&mut self) -> Result<Vec<CellReading>> {
+        let mut cells = Vec::new();
+        loop {
+            let first = u8::try_from(cells.len()).context("too many cells")?;
+            let block = match self.request(&Request::ReadCells(first))? {
+                Response::Cells(block) => block,
+                response => return unexpected(response),
+            };
+            cells.extend_from_slice(block.readings());
+            if block.readings().is_empty() || cells.len() >= usize::from(block.total) {
+                return Ok(cells);
+            }
+        }
+    }
+
+    /// Reads a setting.
+    pub fn setting(&mut self, id: u16) -> Result<i32> {
+        match self.request(&Request::ReadSetting(id))? {
+            Response::Setting(setting) => Ok(setting.value),
+            response => unexpected(response),
+        }
+    }
+
+    /// Changes a setting and returns the value taken.
+    pub fn write_setting(&mut self, id: u16, value: i32) -> Result<i32> {
+        match self.request(&Request::WriteSetting(SettingValue { id, value }))? {
+            Response::SettingWritten(setting) => Ok(setting.value),
+            Response::Error(ErrorCode::OutOfRange) => bail!("value out of range"),
+            response => unexpected(response),
+        }
+    }
+
+    /// Reads an entry of the event log.
+    ///
+    /// Returns `None` past the newest entry.
+    pub fn event(&mut self, index: u16) -> Result<Option<Event>> {
+        match self.request(&Request::ReadEvent(index))? {
+            Response::Event(event) => Ok(Some(event)),
+            Response::Error(ErrorCode::OutOfRange) => Ok(None),
+            response => unexpected(response),
+        }
+    }
+
+    /// Reads the report of the panic that caused the last reset.
+    pub fn crash(&mut self) -> Result<Option<CrashReport>> {
+        match self.request(&Request::ReadCrash)? {
+            Response::Crash(report) => Ok(report),
+            response => unexpected(response),
+        }
+    }
+}
+
+fn unexpected<T>(response: Response) -> Result<T> {
+    match response {
+        Response::Error(code) => bail!("request rejected: {code:?}"),
+        response => bail!("unexpected response: {response:?}"),
+    }
+}
@@ -0,0 +1,196 @@
+//! Command-line tool for the cellcore.
+//!
+//! Talks the cellguard protocol to the cellcore on its debug serial port to
+//! follow the telemetry, read and change settings, download the event log and
+//! read crash reports.
+//! Every command can write text, CSV or JSON to stdout or a file. The
+//! dashboard shows the telemetry in the terminal and can record it for a
+//! replay.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use cellcore::console::Setting;
+use cellguard_cli::dashboard::{self, Live, Replay};
+use cellguard_cli::link::Link;
+use cellguard_cli::output::{
+    self, CrashRecord, EventRecord, Format, Info, Sample, SampleWriter, SettingRecord,
+};
+use clap::{Parser, Subcommand};
+
+/// Talks to the cellcore over a serial port.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Serial port of the cellcore, like /dev/ttyACM0.
+    ///
+    /// Required by all commands but `replay`.
+    #[arg(short, long)]
+    port: Option<String>,
+    /// Baud rate of the serial port.
+    #[arg(long, default_value_t = 115_200)]
+    baud: u32,
+    /// Time to wait for a response in milliseconds.
+    #[arg(long, default_value_t = 500)]
+    timeout_ms: u64,
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// Writes the output to a file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows the firmware and protocol version.
+    Info,
+    /// Follows the pack and cell telemetry.
+    Tail {
+        /// Time between samples in milliseconds.
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Stops after this many samples.
+        #[arg(long)]
+        count: Option<u64>,
+    },
+    /// Shows or changes settings.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Downloads the event log, oldest entry first.
+    Log,
+    /// Shows the panic that caused the last reset, if any.
+    Crash,
+    /// Shows all cells in a terminal dashboard.
+    Dashboard {
+        /// Time between samples in milliseconds.
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Records the samples as JSON Lines for a replay.
+        #[arg(long)]
+        record: Option<PathBuf>,
+    },
+    /// Plays back a recorded session in the dashboard.
+    Replay {
+        /// Recording of the dashboard or of `tail --format json`.
+        file: PathBuf,
+        /// Playback speed relative to the recording.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Shows the settings starting with a prefix, like `ov.trip`.
+    Get {
+        /// Prefix of the setting names.
+        prefix: Option<String>,
+    },
+    /// Changes a setting, temperatures in °C.
+    Set {
+        /// Name of the setting, like `ov.trip.value`.
+        name: String,
+        /// New value.
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Command::Replay { file, speed } = &cli.command {
+        let samples = dashboard::load(file)?;
+        let name = file.display().to_string();
+        return dashboard::run(&mut Replay::new(name, samples, *speed), None);
+    }
+
+    let port = cli
+        .port
+        .as_deref()
+        .context("no serial port given, use --port")?;
+    let mut out: Box<dyn Write> = match &cli.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    };
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let mut link = Link::open(port, cli.baud, timeout)?;
+
+    match cli.command {
+        Command::Info => {
+            let info = Info::from(link.ping()?);
+            output::write_records(&mut out, cli.format, &[info])
+        }
+        Command::Tail { interval_ms, count } => {
+            let interval = Duration::from_millis(interval_ms);
+            let mut writer = SampleWriter::new(out, cli.format);
+            let start = Instant::now();
+            let mut taken = 0;
+            while count.is_none_or(|count| taken < count) {
+                let next = Instant::now() + interval;
+                let status = link.pack()?;
+                let cells = link.cells()?;
+                writer.write(&Sample::new(start.elapsed(), &status, &cells))?;
+                taken += 1;
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+            Ok(())
+        }
+        Command::Config(ConfigCommand::Get { prefix }) => {
+            let prefix = prefix.unwrap_or_default();
+            let mut records = Vec::new();
+            for setting in Setting::all() {
+                if setting.to_string().starts_with(&prefix) {
+                    let value = link.setting(setting.id())?;
+                    records.push(SettingRecord::new(setting, value));
+                }
+            }
+            output::write_records(&mut out, cli.format, &records)
+        }
+        Command::Config(ConfigCommand::Set { name, value }) => {
+            let setting =
+                Setting::from_name(&name).with_context(|| format!("unknown setting {name:?}"))?;
+            let value = output::parse_value(setting, &value)?;
+            let value = link.write_setting(setting.id(), value)?;
+            output::write_records(&mut out, cli.format, &[SettingRecord::new(setting, value)])
+        }
+        Command::Log => {
+            let mut records = Vec::new();
+            for index in 0..=u16::MAX {
+                let Some(event) = link.event(index)? else {
+                    break;
+                };
+                records.push(EventRecord::from(event));
+            }
+            output::write_records(&mut out, cli.format, &records)
+        }
+        Command::Crash => {
+            let records: Vec<_> = link.crash()?.map(CrashRecord::from).into_iter().collect();
+            output::write_records(&mut out, cli.format, &records)
+        }
+        Command::Dashboard {
+            interval_ms,
+            record,
+        } => {
+            let record = match record {
+                Some(path) => {
+                    let file = File::create(&path)
+                        .with_context(|| format!("failed to create {}", path.display()))?;
+                    Some(SampleWriter::new(file, Format::Json))
+                }
+                None => None,
+            };
+            let interval = Duration::from_millis(interval_ms);
+            dashboard::run(&mut Live::new(link, port.into(), interval), record)
+        }
+        Command::Replay { .. } => unreachable!("replays don't need a port"),
+    }
+}
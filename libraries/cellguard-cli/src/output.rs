@@ -0,0 +1,490 @@
+//! Records shown by the commands and their output formats.
+//!
+//! Lists like the settings or the event log are written as a whole: one line
+//! per record as text, a CSV table with a header or a JSON array. Telemetry
+//! samples are streamed: one line per sample as text, a CSV table whose
+//! header follows the first sample or JSON Lines, one object per line.
+
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use cellcore::console::{ResetCause, Setting, Unit};
+use cellcore::contactor::Fault;
+use cellcore::protection::Quantity;
+use cellguard_protocol::{
+    CellReading, CrashReport, Event, EventKind, NodeInfo, NodeKind, PackStatus,
+};
+use serde::{Deserialize, Serialize};
+
+/// Output format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+pub enum Format {
+    /// Human readable text.
+    Text,
+    /// Comma separated values with a header.
+    Csv,
+    /// JSON, JSON Lines for telemetry.
+    Json,
+}
+
+/// Writes a list of records.
+pub fn write_records<T>(out: &mut dyn Write, format: Format, records: &[T]) -> Result<()>
+where
+    T: Serialize + fmt::Display,
+{
+    match format {
+        Format::Text => {
+            for record in records {
+                writeln!(out, "{record}")?;
+            }
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut *out);
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Identification of the cellcore.
+#[derive(Serialize)]
+pub struct Info {
+    /// Kind of the node.
+    pub kind: &'static str,
+    /// Firmware version.
+    pub firmware: String,
+    /// Protocol version.
+    pub protocol: u8,
+}
+
+impl From<NodeInfo> for Info {
+    fn from(info: NodeInfo) -> Self {
+        let version = info.firmware;
+        Self {
+            kind: match info.kind {
+                NodeKind::Agent => "cellagent",
+                NodeKind::Core => "cellcore",
+                NodeKind::Host => "host",
+            },
+            firmware: format!("{}.{}.{}", version.major, version.minor, version.patch),
+            protocol: info.protocol,
+        }
+    }
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} firmware {}, protocol {}",
+            self.kind, self.firmware, self.protocol
+        )
+    }
+}
+
+/// Value of a setting in the unit shown to the user.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Number {
+    /// Value of a setting in an integer unit.
+    Integer(i32),
+    /// Temperature in °C.
+    Decimal(f64),
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{value}"),
+            Self::Decimal(value) => write!(f, "{value:.1}"),
+        }
+    }
+}
+
+/// Setting with its value.
+#[derive(Serialize)]
+pub struct SettingRecord {
+    /// Name like `ov.trip.value`.
+    pub name: String,
+    /// Identifier in the protocol.
+    pub id: u16,
+    /// Value in `unit`.
+    pub value: Number,
+    /// Unit symbol, empty for counts.
+    pub unit: &'static str,
+}
+
+impl SettingRecord {
+    /// Creates a record from a value in the unit of the protocol.
+    pub fn new(setting: Setting, value: i32) -> Self {
+        let unit = setting.unit();
+        Self {
+            name: setting.to_string(),
+            id: setting.id(),
+            value: if unit == Unit::Celsius {
+                Number::Decimal(celsius(value))
+            } else {
+                Number::Integer(value)
+            },
+            unit: unit.symbol().trim_start(),
+        }
+    }
+}
+
+impl fmt::Display for SettingRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.value)?;
+        if !self.unit.is_empty() {
+            write!(f, " {}", self.unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a value entered by the user into the unit of the protocol.
+///
+/// Temperatures are entered in °C and may have decimals.
+pub fn parse_value(setting: Setting, value: &str) -> Result<i32> {
+    if setting.unit() == Unit::Celsius {
+        let deg_c: f64 = value
+            .parse()
+            .with_context(|| format!("invalid temperature {value:?}"))?;
+        let raw = (deg_c * 16.0).round();
+        if !(f64::from(i32::MIN)..=f64::from(i32::MAX)).contains(&raw) {
+            bail!("temperature {value} out of range");
+        }
+        Ok(raw as i32)
+    } else {
+        value
+            .parse()
+            .with_context(|| format!("invalid value {value:?}"))
+    }
+}
+
+/// Converts 1/16 °C to °C.
+fn celsius(raw: i32) -> f64 {
+    f64::from(raw) / 16.0
+}
+
+/// Entry of the event log.
+#[derive(Serialize)]
+pub struct EventRecord {
+    /// Number of the event.
+    pub sequence: u32,
+    /// Time since reset in seconds.
+    pub uptime_s: u32,
+    /// What happened.
+    pub kind: &'static str,
+    /// Details depending on the kind.
+    pub detail: String,
+}
+
+impl From<Event> for EventRecord {
+    fn from(event: Event) -> Self {
+        let quantity = || {
+            Quantity::ALL
+                .get(usize::from(event.data))
+                .map_or_else(|| format!("quantity {}", event.data), |q| q.name().into())
+        };
+        let (kind, detail) = match event.kind {
+            EventKind::Reset => ("reset", ResetCause(event.data as u8).to_string()),
+            EventKind::Warning => ("warning", quantity()),
+            EventKind::Alarm => ("alarm", quantity()),
+            EventKind::Trip => ("trip", quantity()),
+            EventKind::TripsCleared => ("trips cleared", String::new()),
+            EventKind::ConfigChanged => (
+                "config changed",
+                Setting::from_id(event.data)
+                    .map_or_else(|| format!("setting {}", event.data), |s| s.to_string()),
+            ),
+            EventKind::ContactorFault => (
+                "contactor fault",
+                Fault::from_code(event.data)
+                    .map_or_else(|| format!("fault 0x{:04X}", event.data), |f| f.to_string()),
+            ),
+            EventKind::ContactorsClosed => ("contactors closed", String::new()),
+            EventKind::ContactorsOpened => ("contactors opened", String::new()),
+            EventKind::Crash => ("crash", format!("line {}", event.data)),
+        };
+        Self {
+            sequence: event.sequence,
+            uptime_s: event.uptime_s,
+            kind,
+            detail,
+        }
+    }
+}
+
+impl fmt::Display for EventRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:<6} {:>9} s  {}",
+            self.sequence, self.uptime_s, self.kind
+        )?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Panic that caused the last reset of the cellcore.
+#[derive(Serialize)]
+pub struct CrashRecord {
+    /// Name of the source file.
+    pub file: String,
+    /// Line in the source file.
+    pub line: u16,
+    /// CRC-16 of the panic message in hexadecimal.
+    pub message_hash: String,
+    /// Reset cause of the run that crashed.
+    pub reset_cause: String,
+    /// Number of crashes in a row.
+    pub crashes: u8,
+    /// Whether the cellcore runs in safe mode.
+    pub safe_mode: bool,
+}
+
+impl From<CrashReport> for CrashRecord {
+    fn from(report: CrashReport) -> Self {
+        Self {
+            file: report.file().into(),
+            line: report.line,
+            message_hash: format!("0x{:04X}", report.message_hash),
+            reset_cause: ResetCause(report.reset_cause).to_string(),
+            crashes: report.crashes,
+            safe_mode: report.safe_mode,
+        }
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}, message {}, crashed run: {}, crashes in a row: {}",
+            self.file, self.line, self.message_hash, self.reset_cause, self.crashes
+        )?;
+        if self.safe_mode {
+            write!(f, ", safe mode")?;
+        }
+        Ok(())
+    }
+}
+
+/// Telemetry of a cell.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CellSample {
+    /// Cell voltage in millivolts.
+    pub voltage_mv: Option<u16>,
+    /// Temperature in °C.
+    pub temperature_c: Option<f64>,
+    /// Whether the bleed resistor is switched on.
+    pub balancing: bool,
+}
+
+/// Telemetry of the pack at one point in time.
+///
+/// The JSON Lines written by `tail` and recorded by the dashboard parse back
+/// into samples for a replay.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Sample {
+    /// Time since the start of the recording in milliseconds.
+    pub time_ms: u64,
+    /// State of charge in percent.
+    pub soc_percent: Option<f64>,
+    /// Pack voltage in millivolts.
+    pub voltage_mv: Option<u32>,
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+    /// Quantities at least at the warning level.
+    pub warnings: Vec<String>,
+    /// Quantities at least at the alarm level.
+    pub alarms: Vec<String>,
+    /// Quantities that tripped.
+    pub trips: Vec<String>,
+    /// Charge current limit in milliamperes.
+    pub charge_current_ma: u32,
+    /// Discharge current limit in milliamperes.
+    pub discharge_current_ma: u32,
+    /// Charge voltage limit in millivolts.
+    pub charge_voltage_mv: u32,
+    /// Discharge voltage limit in millivolts.
+    pub discharge_voltage_mv: u32,
+    /// Telemetry of every cell.
+    pub cells: Vec<CellSample>,
+}
+
+impl Sample {
+    /// Creates a sample from the responses of the cellcore.
+    pub fn new(time: Duration, status: &PackStatus, cells: &[CellReading]) -> Self {
+        Self {
+            time_ms: time.as_millis().try_into().unwrap_or(u64::MAX),
+            soc_percent: status.soc.map(|soc| f64::from(soc) / 100.0),
+            voltage_mv: status.voltage_mv,
+            current_ma: status.current_ma,
+            warnings: quantities(status.warnings),
+            alarms: quantities(status.alarms),
+            trips: quantities(status.trips),
+            charge_current_ma: status.charge_current_ma,
+            discharge_current_ma: status.discharge_current_ma,
+            charge_voltage_mv: status.charge_voltage_mv,
+            discharge_voltage_mv: status.discharge_voltage_mv,
+            cells: cells
+                .iter()
+                .map(|cell| CellSample {
+                    voltage_mv: cell.voltage_mv,
+                    temperature_c: cell.temperature.map(|t| celsius(t.into())),
+                    balancing: cell.balancing,
+                })
+                .collect(),
+        }
+    }
+
+    fn csv_header(&self) -> Vec<String> {
+        let mut header: Vec<String> = [
+            "time_ms",
+            "soc_percent",
+            "voltage_mv",
+            "current_ma",
+            "warnings",
+            "alarms",
+            "trips",
+            "charge_current_ma",
+            "discharge_current_ma",
+            "charge_voltage_mv",
+            "discharge_voltage_mv",
+        ]
+        .map(String::from)
+        .into();
+        for index in 0..self.cells.len() {
+            header.push(format!("cell{index}_mv"));
+            header.push(format!("cell{index}_temperature_c"));
+            header.push(format!("cell{index}_balancing"));
+        }
+        header
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        let mut record = vec![
+            self.time_ms.to_string(),
+            optional(self.soc_percent),
+            optional(self.voltage_mv),
+            optional(self.current_ma),
+            self.warnings.join(" "),
+            self.alarms.join(" "),
+            self.trips.join(" "),
+            self.charge_current_ma.to_string(),
+            self.discharge_current_ma.to_string(),
+            self.charge_voltage_mv.to_string(),
+            self.discharge_voltage_mv.to_string(),
+        ];
+        for cell in &self.cells {
+            record.push(optional(cell.voltage_mv));
+            record.push(optional(cell.temperature_c));
+            record.push(cell.balancing.to_string());
+        }
+        record
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>8.1} s", self.time_ms as f64 / 1000.0)?;
+        match self.soc_percent {
+            Some(soc) => write!(f, "  SOC {soc:6.2} %")?,
+            None => write!(f, "  SOC      - %")?,
+        }
+        write!(f, "  {:>6} mV", optional(self.voltage_mv))?;
+        write!(f, "  {:>7} mA", optional(self.current_ma))?;
+        let voltages = self.cells.iter().filter_map(|cell| cell.voltage_mv);
+        if let (Some(min), Some(max)) = (voltages.clone().min(), voltages.max()) {
+            write!(f, "  cells {min}..{max} mV")?;
+        }
+        let temperatures = self.cells.iter().filter_map(|cell| cell.temperature_c);
+        let min = temperatures.clone().reduce(f64::min);
+        if let (Some(min), Some(max)) = (min, temperatures.reduce(f64::max)) {
+            write!(f, "  {min:.1}..{max:.1} C")?;
+        }
+        let balancing = self.cells.iter().filter(|cell| cell.balancing).count();
+        if balancing > 0 {
+            write!(f, "  balancing {balancing}")?;
+        }
+        for (name, quantities) in [
+            ("warnings", &self.warnings),
+            ("alarms", &self.alarms),
+            ("trips", &self.trips),
+        ] {
+            if !quantities.is_empty() {
+                write!(f, "  {name}: {}", quantities.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the names of the quantities flagged in `flags`.
+fn quantities(flags: u8) -> Vec<String> {
+    Quantity::ALL
+        .into_iter()
+        .enumerate()
+        .filter(|&(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, quantity)| quantity.name().into())
+        .collect()
+}
+
+/// Formats a value or `-` if it is missing.
+pub(crate) fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".into(), |value| value.to_string())
+}
+
+/// Streams telemetry samples.
+pub struct SampleWriter<W> {
+    out: W,
+    format: Format,
+    header_written: bool,
+}
+
+impl<W: Write> SampleWriter<W> {
+    /// Creates a writer.
+    pub const fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            header_written: false,
+        }
+    }
+
+    /// Writes a sample and flushes the output.
+    pub fn write(&mut self, sample: &Sample) -> Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.out, "{sample}")?,
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut self.out);
+                if !self.header_written {
+                    writer.write_record(sample.csv_header())?;
+                    self.header_written = true;
+                }
+                writer.write_record(sample.csv_record())?;
+                writer.flush()?;
+            }
+            Format::Json => {
+                serde_json::to_writer(&mut self.out, sample)?;
+                writeln!(self.out)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,646 @@
+//! Integration tests running the tool against a simulated cellcore on a
+//! pseudo-terminal.
+
+use std::io::{ErrorKind, Read, Write};
+use std::process::{Command, Output};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use cellcore::balancing::{self, Cell};
+use cellcore::config::PackConfig;
+use cellcore::console::{ResetCause, Setting};
+use cellcore::host::{self, Pack};
+use cellcore::limits::Limits;
+use cellcore::protection::{self, Measurements, Protection};
+use cellguard_cli::dashboard::{self, Dashboard, Live, Replay, Source};
+use cellguard_cli::link::Link;
+use cellguard_cli::output::{CellSample, Sample};
+use cellguard_protocol::{
+    CrashReport, Decoder, Event, EventKind, MAX_FRAME_LEN, PackStatus, Version,
+};
+use p3t1755::Temperature;
+use ratatui::Terminal;
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::style::Color;
+use serialport::{SerialPort, TTYPort};
+
+/// Cellcore state behind the simulated serial port.
+struct SimCore {
+    cells: Vec<Cell>,
+    bleeding: Vec<bool>,
+    protection: Protection,
+    balancing_config: balancing::Config,
+    pack_config: PackConfig,
+    changes: usize,
+    events: Vec<Event>,
+    crash: Option<CrashReport>,
+}
+
+impl SimCore {
+    /// A pack of eight cells with an active over-temperature warning.
+    fn new() -> Self {
+        let cells = (0..8)
+            .map(|index| Cell {
+                voltage_mv: (index != 5).then_some(3300 + index * 5),
+                temperature: Some(Temperature::from_degrees_celsius(25 + index as i8)),
+            })
+            .collect();
+        let mut protection = Protection::new(protection::Config::default());
+        protection.update(
+            60_000,
+            &Measurements {
+                max_cell_mv: Some(3335),
+                min_cell_mv: Some(3300),
+                max_temperature: Some(Temperature::from_degrees_celsius(52)),
+                min_temperature: Some(Temperature::from_degrees_celsius(25)),
+                current_ma: Some(0),
+            },
+        );
+        Self {
+            cells,
+            bleeding: vec![false, false, false, false, false, false, false, true],
+            protection,
+            balancing_config: balancing::Config::default(),
+            pack_config: PackConfig::default(),
+            changes: 0,
+            events: vec![
+                Event {
+                    sequence: 7,
+                    uptime_s: 0,
+                    kind: EventKind::Reset,
+                    data: (ResetCause::POWER_ON | ResetCause::BROWN_OUT).0.into(),
+                },
+                Event {
+                    sequence: 8,
+                    uptime_s: 3600,
+                    kind: EventKind::Warning,
+                    data: 2,
+                },
+                Event {
+                    sequence: 9,
+                    uptime_s: 3700,
+                    kind: EventKind::ConfigChanged,
+                    data: 0x100,
+                },
+                Event {
+                    sequence: 10,
+                    uptime_s: 3710,
+                    kind: EventKind::ContactorFault,
+                    data: 0x0202,
+                },
+                Event {
+                    sequence: 11,
+                    uptime_s: 0,
+                    kind: EventKind::Crash,
+                    data: 57,
+                },
+            ],
+            crash: None,
+        }
+    }
+}
+
+impl Pack for SimCore {
+    fn firmware(&self) -> Version {
+        Version::new(0, 4, 2)
+    }
+
+    fn soc(&self) -> Option<u16> {
+        Some(7550)
+    }
+
+    fn voltage_mv(&self) -> Option<u32> {
+        Some(26_540)
+    }
+
+    fn current_ma(&self) -> Option<i32> {
+        Some(-1500)
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            charge_ma: 20_000,
+            discharge_ma: 50_000,
+            charge_voltage_mv: 28_400,
+            discharge_voltage_mv: 22_400,
+        }
+    }
+
+    fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    fn is_bleeding(&self, cell: usize) -> bool {
+        self.bleeding[cell]
+    }
+
+    fn protection(&mut self) -> &mut Protection {
+        &mut self.protection
+    }
+
+    fn balancing_config(&mut self) -> &mut balancing::Config {
+        &mut self.balancing_config
+    }
+
+    fn pack_config(&mut self) -> &mut PackConfig {
+        &mut self.pack_config
+    }
+
+    fn config_changed(&mut self, _setting: Setting) {
+        self.changes += 1;
+    }
+
+    fn event(&self, index: u16) -> Option<Event> {
+        self.events.get(usize::from(index)).copied()
+    }
+
+    fn crash(&self) -> Option<CrashReport> {
+        self.crash
+    }
+}
+
+/// Simulated cellcore answering on the master side of a pseudo-terminal.
+struct Sim {
+    port: String,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<SimCore>,
+    // Keeps the terminal open between runs of the tool.
+    _slave: TTYPort,
+}
+
+impl Sim {
+    fn start(mut core: SimCore) -> Self {
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        master.set_timeout(Duration::from_millis(10)).unwrap();
+        let port = slave.name().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut decoder = Decoder::new();
+                let mut buf = [0; 64];
+                while !stop.load(Ordering::Relaxed) {
+                    let len = match master.read(&mut buf) {
+                        Ok(len) => len,
+                        Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                        Err(e) => panic!("{e}"),
+                    };
+                    for &byte in &buf[..len] {
+                        let Some(Ok(frame)) = decoder.push(byte) else {
+                            continue;
+                        };
+                        let mut reply = [0; MAX_FRAME_LEN];
+                        if let Some(len) = host::handle(&frame, &mut core, &mut reply) {
+                            master.write_all(&reply[..len]).unwrap();
+                        }
+                    }
+                }
+                core
+            }
+        });
+        Self {
+            port,
+            stop,
+            thread,
+            _slave: slave,
+        }
+    }
+
+    /// Runs the tool with the given arguments.
+    fn run(&self, args: &[&str]) -> Output {
+        cellguard(&self.port, args)
+    }
+
+    /// Stops the simulation and returns the final state.
+    fn stop(self) -> SimCore {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap()
+    }
+}
+
+fn cellguard(port: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cellguard"))
+        .args(["--port", port, "--timeout-ms", "200"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Returns stdout of a successful run.
+fn stdout(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_info() {
+    let sim = Sim::start(SimCore::new());
+    assert_eq!(
+        stdout(sim.run(&["info"])),
+        "cellcore firmware 0.4.2, protocol 1\n"
+    );
+    let info: serde_json::Value =
+        serde_json::from_str(&stdout(sim.run(&["-f", "json", "info"]))).unwrap();
+    assert_eq!(info[0]["firmware"], "0.4.2");
+    sim.stop();
+}
+
+#[test]
+fn test_tail_text() {
+    let sim = Sim::start(SimCore::new());
+    let out = stdout(sim.run(&["tail", "--count", "2", "--interval-ms", "0"]));
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains("SOC  75.50 %"), "{}", lines[0]);
+    assert!(lines[0].contains("26540 mV"));
+    assert!(lines[0].contains("-1500 mA"));
+    assert!(lines[0].contains("cells 3300..3335 mV"));
+    assert!(lines[0].contains("25.0..32.0 C"));
+    assert!(lines[0].contains("balancing 1"));
+    assert!(lines[0].ends_with("warnings: ot"));
+    sim.stop();
+}
+
+#[test]
+fn test_tail_json_lines() {
+    let sim = Sim::start(SimCore::new());
+    let out = stdout(sim.run(&["-f", "json", "tail", "--count", "3", "--interval-ms", "0"]));
+    let samples: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(samples.len(), 3);
+    let sample = &samples[0];
+    assert_eq!(sample["soc_percent"], 75.5);
+    assert_eq!(sample["warnings"], serde_json::json!(["ot"]));
+    assert_eq!(sample["alarms"], serde_json::json!([]));
+    assert_eq!(sample["charge_current_ma"], 20_000);
+    let cells = sample["cells"].as_array().unwrap();
+    assert_eq!(cells.len(), 8);
+    assert_eq!(cells[1]["voltage_mv"], 3305);
+    assert_eq!(cells[5]["voltage_mv"], serde_json::Value::Null);
+    assert_eq!(cells[7]["temperature_c"], 32.0);
+    assert_eq!(cells[7]["balancing"], true);
+    sim.stop();
+}
+
+#[test]
+fn test_tail_csv_to_file() {
+    let sim = Sim::start(SimCore::new());
+    let path = std::env::temp_dir().join(format!("cellguard-tail-{}.csv", std::process::id()));
+    let output = sim.run(&[
+        "-f",
+        "csv",
+        "-o",
+        path.to_str().unwrap(),
+        "tail",
+        "--count",
+        "2",
+        "--interval-ms",
+        "0",
+    ]);
+    assert_eq!(stdout(output), "");
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(
+        rows[0][..4],
+        ["time_ms", "soc_percent", "voltage_mv", "current_ma"]
+    );
+    assert_eq!(rows[0].len(), 11 + 8 * 3);
+    assert_eq!(
+        rows[0][11..14],
+        ["cell0_mv", "cell0_temperature_c", "cell0_balancing"]
+    );
+    assert_eq!(rows[1][1..5], ["75.5", "26540", "-1500", "ot"]);
+    // Cell 5 has no voltage reading.
+    assert_eq!(rows[1][11 + 5 * 3], "-");
+    assert_eq!(rows[2].len(), rows[0].len());
+    sim.stop();
+}
+
+#[test]
+fn test_config_get() {
+    let sim = Sim::start(SimCore::new());
+    assert_eq!(
+        stdout(sim.run(&["config", "get", "ot.trip"])),
+        "ot.trip.value = 60.0 C\not.trip.hysteresis = 10.0 C\not.trip.delay = 1000 ms\n"
+    );
+    let settings: serde_json::Value =
+        serde_json::from_str(&stdout(sim.run(&["-f", "json", "config", "get"]))).unwrap();
+    let settings = settings.as_array().unwrap();
+    assert_eq!(settings.len(), 6 * 3 * 3 + 6 + 2);
+    assert_eq!(settings[0]["name"], "ov.warning.value");
+    assert_eq!(settings[0]["id"], 0);
+    assert_eq!(settings[6 * 3 * 3 + 5]["name"], "bal.duration");
+    assert_eq!(settings[6 * 3 * 3 + 5]["unit"], "s");
+    assert_eq!(settings.last().unwrap()["name"], "pack.modbus_unit");
+    assert_eq!(settings.last().unwrap()["value"], 1);
+    assert_eq!(settings.last().unwrap()["unit"], "");
+
+    let csv = stdout(sim.run(&["-f", "csv", "config", "get", "bal.max_t"]));
+    assert_eq!(csv, "name,id,value,unit\nbal.max_temperature,260,50.0,C\n");
+    sim.stop();
+}
+
+#[test]
+fn test_config_set() {
+    let sim = Sim::start(SimCore::new());
+    assert_eq!(
+        stdout(sim.run(&["config", "set", "ov.trip.value", "3700"])),
+        "ov.trip.value = 3700 mV\n"
+    );
+    assert_eq!(
+        stdout(sim.run(&["config", "set", "ut.alarm.value", "-12.5"])),
+        "ut.alarm.value = -12.5 C\n"
+    );
+
+    // Out of range values and unknown settings change nothing.
+    let output = sim.run(&["config", "set", "bal.max_bleeding", "300"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    let output = sim.run(&["config", "set", "ov.trip", "1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown setting"));
+
+    let core = sim.stop();
+    assert_eq!(core.changes, 2);
+    assert_eq!(core.protection.config().over_voltage.trip.value, 3700);
+    assert_eq!(
+        core.protection.config().under_temperature.alarm.value,
+        -12 * 16 - 8
+    );
+    assert_eq!(core.balancing_config.max_bleeding, 4);
+}
+
+#[test]
+fn test_log() {
+    let sim = Sim::start(SimCore::new());
+    assert_eq!(
+        stdout(sim.run(&["log"])),
+        "#7              0 s  reset: power-on, brown-out\n\
+         #8           3600 s  warning: ot\n\
+         #9           3700 s  config changed: bal.threshold\n\
+         #10          3710 s  contactor fault: main negative welded\n\
+         #11             0 s  crash: line 57\n"
+    );
+    assert_eq!(
+        stdout(sim.run(&["-f", "csv", "log"])),
+        "sequence,uptime_s,kind,detail\n7,0,reset,\"power-on, \
+         brown-out\"\n8,3600,warning,ot\n9,3700,config changed,bal.threshold\n10,3710,contactor \
+         fault,main negative welded\n11,0,crash,line 57\n"
+    );
+    sim.stop();
+}
+
+#[test]
+fn test_empty_log() {
+    let mut core = SimCore::new();
+    core.events.clear();
+    let sim = Sim::start(core);
+    assert_eq!(stdout(sim.run(&["-f", "json", "log"])), "[]\n");
+    sim.stop();
+}
+
+#[test]
+fn test_crash() {
+    let mut core = SimCore::new();
+    core.crash = Some(CrashReport {
+        file: *b"contactor.rs",
+        line: 57,
+        message_hash: 0x0F3D,
+        reset_cause: ResetCause::EXTERNAL.0,
+        crashes: 3,
+        safe_mode: true,
+    });
+    let sim = Sim::start(core);
+    assert_eq!(
+        stdout(sim.run(&["crash"])),
+        "contactor.rs:57, message 0x0F3D, crashed run: external, crashes in a row: 3, safe mode\n"
+    );
+    let crash: serde_json::Value =
+        serde_json::from_str(&stdout(sim.run(&["-f", "json", "crash"]))).unwrap();
+    assert_eq!(crash[0]["file"], "contactor.rs");
+    assert_eq!(crash[0]["safe_mode"], true);
+    sim.stop();
+
+    let sim = Sim::start(SimCore::new());
+    assert_eq!(stdout(sim.run(&["-f", "json", "crash"])), "[]\n");
+    sim.stop();
+}
+
+#[test]
+fn test_no_response() {
+    let (_master, slave) = TTYPort::pair().unwrap();
+    let output = cellguard(&slave.name().unwrap(), &["info"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no response from the cellcore"));
+}
+
+#[test]
+fn test_missing_port() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cellguard"))
+        .arg("info")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no serial port given"));
+}
+
+/// Renders a dashboard into a buffer.
+fn render(dashboard: &Dashboard, width: u16, height: u16) -> Buffer {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|frame| dashboard.render(frame, frame.area()))
+        .unwrap();
+    terminal.backend().buffer().clone()
+}
+
+/// Returns the rows of a buffer as text.
+fn rows(buffer: &Buffer) -> Vec<String> {
+    (0..buffer.area.height)
+        .map(|y| {
+            (0..buffer.area.width)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the row and column at which `text` is shown.
+fn find(rows: &[String], text: &str) -> (u16, u16) {
+    rows.iter()
+        .enumerate()
+        .find_map(|(y, row)| {
+            let x = row.find(text)?;
+            Some((y as u16, row[..x].chars().count() as u16))
+        })
+        .unwrap_or_else(|| panic!("{text:?} not shown:\n{}", rows.join("\n")))
+}
+
+/// Takes a sample from the simulated cellcore.
+fn live_sample(sim: &Sim) -> Sample {
+    let link = Link::open(&sim.port, 115_200, Duration::from_millis(200)).unwrap();
+    let mut live = Live::new(link, sim.port.clone(), Duration::from_secs(1));
+    let sample = live.advance(Duration::ZERO).unwrap().unwrap();
+    // The next sample is due after the interval.
+    assert_eq!(live.advance(Duration::from_millis(500)).unwrap(), None);
+    assert!(live.advance(Duration::from_millis(500)).unwrap().is_some());
+    sample
+}
+
+#[test]
+fn test_dashboard_waiting() {
+    let mut dashboard = Dashboard::new();
+    dashboard.set_status("live /dev/ttyACM0".into());
+    dashboard.set_error("no response from the cellcore".into());
+    let rows = rows(&render(&dashboard, 100, 20));
+    find(&rows, "waiting for the cellcore");
+    let (y, _) = find(&rows, "live /dev/ttyACM0");
+    assert_eq!(y, 19);
+    find(&rows, "no response from the cellcore");
+}
+
+#[test]
+fn test_dashboard() {
+    let sim = Sim::start(SimCore::new());
+    let mut dashboard = Dashboard::new();
+    dashboard.update(live_sample(&sim));
+    dashboard.set_status("live sim".into());
+    sim.stop();
+
+    let buffer = render(&dashboard, 120, 24);
+    let rows = rows(&buffer);
+    find(&rows, "75.50 %");
+    find(&rows, "voltage 26540 mV");
+    find(&rows, "current -1500 mA");
+    find(&rows, "charge     20000 mA  28400 mV");
+    find(&rows, "discharge  50000 mA  22400 mV");
+    find(&rows, "trips: none");
+    let (y, x) = find(&rows, "warnings: ot");
+    assert_eq!(buffer[(x + 10, y)].fg, Color::Yellow);
+    find(&rows, "Cells 3300..3335 mV, spread 35 mV");
+    find(&rows, "Temperatures 25.0..32.0 C");
+
+    // The lowest and the highest cell are highlighted.
+    let (y, x) = find(&rows, "3300 mV MIN");
+    assert!(rows[y as usize].trim_start().starts_with("│  0 "));
+    assert_eq!(buffer[(x, y)].fg, Color::Cyan);
+    let (y, x) = find(&rows, "3335 mV MAX BAL");
+    assert!(rows[y as usize].trim_start().starts_with("│  7 "));
+    assert_eq!(buffer[(x, y)].fg, Color::Red);
+    assert_eq!(buffer[(x + 12, y)].fg, Color::Yellow);
+    // Bars grow with the voltage and cell 5 has none.
+    let bar = |cell: usize| {
+        rows[find(&rows, "Cells").0 as usize + 1 + cell]
+            .matches('█')
+            .count()
+    };
+    assert!(bar(0) < bar(1) && bar(6) < bar(7));
+    assert_eq!(bar(5), 0);
+    find(&rows, "   - mV");
+
+    // The heat-map colors every sensor by its temperature.
+    let (y, x) = find(&rows, " 25.0 ");
+    assert_eq!(buffer[(x, y)].bg, Color::Green);
+    let (y, x) = find(&rows, " 30.0 ");
+    assert_eq!(buffer[(x, y)].bg, Color::Yellow);
+    find(&rows, "  #7  ");
+}
+
+#[test]
+fn test_heat_colors() {
+    assert_eq!(dashboard::heat(-5.0), Color::Blue);
+    assert_eq!(dashboard::heat(10.0), Color::Cyan);
+    assert_eq!(dashboard::heat(29.9), Color::Green);
+    assert_eq!(dashboard::heat(30.0), Color::Yellow);
+    assert_eq!(dashboard::heat(45.0), Color::LightRed);
+    assert_eq!(dashboard::heat(60.0), Color::Red);
+}
+
+#[test]
+fn test_extremes() {
+    let cell = |voltage_mv| CellSample {
+        voltage_mv,
+        temperature_c: None,
+        balancing: false,
+    };
+    assert_eq!(dashboard::extremes(&[]), None);
+    assert_eq!(dashboard::extremes(&[cell(None)]), None);
+    assert_eq!(
+        dashboard::extremes(&[
+            cell(Some(3310)),
+            cell(None),
+            cell(Some(3290)),
+            cell(Some(3350))
+        ]),
+        Some((2, 3))
+    );
+}
+
+#[test]
+fn test_replay_recording() {
+    let sim = Sim::start(SimCore::new());
+    let path = std::env::temp_dir().join(format!("cellguard-replay-{}.jsonl", std::process::id()));
+    let output = sim.run(&[
+        "-f",
+        "json",
+        "-o",
+        path.to_str().unwrap(),
+        "tail",
+        "--count",
+        "3",
+        "--interval-ms",
+        "100",
+    ]);
+    assert_eq!(stdout(output), "");
+    sim.stop();
+    let samples = dashboard::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(samples.len(), 3);
+    assert_eq!(samples[0].soc_percent, Some(75.5));
+    assert_eq!(samples[0].warnings, ["ot"]);
+    assert_eq!(samples[0].cells[5].voltage_mv, None);
+    assert!(samples[1].time_ms >= samples[0].time_ms + 100);
+
+    // The first sample shows right away, the others at twice the pace.
+    let mut replay = Replay::new("session".into(), samples.clone(), 2.0);
+    assert_eq!(
+        replay.advance(Duration::ZERO).unwrap().as_ref(),
+        Some(&samples[0])
+    );
+    assert_eq!(replay.status(), "replay session 1/3 at 2x");
+    let gap = Duration::from_millis(samples[1].time_ms - samples[0].time_ms);
+    assert_eq!(replay.advance(gap / 4).unwrap(), None);
+    assert_eq!(replay.advance(gap / 4).unwrap().as_ref(), Some(&samples[1]));
+    assert!(!replay.is_finished());
+    // Overdue samples are skipped.
+    assert_eq!(
+        replay.advance(Duration::from_secs(60)).unwrap().as_ref(),
+        Some(&samples[2])
+    );
+    assert!(replay.is_finished());
+    assert_eq!(replay.status(), "replay session finished");
+    assert_eq!(replay.advance(Duration::from_secs(60)).unwrap(), None);
+}
+
+#[test]
+fn test_load_invalid_recording() {
+    let path = std::env::temp_dir().join(format!("cellguard-invalid-{}.jsonl", std::process::id()));
+    let mut sample = Sample::new(Duration::ZERO, &PackStatus::default(), &[]);
+    sample.warnings.push("ov".into());
+    let line = serde_json::to_string(&sample).unwrap();
+    std::fs::write(&path, format!("{line}\n\n{line}\n{{}}\n")).unwrap();
+    let error = dashboard::load(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(error.to_string(), "invalid sample on line 4");
+}
@@ -0,0 +1,83 @@
+//! Versioned configuration records in EEPROM with A/B copies.
+//!
+//! A [`Store`] keeps a typed configuration in two slots of the same length,
+//! each holding one record:
+//!
+//! | Offset | Size | Content                                     |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 1    | [`MAGIC`]                                   |
+//! | 1      | 1    | Schema version, see [`Schema::VERSION`]     |
+//! | 2      | 1    | Sequence number                             |
+//! | 3      | 2    | Payload length                              |
+//! | 5      | n    | Payload written by [`Schema::encode`]       |
+//! | 5 + n  | 2    | CRC-16 of offsets 0 to 4 + n, big endian    |
+//!
+//! Multi-byte values are little endian like in the protocol. The record with
+//! the higher sequence number is the current one. Saving writes the other
+//! slot, so a power failure while writing leaves the current record intact
+//! and the torn record fails its CRC. Both slots are written in turn, which
+//! halves the wear of each.
+//!
+//! Records of older schema versions are upgraded by [`Schema::migrate`].
+//! Without a readable record the store falls back to the factory defaults,
+//! which are the [`Default`] of the configuration.
+
+#![no_std]
+
+pub use self::codec::{Reader, Writer};
+pub use self::store::{Error, Origin, Store};
+
+mod codec;
+pub mod mem;
+mod store;
+
+/// First byte of every record.
+///
+/// Erased EEPROM reads as 0xFF, so a blank slot is never taken for a record.
+pub const MAGIC: u8 = 0xC6;
+
+/// Length of a record without its payload.
+pub const OVERHEAD: usize = 7;
+
+/// Byte addressed non-volatile memory like the EEPROM of the AVR.
+pub trait Nvm {
+    /// Reads `buf.len()` bytes starting at `offset`.
+    fn read(&mut self, offset: usize, buf: &mut [u8]);
+
+    /// Writes `data` starting at `offset`.
+    ///
+    /// Returns once the data is written. Implementations take care of
+    /// erasing and of page boundaries.
+    fn write(&mut self, offset: usize, data: &[u8]);
+}
+
+/// A configuration stored in a [`Store`].
+pub trait Schema: Default + Sized {
+    /// Version of the layout written by [`Schema::encode`].
+    ///
+    /// Incremented whenever the layout changes, with a migration from the
+    /// previous version.
+    const VERSION: u8;
+
+    /// Length of the payload written by [`Schema::encode`].
+    const LEN: usize;
+
+    /// Writes the configuration in the current layout.
+    fn encode(&self, writer: &mut Writer<'_>);
+
+    /// Reads a configuration in the current layout.
+    ///
+    /// Returns `None` if a value is invalid.
+    fn decode(reader: &mut Reader<'_>) -> Option<Self>;
+
+    /// Upgrades the `len` bytes at the start of `payload` from `version` to
+    /// the next version and returns the new length.
+    ///
+    /// `payload` has room for the largest payload fitting into the slot.
+    /// Returns `None` if there is no migration from `version`, which is the
+    /// default.
+    fn migrate(version: u8, payload: &mut [u8], len: usize) -> Option<usize> {
+        let _ = (version, payload, len);
+        None
+    }
+}
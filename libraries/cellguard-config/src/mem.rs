@@ -0,0 +1,90 @@
+//! EEPROM model for host tests.
+
+use crate::Nvm;
+
+/// EEPROM of `N` bytes kept in RAM.
+///
+/// Power can be cut after a number of written bytes to test that updates
+/// survive power failures. The byte being written when the power fails is
+/// left erased, like after an interrupted erase and write cycle.
+#[derive(Clone, Debug)]
+pub struct MemNvm<const N: usize> {
+    /// Contents of the memory.
+    pub bytes: [u8; N],
+    /// Number of writes to each byte.
+    pub wear: [u32; N],
+    /// Number of bytes that can still be written before the power fails.
+    budget: Option<usize>,
+    cut: bool,
+}
+
+impl<const N: usize> MemNvm<N> {
+    /// Creates an erased memory, which reads as 0xFF.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0xFF; N],
+            wear: [0; N],
+            budget: None,
+            cut: false,
+        }
+    }
+
+    /// Lets the power fail after `bytes` more bytes have been written.
+    ///
+    /// Later writes are lost until [`MemNvm::restore_power`].
+    pub const fn cut_power_after(&mut self, bytes: usize) {
+        self.budget = Some(bytes);
+    }
+
+    /// Restores the power.
+    pub const fn restore_power(&mut self) {
+        self.budget = None;
+        self.cut = false;
+    }
+
+    /// Returns true once the power has failed.
+    #[must_use]
+    pub const fn is_cut(&self) -> bool {
+        self.cut
+    }
+
+    /// Returns the highest number of writes to a single byte.
+    #[must_use]
+    pub fn max_wear(&self) -> u32 {
+        self.wear.iter().copied().max().unwrap_or(0)
+    }
+}
+
+impl<const N: usize> Default for MemNvm<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Nvm for MemNvm<N> {
+    fn read(&mut self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.bytes[offset..offset + buf.len()]);
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        for (index, &byte) in (offset..).zip(data) {
+            if self.cut {
+                return;
+            }
+            match &mut self.budget {
+                Some(0) => {
+                    // The power fails while this byte is written.
+                    self.bytes[index] = 0xFF;
+                    self.wear[index] += 1;
+                    self.cut = true;
+                    return;
+                }
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+            self.bytes[index] = byte;
+            self.wear[index] += 1;
+        }
+    }
+}
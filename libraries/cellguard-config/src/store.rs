@@ -0,0 +1,250 @@
+//! Loading and saving the A/B copies.
+
+use cellguard_protocol::crc16;
+
+use crate::{MAGIC, Nvm, OVERHEAD, Reader, Schema, Writer};
+
+/// Length of the record in front of the payload.
+const HEADER_LEN: usize = 5;
+
+/// Bytes compared at once when checking a record.
+const CHUNK_LEN: usize = 16;
+
+/// Where the configuration of a [`Store`] came from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Origin {
+    /// Read from a record of the current version.
+    Stored,
+    /// Upgraded from a record of an older version.
+    ///
+    /// Saving writes the current version, so the migration runs only once.
+    /// [`Store::finish_migration`] saves right after loading.
+    Migrated {
+        /// Version of the record.
+        from: u8,
+    },
+    /// Factory defaults since no record could be read.
+    Defaults,
+}
+
+/// Error while saving a configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The record read back differs from the one written.
+    ///
+    /// The EEPROM may be worn out. The previous record stays current.
+    Verify,
+}
+
+/// Header of an intact record.
+#[derive(Clone, Copy)]
+struct Header {
+    version: u8,
+    sequence: u8,
+    len: usize,
+}
+
+/// A configuration kept in two slots of `N` bytes each.
+///
+/// The slots follow each other starting at an offset in the memory.
+pub struct Store<T, M, const N: usize> {
+    nvm: M,
+    offset: usize,
+    config: T,
+    origin: Origin,
+    /// Slot of the record the configuration was read from or last saved to.
+    current: Option<usize>,
+    /// Highest sequence number of an intact record.
+    sequence: u8,
+}
+
+impl<T: Schema, M: Nvm, const N: usize> Store<T, M, N> {
+    /// Loads the configuration from the slots starting at `offset`.
+    ///
+    /// Takes the newest intact record that can be read by this version of
+    /// the schema. Falls back to the other slot and finally to the factory
+    /// defaults.
+    pub fn load(mut nvm: M, offset: usize) -> Self {
+        const {
+            assert!(
+                OVERHEAD + T::LEN <= N,
+                "slot too small for the configuration"
+            )
+        };
+        let mut buf = [0; N];
+        let headers = [0, 1].map(|slot| read_record(&mut nvm, offset + slot * N, &mut buf));
+        let newest = match headers {
+            [Some(a), Some(b)] if is_newer(b.sequence, a.sequence) => 1,
+            [None, Some(_)] => 1,
+            _ => 0,
+        };
+        let sequence = headers[newest].map_or(0, |header| header.sequence);
+
+        for slot in [newest, 1 - newest] {
+            let Some(header) = headers[slot] else {
+                continue;
+            };
+            read_record(&mut nvm, offset + slot * N, &mut buf);
+            if let Some((config, origin)) = decode(header, &mut buf[HEADER_LEN..N - 2]) {
+                return Self {
+                    nvm,
+                    offset,
+                    config,
+                    origin,
+                    current: Some(slot),
+                    sequence,
+                };
+            }
+        }
+        Self {
+            nvm,
+            offset,
+            config: T::default(),
+            origin: Origin::Defaults,
+            current: None,
+            sequence,
+        }
+    }
+
+    /// Returns the configuration.
+    pub const fn config(&self) -> &T {
+        &self.config
+    }
+
+    /// Returns where the configuration came from.
+    pub const fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Changes the configuration and writes it to the other slot.
+    ///
+    /// The configuration is changed even if writing fails. Nothing is written
+    /// if the current record already holds the same configuration.
+    pub fn save(&mut self, config: T) -> Result<(), Error> {
+        self.config = config;
+        let mut buf = [0; N];
+        let mut writer = Writer::new(&mut buf[HEADER_LEN..N - 2]);
+        self.config.encode(&mut writer);
+        let len = writer.len();
+        debug_assert_eq!(len, T::LEN, "encoded length differs from the schema");
+        let end = HEADER_LEN + len;
+
+        if let Some(slot) = self.current
+            && self.origin == Origin::Stored
+            && matches(
+                &mut self.nvm,
+                self.offset + slot * N + HEADER_LEN,
+                &buf[HEADER_LEN..end],
+            )
+        {
+            return Ok(());
+        }
+
+        let sequence = self.sequence.wrapping_add(1);
+        let [len_lo, len_hi] = (len as u16).to_le_bytes();
+        buf[..HEADER_LEN].copy_from_slice(&[MAGIC, T::VERSION, sequence, len_lo, len_hi]);
+        let crc = crc16(&buf[..end]);
+        buf[end..end + 2].copy_from_slice(&crc.to_be_bytes());
+        let record = &buf[..end + 2];
+
+        let slot = self.current.map_or(0, |slot| 1 - slot);
+        let offset = self.offset + slot * N;
+        self.nvm.write(offset, record);
+        if !matches(&mut self.nvm, offset, record) {
+            return Err(Error::Verify);
+        }
+        self.current = Some(slot);
+        self.sequence = sequence;
+        self.origin = Origin::Stored;
+        Ok(())
+    }
+
+    /// Writes a migrated configuration in the current version, so the
+    /// migration runs only once.
+    ///
+    /// Does nothing unless the configuration was [`Origin::Migrated`].
+    pub fn finish_migration(&mut self) -> Result<(), Error> {
+        match self.origin {
+            Origin::Migrated { .. } => {
+                let config = core::mem::take(&mut self.config);
+                self.save(config)
+            }
+            Origin::Stored | Origin::Defaults => Ok(()),
+        }
+    }
+
+    /// Returns the memory, for example to load it again.
+    pub fn release(self) -> M {
+        self.nvm
+    }
+}
+
+/// Returns true if sequence number `a` follows `b`.
+const fn is_newer(a: u8, b: u8) -> bool {
+    (a.wrapping_sub(b) as i8) > 0
+}
+
+/// Reads the record in the slot at `offset` into `buf`, which is as long as
+/// the slot.
+///
+/// Returns the header if the record is intact.
+fn read_record<M: Nvm>(nvm: &mut M, offset: usize, buf: &mut [u8]) -> Option<Header> {
+    nvm.read(offset, &mut buf[..HEADER_LEN]);
+    if buf[0] != MAGIC {
+        return None;
+    }
+    let len = usize::from(u16::from_le_bytes([buf[3], buf[4]]));
+    let end = HEADER_LEN + len;
+    if end + 2 > buf.len() {
+        return None;
+    }
+    nvm.read(offset + HEADER_LEN, &mut buf[HEADER_LEN..end + 2]);
+    if crc16(&buf[..end]).to_be_bytes() != buf[end..end + 2] {
+        return None;
+    }
+    Some(Header {
+        version: buf[1],
+        sequence: buf[2],
+        len,
+    })
+}
+
+/// Migrates and decodes the payload of a record.
+///
+/// `payload` starts with the payload and extends to the end of the slot.
+fn decode<T: Schema>(header: Header, payload: &mut [u8]) -> Option<(T, Origin)> {
+    // Records written by a later firmware can't be read.
+    if header.version > T::VERSION {
+        return None;
+    }
+    let mut len = header.len;
+    for version in header.version..T::VERSION {
+        len = T::migrate(version, payload, len)?;
+        if len > payload.len() {
+            return None;
+        }
+    }
+    let mut reader = Reader::new(&payload[..len]);
+    let config = T::decode(&mut reader)?;
+    if !reader.is_empty() {
+        return None;
+    }
+    let origin = if header.version == T::VERSION {
+        Origin::Stored
+    } else {
+        Origin::Migrated {
+            from: header.version,
+        }
+    };
+    Some((config, origin))
+}
+
+/// Returns true if the memory at `offset` holds `data`.
+fn matches<M: Nvm>(nvm: &mut M, offset: usize, data: &[u8]) -> bool {
+    let mut chunk = [0; CHUNK_LEN];
+    data.chunks(CHUNK_LEN).enumerate().all(|(index, expected)| {
+        let chunk = &mut chunk[..expected.len()];
+        nvm.read(offset + index * CHUNK_LEN, chunk);
+        chunk == expected
+    })
+}
@@ -0,0 +1,360 @@
+//! Integration tests of the configuration store against the EEPROM model.
+
+use cellguard_config::mem::MemNvm;
+use cellguard_config::{Error, MAGIC, OVERHEAD, Origin, Reader, Schema, Store, Writer};
+use cellguard_protocol::crc16;
+
+/// Configuration whose layout grew over three versions.
+///
+/// Version 1 only had the threshold, version 2 added `enabled` and version 3
+/// added `delay_ms`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Demo {
+    threshold_mv: u16,
+    enabled: bool,
+    delay_ms: u32,
+}
+
+impl Default for Demo {
+    fn default() -> Self {
+        Self {
+            threshold_mv: 3600,
+            enabled: false,
+            delay_ms: 500,
+        }
+    }
+}
+
+impl Schema for Demo {
+    const VERSION: u8 = 3;
+    const LEN: usize = 7;
+
+    fn encode(&self, writer: &mut Writer<'_>) {
+        writer.u16(self.threshold_mv);
+        writer.bool(self.enabled);
+        writer.u32(self.delay_ms);
+    }
+
+    fn decode(reader: &mut Reader<'_>) -> Option<Self> {
+        let threshold_mv = reader.u16()?;
+        // Thresholds above 5 V are invalid.
+        if threshold_mv > 5000 {
+            return None;
+        }
+        Some(Self {
+            threshold_mv,
+            enabled: reader.bool()?,
+            delay_ms: reader.u32()?,
+        })
+    }
+
+    fn migrate(version: u8, payload: &mut [u8], len: usize) -> Option<usize> {
+        match version {
+            // Existing installations were enabled.
+            1 => {
+                payload[len] = 1;
+                Some(len + 1)
+            }
+            2 => {
+                payload[len..len + 4].copy_from_slice(&1000u32.to_le_bytes());
+                Some(len + 4)
+            }
+            _ => None,
+        }
+    }
+}
+
+const SLOT_LEN: usize = 16;
+const OFFSET: usize = 8;
+
+type Nvm = MemNvm<64>;
+type DemoStore = Store<Demo, Nvm, SLOT_LEN>;
+
+const CHANGED: Demo = Demo {
+    threshold_mv: 3550,
+    enabled: true,
+    delay_ms: 2000,
+};
+
+/// Builds a record like the store writes it.
+fn record(version: u8, sequence: u8, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len() as u16;
+    let mut record = vec![MAGIC, version, sequence];
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(payload);
+    let crc = crc16(&record);
+    record.extend_from_slice(&crc.to_be_bytes());
+    record
+}
+
+/// Places a record in a slot.
+fn put(nvm: &mut Nvm, slot: usize, record: &[u8]) {
+    let offset = OFFSET + slot * SLOT_LEN;
+    nvm.bytes[offset..offset + record.len()].copy_from_slice(record);
+}
+
+/// Loads the store again from its memory.
+fn reload(store: DemoStore) -> DemoStore {
+    Store::load(store.release(), OFFSET)
+}
+
+#[test]
+fn test_blank_memory_gives_defaults() {
+    let store = DemoStore::load(Nvm::new(), OFFSET);
+    assert_eq!(store.origin(), Origin::Defaults);
+    assert_eq!(*store.config(), Demo::default());
+    // Loading doesn't write anything.
+    assert_eq!(store.release().max_wear(), 0);
+}
+
+#[test]
+fn test_save_and_load() {
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    store.save(CHANGED).unwrap();
+    assert_eq!(store.origin(), Origin::Stored);
+    let store = reload(store);
+    assert_eq!(store.origin(), Origin::Stored);
+    assert_eq!(*store.config(), CHANGED);
+
+    let nvm = store.release();
+    assert_eq!(
+        nvm.bytes[OFFSET..OFFSET + OVERHEAD + Demo::LEN],
+        record(3, 1, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0, 0])
+    );
+    // Nothing is written outside of the slot.
+    assert!(nvm.bytes[..OFFSET].iter().all(|&byte| byte == 0xFF));
+    assert!(
+        nvm.bytes[OFFSET + OVERHEAD + Demo::LEN..]
+            .iter()
+            .all(|&byte| byte == 0xFF)
+    );
+}
+
+#[test]
+fn test_saves_alternate_between_slots() {
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    for threshold_mv in 3000..3100 {
+        store
+            .save(Demo {
+                threshold_mv,
+                ..CHANGED
+            })
+            .unwrap();
+        store = reload(store);
+        assert_eq!(store.config().threshold_mv, threshold_mv);
+    }
+    let nvm = store.release();
+    // Each slot took half of the writes.
+    assert_eq!(nvm.max_wear(), 50);
+    assert_eq!(nvm.wear[OFFSET], 50);
+    assert_eq!(nvm.wear[OFFSET + SLOT_LEN], 50);
+}
+
+#[test]
+fn test_sequence_number_wraps() {
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    for threshold_mv in 0..600 {
+        store
+            .save(Demo {
+                threshold_mv,
+                ..CHANGED
+            })
+            .unwrap();
+        if threshold_mv % 50 == 0 || (250..260).contains(&threshold_mv) {
+            store = reload(store);
+            assert_eq!(store.config().threshold_mv, threshold_mv);
+        }
+    }
+    assert_eq!(reload(store).config().threshold_mv, 599);
+}
+
+#[test]
+fn test_unchanged_config_is_not_written() {
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    store.save(CHANGED).unwrap();
+    let mut store = reload(store);
+    store.save(CHANGED).unwrap();
+    store.save(CHANGED).unwrap();
+    assert_eq!(store.release().max_wear(), 1);
+
+    // The defaults are written once they are saved.
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    store.save(Demo::default()).unwrap();
+    assert_eq!(reload(store).origin(), Origin::Stored);
+}
+
+#[test]
+fn test_power_failure_keeps_previous_record() {
+    let len = OVERHEAD + Demo::LEN;
+    for written in 0..=len {
+        let mut store = DemoStore::load(Nvm::new(), OFFSET);
+        store.save(CHANGED).unwrap();
+        let mut nvm = store.release();
+        nvm.cut_power_after(written);
+        let mut store = DemoStore::load(nvm, OFFSET);
+        let update = Demo {
+            threshold_mv: 3000,
+            ..CHANGED
+        };
+        let result = store.save(update);
+
+        let mut nvm = store.release();
+        nvm.restore_power();
+        let store = DemoStore::load(nvm, OFFSET);
+        if written < len {
+            assert_eq!(result, Err(Error::Verify), "{written} bytes written");
+            assert_eq!(*store.config(), CHANGED, "{written} bytes written");
+        } else {
+            assert_eq!(result, Ok(()));
+            assert_eq!(*store.config(), update);
+        }
+        assert_eq!(store.origin(), Origin::Stored);
+    }
+}
+
+#[test]
+fn test_power_failure_on_first_save() {
+    let mut nvm = Nvm::new();
+    nvm.cut_power_after(4);
+    let mut store = DemoStore::load(nvm, OFFSET);
+    assert_eq!(store.save(CHANGED), Err(Error::Verify));
+    // The configuration is used until the next reset.
+    assert_eq!(*store.config(), CHANGED);
+    let mut nvm = store.release();
+    assert!(nvm.is_cut());
+    nvm.restore_power();
+    assert_eq!(DemoStore::load(nvm, OFFSET).origin(), Origin::Defaults);
+}
+
+#[test]
+fn test_corrupt_record_falls_back_to_other_slot() {
+    let mut store = DemoStore::load(Nvm::new(), OFFSET);
+    store.save(CHANGED).unwrap();
+    store
+        .save(Demo {
+            threshold_mv: 3000,
+            ..CHANGED
+        })
+        .unwrap();
+    let mut nvm = store.release();
+    // The second save went to slot B.
+    nvm.bytes[OFFSET + SLOT_LEN + 6] ^= 0x01;
+    let mut store = DemoStore::load(nvm, OFFSET);
+    assert_eq!(*store.config(), CHANGED);
+
+    // The next save replaces the corrupt record and wins.
+    store.save(Demo::default()).unwrap();
+    let store = reload(store);
+    assert_eq!(*store.config(), Demo::default());
+    assert_eq!(store.release().bytes[OFFSET + SLOT_LEN + 2], 2);
+}
+
+#[test]
+fn test_migration() {
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 1, &record(1, 9, &3450u16.to_le_bytes()));
+    let mut store = DemoStore::load(nvm, OFFSET);
+    assert_eq!(store.origin(), Origin::Migrated { from: 1 });
+    let migrated = Demo {
+        threshold_mv: 3450,
+        enabled: true,
+        delay_ms: 1000,
+    };
+    assert_eq!(*store.config(), migrated);
+
+    // Finishing the migration writes the current version to the other slot.
+    store.finish_migration().unwrap();
+    let mut store = reload(store);
+    assert_eq!(store.origin(), Origin::Stored);
+    // Afterwards there is nothing left to write.
+    store.finish_migration().unwrap();
+    assert_eq!(*store.config(), migrated);
+    assert_eq!(store.release().bytes[OFFSET..OFFSET + 3], [MAGIC, 3, 10]);
+
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 0, &record(2, 0, &[0xD0, 0x07, 0]));
+    let store = DemoStore::load(nvm, OFFSET);
+    assert_eq!(store.origin(), Origin::Migrated { from: 2 });
+    assert_eq!(
+        *store.config(),
+        Demo {
+            threshold_mv: 2000,
+            enabled: false,
+            delay_ms: 1000,
+        }
+    );
+}
+
+#[test]
+fn test_unreadable_versions() {
+    let current = record(3, 4, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0, 0]);
+
+    // A record of a later firmware is skipped.
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 0, &current);
+    put(
+        &mut nvm,
+        1,
+        &record(4, 5, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0, 0, 0]),
+    );
+    let mut store = DemoStore::load(nvm, OFFSET);
+    assert_eq!(*store.config(), CHANGED);
+    // Saving overwrites it, but continues its sequence.
+    store.save(Demo::default()).unwrap();
+    let nvm = store.release();
+    assert_eq!(nvm.bytes[OFFSET + SLOT_LEN..][..3], [MAGIC, 3, 6]);
+
+    // Without a migration from version 0 only the defaults are left.
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 0, &record(0, 1, &[0xDE, 0x0D]));
+    assert_eq!(DemoStore::load(nvm, OFFSET).origin(), Origin::Defaults);
+}
+
+#[test]
+fn test_invalid_records() {
+    for record in [
+        // Threshold out of range.
+        record(3, 1, &[0x89, 0x13, 1, 0xD0, 0x07, 0, 0]),
+        // Not a bool.
+        record(3, 1, &[0xDE, 0x0D, 2, 0xD0, 0x07, 0, 0]),
+        // Too short and too long.
+        record(3, 1, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0]),
+        record(3, 1, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0, 0, 0]),
+        // Longer than the slot.
+        record(3, 1, &[0; SLOT_LEN]),
+    ] {
+        let mut nvm = Nvm::new();
+        let len = record.len().min(SLOT_LEN);
+        put(&mut nvm, 0, &record[..len]);
+        assert_eq!(DemoStore::load(nvm, OFFSET).origin(), Origin::Defaults);
+    }
+
+    // A wrong magic byte.
+    let mut record = record(3, 1, &[0xDE, 0x0D, 1, 0xD0, 0x07, 0, 0]);
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 0, &record);
+    assert_eq!(DemoStore::load(nvm, OFFSET).origin(), Origin::Stored);
+    record[0] = 0xC5;
+    let mut nvm = Nvm::new();
+    put(&mut nvm, 0, &record);
+    assert_eq!(DemoStore::load(nvm, OFFSET).origin(), Origin::Defaults);
+}
+
+#[test]
+fn test_power_cut_model() {
+    use cellguard_config::Nvm as _;
+
+    let mut nvm = MemNvm::<8>::new();
+    nvm.bytes = [0; 8];
+    nvm.cut_power_after(3);
+    nvm.write(1, &[1, 2, 3, 4, 5]);
+    assert!(nvm.is_cut());
+    // The fourth byte was being written when the power failed.
+    assert_eq!(nvm.bytes, [0, 1, 2, 3, 0xFF, 0, 0, 0]);
+    nvm.write(6, &[6]);
+    assert_eq!(nvm.bytes[6], 0);
+    nvm.restore_power();
+    nvm.write(6, &[6]);
+    assert_eq!(nvm.bytes[6], 6);
+    assert_eq!(nvm.wear, [0, 1, 1, 1, 1, 0, 1, 0]);
+}
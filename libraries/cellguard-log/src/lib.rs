@@ -0,0 +1,65 @@
+//! Append-only event log in a flash ring buffer.
+//!
+//! A [`Log`] keeps [`Event`]s of the protocol in fixed size records that are
+//! appended to the pages of a [`Flash`] in turn:
+//!
+//! | Offset | Size | Content                                  |
+//! |--------|------|------------------------------------------|
+//! | 0      | 1    | [`MAGIC`], written last                  |
+//! | 1      | 4    | Sequence number                          |
+//! | 5      | 4    | Uptime in seconds                        |
+//! | 9      | 1    | [`EventKind`]                            |
+//! | 10     | 2    | Data                                     |
+//! | 12     | 2    | CRC-16 of offsets 0 to 11, big endian    |
+//!
+//! The sequence number, uptime and data are little endian, in the byte order
+//! of an [`Event`] sent to host tools. The record with the highest sequence
+//! number is the newest one and the next record goes into the slot after it.
+//! Once the log reaches the end of a page, the next page is erased before the
+//! first record is written to it, which drops the oldest records. Every page
+//! is erased once per round, which spreads the wear evenly.
+//!
+//! A record is committed by writing its first byte after the rest. A power
+//! failure while writing leaves a record without [`MAGIC`] or with a wrong
+//! CRC, which is skipped. A power failure while erasing leaves the page
+//! after the newest record, which is erased again by the next append.
+//!
+//! [`Event`]: cellguard_protocol::Event
+//! [`EventKind`]: cellguard_protocol::EventKind
+
+#![no_std]
+
+pub use self::log::{Error, Log};
+
+mod log;
+pub mod mem;
+
+/// First byte of every record.
+///
+/// Differs from the 0xFF of an erased page and is written after the rest of
+/// the record, so neither a blank nor a torn slot starts with it.
+pub const MAGIC: u8 = 0xE5;
+
+/// Length of a record.
+pub const RECORD_LEN: usize = 14;
+
+/// Flash memory erased in pages, like the APPDATA section of the AVR.
+pub trait Flash {
+    /// Length of a page in bytes.
+    const PAGE_LEN: usize;
+
+    /// Number of pages.
+    const PAGES: usize;
+
+    /// Reads `buf.len()` bytes starting at `offset`.
+    fn read(&self, offset: usize, buf: &mut [u8]);
+
+    /// Sets every byte of a page to 0xFF.
+    fn erase(&mut self, page: usize);
+
+    /// Writes `data` starting at `offset`.
+    ///
+    /// Writing can only clear bits, so the bytes must have been erased.
+    /// Returns once the data is written.
+    fn program(&mut self, offset: usize, data: &[u8]);
+}
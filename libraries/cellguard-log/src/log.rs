@@ -0,0 +1,214 @@
+//! Appending to and reading the ring buffer.
+
+use cellguard_protocol::{Event, EventKind, crc16};
+
+use crate::{Flash, MAGIC, RECORD_LEN};
+
+/// Length of a record without its CRC.
+const BODY_LEN: usize = RECORD_LEN - 2;
+
+/// Error while appending an event.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The record read back differs from the one written.
+    ///
+    /// The flash may be worn out. The sequence number is used up and the
+    /// next event goes to the following slot.
+    Verify,
+}
+
+/// Event log in the pages of a flash.
+pub struct Log<F> {
+    flash: F,
+    /// Slot of the next record.
+    head: usize,
+    /// Sequence number of the next event.
+    sequence: u32,
+    /// Number of readable records.
+    len: usize,
+}
+
+impl<F: Flash> Log<F> {
+    /// Number of records in a page, the rest of the page is unused.
+    const SLOTS_PER_PAGE: usize = F::PAGE_LEN / RECORD_LEN;
+    /// Number of records in the flash.
+    const SLOTS: usize = F::PAGES * Self::SLOTS_PER_PAGE;
+
+    /// Opens the log in `flash`.
+    ///
+    /// Finds the newest record so the sequence numbers continue after it.
+    /// Nothing is written until the next append.
+    pub fn mount(flash: F) -> Self {
+        const {
+            assert!(F::PAGES >= 2, "the log needs two pages to keep events");
+            assert!(RECORD_LEN <= F::PAGE_LEN, "page too small for a record");
+        };
+        let mut newest: Option<(usize, u32)> = None;
+        let mut len = 0;
+        for slot in 0..Self::SLOTS {
+            let Some(record) = read_record(&flash, Self::offset(slot)) else {
+                continue;
+            };
+            let sequence = sequence(&record);
+            if newest.is_none_or(|(_, newest)| sequence > newest) {
+                newest = Some((slot, sequence));
+            }
+            len += usize::from(decode(&record).is_some());
+        }
+        let (head, sequence) = newest.map_or((0, 0), |(slot, sequence)| {
+            ((slot + 1) % Self::SLOTS, sequence.wrapping_add(1))
+        });
+        Self {
+            flash,
+            head,
+            sequence,
+            len,
+        }
+    }
+
+    /// Returns the maximum number of events kept.
+    ///
+    /// Up to a page less are kept right after the oldest page was erased.
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        Self::SLOTS
+    }
+
+    /// Returns the number of events that can be read.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no events.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the sequence number of the next event.
+    #[must_use]
+    pub const fn next_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the events, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = Event> + '_ {
+        (0..Self::SLOTS).filter_map(|index| {
+            let slot = (self.head + index) % Self::SLOTS;
+            decode(&read_record(&self.flash, Self::offset(slot))?)
+        })
+    }
+
+    /// Returns an event, index 0 being the oldest.
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<Event> {
+        self.iter().nth(index)
+    }
+
+    /// Appends an event and returns it with its sequence number.
+    pub fn append(&mut self, uptime_s: u32, kind: EventKind, data: u16) -> Result<Event, Error> {
+        let event = Event {
+            sequence: self.sequence,
+            uptime_s,
+            kind,
+            data,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        let record = encode(event);
+
+        let slot = self.claim()?;
+        self.head = (slot + 1) % Self::SLOTS;
+        let offset = Self::offset(slot);
+        // The magic byte commits the record.
+        self.flash.program(offset + 1, &record[1..]);
+        self.flash.program(offset, &record[..1]);
+
+        let mut written = [0; RECORD_LEN];
+        self.flash.read(offset, &mut written);
+        if written != record {
+            return Err(Error::Verify);
+        }
+        self.len += 1;
+        Ok(event)
+    }
+
+    /// Returns the memory, for example to mount it again.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Returns the first blank slot from the head on.
+    ///
+    /// Pages are erased when the head enters them.
+    fn claim(&mut self) -> Result<usize, Error> {
+        for _ in 0..Self::SLOTS {
+            let slot = self.head;
+            if slot.is_multiple_of(Self::SLOTS_PER_PAGE) {
+                let dropped = (slot..slot + Self::SLOTS_PER_PAGE)
+                    .filter(|&slot| {
+                        read_record(&self.flash, Self::offset(slot))
+                            .and_then(|record| decode(&record))
+                            .is_some()
+                    })
+                    .count();
+                self.len = self.len.saturating_sub(dropped);
+                self.flash.erase(slot / Self::SLOTS_PER_PAGE);
+            }
+            let mut bytes = [0; RECORD_LEN];
+            self.flash.read(Self::offset(slot), &mut bytes);
+            if bytes.iter().all(|&byte| byte == 0xFF) {
+                return Ok(slot);
+            }
+            // Skips a record torn by a power failure.
+            self.head = (slot + 1) % Self::SLOTS;
+        }
+        // Not even a freshly erased page is blank.
+        Err(Error::Verify)
+    }
+
+    /// Returns the offset of a slot.
+    const fn offset(slot: usize) -> usize {
+        slot / Self::SLOTS_PER_PAGE * F::PAGE_LEN + slot % Self::SLOTS_PER_PAGE * RECORD_LEN
+    }
+}
+
+/// Reads the record at `offset`.
+///
+/// Returns `None` unless the record is committed and intact.
+fn read_record<F: Flash>(flash: &F, offset: usize) -> Option<[u8; RECORD_LEN]> {
+    let mut record = [0; RECORD_LEN];
+    flash.read(offset, &mut record);
+    if record[0] != MAGIC || crc16(&record[..BODY_LEN]).to_be_bytes() != record[BODY_LEN..] {
+        return None;
+    }
+    Some(record)
+}
+
+fn sequence(record: &[u8; RECORD_LEN]) -> u32 {
+    u32::from_le_bytes([record[1], record[2], record[3], record[4]])
+}
+
+fn encode(event: Event) -> [u8; RECORD_LEN] {
+    let mut record = [0; RECORD_LEN];
+    record[0] = MAGIC;
+    record[1..5].copy_from_slice(&event.sequence.to_le_bytes());
+    record[5..9].copy_from_slice(&event.uptime_s.to_le_bytes());
+    record[9] = event.kind as u8;
+    record[10..12].copy_from_slice(&event.data.to_le_bytes());
+    let crc = crc16(&record[..BODY_LEN]);
+    record[BODY_LEN..].copy_from_slice(&crc.to_be_bytes());
+    record
+}
+
+/// Decodes an intact record.
+///
+/// Returns `None` for kinds unknown to this firmware, which are skipped.
+fn decode(record: &[u8; RECORD_LEN]) -> Option<Event> {
+    Some(Event {
+        sequence: sequence(record),
+        uptime_s: u32::from_le_bytes([record[5], record[6], record[7], record[8]]),
+        kind: EventKind::from_u8(record[9])?,
+        data: u16::from_le_bytes([record[10], record[11]]),
+    })
+}
@@ -0,0 +1,120 @@
+//! Flash model for host tests.
+
+use crate::Flash;
+
+/// Flash of `PAGES` pages with `PAGE_LEN` bytes each, kept in RAM.
+///
+/// Power can be cut after a number of operations, each written byte and each
+/// erased page counting as one, to test that appending survives power
+/// failures. A byte being written when the power fails only gets its low
+/// nibble programmed. A page being erased when the power fails only gets
+/// its first half erased.
+#[derive(Clone, Debug)]
+pub struct MemFlash<const PAGES: usize, const PAGE_LEN: usize> {
+    /// Contents of the pages.
+    pub pages: [[u8; PAGE_LEN]; PAGES],
+    /// Number of erases of each page.
+    pub erases: [u32; PAGES],
+    /// Number of operations that can still be done before the power fails.
+    budget: Option<usize>,
+    cut: bool,
+}
+
+impl<const PAGES: usize, const PAGE_LEN: usize> MemFlash<PAGES, PAGE_LEN> {
+    /// Creates an erased flash, which reads as 0xFF.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            pages: [[0xFF; PAGE_LEN]; PAGES],
+            erases: [0; PAGES],
+            budget: None,
+            cut: false,
+        }
+    }
+
+    /// Lets the power fail after `operations` more bytes have been written or
+    /// pages erased.
+    ///
+    /// Later operations are lost until [`MemFlash::restore_power`].
+    pub const fn cut_power_after(&mut self, operations: usize) {
+        self.budget = Some(operations);
+    }
+
+    /// Restores the power.
+    pub const fn restore_power(&mut self) {
+        self.budget = None;
+        self.cut = false;
+    }
+
+    /// Returns true once the power has failed.
+    #[must_use]
+    pub const fn is_cut(&self) -> bool {
+        self.cut
+    }
+
+    /// Returns the highest number of erases of a single page.
+    #[must_use]
+    pub fn max_erases(&self) -> u32 {
+        self.erases.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Takes an operation from the budget.
+    ///
+    /// Returns `None` if the power is off, or `Some(true)` if it fails
+    /// during this operation.
+    fn operate(&mut self) -> Option<bool> {
+        if self.cut {
+            return None;
+        }
+        match &mut self.budget {
+            Some(0) => {
+                self.cut = true;
+                Some(true)
+            }
+            Some(budget) => {
+                *budget -= 1;
+                Some(false)
+            }
+            None => Some(false),
+        }
+    }
+}
+
+impl<const PAGES: usize, const PAGE_LEN: usize> Default for MemFlash<PAGES, PAGE_LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGES: usize, const PAGE_LEN: usize> Flash for MemFlash<PAGES, PAGE_LEN> {
+    const PAGE_LEN: usize = PAGE_LEN;
+    const PAGES: usize = PAGES;
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        for (index, byte) in (offset..).zip(buf) {
+            *byte = self.pages[index / PAGE_LEN][index % PAGE_LEN];
+        }
+    }
+
+    fn erase(&mut self, page: usize) {
+        let Some(fails) = self.operate() else {
+            return;
+        };
+        let len = if fails { PAGE_LEN / 2 } else { PAGE_LEN };
+        self.pages[page][..len].fill(0xFF);
+        self.erases[page] += 1;
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) {
+        for (index, &byte) in (offset..).zip(data) {
+            let Some(fails) = self.operate() else {
+                return;
+            };
+            let byte = if fails { byte | 0xF0 } else { byte };
+            self.pages[index / PAGE_LEN][index % PAGE_LEN] &= byte;
+            if fails {
+                return;
+            }
+        }
+    }
+}
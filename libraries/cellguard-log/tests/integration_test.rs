@@ -0,0 +1,296 @@
+//! Integration tests of the event log against the flash model.
+
+use cellguard_log::mem::MemFlash;
+use cellguard_log::{Error, Flash as _, Log, MAGIC, RECORD_LEN};
+use cellguard_protocol::{Event, EventKind, crc16};
+
+/// Three pages of four records each, with 8 bytes left over per page.
+type Flash = MemFlash<3, 64>;
+
+const SLOTS_PER_PAGE: usize = 4;
+const SLOTS: usize = 12;
+
+/// Appends `count` events whose uptime is their number.
+fn fill(log: &mut Log<Flash>, count: u32) {
+    for _ in 0..count {
+        let uptime_s = log.next_sequence();
+        log.append(uptime_s, EventKind::Warning, 1).unwrap();
+    }
+}
+
+/// Returns the sequence numbers of the events, oldest first.
+fn sequences(log: &Log<Flash>) -> Vec<u32> {
+    log.iter().map(|event| event.sequence).collect()
+}
+
+/// Mounts the log again from its flash.
+fn remount(log: Log<Flash>) -> Log<Flash> {
+    Log::mount(log.release())
+}
+
+#[test]
+fn test_blank_flash() {
+    let log = Log::mount(Flash::new());
+    assert!(log.is_empty());
+    assert_eq!(log.capacity(), SLOTS);
+    assert_eq!(log.next_sequence(), 0);
+    assert_eq!(log.get(0), None);
+    // Mounting doesn't write anything.
+    assert_eq!(log.release().max_erases(), 0);
+}
+
+#[test]
+fn test_append_and_read() {
+    let mut log = Log::mount(Flash::new());
+    let event = log.append(125, EventKind::Trip, 2).unwrap();
+    assert_eq!(
+        event,
+        Event {
+            sequence: 0,
+            uptime_s: 125,
+            kind: EventKind::Trip,
+            data: 2,
+        }
+    );
+    log.append(130, EventKind::ConfigChanged, 0x100).unwrap();
+
+    let log = remount(log);
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.next_sequence(), 2);
+    assert_eq!(log.get(0), Some(event));
+    assert_eq!(log.get(1).unwrap().kind, EventKind::ConfigChanged);
+    assert_eq!(log.get(2), None);
+
+    let flash = log.release();
+    let mut record = vec![MAGIC, 0, 0, 0, 0, 125, 0, 0, 0, 0x04, 2, 0];
+    let crc = crc16(&record);
+    record.extend_from_slice(&crc.to_be_bytes());
+    assert_eq!(flash.pages[0][..RECORD_LEN], record);
+    // The first page was erased before the first record.
+    assert_eq!(flash.erases, [1, 0, 0]);
+    assert!(flash.pages[0][2 * RECORD_LEN..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn test_wraps_around_dropping_oldest_page() {
+    let mut log = Log::mount(Flash::new());
+    fill(&mut log, SLOTS as u32);
+    assert_eq!(log.len(), SLOTS);
+    assert_eq!(sequences(&log), (0..12).collect::<Vec<_>>());
+
+    // The next event erases the first page.
+    fill(&mut log, 1);
+    assert_eq!(log.len(), 9);
+    assert_eq!(sequences(&log), (4..13).collect::<Vec<_>>());
+
+    let mut log = remount(log);
+    assert_eq!(log.len(), 9);
+    assert_eq!(log.next_sequence(), 13);
+    fill(&mut log, 5);
+    assert_eq!(sequences(&remount(log)), (8..18).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_wear_is_spread_over_pages() {
+    let mut log = Log::mount(Flash::new());
+    for round in 0..100 {
+        fill(&mut log, 5);
+        if round % 7 == 0 {
+            log = remount(log);
+        }
+    }
+    let sequences = sequences(&log);
+    assert_eq!(sequences.last(), Some(&499));
+    assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+    let flash = log.release();
+    // 500 events in pages of four records.
+    assert_eq!(flash.erases.iter().sum::<u32>(), 125);
+    assert!(flash.max_erases() <= 42);
+    assert!(flash.erases.iter().all(|&erases| erases >= 41));
+}
+
+/// Runs `append` on a log holding `before` events with the power failing
+/// after `operations`, then mounts the log again.
+///
+/// Checks that all earlier events survived and that appending works again.
+fn append_with_power_failure(before: u32, operations: usize) {
+    let mut log = Log::mount(Flash::new());
+    fill(&mut log, before);
+    let expected = sequences(&log);
+
+    let mut flash = log.release();
+    flash.cut_power_after(operations);
+    let mut log = Log::mount(flash);
+    let result = log.append(999, EventKind::Reset, 0x01);
+    let mut flash = log.release();
+    let cut = flash.is_cut();
+    flash.restore_power();
+
+    let mut log = Log::mount(flash);
+    let mut kept = sequences(&log);
+    if cut {
+        assert_eq!(result, Err(Error::Verify), "{operations} operations");
+    } else {
+        assert!(result.is_ok());
+        assert_eq!(kept.pop(), Some(before));
+    }
+    assert!(expected.ends_with(&kept), "{operations} operations");
+    // At most the erased page was lost.
+    assert!(expected.len() - kept.len() <= SLOTS_PER_PAGE);
+
+    // A lost record doesn't use up its sequence number.
+    let event = log.append(1000, EventKind::TripsCleared, 0).unwrap();
+    assert_eq!(event.sequence, if cut { before } else { before + 1 });
+    let mut log = remount(log);
+    assert_eq!(log.iter().last(), Some(event));
+    fill(&mut log, SLOTS as u32);
+    let sequences = sequences(&log);
+    assert!(sequences.len() > SLOTS - SLOTS_PER_PAGE);
+    assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+}
+
+#[test]
+fn test_power_failure_while_writing() {
+    for operations in 0..=RECORD_LEN {
+        append_with_power_failure(5, operations);
+    }
+}
+
+#[test]
+fn test_power_failure_while_erasing() {
+    // The first operation of this append is the erase of the first page.
+    for operations in 0..=RECORD_LEN + 1 {
+        append_with_power_failure(SLOTS as u32, operations);
+    }
+}
+
+#[test]
+fn test_power_failure_at_page_end() {
+    // The next append erases the second page.
+    for operations in 0..=RECORD_LEN + 1 {
+        append_with_power_failure(SLOTS_PER_PAGE as u32, operations);
+    }
+}
+
+#[test]
+fn test_torn_record_is_skipped() {
+    let mut log = Log::mount(Flash::new());
+    fill(&mut log, 2);
+    let mut flash = log.release();
+    // A record without its magic byte, as left by a power failure.
+    flash.pages[0][2 * RECORD_LEN + 5] = 0x12;
+    let mut log = Log::mount(flash);
+    assert_eq!(log.len(), 2);
+    let event = log.append(7, EventKind::Reset, 0).unwrap();
+    assert_eq!(event.sequence, 2);
+
+    let flash = log.release();
+    assert_eq!(flash.pages[0][3 * RECORD_LEN], MAGIC);
+    let log = Log::mount(flash);
+    assert_eq!(sequences(&log), [0, 1, 2]);
+}
+
+#[test]
+fn test_corrupt_record_is_skipped() {
+    let mut log = Log::mount(Flash::new());
+    fill(&mut log, 3);
+    let mut flash = log.release();
+    flash.pages[0][RECORD_LEN + 6] ^= 0x01;
+    let log = Log::mount(flash);
+    assert_eq!(log.len(), 2);
+    assert_eq!(sequences(&log), [0, 2]);
+    assert_eq!(log.get(1).unwrap().sequence, 2);
+}
+
+#[test]
+fn test_unknown_kind_is_skipped() {
+    let mut log = Log::mount(Flash::new());
+    fill(&mut log, 1);
+    let mut flash = log.release();
+    // A record of a later firmware.
+    let mut record = vec![MAGIC, 1, 0, 0, 0, 0, 0, 0, 0, 0x7F, 0, 0];
+    let crc = crc16(&record);
+    record.extend_from_slice(&crc.to_be_bytes());
+    flash.program(RECORD_LEN, &record);
+
+    let mut log = Log::mount(flash);
+    assert_eq!(log.len(), 1);
+    // Its sequence number isn't used again.
+    assert_eq!(log.append(0, EventKind::Reset, 0).unwrap().sequence, 2);
+    assert_eq!(sequences(&log), [0, 2]);
+}
+
+/// Flash where one byte can't be written anymore.
+struct Worn {
+    flash: Flash,
+    offset: usize,
+}
+
+impl cellguard_log::Flash for Worn {
+    const PAGE_LEN: usize = 64;
+    const PAGES: usize = 3;
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        self.flash.read(offset, buf);
+    }
+
+    fn erase(&mut self, page: usize) {
+        self.flash.erase(page);
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) {
+        for (index, &byte) in (offset..).zip(data) {
+            if index != self.offset {
+                self.flash.program(index, &[byte]);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_worn_out_flash() {
+    let mut log = Log::mount(Worn {
+        flash: Flash::new(),
+        offset: RECORD_LEN + 3,
+    });
+    log.append(0, EventKind::Reset, 0).unwrap();
+    assert_eq!(log.append(1, EventKind::Trip, 3), Err(Error::Verify));
+    assert_eq!(log.len(), 1);
+    // The next event goes to the following slot.
+    assert_eq!(log.append(2, EventKind::Trip, 3).unwrap().sequence, 2);
+
+    let log = Log::mount(log.release());
+    let sequences: Vec<_> = log.iter().map(|event| event.sequence).collect();
+    assert_eq!(sequences, [0, 2]);
+}
+
+#[test]
+fn test_flash_model() {
+    let mut flash = MemFlash::<2, 8>::new();
+    flash.program(2, &[0xF0, 0x0F]);
+    // Programming only clears bits.
+    flash.program(2, &[0x3C]);
+    assert_eq!(flash.pages[0][2..4], [0x30, 0x0F]);
+
+    flash.cut_power_after(2);
+    flash.program(7, &[1, 2, 3]);
+    assert!(flash.is_cut());
+    // The third byte was being written when the power failed.
+    assert_eq!(flash.pages[0][7], 1);
+    assert_eq!(flash.pages[1][..2], [2, 0xF3]);
+    flash.erase(0);
+    assert_eq!(flash.erases, [0, 0]);
+    flash.restore_power();
+
+    let mut buf = [0; 4];
+    flash.read(6, &mut buf);
+    assert_eq!(buf, [0xFF, 1, 2, 0xF3]);
+
+    flash.pages[1] = [0; 8];
+    flash.cut_power_after(0);
+    flash.erase(1);
+    // Only the first half is erased.
+    assert_eq!(flash.pages[1], [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+    assert_eq!(flash.erases, [0, 1]);
+}
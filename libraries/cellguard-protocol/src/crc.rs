@@ -0,0 +1,44 @@
+//! Frame check sequence.
+
+/// Computes the CRC-16/CCITT-FALSE of `data`.
+///
+/// Polynomial 0x1021, initial value 0xFFFF, no reflection. Calculated bitwise
+/// since a lookup table wouldn't fit into the flash of the cellagent.
+#[must_use]
+pub const fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xFFFF, data)
+}
+
+/// Continues a CRC-16/CCITT-FALSE calculation with `data`.
+///
+/// Lets data arriving in pieces be checked, starting with 0xFFFF.
+#[must_use]
+pub const fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    let mut i = 0;
+    while i < data.len() {
+        crc ^= (data[i] as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), 0x29B1);
+    }
+}
@@ -0,0 +1,280 @@
+//! Framing, addressing and frame check.
+
+use crate::{CRC_LEN, HEADER_LEN, MAX_FRAME_LEN, MAX_PAYLOAD, PROTOCOL_VERSION, SYNC, crc16};
+
+/// Address of a node.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Address(pub u8);
+
+impl Address {
+    /// The cellcore.
+    pub const CORE: Self = Self(0x00);
+    /// First address of a cellagent.
+    pub const FIRST_AGENT: Self = Self(0x01);
+    /// Last address of a cellagent.
+    pub const LAST_AGENT: Self = Self(0xEF);
+    /// A host tool connected to the cellcore.
+    pub const HOST: Self = Self(0xFD);
+    /// A cellagent that hasn't been assigned an address yet.
+    pub const UNASSIGNED: Self = Self(0xFE);
+    /// All nodes. Broadcasts are not answered unless stated otherwise.
+    pub const BROADCAST: Self = Self(0xFF);
+
+    /// Returns the address of the cellagent with the given index, starting
+    /// at zero.
+    #[must_use]
+    pub const fn agent(index: u8) -> Option<Self> {
+        if index < Self::LAST_AGENT.0 {
+            Some(Self(Self::FIRST_AGENT.0 + index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of a cellagent address, starting at zero.
+    #[must_use]
+    pub const fn agent_index(self) -> Option<u8> {
+        if self.is_agent() {
+            Some(self.0 - Self::FIRST_AGENT.0)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if this is the address of a cellagent.
+    #[must_use]
+    pub const fn is_agent(self) -> bool {
+        self.0 >= Self::FIRST_AGENT.0 && self.0 <= Self::LAST_AGENT.0
+    }
+
+    /// Returns true if a node with address `own` accepts frames sent to this
+    /// address.
+    #[must_use]
+    pub const fn accepts(self, own: Self) -> bool {
+        self.0 == own.0 || self.0 == Self::BROADCAST.0
+    }
+}
+
+/// Routing information of a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Header {
+    /// Node the frame is sent to.
+    pub destination: Address,
+    /// Node that sent the frame.
+    pub source: Address,
+    /// Sequence number chosen by the requester.
+    pub sequence: u8,
+}
+
+impl Header {
+    /// Creates a header.
+    #[must_use]
+    pub const fn new(destination: Address, source: Address, sequence: u8) -> Self {
+        Self {
+            destination,
+            source,
+            sequence,
+        }
+    }
+
+    /// Returns the header of the response to a request with this header.
+    ///
+    /// `own` is the address of the responding node, which differs from the
+    /// destination of the request for broadcasts.
+    #[must_use]
+    pub const fn reply(&self, own: Address) -> Self {
+        Self::new(self.source, own, self.sequence)
+    }
+
+    /// Returns true if a frame with this header answers `request`.
+    ///
+    /// Replies to requests sent to [`Address::BROADCAST`] or
+    /// [`Address::UNASSIGNED`] may come from any node.
+    #[must_use]
+    pub const fn is_reply_to(&self, request: &Self) -> bool {
+        self.destination.0 == request.source.0
+            && self.sequence == request.sequence
+            && (self.source.0 == request.destination.0
+                || request.destination.0 == Address::BROADCAST.0
+                || request.destination.0 == Address::UNASSIGNED.0)
+    }
+}
+
+/// Error when encoding a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EncodeError {
+    /// The payload is longer than [`MAX_PAYLOAD`].
+    PayloadTooLong,
+    /// The output buffer can't hold the frame.
+    BufferTooSmall,
+}
+
+/// Error when decoding a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    /// The buffer doesn't start with [`SYNC`].
+    Sync,
+    /// The buffer ends before the frame is complete.
+    Incomplete,
+    /// The frame uses another protocol version.
+    Version(u8),
+    /// The payload length exceeds [`MAX_PAYLOAD`].
+    Length(u8),
+    /// The frame check failed.
+    Crc,
+}
+
+/// A frame referencing its payload.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Frame<'a> {
+    /// Routing information.
+    pub header: Header,
+    /// Message code.
+    pub code: u8,
+    /// Message specific data.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Creates a frame.
+    #[must_use]
+    pub const fn new(header: Header, code: u8, payload: &'a [u8]) -> Self {
+        Self {
+            header,
+            code,
+            payload,
+        }
+    }
+
+    /// Returns the length of the encoded frame.
+    #[must_use]
+    pub const fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len() + CRC_LEN
+    }
+
+    /// Encodes the frame into `buf` and returns the number of bytes written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = self.payload.len();
+        if len > MAX_PAYLOAD {
+            return Err(EncodeError::PayloadTooLong);
+        }
+        let total = self.encoded_len();
+        let buf = buf.get_mut(..total).ok_or(EncodeError::BufferTooSmall)?;
+        buf[..HEADER_LEN].copy_from_slice(&[
+            SYNC,
+            PROTOCOL_VERSION,
+            self.header.destination.0,
+            self.header.source.0,
+            self.header.sequence,
+            self.code,
+            len as u8,
+        ]);
+        buf[HEADER_LEN..HEADER_LEN + len].copy_from_slice(self.payload);
+        let crc = crc16(&buf[1..HEADER_LEN + len]);
+        buf[HEADER_LEN + len..].copy_from_slice(&crc.to_be_bytes());
+        Ok(total)
+    }
+
+    /// Decodes a frame at the start of `buf`.
+    ///
+    /// Returns the frame and the number of bytes it occupies.
+    pub fn decode(buf: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        match buf.first() {
+            None => return Err(DecodeError::Incomplete),
+            Some(&SYNC) => {}
+            Some(_) => return Err(DecodeError::Sync),
+        }
+        let header = buf.get(..HEADER_LEN).ok_or(DecodeError::Incomplete)?;
+        check_header(header)?;
+        let total = HEADER_LEN + usize::from(header[6]) + CRC_LEN;
+        let frame = buf.get(..total).ok_or(DecodeError::Incomplete)?;
+        Ok((parse(frame)?, total))
+    }
+}
+
+/// Checks the version and the payload length of a complete header.
+fn check_header(header: &[u8]) -> Result<(), DecodeError> {
+    if header[1] != PROTOCOL_VERSION {
+        return Err(DecodeError::Version(header[1]));
+    }
+    if usize::from(header[6]) > MAX_PAYLOAD {
+        return Err(DecodeError::Length(header[6]));
+    }
+    Ok(())
+}
+
+/// Checks the CRC of a complete frame and splits it into its fields.
+fn parse(frame: &[u8]) -> Result<Frame<'_>, DecodeError> {
+    let (data, crc) = frame.split_at(frame.len() - CRC_LEN);
+    if crc16(&data[1..]).to_be_bytes() != crc {
+        return Err(DecodeError::Crc);
+    }
+    let header = Header::new(Address(data[2]), Address(data[3]), data[4]);
+    Ok(Frame::new(header, data[5], &data[HEADER_LEN..]))
+}
+
+/// Reassembles frames from a byte stream.
+///
+/// Bytes before a [`SYNC`] are skipped. After an error the decoder waits for
+/// the next [`SYNC`], so a frame starting within a corrupted frame is lost
+/// and has to be recovered by a retry of the requester.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+}
+
+impl Decoder {
+    /// Creates a decoder waiting for a frame.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Discards a partially received frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Returns true while a frame is partially received.
+    #[must_use]
+    pub const fn is_receiving(&self) -> bool {
+        self.len > 0
+    }
+
+    /// Processes a received byte.
+    ///
+    /// Returns the frame once it is complete, or an error as soon as it is
+    /// known to be invalid.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame<'_>, DecodeError>> {
+        if self.len == 0 && byte != SYNC {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        if self.len == HEADER_LEN
+            && let Err(e) = check_header(&self.buf[..HEADER_LEN])
+        {
+            self.len = 0;
+            return Some(Err(e));
+        }
+        let total = HEADER_LEN + usize::from(self.buf[6]) + CRC_LEN;
+        if self.len < total {
+            return None;
+        }
+        self.len = 0;
+        Some(parse(&self.buf[..total]))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
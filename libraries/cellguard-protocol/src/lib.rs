@@ -0,0 +1,53 @@
+//! Binary protocol between cellagents, the cellcore and host tools.
+//!
+//! Every message is sent in a [`Frame`]:
+//!
+//! | Offset | Size | Content                                  |
+//! |--------|------|------------------------------------------|
+//! | 0      | 1    | [`SYNC`]                                 |
+//! | 1      | 1    | [`PROTOCOL_VERSION`]                     |
+//! | 2      | 1    | Destination [`Address`]                  |
+//! | 3      | 1    | Source [`Address`]                       |
+//! | 4      | 1    | Sequence number                          |
+//! | 5      | 1    | Message code                             |
+//! | 6      | 1    | Payload length, at most [`MAX_PAYLOAD`]  |
+//! | 7      | n    | Payload                                  |
+//! | 7 + n  | 2    | CRC-16 of offsets 1 to 6 + n, big endian |
+//!
+//! The requester picks the sequence number, the responder echoes it together
+//! with the message code of the request with [`RESPONSE_FLAG`] set. Errors are
+//! answered with [`ERROR_CODE`] and an [`ErrorCode`] as payload. Multi-byte
+//! values in payloads are little endian.
+//!
+//! A receiver with a different protocol version drops the frame, so a version
+//! mismatch shows up as a timeout. [`Response::Pong`] reports the version of a
+//! node so the mismatch can be diagnosed.
+
+#![no_std]
+
+pub use self::crc::{crc16, crc16_update};
+pub use self::frame::{Address, DecodeError, Decoder, EncodeError, Frame, Header};
+pub use self::message::{
+    Balancing, BalancingReport, CELLS_PER_BLOCK, CellBlock, CellReading, CrashReport, ERROR_CODE,
+    ErrorCode, Event, EventKind, NodeInfo, NodeKind, PackStatus, RESPONSE_FLAG, Request, Response,
+    SettingValue, Status, StatusFlags, Version,
+};
+
+mod crc;
+mod frame;
+mod message;
+
+/// First byte of every frame.
+pub const SYNC: u8 = 0xA5;
+/// Version of the protocol implemented by this crate.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Maximum length of the payload of a frame.
+///
+/// Kept small so a frame fits into the RAM of the cellagent twice.
+pub const MAX_PAYLOAD: usize = 32;
+/// Length of the frame header including the sync byte.
+pub const HEADER_LEN: usize = 7;
+/// Length of the CRC at the end of a frame.
+pub const CRC_LEN: usize = 2;
+/// Maximum length of an encoded frame.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + MAX_PAYLOAD + CRC_LEN;
@@ -0,0 +1,997 @@
+//! Commands, responses and their payloads.
+
+use crate::{Address, EncodeError, Frame, Header, MAX_PAYLOAD, PROTOCOL_VERSION};
+
+/// Set in the message code of every response.
+pub const RESPONSE_FLAG: u8 = 0x80;
+/// Message code of an error response.
+pub const ERROR_CODE: u8 = 0xFF;
+
+const PING: u8 = 0x01;
+const READ_VOLTAGE: u8 = 0x02;
+const READ_TEMPERATURE: u8 = 0x03;
+const SET_BALANCING: u8 = 0x04;
+const READ_STATUS: u8 = 0x05;
+const RESET_ADDRESSES: u8 = 0x06;
+const ASSIGN_ADDRESS: u8 = 0x07;
+const READ_BALANCING: u8 = 0x08;
+const READ_PACK: u8 = 0x09;
+const READ_CELLS: u8 = 0x0A;
+const READ_SETTING: u8 = 0x0B;
+const WRITE_SETTING: u8 = 0x0C;
+const READ_EVENT: u8 = 0x0D;
+const READ_CRASH: u8 = 0x0E;
+
+/// Number of cells in a [`CellBlock`].
+pub const CELLS_PER_BLOCK: usize = 6;
+
+/// Reason for rejecting a request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The message code is not known.
+    UnknownCommand = 0x01,
+    /// The payload has the wrong length or an invalid value.
+    InvalidPayload = 0x02,
+    /// The node can't process the request right now.
+    Busy = 0x03,
+    /// The node knows the request but doesn't implement it.
+    Unsupported = 0x04,
+    /// The hardware needed for the request failed.
+    HardwareFault = 0x05,
+    /// An index or a value is out of range.
+    OutOfRange = 0x06,
+}
+
+impl ErrorCode {
+    /// Converts a wire value to an error code.
+    #[must_use]
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x01 => Self::UnknownCommand,
+            0x02 => Self::InvalidPayload,
+            0x03 => Self::Busy,
+            0x04 => Self::Unsupported,
+            0x05 => Self::HardwareFault,
+            0x06 => Self::OutOfRange,
+            _ => return None,
+        })
+    }
+}
+
+/// Kind of a node.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum NodeKind {
+    /// Cell monitoring node.
+    Agent = 0x01,
+    /// Pack controller.
+    Core = 0x02,
+    /// Host tool.
+    Host = 0x03,
+}
+
+impl NodeKind {
+    /// Converts a wire value to a node kind.
+    #[must_use]
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x01 => Self::Agent,
+            0x02 => Self::Core,
+            0x03 => Self::Host,
+            _ => return None,
+        })
+    }
+}
+
+/// Firmware version of a node.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version {
+    /// Incompatible changes.
+    pub major: u8,
+    /// Compatible additions.
+    pub minor: u8,
+    /// Fixes.
+    pub patch: u8,
+}
+
+impl Version {
+    /// Creates a version.
+    #[must_use]
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+/// Identification of a node returned by [`Request::Ping`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NodeInfo {
+    /// Protocol version implemented by the node.
+    pub protocol: u8,
+    /// Kind of the node.
+    pub kind: NodeKind,
+    /// Firmware version of the node.
+    pub firmware: Version,
+}
+
+impl NodeInfo {
+    /// Creates the identification of a node implementing this protocol
+    /// version.
+    #[must_use]
+    pub const fn new(kind: NodeKind, firmware: Version) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            kind,
+            firmware,
+        }
+    }
+}
+
+/// Balancing request for a cellagent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Balancing {
+    /// Whether the bleed resistor is switched on.
+    pub enable: bool,
+    /// Time after which the cellagent stops balancing on its own in seconds.
+    pub duration_s: u16,
+}
+
+impl Balancing {
+    /// Request to stop balancing.
+    pub const OFF: Self = Self {
+        enable: false,
+        duration_s: 0,
+    };
+
+    /// Creates a request to balance for the given time.
+    #[must_use]
+    pub const fn on(duration_s: u16) -> Self {
+        Self {
+            enable: true,
+            duration_s,
+        }
+    }
+
+    fn encode(self) -> [u8; 3] {
+        let [lo, hi] = self.duration_s.to_le_bytes();
+        [u8::from(self.enable), lo, hi]
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let &[enable, lo, hi] = payload else {
+            return Err(ErrorCode::InvalidPayload);
+        };
+        let enable = match enable {
+            0 => false,
+            1 => true,
+            _ => return Err(ErrorCode::InvalidPayload),
+        };
+        Ok(Self {
+            enable,
+            duration_s: u16::from_le_bytes([lo, hi]),
+        })
+    }
+}
+
+/// Balancing totals of a cellagent returned by [`Request::ReadBalancing`].
+///
+/// Both values count up from reset and wrap around.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct BalancingReport {
+    /// Time the bleed resistor was switched on in seconds.
+    pub on_time_s: u32,
+    /// Charge removed from the cell in milliampere-seconds.
+    pub charge_mas: u32,
+}
+
+impl BalancingReport {
+    fn encode(self) -> [u8; 8] {
+        let [a, b, c, d] = self.on_time_s.to_le_bytes();
+        let [e, f, g, h] = self.charge_mas.to_le_bytes();
+        [a, b, c, d, e, f, g, h]
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let &[a, b, c, d, e, f, g, h] = payload else {
+            return Err(ErrorCode::InvalidPayload);
+        };
+        Ok(Self {
+            on_time_s: u32::from_le_bytes([a, b, c, d]),
+            charge_mas: u32::from_le_bytes([e, f, g, h]),
+        })
+    }
+}
+
+/// Condition flags of a node.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct StatusFlags(pub u8);
+
+impl StatusFlags {
+    /// The bleed resistor is switched on.
+    pub const BALANCING: Self = Self(1 << 0);
+    /// Balancing is reduced or stopped due to the temperature.
+    pub const OVER_TEMPERATURE: Self = Self(1 << 1);
+    /// No valid request was received within the command timeout.
+    pub const COMMAND_TIMEOUT: Self = Self(1 << 2);
+    /// The voltage measurement failed.
+    pub const VOLTAGE_FAULT: Self = Self(1 << 3);
+    /// The temperature sensor failed.
+    pub const TEMPERATURE_FAULT: Self = Self(1 << 4);
+    /// The node restarted after a crash, see [`Request::ReadCrash`].
+    pub const CRASHED: Self = Self(1 << 5);
+    /// The node crashed repeatedly and only runs what is needed to report it.
+    pub const SAFE_MODE: Self = Self(1 << 6);
+
+    /// No flag is set.
+    pub const NONE: Self = Self(0);
+
+    /// Returns true if all flags of `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears the flags of `other`.
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl core::ops::BitOr for StatusFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Status of a node returned by [`Request::ReadStatus`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Status {
+    /// Condition flags.
+    pub flags: StatusFlags,
+    /// Time since reset in seconds.
+    pub uptime_s: u32,
+}
+
+impl Status {
+    fn encode(self) -> [u8; 5] {
+        let [a, b, c, d] = self.uptime_s.to_le_bytes();
+        [self.flags.0, a, b, c, d]
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let &[flags, a, b, c, d] = payload else {
+            return Err(ErrorCode::InvalidPayload);
+        };
+        Ok(Self {
+            flags: StatusFlags(flags),
+            uptime_s: u32::from_le_bytes([a, b, c, d]),
+        })
+    }
+}
+
+/// Value of a missing state of charge.
+const MISSING_SOC: u16 = u16::MAX;
+/// Value of a missing pack voltage.
+const MISSING_VOLTAGE: u32 = u32::MAX;
+/// Value of a missing pack current.
+const MISSING_CURRENT: i32 = i32::MIN;
+/// Value of a missing cell voltage.
+const MISSING_CELL_VOLTAGE: u16 = u16::MAX;
+/// Value of a missing temperature.
+const MISSING_TEMPERATURE: i16 = i16::MIN;
+
+/// Pack state returned by [`Request::ReadPack`].
+///
+/// Bit n of the protection flags stands for the n-th quantity of the pack
+/// protection: over-voltage, under-voltage, over-temperature,
+/// under-temperature, charge over-current and discharge over-current.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct PackStatus {
+    /// State of charge in 0.01 %.
+    pub soc: Option<u16>,
+    /// Pack voltage in millivolts.
+    pub voltage_mv: Option<u32>,
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+    /// Number of cells.
+    pub cells: u8,
+    /// Quantities at least at the warning level.
+    pub warnings: u8,
+    /// Quantities at least at the alarm level.
+    pub alarms: u8,
+    /// Quantities that tripped.
+    pub trips: u8,
+    /// Charge current limit in milliamperes.
+    pub charge_current_ma: u32,
+    /// Discharge current limit in milliamperes.
+    pub discharge_current_ma: u32,
+    /// Charge voltage limit in millivolts.
+    pub charge_voltage_mv: u32,
+    /// Discharge voltage limit in millivolts.
+    pub discharge_voltage_mv: u32,
+}
+
+impl PackStatus {
+    fn encode(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+        let mut w = Writer::new(payload);
+        w.put(&self.soc.unwrap_or(MISSING_SOC).to_le_bytes());
+        w.put(&self.voltage_mv.unwrap_or(MISSING_VOLTAGE).to_le_bytes());
+        w.put(&self.current_ma.unwrap_or(MISSING_CURRENT).to_le_bytes());
+        w.put(&[self.cells, self.warnings, self.alarms, self.trips]);
+        w.put(&self.charge_current_ma.to_le_bytes());
+        w.put(&self.discharge_current_ma.to_le_bytes());
+        w.put(&self.charge_voltage_mv.to_le_bytes());
+        w.put(&self.discharge_voltage_mv.to_le_bytes());
+        w.len
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let mut r = Reader(payload);
+        let status = Self {
+            soc: Some(r.u16()?).filter(|&soc| soc != MISSING_SOC),
+            voltage_mv: Some(r.u32()?).filter(|&mv| mv != MISSING_VOLTAGE),
+            current_ma: Some(r.i32()?).filter(|&ma| ma != MISSING_CURRENT),
+            cells: r.u8()?,
+            warnings: r.u8()?,
+            alarms: r.u8()?,
+            trips: r.u8()?,
+            charge_current_ma: r.u32()?,
+            discharge_current_ma: r.u32()?,
+            charge_voltage_mv: r.u32()?,
+            discharge_voltage_mv: r.u32()?,
+        };
+        r.finish()?;
+        Ok(status)
+    }
+}
+
+/// Latest readings of a cell in a [`CellBlock`].
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct CellReading {
+    /// Cell voltage in millivolts.
+    pub voltage_mv: Option<u16>,
+    /// Temperature in 1/16 °C.
+    pub temperature: Option<i16>,
+    /// Whether the bleed resistor is switched on.
+    pub balancing: bool,
+}
+
+/// Consecutive cells returned by [`Request::ReadCells`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CellBlock {
+    /// Index of the first cell in the block.
+    pub first: u8,
+    /// Number of cells of the pack.
+    pub total: u8,
+    len: u8,
+    readings: [CellReading; CELLS_PER_BLOCK],
+}
+
+impl CellBlock {
+    /// Creates an empty block starting at cell `first`.
+    #[must_use]
+    pub const fn new(first: u8, total: u8) -> Self {
+        Self {
+            first,
+            total,
+            len: 0,
+            readings: [CellReading {
+                voltage_mv: None,
+                temperature: None,
+                balancing: false,
+            }; CELLS_PER_BLOCK],
+        }
+    }
+
+    /// Appends the readings of the next cell.
+    ///
+    /// Returns false if the block is full.
+    pub fn push(&mut self, reading: CellReading) -> bool {
+        let Some(slot) = self.readings.get_mut(usize::from(self.len)) else {
+            return false;
+        };
+        *slot = reading;
+        self.len += 1;
+        true
+    }
+
+    /// Returns the readings in the block.
+    #[must_use]
+    pub fn readings(&self) -> &[CellReading] {
+        &self.readings[..usize::from(self.len)]
+    }
+
+    fn encode(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+        let mut w = Writer::new(payload);
+        w.put(&[self.first, self.total]);
+        for reading in self.readings() {
+            w.put(
+                &reading
+                    .voltage_mv
+                    .unwrap_or(MISSING_CELL_VOLTAGE)
+                    .to_le_bytes(),
+            );
+            w.put(
+                &reading
+                    .temperature
+                    .unwrap_or(MISSING_TEMPERATURE)
+                    .to_le_bytes(),
+            );
+            w.put(&[u8::from(reading.balancing)]);
+        }
+        w.len
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let mut r = Reader(payload);
+        let mut block = Self::new(r.u8()?, r.u8()?);
+        while !r.0.is_empty() {
+            let reading = CellReading {
+                voltage_mv: Some(r.u16()?).filter(|&mv| mv != MISSING_CELL_VOLTAGE),
+                temperature: Some(r.i16()?).filter(|&t| t != MISSING_TEMPERATURE),
+                balancing: match r.u8()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(ErrorCode::InvalidPayload),
+                },
+            };
+            if !block.push(reading) {
+                return Err(ErrorCode::InvalidPayload);
+            }
+        }
+        Ok(block)
+    }
+}
+
+/// Value of a setting of the cellcore.
+///
+/// The cellcore defines the identifiers and units of its settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SettingValue {
+    /// Identifier of the setting.
+    pub id: u16,
+    /// Value in the unit of the setting.
+    pub value: i32,
+}
+
+impl SettingValue {
+    fn encode(self) -> [u8; 6] {
+        let [a, b] = self.id.to_le_bytes();
+        let [c, d, e, f] = self.value.to_le_bytes();
+        [a, b, c, d, e, f]
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let &[a, b, c, d, e, f] = payload else {
+            return Err(ErrorCode::InvalidPayload);
+        };
+        Ok(Self {
+            id: u16::from_le_bytes([a, b]),
+            value: i32::from_le_bytes([c, d, e, f]),
+        })
+    }
+}
+
+/// Kind of an [`Event`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum EventKind {
+    /// The cellcore started, the data holds the reset cause flags.
+    Reset = 0x01,
+    /// A warning became active, the data holds the quantity index.
+    Warning = 0x02,
+    /// An alarm became active, the data holds the quantity index.
+    Alarm = 0x03,
+    /// A quantity tripped, the data holds the quantity index.
+    Trip = 0x04,
+    /// Latched trips were cleared.
+    TripsCleared = 0x05,
+    /// A setting was changed, the data holds the setting identifier.
+    ConfigChanged = 0x06,
+    /// A contactor failed, the data holds the fault code.
+    ContactorFault = 0x07,
+    /// The main contactors closed.
+    ContactorsClosed = 0x08,
+    /// The main contactors opened without a fault.
+    ContactorsOpened = 0x09,
+    /// The cellcore restarted after a crash, the data holds the line of the
+    /// panic.
+    Crash = 0x0A,
+}
+
+impl EventKind {
+    /// Converts a wire value to an event kind.
+    #[must_use]
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x01 => Self::Reset,
+            0x02 => Self::Warning,
+            0x03 => Self::Alarm,
+            0x04 => Self::Trip,
+            0x05 => Self::TripsCleared,
+            0x06 => Self::ConfigChanged,
+            0x07 => Self::ContactorFault,
+            0x08 => Self::ContactorsClosed,
+            0x09 => Self::ContactorsOpened,
+            0x0A => Self::Crash,
+            _ => return None,
+        })
+    }
+}
+
+/// Entry of the event log returned by [`Request::ReadEvent`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Event {
+    /// Number of the event, counting up over the lifetime of the cellcore.
+    pub sequence: u32,
+    /// Time since reset in seconds.
+    pub uptime_s: u32,
+    /// What happened.
+    pub kind: EventKind,
+    /// Details depending on the kind.
+    pub data: u16,
+}
+
+impl Event {
+    fn encode(self) -> [u8; 11] {
+        let [a, b, c, d] = self.sequence.to_le_bytes();
+        let [e, f, g, h] = self.uptime_s.to_le_bytes();
+        let [i, j] = self.data.to_le_bytes();
+        [a, b, c, d, e, f, g, h, self.kind as u8, i, j]
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let &[a, b, c, d, e, f, g, h, kind, i, j] = payload else {
+            return Err(ErrorCode::InvalidPayload);
+        };
+        Ok(Self {
+            sequence: u32::from_le_bytes([a, b, c, d]),
+            uptime_s: u32::from_le_bytes([e, f, g, h]),
+            kind: EventKind::from_u8(kind).ok_or(ErrorCode::InvalidPayload)?,
+            data: u16::from_le_bytes([i, j]),
+        })
+    }
+}
+
+/// Panic of a node before its last reset, returned by [`Request::ReadCrash`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CrashReport {
+    /// Name of the source file without its directories, cut to
+    /// [`Self::FILE_LEN`] bytes and padded with zeros.
+    pub file: [u8; Self::FILE_LEN],
+    /// Line in the source file, [`u16::MAX`] for later lines.
+    pub line: u16,
+    /// CRC-16 of the panic message, which is too long to keep.
+    pub message_hash: u16,
+    /// Reset flags of the run that crashed.
+    pub reset_cause: u8,
+    /// Number of crashes in a row, including this one.
+    pub crashes: u8,
+    /// The node runs in safe mode due to the crashes.
+    pub safe_mode: bool,
+}
+
+impl CrashReport {
+    /// Length of the file name.
+    pub const FILE_LEN: usize = 12;
+
+    /// Returns the file name.
+    ///
+    /// A character cut in half is left out.
+    #[must_use]
+    pub fn file(&self) -> &str {
+        let len = self
+            .file
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(Self::FILE_LEN);
+        let file = &self.file[..len];
+        core::str::from_utf8(file).unwrap_or_else(|error| {
+            core::str::from_utf8(&file[..error.valid_up_to()]).unwrap_or_default()
+        })
+    }
+
+    fn encode(&self) -> [u8; Self::FILE_LEN + 7] {
+        let mut payload = [0; Self::FILE_LEN + 7];
+        let (file, rest) = payload.split_at_mut(Self::FILE_LEN);
+        file.copy_from_slice(&self.file);
+        let [a, b] = self.line.to_le_bytes();
+        let [c, d] = self.message_hash.to_le_bytes();
+        rest.copy_from_slice(&[
+            a,
+            b,
+            c,
+            d,
+            self.reset_cause,
+            self.crashes,
+            u8::from(self.safe_mode),
+        ]);
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ErrorCode> {
+        let mut r = Reader(payload);
+        let report = Self {
+            file: r.take()?,
+            line: r.u16()?,
+            message_hash: r.u16()?,
+            reset_cause: r.u8()?,
+            crashes: r.u8()?,
+            safe_mode: match r.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(ErrorCode::InvalidPayload),
+            },
+        };
+        r.finish()?;
+        Ok(report)
+    }
+}
+
+/// A request sent by the cellcore or a host tool.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Request {
+    /// Checks that the node is alive and returns its [`NodeInfo`].
+    Ping,
+    /// Reads the cell voltage.
+    ReadVoltage,
+    /// Reads the temperature.
+    ReadTemperature,
+    /// Switches balancing on or off.
+    SetBalancing(Balancing),
+    /// Reads the [`Status`].
+    ReadStatus,
+    /// Makes all cellagents forget their address.
+    ///
+    /// Sent as broadcast and not answered. Cellagents without an address drop
+    /// the enable line of the next cellagent in the daisy chain, so afterwards
+    /// only the first cellagent can be reached at [`Address::UNASSIGNED`].
+    ResetAddresses,
+    /// Assigns and persists the address of a cellagent.
+    ///
+    /// Sent to [`Address::UNASSIGNED`]. The cellagent enables the next
+    /// cellagent in the daisy chain after answering with
+    /// [`Response::Address`].
+    AssignAddress(Address),
+    /// Reads the [`BalancingReport`].
+    ReadBalancing,
+    /// Reads the [`PackStatus`] of the cellcore.
+    ReadPack,
+    /// Reads the cells of the cellcore starting at the given index.
+    ReadCells(u8),
+    /// Reads a setting of the cellcore.
+    ReadSetting(u16),
+    /// Changes a setting of the cellcore.
+    ///
+    /// Answered with [`Response::SettingWritten`] holding the value taken.
+    WriteSetting(SettingValue),
+    /// Reads an entry of the event log of the cellcore.
+    ///
+    /// Index 0 is the oldest entry still stored. Indices past the newest entry
+    /// are answered with [`ErrorCode::OutOfRange`].
+    ReadEvent(u16),
+    /// Reads the [`CrashReport`] of the panic before the last reset.
+    ReadCrash,
+}
+
+impl Request {
+    /// Returns the message code.
+    #[must_use]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Ping => PING,
+            Self::ReadVoltage => READ_VOLTAGE,
+            Self::ReadTemperature => READ_TEMPERATURE,
+            Self::SetBalancing(_) => SET_BALANCING,
+            Self::ReadStatus => READ_STATUS,
+            Self::ResetAddresses => RESET_ADDRESSES,
+            Self::AssignAddress(_) => ASSIGN_ADDRESS,
+            Self::ReadBalancing => READ_BALANCING,
+            Self::ReadPack => READ_PACK,
+            Self::ReadCells(_) => READ_CELLS,
+            Self::ReadSetting(_) => READ_SETTING,
+            Self::WriteSetting(_) => WRITE_SETTING,
+            Self::ReadEvent(_) => READ_EVENT,
+            Self::ReadCrash => READ_CRASH,
+        }
+    }
+
+    /// Returns the message code of a successful response.
+    #[must_use]
+    pub const fn response_code(&self) -> u8 {
+        self.code() | RESPONSE_FLAG
+    }
+
+    /// Encodes the request in a frame into `buf` and returns the number of
+    /// bytes written.
+    pub fn encode(&self, header: Header, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = match *self {
+            Self::Ping
+            | Self::ReadVoltage
+            | Self::ReadTemperature
+            | Self::ReadStatus
+            | Self::ResetAddresses
+            | Self::ReadBalancing
+            | Self::ReadPack
+            | Self::ReadCrash => 0,
+            Self::SetBalancing(balancing) => put(&mut payload, &balancing.encode()),
+            Self::AssignAddress(address) => put(&mut payload, &[address.0]),
+            Self::ReadCells(first) => put(&mut payload, &[first]),
+            Self::ReadSetting(id) => put(&mut payload, &id.to_le_bytes()),
+            Self::WriteSetting(setting) => put(&mut payload, &setting.encode()),
+            Self::ReadEvent(index) => put(&mut payload, &index.to_le_bytes()),
+        };
+        Frame::new(header, self.code(), &payload[..len]).encode(buf)
+    }
+
+    /// Decodes the request in a frame.
+    ///
+    /// The error is meant to be sent back in [`Response::Error`].
+    pub fn decode(frame: &Frame<'_>) -> Result<Self, ErrorCode> {
+        let request = match frame.code {
+            PING => Self::Ping,
+            READ_VOLTAGE => Self::ReadVoltage,
+            READ_TEMPERATURE => Self::ReadTemperature,
+            SET_BALANCING => return Ok(Self::SetBalancing(Balancing::decode(frame.payload)?)),
+            READ_STATUS => Self::ReadStatus,
+            RESET_ADDRESSES => Self::ResetAddresses,
+            READ_BALANCING => Self::ReadBalancing,
+            READ_PACK => Self::ReadPack,
+            READ_CRASH => Self::ReadCrash,
+            ASSIGN_ADDRESS => match frame.payload {
+                &[address] => return Ok(Self::AssignAddress(Address(address))),
+                _ => return Err(ErrorCode::InvalidPayload),
+            },
+            READ_CELLS => match frame.payload {
+                &[first] => return Ok(Self::ReadCells(first)),
+                _ => return Err(ErrorCode::InvalidPayload),
+            },
+            READ_SETTING => match frame.payload {
+                &[a, b] => return Ok(Self::ReadSetting(u16::from_le_bytes([a, b]))),
+                _ => return Err(ErrorCode::InvalidPayload),
+            },
+            WRITE_SETTING => {
+                return Ok(Self::WriteSetting(SettingValue::decode(frame.payload)?));
+            }
+            READ_EVENT => match frame.payload {
+                &[a, b] => return Ok(Self::ReadEvent(u16::from_le_bytes([a, b]))),
+                _ => return Err(ErrorCode::InvalidPayload),
+            },
+            _ => return Err(ErrorCode::UnknownCommand),
+        };
+        if frame.payload.is_empty() {
+            Ok(request)
+        } else {
+            Err(ErrorCode::InvalidPayload)
+        }
+    }
+}
+
+/// A response to a [`Request`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Response {
+    /// Response to [`Request::Ping`].
+    Pong(NodeInfo),
+    /// Cell voltage in millivolts.
+    Voltage(u16),
+    /// Temperature in 1/16 °C, the resolution of the P3T1755.
+    Temperature(i16),
+    /// Balancing state after processing [`Request::SetBalancing`].
+    ///
+    /// The duration may be shorter than requested if the node limits the
+    /// on-time.
+    Balancing(Balancing),
+    /// Response to [`Request::ReadStatus`].
+    Status(Status),
+    /// Address taken after processing [`Request::AssignAddress`].
+    Address(Address),
+    /// Response to [`Request::ReadBalancing`].
+    BalancingReport(BalancingReport),
+    /// Response to [`Request::ReadPack`].
+    Pack(PackStatus),
+    /// Response to [`Request::ReadCells`].
+    ///
+    /// Holds fewer than [`CELLS_PER_BLOCK`] cells at the end of the pack.
+    Cells(CellBlock),
+    /// Response to [`Request::ReadSetting`].
+    Setting(SettingValue),
+    /// Value taken after processing [`Request::WriteSetting`].
+    SettingWritten(SettingValue),
+    /// Response to [`Request::ReadEvent`].
+    Event(Event),
+    /// Response to [`Request::ReadCrash`], `None` if the node didn't crash.
+    Crash(Option<CrashReport>),
+    /// The request was rejected.
+    Error(ErrorCode),
+}
+
+impl Response {
+    /// Returns the message code.
+    #[must_use]
+    pub const fn code(&self) -> u8 {
+        match self {
+            Self::Pong(_) => PING | RESPONSE_FLAG,
+            Self::Voltage(_) => READ_VOLTAGE | RESPONSE_FLAG,
+            Self::Temperature(_) => READ_TEMPERATURE | RESPONSE_FLAG,
+            Self::Balancing(_) => SET_BALANCING | RESPONSE_FLAG,
+            Self::Status(_) => READ_STATUS | RESPONSE_FLAG,
+            Self::Address(_) => ASSIGN_ADDRESS | RESPONSE_FLAG,
+            Self::BalancingReport(_) => READ_BALANCING | RESPONSE_FLAG,
+            Self::Pack(_) => READ_PACK | RESPONSE_FLAG,
+            Self::Cells(_) => READ_CELLS | RESPONSE_FLAG,
+            Self::Setting(_) => READ_SETTING | RESPONSE_FLAG,
+            Self::SettingWritten(_) => WRITE_SETTING | RESPONSE_FLAG,
+            Self::Event(_) => READ_EVENT | RESPONSE_FLAG,
+            Self::Crash(_) => READ_CRASH | RESPONSE_FLAG,
+            Self::Error(_) => ERROR_CODE,
+        }
+    }
+
+    /// Encodes the response in a frame into `buf` and returns the number of
+    /// bytes written.
+    ///
+    /// The header is usually created with [`Header::reply`].
+    pub fn encode(&self, header: Header, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = match *self {
+            Self::Pong(info) => put(
+                &mut payload,
+                &[
+                    info.protocol,
+                    info.kind as u8,
+                    info.firmware.major,
+                    info.firmware.minor,
+                    info.firmware.patch,
+                ],
+            ),
+            Self::Voltage(mv) => put(&mut payload, &mv.to_le_bytes()),
+            Self::Temperature(t) => put(&mut payload, &t.to_le_bytes()),
+            Self::Balancing(balancing) => put(&mut payload, &balancing.encode()),
+            Self::Status(status) => put(&mut payload, &status.encode()),
+            Self::Address(address) => put(&mut payload, &[address.0]),
+            Self::BalancingReport(report) => put(&mut payload, &report.encode()),
+            Self::Pack(status) => status.encode(&mut payload),
+            Self::Cells(block) => block.encode(&mut payload),
+            Self::Setting(setting) | Self::SettingWritten(setting) => {
+                put(&mut payload, &setting.encode())
+            }
+            Self::Event(event) => put(&mut payload, &event.encode()),
+            Self::Crash(report) => report.map_or(0, |report| put(&mut payload, &report.encode())),
+            Self::Error(code) => put(&mut payload, &[code as u8]),
+        };
+        Frame::new(header, self.code(), &payload[..len]).encode(buf)
+    }
+
+    /// Decodes the response in a frame.
+    pub fn decode(frame: &Frame<'_>) -> Result<Self, ErrorCode> {
+        let payload = frame.payload;
+        match frame.code {
+            ERROR_CODE => match payload {
+                &[code] => ErrorCode::from_u8(code)
+                    .map(Self::Error)
+                    .ok_or(ErrorCode::InvalidPayload),
+                _ => Err(ErrorCode::InvalidPayload),
+            },
+            c if c == PING | RESPONSE_FLAG => {
+                // Later protocol versions may append fields.
+                let &[protocol, kind, major, minor, patch, ..] = payload else {
+                    return Err(ErrorCode::InvalidPayload);
+                };
+                let kind = NodeKind::from_u8(kind).ok_or(ErrorCode::InvalidPayload)?;
+                Ok(Self::Pong(NodeInfo {
+                    protocol,
+                    kind,
+                    firmware: Version::new(major, minor, patch),
+                }))
+            }
+            c if c == READ_VOLTAGE | RESPONSE_FLAG => Ok(Self::Voltage(u16::from_le_bytes(
+                payload.try_into().map_err(|_| ErrorCode::InvalidPayload)?,
+            ))),
+            c if c == READ_TEMPERATURE | RESPONSE_FLAG => Ok(Self::Temperature(
+                i16::from_le_bytes(payload.try_into().map_err(|_| ErrorCode::InvalidPayload)?),
+            )),
+            c if c == SET_BALANCING | RESPONSE_FLAG => {
+                Balancing::decode(payload).map(Self::Balancing)
+            }
+            c if c == READ_STATUS | RESPONSE_FLAG => Status::decode(payload).map(Self::Status),
+            c if c == ASSIGN_ADDRESS | RESPONSE_FLAG => match payload {
+                &[address] => Ok(Self::Address(Address(address))),
+                _ => Err(ErrorCode::InvalidPayload),
+            },
+            c if c == READ_BALANCING | RESPONSE_FLAG => {
+                BalancingReport::decode(payload).map(Self::BalancingReport)
+            }
+            c if c == READ_PACK | RESPONSE_FLAG => PackStatus::decode(payload).map(Self::Pack),
+            c if c == READ_CELLS | RESPONSE_FLAG => CellBlock::decode(payload).map(Self::Cells),
+            c if c == READ_SETTING | RESPONSE_FLAG => {
+                SettingValue::decode(payload).map(Self::Setting)
+            }
+            c if c == WRITE_SETTING | RESPONSE_FLAG => {
+                SettingValue::decode(payload).map(Self::SettingWritten)
+            }
+            c if c == READ_EVENT | RESPONSE_FLAG => Event::decode(payload).map(Self::Event),
+            c if c == READ_CRASH | RESPONSE_FLAG => match payload {
+                [] => Ok(Self::Crash(None)),
+                _ => CrashReport::decode(payload).map(|report| Self::Crash(Some(report))),
+            },
+            _ => Err(ErrorCode::UnknownCommand),
+        }
+    }
+}
+
+/// Copies `data` to the start of `payload` and returns its length.
+fn put(payload: &mut [u8; MAX_PAYLOAD], data: &[u8]) -> usize {
+    payload[..data.len()].copy_from_slice(data);
+    data.len()
+}
+
+/// Appends to a payload.
+struct Writer<'a> {
+    payload: &'a mut [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    const fn new(payload: &'a mut [u8; MAX_PAYLOAD]) -> Self {
+        Self { payload, len: 0 }
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        self.payload[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+    }
+}
+
+/// Takes little endian values from the start of a payload.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ErrorCode> {
+        let (bytes, rest) = self
+            .0
+            .split_first_chunk()
+            .ok_or(ErrorCode::InvalidPayload)?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ErrorCode> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, ErrorCode> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, ErrorCode> {
+        self.take().map(i16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ErrorCode> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, ErrorCode> {
+        self.take().map(i32::from_le_bytes)
+    }
+
+    /// Fails if there are bytes left.
+    fn finish(self) -> Result<(), ErrorCode> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorCode::InvalidPayload)
+        }
+    }
+}
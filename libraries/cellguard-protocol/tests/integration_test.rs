@@ -0,0 +1,441 @@
+//! Integration tests for the cellguard protocol.
+
+use cellguard_protocol::{
+    Address, Balancing, BalancingReport, CELLS_PER_BLOCK, CellBlock, CellReading, CrashReport,
+    DecodeError, Decoder, EncodeError, ErrorCode, Event, EventKind, Frame, Header, MAX_FRAME_LEN,
+    MAX_PAYLOAD, NodeInfo, NodeKind, PROTOCOL_VERSION, PackStatus, Request, Response, SYNC,
+    SettingValue, Status, StatusFlags, Version,
+};
+
+const REQUEST: Header = Header::new(Address(0x03), Address::CORE, 0x42);
+
+fn encode_request(request: Request) -> ([u8; MAX_FRAME_LEN], usize) {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = request.encode(REQUEST, &mut buf).unwrap();
+    (buf, len)
+}
+
+fn roundtrip_response(response: Response) -> Response {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = response
+        .encode(REQUEST.reply(Address(0x03)), &mut buf)
+        .unwrap();
+    let (frame, used) = Frame::decode(&buf[..len]).unwrap();
+    assert_eq!(used, len);
+    assert!(frame.header.is_reply_to(&REQUEST));
+    Response::decode(&frame).unwrap()
+}
+
+#[test]
+fn test_encode_layout() {
+    let (buf, len) = encode_request(Request::SetBalancing(Balancing::on(600)));
+    assert_eq!(
+        &buf[..len - 2],
+        &[
+            SYNC,
+            PROTOCOL_VERSION,
+            0x03,
+            0x00,
+            0x42,
+            0x04,
+            3,
+            1,
+            0x58,
+            0x02
+        ]
+    );
+    let crc = cellguard_protocol::crc16(&buf[1..len - 2]);
+    assert_eq!(&buf[len - 2..len], &crc.to_be_bytes());
+}
+
+#[test]
+fn test_request_roundtrip() {
+    for request in [
+        Request::Ping,
+        Request::ReadVoltage,
+        Request::ReadTemperature,
+        Request::SetBalancing(Balancing::on(30)),
+        Request::SetBalancing(Balancing::OFF),
+        Request::ReadStatus,
+        Request::ResetAddresses,
+        Request::AssignAddress(Address(0x07)),
+        Request::ReadBalancing,
+        Request::ReadPack,
+        Request::ReadCells(12),
+        Request::ReadSetting(0x0102),
+        Request::WriteSetting(SettingValue {
+            id: 4,
+            value: -1200,
+        }),
+        Request::ReadEvent(513),
+        Request::ReadCrash,
+    ] {
+        let (buf, len) = encode_request(request);
+        let (frame, used) = Frame::decode(&buf[..len]).unwrap();
+        assert_eq!(used, len);
+        assert_eq!(frame.header, REQUEST);
+        assert_eq!(frame.code, request.code());
+        assert_eq!(Request::decode(&frame), Ok(request));
+    }
+}
+
+#[test]
+fn test_response_roundtrip() {
+    for response in [
+        Response::Pong(NodeInfo::new(NodeKind::Agent, Version::new(1, 2, 3))),
+        Response::Voltage(3312),
+        Response::Temperature(-160),
+        Response::Balancing(Balancing::on(120)),
+        Response::Status(Status {
+            flags: StatusFlags::BALANCING | StatusFlags::OVER_TEMPERATURE,
+            uptime_s: 86_400,
+        }),
+        Response::Address(Address(0x07)),
+        Response::BalancingReport(BalancingReport {
+            on_time_s: 3600,
+            charge_mas: 360_000,
+        }),
+        Response::Pack(PackStatus {
+            soc: Some(8125),
+            voltage_mv: Some(52_800),
+            current_ma: Some(-12_500),
+            cells: 16,
+            warnings: 0b01,
+            alarms: 0,
+            trips: 0b10_0000,
+            charge_current_ma: 50_000,
+            discharge_current_ma: 100_000,
+            charge_voltage_mv: 56_800,
+            discharge_voltage_mv: 44_800,
+        }),
+        Response::Pack(PackStatus::default()),
+        Response::Setting(SettingValue { id: 3, value: 3650 }),
+        Response::SettingWritten(SettingValue {
+            id: 0x100,
+            value: 5,
+        }),
+        Response::Event(Event {
+            sequence: 70_000,
+            uptime_s: 3600,
+            kind: EventKind::Trip,
+            data: 2,
+        }),
+        Response::Crash(Some(crash_report())),
+        Response::Crash(None),
+        Response::Error(ErrorCode::HardwareFault),
+        Response::Error(ErrorCode::OutOfRange),
+    ] {
+        assert_eq!(roundtrip_response(response), response);
+    }
+}
+
+fn crash_report() -> CrashReport {
+    CrashReport {
+        file: *b"balancing.rs",
+        line: 214,
+        message_hash: 0xB1C3,
+        reset_cause: 0x01,
+        crashes: 3,
+        safe_mode: true,
+    }
+}
+
+#[test]
+fn test_crash_report() {
+    let report = crash_report();
+    assert_eq!(report.file(), "balancing.rs");
+
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = Response::Crash(Some(report))
+        .encode(REQUEST.reply(Address(0x03)), &mut buf)
+        .unwrap();
+    let (frame, _) = Frame::decode(&buf[..len]).unwrap();
+    assert_eq!(frame.payload[12..], [214, 0, 0xC3, 0xB1, 0x01, 3, 1]);
+
+    let mut short = CrashReport {
+        file: *b"main.rs\0\0\0\0\0",
+        ..report
+    };
+    assert_eq!(short.file(), "main.rs");
+    // A name cut in the middle of a character.
+    short.file = *b"temp_\xC2\xB0C.rs\xC2";
+    assert_eq!(short.file(), "temp_\u{B0}C.rs");
+}
+
+#[test]
+fn test_response_code_matches_request() {
+    assert_eq!(
+        Response::Voltage(0).code(),
+        Request::ReadVoltage.response_code()
+    );
+    assert_eq!(
+        Response::Status(Status {
+            flags: StatusFlags::NONE,
+            uptime_s: 0
+        })
+        .code(),
+        Request::ReadStatus.response_code()
+    );
+}
+
+#[test]
+fn test_cell_block_roundtrip() {
+    let mut block = CellBlock::new(12, 16);
+    let readings = [
+        CellReading {
+            voltage_mv: Some(3301),
+            temperature: Some(400),
+            balancing: true,
+        },
+        CellReading {
+            voltage_mv: None,
+            temperature: Some(-24),
+            balancing: false,
+        },
+        CellReading::default(),
+    ];
+    for reading in readings {
+        assert!(block.push(reading));
+    }
+    let Response::Cells(decoded) = roundtrip_response(Response::Cells(block)) else {
+        panic!("not a cell block");
+    };
+    assert_eq!(decoded, block);
+    assert_eq!((decoded.first, decoded.total), (12, 16));
+    assert_eq!(decoded.readings(), readings);
+}
+
+#[test]
+fn test_cell_block_capacity() {
+    let mut block = CellBlock::new(0, 8);
+    for _ in 0..CELLS_PER_BLOCK {
+        assert!(block.push(CellReading::default()));
+    }
+    assert!(!block.push(CellReading::default()));
+    assert_eq!(block.readings().len(), CELLS_PER_BLOCK);
+    assert_eq!(
+        roundtrip_response(Response::Cells(block)),
+        Response::Cells(block)
+    );
+}
+
+#[test]
+fn test_setting_responses_match_request() {
+    let setting = SettingValue { id: 1, value: 2 };
+    assert_eq!(
+        Response::Setting(setting).code(),
+        Request::ReadSetting(1).response_code()
+    );
+    assert_eq!(
+        Response::SettingWritten(setting).code(),
+        Request::WriteSetting(setting).response_code()
+    );
+}
+
+#[test]
+fn test_request_errors() {
+    let header = REQUEST;
+    let unknown = Frame::new(header, 0x7E, &[]);
+    assert_eq!(Request::decode(&unknown), Err(ErrorCode::UnknownCommand));
+
+    let extra = Frame::new(header, Request::Ping.code(), &[0]);
+    assert_eq!(Request::decode(&extra), Err(ErrorCode::InvalidPayload));
+
+    let short = Frame::new(header, 0x04, &[1, 0]);
+    assert_eq!(Request::decode(&short), Err(ErrorCode::InvalidPayload));
+
+    let bad_bool = Frame::new(header, 0x04, &[2, 0, 0]);
+    assert_eq!(Request::decode(&bad_bool), Err(ErrorCode::InvalidPayload));
+
+    let short_setting = Frame::new(header, Request::ReadSetting(0).code(), &[1]);
+    assert_eq!(
+        Request::decode(&short_setting),
+        Err(ErrorCode::InvalidPayload)
+    );
+}
+
+#[test]
+fn test_response_errors() {
+    let short_pack = Frame::new(REQUEST, 0x89, &[0; 29]);
+    assert_eq!(
+        Response::decode(&short_pack),
+        Err(ErrorCode::InvalidPayload)
+    );
+
+    let partial_cell = Frame::new(REQUEST, 0x8A, &[0, 1, 0xE4, 0x0C]);
+    assert_eq!(
+        Response::decode(&partial_cell),
+        Err(ErrorCode::InvalidPayload)
+    );
+
+    let mut event = [0; 11];
+    event[8] = 0x7F;
+    let unknown_event = Frame::new(REQUEST, 0x8D, &event);
+    assert_eq!(
+        Response::decode(&unknown_event),
+        Err(ErrorCode::InvalidPayload)
+    );
+
+    let mut crash = [0; 19];
+    crash[18] = 2;
+    let bad_safe_mode = Frame::new(REQUEST, 0x8E, &crash);
+    assert_eq!(
+        Response::decode(&bad_safe_mode),
+        Err(ErrorCode::InvalidPayload)
+    );
+    let short_crash = Frame::new(REQUEST, 0x8E, &crash[..18]);
+    assert_eq!(
+        Response::decode(&short_crash),
+        Err(ErrorCode::InvalidPayload)
+    );
+}
+
+#[test]
+fn test_pong_accepts_extensions() {
+    let frame = Frame::new(REQUEST, 0x81, &[1, 2, 0, 9, 1, 0xAA, 0xBB]);
+    assert_eq!(
+        Response::decode(&frame),
+        Ok(Response::Pong(NodeInfo {
+            protocol: 1,
+            kind: NodeKind::Core,
+            firmware: Version::new(0, 9, 1),
+        }))
+    );
+}
+
+#[test]
+fn test_encode_errors() {
+    let payload = [0; MAX_PAYLOAD + 1];
+    let mut buf = [0; 64];
+    assert_eq!(
+        Frame::new(REQUEST, 0x01, &payload).encode(&mut buf),
+        Err(EncodeError::PayloadTooLong)
+    );
+    assert_eq!(
+        Request::Ping.encode(REQUEST, &mut buf[..8]),
+        Err(EncodeError::BufferTooSmall)
+    );
+}
+
+#[test]
+fn test_decode_errors() {
+    let (mut buf, len) = encode_request(Request::ReadVoltage);
+    assert_eq!(Frame::decode(&[]), Err(DecodeError::Incomplete));
+    assert_eq!(Frame::decode(&buf[..len - 1]), Err(DecodeError::Incomplete));
+    assert_eq!(Frame::decode(&buf[1..len]), Err(DecodeError::Sync));
+
+    buf[5] ^= 0x01;
+    assert_eq!(Frame::decode(&buf[..len]), Err(DecodeError::Crc));
+    buf[5] ^= 0x01;
+
+    buf[1] = PROTOCOL_VERSION + 1;
+    assert_eq!(
+        Frame::decode(&buf[..len]),
+        Err(DecodeError::Version(PROTOCOL_VERSION + 1))
+    );
+    buf[1] = PROTOCOL_VERSION;
+
+    buf[6] = MAX_PAYLOAD as u8 + 1;
+    assert_eq!(
+        Frame::decode(&buf[..len]),
+        Err(DecodeError::Length(MAX_PAYLOAD as u8 + 1))
+    );
+}
+
+#[test]
+fn test_decoder_stream() {
+    let (first, first_len) = encode_request(Request::Ping);
+    let (second, second_len) = encode_request(Request::SetBalancing(Balancing::on(5)));
+    let mut stream = vec![0x00, 0x13, 0xFF];
+    stream.extend_from_slice(&first[..first_len]);
+    stream.push(0x55);
+    stream.extend_from_slice(&second[..second_len]);
+
+    let mut decoder = Decoder::new();
+    let mut requests = Vec::new();
+    for byte in stream {
+        if let Some(result) = decoder.push(byte) {
+            requests.push(Request::decode(&result.unwrap()).unwrap());
+        }
+    }
+    assert_eq!(
+        requests,
+        [Request::Ping, Request::SetBalancing(Balancing::on(5))]
+    );
+    assert!(!decoder.is_receiving());
+}
+
+#[test]
+fn test_decoder_recovers_after_error() {
+    let (mut bad, len) = encode_request(Request::ReadStatus);
+    bad[len - 1] ^= 0xFF;
+    let (good, good_len) = encode_request(Request::ReadTemperature);
+
+    let mut decoder = Decoder::new();
+    let mut results = Vec::new();
+    for &byte in bad[..len].iter().chain(&good[..good_len]) {
+        if let Some(result) = decoder.push(byte) {
+            results.push(result.map(|frame| frame.code));
+        }
+    }
+    assert_eq!(
+        results,
+        [Err(DecodeError::Crc), Ok(Request::ReadTemperature.code())]
+    );
+}
+
+#[test]
+fn test_decoder_rejects_header_early() {
+    let mut decoder = Decoder::new();
+    let header = [SYNC, PROTOCOL_VERSION, 1, 0, 0, 0x02, 0xFF];
+    for &byte in &header[..6] {
+        assert_eq!(decoder.push(byte), None);
+    }
+    assert_eq!(
+        decoder.push(header[6]),
+        Some(Err(DecodeError::Length(0xFF)))
+    );
+    assert!(!decoder.is_receiving());
+}
+
+#[test]
+fn test_addresses() {
+    assert_eq!(Address::agent(0), Some(Address::FIRST_AGENT));
+    assert_eq!(Address::agent(0xEE), Some(Address::LAST_AGENT));
+    assert_eq!(Address::agent(0xEF), None);
+    assert_eq!(Address(0x05).agent_index(), Some(4));
+    assert_eq!(Address::CORE.agent_index(), None);
+    assert!(!Address::UNASSIGNED.is_agent());
+
+    assert!(Address(0x05).accepts(Address(0x05)));
+    assert!(Address::BROADCAST.accepts(Address(0x05)));
+    assert!(!Address(0x06).accepts(Address(0x05)));
+}
+
+#[test]
+fn test_reply_matching() {
+    let reply = REQUEST.reply(Address(0x03));
+    assert!(reply.is_reply_to(&REQUEST));
+    assert!(
+        !Header {
+            sequence: 0x43,
+            ..reply
+        }
+        .is_reply_to(&REQUEST)
+    );
+    assert!(!REQUEST.reply(Address(0x04)).is_reply_to(&REQUEST));
+
+    let broadcast = Header::new(Address::BROADCAST, Address::CORE, 7);
+    assert!(broadcast.reply(Address(0x04)).is_reply_to(&broadcast));
+}
+
+#[test]
+fn test_status_flags() {
+    let mut flags = StatusFlags::NONE;
+    flags.set(StatusFlags::COMMAND_TIMEOUT, true);
+    flags.set(StatusFlags::BALANCING, true);
+    assert!(flags.contains(StatusFlags::COMMAND_TIMEOUT | StatusFlags::BALANCING));
+    flags.set(StatusFlags::BALANCING, false);
+    assert!(!flags.contains(StatusFlags::BALANCING));
+    assert_eq!(flags, StatusFlags::COMMAND_TIMEOUT);
+}
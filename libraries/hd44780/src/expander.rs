@@ -0,0 +1,146 @@
+//! Interface using the outputs of a TCA9535 I/O expander.
+
+use embedded_hal::i2c::I2c;
+use tca9535::{Configuration, Output, PinIndex, Tca9535};
+
+use crate::interface::{Interface, Register};
+
+/// Assignment of the display lines to expander pins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinMap {
+    /// Register select line.
+    pub rs: PinIndex,
+    /// Enable line.
+    pub en: PinIndex,
+    /// Data lines D4, D5, D6 and D7 in that order.
+    pub data: [PinIndex; 4],
+    /// Active-high backlight control, if wired.
+    pub backlight: Option<PinIndex>,
+}
+
+impl PinMap {
+    /// Returns a mask of all pins used by the display.
+    const fn mask(&self) -> u16 {
+        let mut mask = self.rs.mask() | self.en.mask();
+        let mut i = 0;
+        while i < self.data.len() {
+            mask |= self.data[i].mask();
+            i += 1;
+        }
+        if let Some(pin) = self.backlight {
+            mask |= pin.mask();
+        }
+        mask
+    }
+}
+
+/// Interface driving the display through a TCA9535.
+///
+/// Every nibble is transferred as two port states: one with the data and the
+/// enable line high and one with the enable line low again. Both nibbles of a
+/// byte are combined into a single I2C transfer using
+/// [`Tca9535::write_output_sequence`].
+///
+/// Pins that aren't part of the [`PinMap`] keep the state they had in the
+/// `output` value passed to [`Self::new`].
+pub struct ExpanderInterface<I> {
+    device: Tca9535<I>,
+    pins: PinMap,
+    output: Output,
+}
+
+impl<I: I2c> ExpanderInterface<I> {
+    /// Creates a new interface.
+    ///
+    /// `output` is the current value of the output registers. It's used to
+    /// preserve the state of pins not used by the display.
+    pub const fn new(device: Tca9535<I>, pins: PinMap, output: Output) -> Self {
+        Self {
+            device,
+            pins,
+            output,
+        }
+    }
+
+    /// Releases the expander from the interface.
+    pub fn into_inner(self) -> Tca9535<I> {
+        self.device
+    }
+
+    /// Configures all pins used by the display as outputs.
+    ///
+    /// The outputs are driven low before the pin directions are changed, except
+    /// for the backlight which keeps its current state.
+    pub fn configure(&mut self) -> Result<(), I::Error> {
+        let backlight = self.pins.backlight.map_or(0, PinIndex::mask);
+        self.output = Output(self.output.0 & !(self.pins.mask() & !backlight));
+        self.device.write_output(self.output)?;
+        let configuration = self.device.read_configuration()?;
+        self.device
+            .write_configuration(Configuration(configuration.0 & !self.pins.mask()))
+    }
+
+    /// Switches the backlight on or off.
+    ///
+    /// Does nothing if no backlight pin is assigned.
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), I::Error> {
+        let Some(pin) = self.pins.backlight else {
+            return Ok(());
+        };
+        self.output = if on {
+            self.output.with_high(pin)
+        } else {
+            self.output.with_low(pin)
+        };
+        self.device.write_output(self.output)
+    }
+
+    /// Returns the port state for a nibble with the enable line at the given
+    /// level.
+    fn port_state(&self, register: Register, nibble: u8, enable: bool) -> Output {
+        let mut output = self.output.with_low(self.pins.en);
+        output = if register == Register::Data {
+            output.with_high(self.pins.rs)
+        } else {
+            output.with_low(self.pins.rs)
+        };
+        for (i, pin) in self.pins.data.into_iter().enumerate() {
+            output = if nibble & (1 << i) != 0 {
+                output.with_high(pin)
+            } else {
+                output.with_low(pin)
+            };
+        }
+        if enable {
+            output.with_high(self.pins.en)
+        } else {
+            output
+        }
+    }
+}
+
+impl<I: I2c> Interface for ExpanderInterface<I> {
+    type Error = I::Error;
+
+    fn write_nibble(&mut self, register: Register, nibble: u8) -> Result<(), Self::Error> {
+        let strobe = self.port_state(register, nibble, true);
+        let idle = self.port_state(register, nibble, false);
+        self.device.write_output_sequence([strobe, idle])?;
+        self.output = idle;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, register: Register, byte: u8) -> Result<(), Self::Error> {
+        let high = byte >> 4;
+        let low = byte & 0x0F;
+        let idle = self.port_state(register, low, false);
+        self.device.write_output_sequence([
+            self.port_state(register, high, true),
+            self.port_state(register, high, false),
+            self.port_state(register, low, true),
+            idle,
+        ])?;
+        self.output = idle;
+        Ok(())
+    }
+}
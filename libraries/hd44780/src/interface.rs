@@ -0,0 +1,106 @@
+//! Physical interfaces to the 4-bit data bus of the display.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+
+/// Register selected by the RS line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    /// Instruction register (RS low).
+    Instruction,
+    /// Data register (RS high).
+    Data,
+}
+
+/// Write-only 4-bit interface to the display.
+///
+/// The R/W line is expected to be tied low, so the busy flag can't be read and
+/// the driver relies on the worst case execution times instead.
+pub trait Interface {
+    /// Error type of the underlying bus.
+    type Error;
+
+    /// Places the lower four bits of `nibble` on D4-D7 and strobes the enable
+    /// line.
+    fn write_nibble(&mut self, register: Register, nibble: u8) -> Result<(), Self::Error>;
+
+    /// Writes a full byte as two nibbles, most significant nibble first.
+    ///
+    /// Interfaces that can combine several bus states into a single transfer
+    /// should override this.
+    fn write_byte(&mut self, register: Register, byte: u8) -> Result<(), Self::Error> {
+        self.write_nibble(register, byte >> 4)?;
+        self.write_nibble(register, byte & 0x0F)
+    }
+}
+
+/// Interface using individual output pins.
+pub struct PinInterface<RS, EN, D4, D5, D6, D7, D> {
+    rs: RS,
+    en: EN,
+    d4: D4,
+    d5: D5,
+    d6: D6,
+    d7: D7,
+    delay: D,
+}
+
+impl<RS, EN, D4, D5, D6, D7, D, E> PinInterface<RS, EN, D4, D5, D6, D7, D>
+where
+    RS: OutputPin<Error = E>,
+    EN: OutputPin<Error = E>,
+    D4: OutputPin<Error = E>,
+    D5: OutputPin<Error = E>,
+    D6: OutputPin<Error = E>,
+    D7: OutputPin<Error = E>,
+    D: DelayNs,
+{
+    /// Creates a new interface from the RS, E and D4-D7 pins.
+    ///
+    /// The delay is only used to time the enable strobe.
+    pub const fn new(rs: RS, en: EN, d4: D4, d5: D5, d6: D6, d7: D7, delay: D) -> Self {
+        Self {
+            rs,
+            en,
+            d4,
+            d5,
+            d6,
+            d7,
+            delay,
+        }
+    }
+
+    /// Releases the pins and the delay.
+    pub fn release(self) -> (RS, EN, D4, D5, D6, D7, D) {
+        (
+            self.rs, self.en, self.d4, self.d5, self.d6, self.d7, self.delay,
+        )
+    }
+}
+
+impl<RS, EN, D4, D5, D6, D7, D, E> Interface for PinInterface<RS, EN, D4, D5, D6, D7, D>
+where
+    RS: OutputPin<Error = E>,
+    EN: OutputPin<Error = E>,
+    D4: OutputPin<Error = E>,
+    D5: OutputPin<Error = E>,
+    D6: OutputPin<Error = E>,
+    D7: OutputPin<Error = E>,
+    D: DelayNs,
+{
+    type Error = E;
+
+    fn write_nibble(&mut self, register: Register, nibble: u8) -> Result<(), E> {
+        self.rs.set_state((register == Register::Data).into())?;
+        self.d4.set_state((nibble & 0x01 != 0).into())?;
+        self.d5.set_state((nibble & 0x02 != 0).into())?;
+        self.d6.set_state((nibble & 0x04 != 0).into())?;
+        self.d7.set_state((nibble & 0x08 != 0).into())?;
+        // Enable pulse width and cycle time are 450 ns and 1000 ns respectively.
+        self.en.set_high()?;
+        self.delay.delay_ns(450);
+        self.en.set_low()?;
+        self.delay.delay_ns(550);
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+//! HD44780 character LCD driver.
+//!
+//! The display is driven in 4-bit mode with the R/W line tied low. The
+//! physical connection is abstracted by the [`Interface`] trait which is
+//! implemented for individual output pins ([`PinInterface`]) and for the
+//! outputs of a TCA9535 I/O expander ([`ExpanderInterface`]).
+
+#![no_std]
+
+use core::fmt;
+
+use embedded_hal::delay::DelayNs;
+
+pub use self::expander::{ExpanderInterface, PinMap};
+pub use self::interface::{Interface, PinInterface, Register};
+
+mod expander;
+mod interface;
+
+const CLEAR_DISPLAY: u8 = 0x01;
+const RETURN_HOME: u8 = 0x02;
+const ENTRY_MODE_SET: u8 = 0x04;
+const DISPLAY_CONTROL: u8 = 0x08;
+const FUNCTION_SET: u8 = 0x20;
+const SET_CGRAM_ADDR: u8 = 0x40;
+const SET_DDRAM_ADDR: u8 = 0x80;
+
+/// Entry mode: increment the address counter after every access.
+const ENTRY_INCREMENT: u8 = 0x02;
+/// Display control: display on.
+const DISPLAY_ON: u8 = 0x04;
+/// Display control: cursor on.
+const CURSOR_ON: u8 = 0x02;
+/// Display control: blinking cursor.
+const BLINK_ON: u8 = 0x01;
+/// Function set: two line display.
+const TWO_LINES: u8 = 0x08;
+
+/// Execution time of all instructions except clear and home (37 µs) with some
+/// margin.
+const EXEC_DELAY_US: u32 = 40;
+/// Execution time of the clear and home instructions (1.52 ms) with some
+/// margin.
+const HOME_DELAY_US: u32 = 1600;
+
+/// Character code written for characters the display can't show.
+const REPLACEMENT_CHAR: u8 = b'?';
+
+/// Dimensions of the display in characters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Geometry {
+    columns: u8,
+    rows: u8,
+}
+
+impl Geometry {
+    /// 16 columns and 2 rows.
+    pub const LCD_16X2: Self = Self {
+        columns: 16,
+        rows: 2,
+    };
+    /// 20 columns and 4 rows.
+    pub const LCD_20X4: Self = Self {
+        columns: 20,
+        rows: 4,
+    };
+
+    /// Creates a custom geometry.
+    ///
+    /// Returns `None` if the display has more than four rows or if the
+    /// columns don't fit into the display memory.
+    #[must_use]
+    pub const fn new(columns: u8, rows: u8) -> Option<Self> {
+        let max_columns = match rows {
+            1 => 80,
+            2 => 40,
+            // Rows 3 and 4 continue rows 1 and 2 in display memory.
+            3 | 4 => 20,
+            _ => return None,
+        };
+        if columns == 0 || columns > max_columns {
+            return None;
+        }
+        Some(Self { columns, rows })
+    }
+
+    /// Returns the number of columns.
+    #[must_use]
+    pub const fn columns(self) -> u8 {
+        self.columns
+    }
+
+    /// Returns the number of rows.
+    #[must_use]
+    pub const fn rows(self) -> u8 {
+        self.rows
+    }
+
+    /// Returns the display memory address of the given position.
+    const fn address(self, column: u8, row: u8) -> u8 {
+        let row_start = match row {
+            0 => 0x00,
+            1 => 0x40,
+            2 => self.columns,
+            _ => 0x40 + self.columns,
+        };
+        row_start + column
+    }
+}
+
+/// Driver error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error<E> {
+    /// The interface failed.
+    Interface(E),
+    /// A position or glyph location is outside of the valid range.
+    OutOfRange,
+}
+
+/// HD44780 display driver.
+///
+/// The driver tracks the cursor position so text wraps to the next row
+/// instead of continuing in invisible display memory.
+pub struct Hd44780<IF, D> {
+    interface: IF,
+    delay: D,
+    geometry: Geometry,
+    display_control: u8,
+    column: u8,
+    row: u8,
+}
+
+impl<IF: Interface, D: DelayNs> Hd44780<IF, D> {
+    /// Creates a new driver instance.
+    ///
+    /// The display must be initialised with [`Self::init`] before use.
+    pub const fn new(interface: IF, delay: D, geometry: Geometry) -> Self {
+        Self {
+            interface,
+            delay,
+            geometry,
+            display_control: DISPLAY_ON,
+            column: 0,
+            row: 0,
+        }
+    }
+
+    /// Releases the interface and the delay.
+    pub fn release(self) -> (IF, D) {
+        (self.interface, self.delay)
+    }
+
+    /// Returns a mutable reference to the interface.
+    ///
+    /// This can be used to access interface specific functionality such as
+    /// the backlight of an [`ExpanderInterface`].
+    pub fn interface_mut(&mut self) -> &mut IF {
+        &mut self.interface
+    }
+
+    /// Initialises the display into 4-bit mode, clears it and switches it on.
+    ///
+    /// Uses the initialisation by instruction sequence from the datasheet, so
+    /// it works regardless of the state the controller is in.
+    pub fn init(&mut self) -> Result<(), Error<IF::Error>> {
+        // Wait for the supply to settle.
+        self.delay.delay_ms(50);
+        // Three times "8-bit mode" brings the controller into a known state even
+        // if it was in the middle of a 4-bit transfer.
+        self.write_nibble(0x03)?;
+        self.delay.delay_us(4500);
+        self.write_nibble(0x03)?;
+        self.delay.delay_us(150);
+        self.write_nibble(0x03)?;
+        self.delay.delay_us(150);
+        self.write_nibble(0x02)?;
+        self.delay.delay_us(150);
+
+        let lines = if self.geometry.rows > 1 { TWO_LINES } else { 0 };
+        self.command(FUNCTION_SET | lines)?;
+        self.command(DISPLAY_CONTROL)?;
+        self.clear()?;
+        self.command(ENTRY_MODE_SET | ENTRY_INCREMENT)?;
+        self.command(DISPLAY_CONTROL | self.display_control)
+    }
+
+    /// Clears the display and moves the cursor to the top left corner.
+    pub fn clear(&mut self) -> Result<(), Error<IF::Error>> {
+        self.command(CLEAR_DISPLAY)?;
+        self.delay.delay_us(HOME_DELAY_US);
+        self.column = 0;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Moves the cursor to the top left corner without clearing the display.
+    pub fn home(&mut self) -> Result<(), Error<IF::Error>> {
+        self.command(RETURN_HOME)?;
+        self.delay.delay_us(HOME_DELAY_US);
+        self.column = 0;
+        self.row = 0;
+        Ok(())
+    }
+
+    /// Returns the current cursor position as `(column, row)`.
+    pub const fn cursor(&self) -> (u8, u8) {
+        (self.column, self.row)
+    }
+
+    /// Moves the cursor to the given position.
+    pub fn set_cursor(&mut self, column: u8, row: u8) -> Result<(), Error<IF::Error>> {
+        if column >= self.geometry.columns || row >= self.geometry.rows {
+            return Err(Error::OutOfRange);
+        }
+        self.command(SET_DDRAM_ADDR | self.geometry.address(column, row))?;
+        self.column = column;
+        self.row = row;
+        Ok(())
+    }
+
+    /// Switches the display on or off without changing its contents.
+    pub fn set_display_on(&mut self, on: bool) -> Result<(), Error<IF::Error>> {
+        self.set_display_control(DISPLAY_ON, on)
+    }
+
+    /// Shows or hides the underline cursor.
+    pub fn set_cursor_visible(&mut self, visible: bool) -> Result<(), Error<IF::Error>> {
+        self.set_display_control(CURSOR_ON, visible)
+    }
+
+    /// Enables or disables the blinking block cursor.
+    pub fn set_cursor_blink(&mut self, blink: bool) -> Result<(), Error<IF::Error>> {
+        self.set_display_control(BLINK_ON, blink)
+    }
+
+    /// Defines a custom glyph.
+    ///
+    /// `location` selects one of the eight character codes `0..=7` that can be
+    /// written to show the glyph. Every row of `glyph` uses the five least
+    /// significant bits, the eighth row is the cursor line.
+    pub fn create_char(&mut self, location: u8, glyph: &[u8; 8]) -> Result<(), Error<IF::Error>> {
+        if location >= 8 {
+            return Err(Error::OutOfRange);
+        }
+        self.command(SET_CGRAM_ADDR | (location << 3))?;
+        for row in glyph {
+            self.data(row & 0x1F)?;
+        }
+        // Writing to CGRAM moved the address counter away from the cursor.
+        self.set_cursor(self.column, self.row)
+    }
+
+    /// Writes a raw character code at the cursor position.
+    ///
+    /// Wraps to the start of the next row (or the first row) once the end of
+    /// a row is reached.
+    pub fn write_code(&mut self, code: u8) -> Result<(), Error<IF::Error>> {
+        if self.column >= self.geometry.columns {
+            self.set_cursor(0, (self.row + 1) % self.geometry.rows)?;
+        }
+        self.data(code)?;
+        self.column += 1;
+        Ok(())
+    }
+
+    /// Writes text at the cursor position.
+    ///
+    /// A line feed moves the cursor to the start of the next row and a
+    /// carriage return to the start of the current row. Characters outside of
+    /// the display's character set are replaced by a question mark, see
+    /// [`encode_char`].
+    pub fn write_text(&mut self, text: &str) -> Result<(), Error<IF::Error>> {
+        for ch in text.chars() {
+            match ch {
+                '\n' => self.set_cursor(0, (self.row + 1) % self.geometry.rows)?,
+                '\r' => self.set_cursor(0, self.row)?,
+                _ => self.write_code(encode_char(ch))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn set_display_control(&mut self, bit: u8, enable: bool) -> Result<(), Error<IF::Error>> {
+        let value = if enable {
+            self.display_control | bit
+        } else {
+            self.display_control & !bit
+        };
+        self.command(DISPLAY_CONTROL | value)?;
+        self.display_control = value;
+        Ok(())
+    }
+
+    fn write_nibble(&mut self, nibble: u8) -> Result<(), Error<IF::Error>> {
+        self.interface
+            .write_nibble(Register::Instruction, nibble)
+            .map_err(Error::Interface)
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), Error<IF::Error>> {
+        self.interface
+            .write_byte(Register::Instruction, command)
+            .map_err(Error::Interface)?;
+        self.delay.delay_us(EXEC_DELAY_US);
+        Ok(())
+    }
+
+    fn data(&mut self, data: u8) -> Result<(), Error<IF::Error>> {
+        self.interface
+            .write_byte(Register::Data, data)
+            .map_err(Error::Interface)?;
+        self.delay.delay_us(EXEC_DELAY_US);
+        Ok(())
+    }
+}
+
+impl<IF: Interface, D: DelayNs> fmt::Write for Hd44780<IF, D> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_text(s).map_err(|_| fmt::Error)
+    }
+}
+
+/// Maps a character to a code of the A00 (Japanese) character ROM.
+///
+/// - `'\0'` to `'\x07'` map to the custom glyphs.
+/// - Printable ASCII maps to itself, except that the ROM shows `'\\'` as `¥`
+///   and has no `'~'`.
+/// - `'°'` and `'µ'` map to their ROM equivalents.
+/// - Everything else maps to `'?'`.
+#[must_use]
+pub const fn encode_char(ch: char) -> u8 {
+    match ch {
+        '\0'..='\x07' | ' '..='}' => ch as u8,
+        '°' => 0xDF,
+        'µ' => 0xE4,
+        _ => REPLACEMENT_CHAR,
+    }
+}
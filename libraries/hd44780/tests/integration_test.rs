@@ -0,0 +1,313 @@
+//! Integration tests for the HD44780 display driver.
+
+use core::convert::Infallible;
+use core::fmt::Write as _;
+
+use embedded_hal_mock::eh1::delay::NoopDelay;
+use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction};
+use hd44780::{
+    Error, ExpanderInterface, Geometry, Hd44780, Interface, PinInterface, PinMap, Register,
+    encode_char,
+};
+use tca9535::{Address, Output, PinIndex, Tca9535};
+
+/// Interface recording every access.
+#[derive(Default)]
+struct RecordingInterface {
+    log: Vec<Access>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Access {
+    Nibble(u8),
+    Command(u8),
+    Data(u8),
+}
+
+impl Interface for RecordingInterface {
+    type Error = Infallible;
+
+    fn write_nibble(&mut self, register: Register, nibble: u8) -> Result<(), Self::Error> {
+        assert_eq!(register, Register::Instruction);
+        self.log.push(Access::Nibble(nibble));
+        Ok(())
+    }
+
+    fn write_byte(&mut self, register: Register, byte: u8) -> Result<(), Self::Error> {
+        self.log.push(match register {
+            Register::Instruction => Access::Command(byte),
+            Register::Data => Access::Data(byte),
+        });
+        Ok(())
+    }
+}
+
+fn display(geometry: Geometry) -> Hd44780<RecordingInterface, NoopDelay> {
+    Hd44780::new(RecordingInterface::default(), NoopDelay::new(), geometry)
+}
+
+fn take_log(lcd: &mut Hd44780<RecordingInterface, NoopDelay>) -> Vec<Access> {
+    core::mem::take(&mut lcd.interface_mut().log)
+}
+
+/// PCF8574 style backpack wiring: RS=P0, E=P2, backlight=P3, D4-D7=P4-P7.
+const PIN_MAP: PinMap = PinMap {
+    rs: PinIndex::P0,
+    en: PinIndex::P2,
+    data: [PinIndex::P4, PinIndex::P5, PinIndex::P6, PinIndex::P7],
+    backlight: Some(PinIndex::P3),
+};
+
+#[test]
+fn test_init_sequence() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    lcd.init().unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Nibble(0x03),
+            Access::Nibble(0x03),
+            Access::Nibble(0x03),
+            Access::Nibble(0x02),
+            // Function set: 4-bit, two lines, 5x8 font.
+            Access::Command(0x28),
+            // Display off.
+            Access::Command(0x08),
+            Access::Command(0x01),
+            // Entry mode: increment, no shift.
+            Access::Command(0x06),
+            // Display on, no cursor.
+            Access::Command(0x0C),
+        ]
+    );
+}
+
+#[test]
+fn test_init_single_line() {
+    let mut lcd = display(Geometry::new(8, 1).unwrap());
+    lcd.init().unwrap();
+    assert!(take_log(&mut lcd).contains(&Access::Command(0x20)));
+}
+
+#[test]
+fn test_geometry_limits() {
+    assert!(Geometry::new(40, 2).is_some());
+    assert!(Geometry::new(41, 2).is_none());
+    assert!(Geometry::new(20, 4).is_some());
+    assert!(Geometry::new(21, 4).is_none());
+    assert!(Geometry::new(16, 0).is_none());
+    assert!(Geometry::new(16, 5).is_none());
+    assert!(Geometry::new(0, 1).is_none());
+}
+
+#[test]
+fn test_set_cursor_addresses() {
+    let mut lcd = display(Geometry::LCD_20X4);
+    lcd.set_cursor(0, 0).unwrap();
+    lcd.set_cursor(3, 1).unwrap();
+    lcd.set_cursor(0, 2).unwrap();
+    lcd.set_cursor(19, 3).unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Command(0x80),
+            Access::Command(0x80 | 0x43),
+            Access::Command(0x80 | 0x14),
+            Access::Command(0x80 | 0x67),
+        ]
+    );
+    assert_eq!(lcd.cursor(), (19, 3));
+}
+
+#[test]
+fn test_set_cursor_out_of_range() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    assert_eq!(lcd.set_cursor(16, 0), Err(Error::OutOfRange));
+    assert_eq!(lcd.set_cursor(0, 2), Err(Error::OutOfRange));
+    assert!(take_log(&mut lcd).is_empty());
+}
+
+#[test]
+fn test_text_wraps_to_next_row() {
+    let mut lcd = display(Geometry::new(4, 2).unwrap());
+    lcd.write_text("abcdef").unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Data(b'a'),
+            Access::Data(b'b'),
+            Access::Data(b'c'),
+            Access::Data(b'd'),
+            Access::Command(0xC0),
+            Access::Data(b'e'),
+            Access::Data(b'f'),
+        ]
+    );
+    assert_eq!(lcd.cursor(), (2, 1));
+}
+
+#[test]
+fn test_text_control_characters() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    lcd.write_text("a\nb\rc\n").unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Data(b'a'),
+            Access::Command(0xC0),
+            Access::Data(b'b'),
+            Access::Command(0xC0),
+            Access::Data(b'c'),
+            // Wraps around to the first row.
+            Access::Command(0x80),
+        ]
+    );
+}
+
+#[test]
+fn test_fmt_write() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    write!(lcd, "{}°C", 25).unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Data(b'2'),
+            Access::Data(b'5'),
+            Access::Data(0xDF),
+            Access::Data(b'C'),
+        ]
+    );
+}
+
+#[test]
+fn test_create_char_restores_cursor() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    lcd.set_cursor(5, 1).unwrap();
+    take_log(&mut lcd);
+
+    let glyph = [0x0E, 0x11, 0x11, 0x11, 0x11, 0x1F, 0x1F, 0xFF];
+    lcd.create_char(2, &glyph).unwrap();
+    let mut expected = vec![Access::Command(0x40 | 0x10)];
+    expected.extend(glyph.iter().map(|row| Access::Data(row & 0x1F)));
+    expected.push(Access::Command(0x80 | 0x45));
+    assert_eq!(take_log(&mut lcd), expected);
+
+    assert_eq!(lcd.create_char(8, &glyph), Err(Error::OutOfRange));
+}
+
+#[test]
+fn test_display_control() {
+    let mut lcd = display(Geometry::LCD_16X2);
+    lcd.set_cursor_visible(true).unwrap();
+    lcd.set_cursor_blink(true).unwrap();
+    lcd.set_display_on(false).unwrap();
+    lcd.set_cursor_visible(false).unwrap();
+    assert_eq!(
+        take_log(&mut lcd),
+        [
+            Access::Command(0x0E),
+            Access::Command(0x0F),
+            Access::Command(0x0B),
+            Access::Command(0x09),
+        ]
+    );
+}
+
+#[test]
+fn test_encode_char() {
+    assert_eq!(encode_char('\0'), 0);
+    assert_eq!(encode_char('\x07'), 7);
+    assert_eq!(encode_char('A'), b'A');
+    assert_eq!(encode_char('}'), b'}');
+    assert_eq!(encode_char('~'), b'?');
+    assert_eq!(encode_char('°'), 0xDF);
+    assert_eq!(encode_char('µ'), 0xE4);
+    assert_eq!(encode_char('ä'), b'?');
+}
+
+#[test]
+fn test_expander_byte_is_single_transfer() {
+    let expectations = [
+        Transaction::transaction_start(0x20),
+        Transaction::write(0x20, vec![0x02]),
+        // 'A' = 0x41 with RS high and the backlight (P3) on. Pins P8-P15 keep
+        // their state.
+        Transaction::write(0x20, vec![0x4D, 0xA5, 0x49, 0xA5, 0x1D, 0xA5, 0x19, 0xA5]),
+        Transaction::transaction_end(0x20),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let device = Tca9535::new(mock, Address::Lll);
+    let mut interface = ExpanderInterface::new(device, PIN_MAP, Output(0xA508));
+    interface.write_byte(Register::Data, b'A').unwrap();
+
+    interface.into_inner().into_inner().done();
+}
+
+#[test]
+fn test_expander_nibble() {
+    let expectations = [
+        Transaction::transaction_start(0x20),
+        Transaction::write(0x20, vec![0x02]),
+        Transaction::write(0x20, vec![0x34, 0x00, 0x30, 0x00]),
+        Transaction::transaction_end(0x20),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let device = Tca9535::new(mock, Address::Lll);
+    let mut interface = ExpanderInterface::new(device, PIN_MAP, Output(0x0000));
+    interface.write_nibble(Register::Instruction, 0x03).unwrap();
+
+    interface.into_inner().into_inner().done();
+}
+
+#[test]
+fn test_expander_configure_and_backlight() {
+    let expectations = [
+        // Display pins driven low, backlight and unrelated pins untouched.
+        Transaction::write(0x20, vec![0x02, 0x0A, 0xFF]),
+        Transaction::write_read(0x20, vec![0x06], vec![0xFF, 0xFF]),
+        Transaction::write(0x20, vec![0x06, 0x02, 0xFF]),
+        Transaction::write(0x20, vec![0x02, 0x02, 0xFF]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let device = Tca9535::new(mock, Address::Lll);
+    let mut interface = ExpanderInterface::new(device, PIN_MAP, Output(0xFFFF));
+    interface.configure().unwrap();
+    interface.set_backlight(false).unwrap();
+
+    interface.into_inner().into_inner().done();
+}
+
+#[test]
+fn test_pin_interface_nibble() {
+    let mut rs = PinMock::new(&[PinTransaction::set(State::High)]);
+    let mut en = PinMock::new(&[
+        PinTransaction::set(State::High),
+        PinTransaction::set(State::Low),
+    ]);
+    let mut d4 = PinMock::new(&[PinTransaction::set(State::High)]);
+    let mut d5 = PinMock::new(&[PinTransaction::set(State::Low)]);
+    let mut d6 = PinMock::new(&[PinTransaction::set(State::Low)]);
+    let mut d7 = PinMock::new(&[PinTransaction::set(State::High)]);
+
+    let mut interface = PinInterface::new(
+        rs.clone(),
+        en.clone(),
+        d4.clone(),
+        d5.clone(),
+        d6.clone(),
+        d7.clone(),
+        NoopDelay::new(),
+    );
+    interface.write_nibble(Register::Data, 0x09).unwrap();
+
+    rs.done();
+    en.done();
+    d4.done();
+    d5.done();
+    d6.done();
+    d7.done();
+}
@@ -0,0 +1,153 @@
+use embedded_can::{ExtendedId, Id, StandardId};
+
+use crate::register::{EXIDE, RTR};
+
+/// Standard frame remote transmission request bit of `RXBnSIDL`.
+const SRR: u8 = 1 << 4;
+
+/// Length of the identifier, length and data registers of a buffer.
+pub(crate) const BUFFER_LEN: usize = 13;
+
+/// Returns the `SIDH`, `SIDL`, `EID8` and `EID0` registers of an identifier.
+///
+/// Extended identifiers set `EXIDE`, standard identifiers leave it and the
+/// extended bits clear.
+pub(crate) fn id_to_regs(id: Id) -> [u8; 4] {
+    match id {
+        Id::Standard(id) => {
+            let sid = id.as_raw();
+            [(sid >> 3) as u8, (sid << 5) as u8, 0, 0]
+        }
+        Id::Extended(id) => {
+            let raw = id.as_raw();
+            let sid = raw >> 18;
+            [
+                (sid >> 3) as u8,
+                (sid << 5) as u8 | EXIDE | (raw >> 16) as u8 & 0b11,
+                (raw >> 8) as u8,
+                raw as u8,
+            ]
+        }
+    }
+}
+
+/// Decodes the identifier of a receive buffer.
+fn id_from_regs(regs: [u8; 4]) -> Id {
+    let [sidh, sidl, eid8, eid0] = regs;
+    let sid = u16::from(sidh) << 3 | u16::from(sidl >> 5);
+    if sidl & EXIDE == 0 {
+        // SAFETY: Eleven bits always fit.
+        Id::Standard(unsafe { StandardId::new_unchecked(sid) })
+    } else {
+        let raw = u32::from(sid) << 18
+            | u32::from(sidl & 0b11) << 16
+            | u32::from(eid8) << 8
+            | u32::from(eid0);
+        // SAFETY: Twenty-nine bits always fit.
+        Id::Extended(unsafe { ExtendedId::new_unchecked(raw) })
+    }
+}
+
+/// CAN frame as stored in the buffers of the controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+impl Frame {
+    /// Encodes the frame for a transmit buffer.
+    ///
+    /// Returns the registers and the number of them that are used.
+    pub(crate) fn to_regs(self) -> ([u8; BUFFER_LEN], usize) {
+        let mut regs = [0; BUFFER_LEN];
+        let [sidh, sidl, eid8, eid0] = id_to_regs(self.id);
+        regs[0] = sidh;
+        regs[1] = sidl;
+        regs[2] = eid8;
+        regs[3] = eid0;
+        regs[4] = self.dlc | if self.remote { RTR } else { 0 };
+        let len = if self.remote { 0 } else { self.dlc as usize };
+        let mut i = 0;
+        while i < len {
+            regs[5 + i] = self.data[i];
+            i += 1;
+        }
+        (regs, 5 + len)
+    }
+
+    /// Decodes the frame of a receive buffer.
+    pub(crate) fn from_regs(regs: &[u8; BUFFER_LEN]) -> Self {
+        let id = id_from_regs([regs[0], regs[1], regs[2], regs[3]]);
+        let remote = match id {
+            Id::Standard(_) => regs[1] & SRR != 0,
+            Id::Extended(_) => regs[4] & RTR != 0,
+        };
+        // Lengths above 8 are allowed on the bus but still carry 8 bytes.
+        let dlc = (regs[4] & 0x0F).min(8);
+        let mut data = [0; 8];
+        if !remote {
+            data[..usize::from(dlc)].copy_from_slice(&regs[5..5 + usize::from(dlc)]);
+        }
+        Self {
+            id,
+            remote,
+            dlc,
+            data,
+        }
+    }
+}
+
+impl embedded_can::Frame for Frame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+        let mut buf = [0; 8];
+        buf[..data.len()].copy_from_slice(data);
+        Some(Self {
+            id: id.into(),
+            remote: false,
+            dlc: data.len() as u8,
+            data: buf,
+        })
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+        Some(Self {
+            id: id.into(),
+            remote: true,
+            dlc: dlc as u8,
+            data: [0; 8],
+        })
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        usize::from(self.dlc)
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..usize::from(self.dlc)]
+        }
+    }
+}
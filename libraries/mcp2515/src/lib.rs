@@ -0,0 +1,291 @@
+//! Driver for the Microchip MCP2515 SPI CAN controller.
+//!
+//! The controller has three transmit buffers with a configurable priority and
+//! two receive buffers, each behind an acceptance [`Mask`] and its
+//! [`Filter`]s:
+//!
+//! | Buffer             | Mask           | Filters                                        |
+//! |--------------------|----------------|------------------------------------------------|
+//! | [`RxBuffer::Rxb0`] | [`Mask::Rxm0`] | [`Filter::Rxf0`], [`Filter::Rxf1`]             |
+//! | [`RxBuffer::Rxb1`] | [`Mask::Rxm1`] | [`Filter::Rxf2`] to [`Filter::Rxf5`]           |
+//!
+//! With [`Config::rollover`] a message for a full buffer 0 goes to buffer 1.
+//!
+//! After [`Mcp2515::reset`] the controller is in [`Mode::Configuration`],
+//! the only mode in which [`Mcp2515::configure`], filters and masks can be
+//! written. Mode changes take effect once the bus is idle, so the result of
+//! [`Mcp2515::set_mode`] has to be polled with [`Mcp2515::read_mode`].
+
+#![no_std]
+
+use embedded_can::{ErrorKind, Id};
+use embedded_hal::spi::{Operation, SpiDevice};
+
+pub use self::frame::Frame;
+use self::frame::{BUFFER_LEN, id_to_regs};
+use self::register::{
+    ABAT, BIT_MODIFY, BUKT, CANCTRL, CANINTE, CANINTF, CANSTAT, CNF3, EFLG, EXIDE, OSM, READ,
+    READ_STATUS, RESET, RTS, RX_STATUS, RXB0CTRL, TEC, TXREQ, WRITE,
+};
+pub use self::register::{
+    BitTiming, ErrorCounters, ErrorFlags, ErrorState, Filter, Interrupts, Mask, Mode, Priority,
+    RxBuffer, TxBuffer, TxStatus,
+};
+
+mod frame;
+mod register;
+
+/// Settings written by [`Mcp2515::configure`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Bitrate and sample point.
+    pub bit_timing: BitTiming,
+    /// Interrupts that drive the `INT` pin.
+    pub interrupts: Interrupts,
+    /// Moves messages to receive buffer 1 while buffer 0 is full.
+    pub rollover: bool,
+    /// Sends messages only once, even if they lose arbitration or fail.
+    pub one_shot: bool,
+}
+
+impl Config {
+    /// Creates settings that signal received messages and errors and enable
+    /// rollover.
+    #[must_use]
+    pub const fn new(bit_timing: BitTiming) -> Self {
+        Self {
+            bit_timing,
+            interrupts: Interrupts(Interrupts::RX0.0 | Interrupts::RX1.0 | Interrupts::ERROR.0),
+            rollover: true,
+            one_shot: false,
+        }
+    }
+}
+
+/// Error of the SPI bus, wrapped for [`embedded_can`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Error<E>(pub E);
+
+impl<E: core::fmt::Debug> embedded_can::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// MCP2515 CAN controller driver.
+pub struct Mcp2515<S> {
+    spi: S,
+}
+
+impl<S: SpiDevice> Mcp2515<S> {
+    /// Creates a new driver instance.
+    pub const fn new(spi: S) -> Self {
+        Self { spi }
+    }
+
+    /// Releases the SPI device from the driver.
+    pub fn into_inner(self) -> S {
+        self.spi
+    }
+
+    /// Resets all registers and enters [`Mode::Configuration`].
+    ///
+    /// The oscillator restarts, so the controller needs 128 oscillator
+    /// periods before it accepts the next instruction.
+    pub fn reset(&mut self) -> Result<(), S::Error> {
+        self.spi.write(&[RESET])
+    }
+
+    /// Writes the bit timing, interrupt enables, rollover and one-shot mode.
+    ///
+    /// Only takes effect in [`Mode::Configuration`].
+    pub fn configure(&mut self, config: &Config) -> Result<(), S::Error> {
+        let [cnf3, cnf2, cnf1] = config.bit_timing.to_regs();
+        self.write_registers(CNF3, &[cnf3, cnf2, cnf1, config.interrupts.0])?;
+        let rollover = if config.rollover { BUKT } else { 0 };
+        self.bit_modify(RXB0CTRL, BUKT, rollover)?;
+        let one_shot = if config.one_shot { OSM } else { 0 };
+        self.bit_modify(CANCTRL, OSM, one_shot)
+    }
+
+    /// Requests an operation mode.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), S::Error> {
+        self.bit_modify(CANCTRL, Mode::MASK, mode.to_reg())
+    }
+
+    /// Reads the current operation mode.
+    ///
+    /// Returns `None` for a reserved mode.
+    pub fn read_mode(&mut self) -> Result<Option<Mode>, S::Error> {
+        self.read_register(CANSTAT).map(Mode::from_reg)
+    }
+
+    /// Writes an acceptance filter.
+    ///
+    /// A standard identifier only accepts standard frames and an extended
+    /// identifier only extended frames. Only takes effect in
+    /// [`Mode::Configuration`].
+    pub fn set_filter(&mut self, filter: Filter, id: Id) -> Result<(), S::Error> {
+        self.write_registers(filter.addr(), &id_to_regs(id))
+    }
+
+    /// Writes an acceptance mask.
+    ///
+    /// Set bits of the identifier must match the filter, clear bits are
+    /// ignored. A standard identifier only masks the standard bits. Only
+    /// takes effect in [`Mode::Configuration`].
+    pub fn set_mask(&mut self, mask: Mask, id: Id) -> Result<(), S::Error> {
+        let [sidh, sidl, eid8, eid0] = id_to_regs(id);
+        self.write_registers(mask.addr(), &[sidh, sidl & !EXIDE, eid8, eid0])
+    }
+
+    /// Loads a frame into the first free transmit buffer and requests to send
+    /// it.
+    ///
+    /// Returns the buffer, or `None` if all buffers are pending. The
+    /// transmit interrupt flag of the buffer stays set from its last message
+    /// until it is cleared with [`Mcp2515::clear_interrupts`].
+    pub fn transmit(
+        &mut self,
+        frame: &Frame,
+        priority: Priority,
+    ) -> Result<Option<TxBuffer>, S::Error> {
+        let status = self.read_status()?;
+        let Some(buffer) = TxBuffer::ALL
+            .into_iter()
+            .find(|buffer| status & buffer.pending_status() == 0)
+        else {
+            return Ok(None);
+        };
+
+        // The control register precedes the identifier, length and data.
+        let (regs, len) = frame.to_regs();
+        let mut buf = [0; 3 + BUFFER_LEN];
+        buf[..3].copy_from_slice(&[WRITE, buffer.ctrl(), priority as u8]);
+        buf[3..3 + len].copy_from_slice(&regs[..len]);
+        self.spi.write(&buf[..3 + len])?;
+        self.spi.write(&[RTS | 1 << buffer.index()])?;
+        Ok(Some(buffer))
+    }
+
+    /// Reads the state of a transmit buffer.
+    pub fn read_tx_status(&mut self, buffer: TxBuffer) -> Result<TxStatus, S::Error> {
+        self.read_register(buffer.ctrl()).map(TxStatus)
+    }
+
+    /// Aborts the message of a transmit buffer unless it is being sent.
+    pub fn abort(&mut self, buffer: TxBuffer) -> Result<(), S::Error> {
+        self.bit_modify(buffer.ctrl(), TXREQ, 0)
+    }
+
+    /// Aborts all pending messages while enabled.
+    ///
+    /// Has to be disabled again before messages can be sent.
+    pub fn set_abort_all(&mut self, enable: bool) -> Result<(), S::Error> {
+        self.bit_modify(CANCTRL, ABAT, if enable { ABAT } else { 0 })
+    }
+
+    /// Reads a received frame and frees its buffer.
+    ///
+    /// Buffer 0 is read before buffer 1. Returns `None` if both are empty.
+    pub fn receive(&mut self) -> Result<Option<Frame>, S::Error> {
+        let mut status = [0];
+        self.spi
+            .transaction(&mut [Operation::Write(&[RX_STATUS]), Operation::Read(&mut status)])?;
+        let buffer = match status[0] >> 6 {
+            0b00 => return Ok(None),
+            0b01 | 0b11 => RxBuffer::Rxb0,
+            _ => RxBuffer::Rxb1,
+        };
+
+        let mut regs = [0; BUFFER_LEN];
+        self.spi.transaction(&mut [
+            Operation::Write(&[buffer.read_instruction()]),
+            Operation::Read(&mut regs),
+        ])?;
+        Ok(Some(Frame::from_regs(&regs)))
+    }
+
+    /// Reads the interrupt flags.
+    pub fn read_interrupts(&mut self) -> Result<Interrupts, S::Error> {
+        self.read_register(CANINTF).map(Interrupts)
+    }
+
+    /// Writes the interrupt enables.
+    pub fn write_interrupt_enable(&mut self, interrupts: Interrupts) -> Result<(), S::Error> {
+        self.write_registers(CANINTE, &[interrupts.0])
+    }
+
+    /// Clears interrupt flags.
+    pub fn clear_interrupts(&mut self, interrupts: Interrupts) -> Result<(), S::Error> {
+        self.bit_modify(CANINTF, interrupts.0, 0)
+    }
+
+    /// Reads the error flags.
+    pub fn read_error_flags(&mut self) -> Result<ErrorFlags, S::Error> {
+        self.read_register(EFLG).map(ErrorFlags)
+    }
+
+    /// Clears the receive overflow flags, the only error flags that aren't
+    /// cleared by the controller.
+    pub fn clear_overflow(&mut self) -> Result<(), S::Error> {
+        let overflow = ErrorFlags::RX0_OVERFLOW | ErrorFlags::RX1_OVERFLOW;
+        self.bit_modify(EFLG, overflow.0, 0)
+    }
+
+    /// Reads the transmit and receive error counters.
+    pub fn read_error_counters(&mut self) -> Result<ErrorCounters, S::Error> {
+        let mut buf = [0; 2];
+        self.read_registers(TEC, &mut buf)?;
+        let [tx, rx] = buf;
+        Ok(ErrorCounters { tx, rx })
+    }
+
+    fn read_status(&mut self) -> Result<u8, S::Error> {
+        let mut status = [0];
+        self.spi.transaction(&mut [
+            Operation::Write(&[READ_STATUS]),
+            Operation::Read(&mut status),
+        ])?;
+        Ok(status[0])
+    }
+
+    fn read_register(&mut self, addr: u8) -> Result<u8, S::Error> {
+        let mut buf = [0];
+        self.read_registers(addr, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_registers(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), S::Error> {
+        self.spi
+            .transaction(&mut [Operation::Write(&[READ, addr]), Operation::Read(buf)])
+    }
+
+    fn write_registers(&mut self, addr: u8, data: &[u8]) -> Result<(), S::Error> {
+        self.spi
+            .transaction(&mut [Operation::Write(&[WRITE, addr]), Operation::Write(data)])
+    }
+
+    fn bit_modify(&mut self, addr: u8, mask: u8, data: u8) -> Result<(), S::Error> {
+        self.spi.write(&[BIT_MODIFY, addr, mask, data])
+    }
+}
+
+impl<S: SpiDevice> embedded_can::nb::Can for Mcp2515<S> {
+    type Frame = Frame;
+    type Error = Error<S::Error>;
+
+    /// Sends with [`Priority::Lowest`] and never replaces a pending frame.
+    fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, Self::Error> {
+        match Mcp2515::transmit(self, frame, Priority::Lowest).map_err(Error)? {
+            Some(_) => Ok(None),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    fn receive(&mut self) -> nb::Result<Frame, Self::Error> {
+        Mcp2515::receive(self)
+            .map_err(Error)?
+            .ok_or(nb::Error::WouldBlock)
+    }
+}
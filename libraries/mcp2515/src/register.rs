@@ -0,0 +1,497 @@
+//! Register map and typed register values.
+
+/// Resets the internal registers to their default state.
+pub const RESET: u8 = 0xC0;
+/// Reads registers starting at the given address.
+pub const READ: u8 = 0x03;
+/// Reads a receive buffer starting at `RXBnSIDH` and clears its interrupt
+/// flag when chip select is raised.
+pub const READ_RX_BUFFER: u8 = 0x90;
+/// Writes registers starting at the given address.
+pub const WRITE: u8 = 0x02;
+/// Requests to send the transmit buffers in the low three bits.
+pub const RTS: u8 = 0x80;
+/// Reads the receive and transmit flags of all buffers.
+pub const READ_STATUS: u8 = 0xA0;
+/// Reads which receive buffers hold a message and which filter matched.
+pub const RX_STATUS: u8 = 0xB0;
+/// Sets or clears individual bits of a register.
+pub const BIT_MODIFY: u8 = 0x05;
+
+/// CAN status register.
+pub const CANSTAT: u8 = 0x0E;
+/// CAN control register.
+pub const CANCTRL: u8 = 0x0F;
+/// Transmit error counter, followed by the receive error counter.
+pub const TEC: u8 = 0x1C;
+/// Bit timing configuration register 3, followed by `CNF2`, `CNF1` and
+/// `CANINTE`.
+pub const CNF3: u8 = 0x28;
+/// Interrupt enable register.
+pub const CANINTE: u8 = 0x2B;
+/// Interrupt flag register.
+pub const CANINTF: u8 = 0x2C;
+/// Error flag register.
+pub const EFLG: u8 = 0x2D;
+/// Receive buffer 0 control register.
+pub const RXB0CTRL: u8 = 0x60;
+
+/// Request abort of all pending transmissions bit of `CANCTRL`.
+pub const ABAT: u8 = 1 << 4;
+/// One-shot mode bit of `CANCTRL`.
+pub const OSM: u8 = 1 << 3;
+/// Rollover enable bit of `RXB0CTRL`.
+pub const BUKT: u8 = 1 << 2;
+/// Message transmit request bit of `TXBnCTRL`.
+pub const TXREQ: u8 = 1 << 3;
+/// Extended identifier enable bit of `SIDL`.
+pub const EXIDE: u8 = 1 << 3;
+/// Remote transmission request bit of `DLC`.
+pub const RTR: u8 = 1 << 6;
+
+/// Operation mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Mode {
+    /// Sends and receives on the bus.
+    Normal = 0b000,
+    /// Oscillator stopped until a wake-up interrupt.
+    Sleep = 0b001,
+    /// Sent messages are received internally and nothing reaches the bus.
+    Loopback = 0b010,
+    /// Receives all messages without acknowledging them or sending errors.
+    ListenOnly = 0b011,
+    /// Bit timing, filters and masks can only be written in this mode.
+    Configuration = 0b100,
+}
+
+impl Mode {
+    const SHIFT: u8 = 5;
+    /// Mask of `REQOP` in `CANCTRL` and `OPMOD` in `CANSTAT`.
+    pub(crate) const MASK: u8 = 0b111 << Self::SHIFT;
+
+    pub(crate) const fn from_reg(value: u8) -> Option<Self> {
+        match (value & Self::MASK) >> Self::SHIFT {
+            0b000 => Some(Self::Normal),
+            0b001 => Some(Self::Sleep),
+            0b010 => Some(Self::Loopback),
+            0b011 => Some(Self::ListenOnly),
+            0b100 => Some(Self::Configuration),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn to_reg(self) -> u8 {
+        (self as u8) << Self::SHIFT
+    }
+}
+
+/// Bit timing in time quanta.
+///
+/// A bit consists of one synchronization quantum, the propagation segment
+/// and the two phase segments. The bus samples between the phase segments.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BitTiming {
+    /// Baud rate prescaler; a time quantum lasts `2 * prescaler` oscillator
+    /// periods (1..=64).
+    pub prescaler: u8,
+    /// Synchronization jump width (1..=4).
+    pub sjw: u8,
+    /// Propagation segment (1..=8).
+    pub prop_seg: u8,
+    /// Phase segment 1 (1..=8).
+    pub phase_seg1: u8,
+    /// Phase segment 2 (2..=8).
+    pub phase_seg2: u8,
+    /// Samples three times per bit instead of once.
+    pub triple_sample: bool,
+}
+
+impl BitTiming {
+    /// Fewest time quanta per bit.
+    const MIN_QUANTA: u32 = 8;
+    /// Most time quanta per bit. The controller allows 25, but above 20 the
+    /// phase segments can't sample late enough.
+    const MAX_QUANTA: u32 = 20;
+
+    /// Calculates a timing for a bitrate with an oscillator.
+    ///
+    /// Picks the smallest prescaler that divides the bit into a whole number
+    /// of quanta and samples at about 87.5 %. Returns `None` if the
+    /// oscillator can't generate the bitrate exactly.
+    #[must_use]
+    pub const fn new(oscillator_hz: u32, bitrate: u32) -> Option<Self> {
+        if bitrate == 0 {
+            return None;
+        }
+        let mut prescaler: u32 = 1;
+        while prescaler <= 64 {
+            let Some(divisor) = (2 * prescaler).checked_mul(bitrate) else {
+                return None;
+            };
+            let quanta = oscillator_hz / divisor;
+            if quanta < Self::MIN_QUANTA {
+                return None;
+            }
+            if quanta <= Self::MAX_QUANTA && quanta * divisor == oscillator_hz {
+                return Some(Self::split(prescaler as u8, quanta as u8));
+            }
+            prescaler += 1;
+        }
+        None
+    }
+
+    /// Divides the quanta of a bit into segments.
+    const fn split(prescaler: u8, quanta: u8) -> Self {
+        let mut phase_seg2 = quanta / 8;
+        if phase_seg2 < 2 {
+            phase_seg2 = 2;
+        }
+        // The propagation and first phase segment hold at most 16 quanta.
+        if quanta - 1 - phase_seg2 > 16 {
+            phase_seg2 = quanta - 17;
+        }
+        let rest = quanta - 1 - phase_seg2;
+        let prop_seg = rest / 2;
+        Self {
+            prescaler,
+            sjw: 1,
+            prop_seg,
+            phase_seg1: rest - prop_seg,
+            phase_seg2,
+            triple_sample: false,
+        }
+    }
+
+    /// Returns the number of time quanta per bit.
+    #[must_use]
+    pub const fn quanta(&self) -> u8 {
+        1 + self.prop_seg + self.phase_seg1 + self.phase_seg2
+    }
+
+    /// Returns true if all segments are in range and satisfy the constraints
+    /// of the datasheet.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        matches!(self.prescaler, 1..=64)
+            && matches!(self.sjw, 1..=4)
+            && matches!(self.prop_seg, 1..=8)
+            && matches!(self.phase_seg1, 1..=8)
+            && matches!(self.phase_seg2, 2..=8)
+            && self.prop_seg + self.phase_seg1 >= self.phase_seg2
+            && self.phase_seg2 >= self.sjw
+    }
+
+    /// Returns the values of `CNF3`, `CNF2` and `CNF1` in address order.
+    pub(crate) const fn to_regs(self) -> [u8; 3] {
+        // Phase segment 2 is always taken from `CNF3`.
+        const BTLMODE: u8 = 1 << 7;
+        const SAM: u8 = 1 << 6;
+        let cnf1 = (self.sjw - 1) << 6 | (self.prescaler - 1);
+        let sam = if self.triple_sample { SAM } else { 0 };
+        let cnf2 = BTLMODE | sam | (self.phase_seg1 - 1) << 3 | (self.prop_seg - 1);
+        let cnf3 = self.phase_seg2 - 1;
+        [cnf3, cnf2, cnf1]
+    }
+}
+
+/// Interrupt enable or flag register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Interrupts(pub u8);
+
+impl Interrupts {
+    /// Receive buffer 0 is full.
+    pub const RX0: Self = Self(1 << 0);
+    /// Receive buffer 1 is full.
+    pub const RX1: Self = Self(1 << 1);
+    /// Transmit buffer 0 is empty.
+    pub const TX0: Self = Self(1 << 2);
+    /// Transmit buffer 1 is empty.
+    pub const TX1: Self = Self(1 << 3);
+    /// Transmit buffer 2 is empty.
+    pub const TX2: Self = Self(1 << 4);
+    /// One of the flags in [`ErrorFlags`] changed.
+    pub const ERROR: Self = Self(1 << 5);
+    /// Activity on the bus while sleeping.
+    pub const WAKE_UP: Self = Self(1 << 6);
+    /// An error occurred while sending or receiving a message.
+    pub const MESSAGE_ERROR: Self = Self(1 << 7);
+
+    /// No interrupt.
+    pub const NONE: Self = Self(0);
+
+    /// Returns true if all interrupts of `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears the interrupts of `other`.
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl core::ops::BitOr for Interrupts {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Error flag register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ErrorFlags(pub u8);
+
+impl ErrorFlags {
+    /// One of the error counters reached 96.
+    pub const WARNING: Self = Self(1 << 0);
+    /// The receive error counter reached 96.
+    pub const RX_WARNING: Self = Self(1 << 1);
+    /// The transmit error counter reached 96.
+    pub const TX_WARNING: Self = Self(1 << 2);
+    /// The receive error counter reached 128.
+    pub const RX_PASSIVE: Self = Self(1 << 3);
+    /// The transmit error counter reached 128.
+    pub const TX_PASSIVE: Self = Self(1 << 4);
+    /// The transmit error counter reached 255 and the controller left the
+    /// bus.
+    pub const BUS_OFF: Self = Self(1 << 5);
+    /// A message was received while receive buffer 0 was full.
+    pub const RX0_OVERFLOW: Self = Self(1 << 6);
+    /// A message was received while receive buffer 1 was full.
+    pub const RX1_OVERFLOW: Self = Self(1 << 7);
+
+    /// No flag is set.
+    pub const NONE: Self = Self(0);
+
+    /// Returns true if all flags of `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any flag of `other` is set.
+    #[must_use]
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns the fault confinement state.
+    #[must_use]
+    pub const fn state(self) -> ErrorState {
+        if self.contains(Self::BUS_OFF) {
+            ErrorState::BusOff
+        } else if self.intersects(Self(Self::RX_PASSIVE.0 | Self::TX_PASSIVE.0)) {
+            ErrorState::Passive
+        } else {
+            ErrorState::Active
+        }
+    }
+}
+
+impl core::ops::BitOr for ErrorFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Fault confinement state of the controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorState {
+    /// Sends active error frames.
+    Active,
+    /// Sends passive error frames and waits before sending.
+    Passive,
+    /// Doesn't take part in bus activity until 128 times 11 recessive bits
+    /// were seen.
+    BusOff,
+}
+
+/// Transmit error counter and receive error counter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ErrorCounters {
+    /// Transmit error counter.
+    pub tx: u8,
+    /// Receive error counter.
+    pub rx: u8,
+}
+
+/// Transmit buffer control register.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TxStatus(pub u8);
+
+impl TxStatus {
+    const ABTF: u8 = 1 << 6;
+    const MLOA: u8 = 1 << 5;
+    const TXERR: u8 = 1 << 4;
+
+    /// Returns true if the message waits to be sent.
+    #[must_use]
+    pub const fn is_pending(self) -> bool {
+        self.0 & TXREQ != 0
+    }
+
+    /// Returns true if the message was aborted.
+    #[must_use]
+    pub const fn is_aborted(self) -> bool {
+        self.0 & Self::ABTF != 0
+    }
+
+    /// Returns true if the message lost arbitration.
+    #[must_use]
+    pub const fn lost_arbitration(self) -> bool {
+        self.0 & Self::MLOA != 0
+    }
+
+    /// Returns true if a bus error occurred while sending the message.
+    #[must_use]
+    pub const fn is_error(self) -> bool {
+        self.0 & Self::TXERR != 0
+    }
+
+    /// Returns the priority of the buffer.
+    #[must_use]
+    pub const fn priority(self) -> Priority {
+        match self.0 & 0b11 {
+            0b00 => Priority::Lowest,
+            0b01 => Priority::Low,
+            0b10 => Priority::High,
+            _ => Priority::Highest,
+        }
+    }
+}
+
+/// Priority of a transmit buffer.
+///
+/// If several buffers are pending, the one with the highest priority is sent
+/// first and the one with the higher number among equal priorities.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[repr(u8)]
+pub enum Priority {
+    /// Sent last.
+    #[default]
+    Lowest = 0b00,
+    /// Sent before [`Priority::Lowest`].
+    Low = 0b01,
+    /// Sent before [`Priority::Low`].
+    High = 0b10,
+    /// Sent first.
+    Highest = 0b11,
+}
+
+/// Transmit buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TxBuffer {
+    Txb0,
+    Txb1,
+    Txb2,
+}
+
+impl TxBuffer {
+    /// All transmit buffers.
+    pub const ALL: [Self; 3] = [Self::Txb0, Self::Txb1, Self::Txb2];
+
+    /// Returns the index of the buffer.
+    #[must_use]
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the address of `TXBnCTRL`, which is followed by the
+    /// identifier, length and data.
+    pub(crate) const fn ctrl(self) -> u8 {
+        0x30 + 0x10 * self.index()
+    }
+
+    /// Returns the `TXREQ` bit of the buffer in the result of
+    /// `READ STATUS`.
+    pub(crate) const fn pending_status(self) -> u8 {
+        1 << (2 + 2 * self.index())
+    }
+
+    /// Returns the transmit interrupt of the buffer.
+    #[must_use]
+    pub const fn interrupt(self) -> Interrupts {
+        Interrupts(Interrupts::TX0.0 << self.index())
+    }
+}
+
+/// Receive buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RxBuffer {
+    /// Filtered by mask 0 with filters 0 and 1.
+    Rxb0,
+    /// Filtered by mask 1 with filters 2 to 5.
+    Rxb1,
+}
+
+impl RxBuffer {
+    /// Returns the index of the buffer.
+    #[must_use]
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the `READ RX BUFFER` instruction starting at `RXBnSIDH`.
+    pub(crate) const fn read_instruction(self) -> u8 {
+        READ_RX_BUFFER | self.index() << 2
+    }
+}
+
+/// Acceptance filter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    /// Filter 0 of receive buffer 0.
+    Rxf0,
+    /// Filter 1 of receive buffer 0.
+    Rxf1,
+    /// Filter 2 of receive buffer 1.
+    Rxf2,
+    /// Filter 3 of receive buffer 1.
+    Rxf3,
+    /// Filter 4 of receive buffer 1.
+    Rxf4,
+    /// Filter 5 of receive buffer 1.
+    Rxf5,
+}
+
+impl Filter {
+    /// Returns the address of `RXFnSIDH`.
+    pub(crate) const fn addr(self) -> u8 {
+        match self {
+            Self::Rxf0 => 0x00,
+            Self::Rxf1 => 0x04,
+            Self::Rxf2 => 0x08,
+            Self::Rxf3 => 0x10,
+            Self::Rxf4 => 0x14,
+            Self::Rxf5 => 0x18,
+        }
+    }
+}
+
+/// Acceptance mask.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mask {
+    /// Mask of receive buffer 0.
+    Rxm0,
+    /// Mask of receive buffer 1.
+    Rxm1,
+}
+
+impl Mask {
+    /// Returns the address of `RXMnSIDH`.
+    pub(crate) const fn addr(self) -> u8 {
+        match self {
+            Self::Rxm0 => 0x20,
+            Self::Rxm1 => 0x24,
+        }
+    }
+}
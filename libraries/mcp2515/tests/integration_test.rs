@@ -0,0 +1,548 @@
+//! Integration tests for the MCP2515 CAN controller driver.
+
+use embedded_can::{ExtendedId, Frame as _, Id, StandardId};
+use embedded_hal_mock::eh1::spi::{Mock as SpiMock, Transaction};
+use mcp2515::{
+    BitTiming, Config, ErrorCounters, ErrorFlags, ErrorState, Filter, Frame, Interrupts, Mask,
+    Mcp2515, Mode, Priority, TxBuffer,
+};
+
+/// Returns the expectations of a transaction with a single write.
+fn write(bytes: &[u8]) -> Vec<Transaction<u8>> {
+    vec![
+        Transaction::transaction_start(),
+        Transaction::write_vec(bytes.to_vec()),
+        Transaction::transaction_end(),
+    ]
+}
+
+/// Returns the expectations of a `WRITE` instruction.
+fn write_registers(addr: u8, data: &[u8]) -> Vec<Transaction<u8>> {
+    vec![
+        Transaction::transaction_start(),
+        Transaction::write_vec(vec![0x02, addr]),
+        Transaction::write_vec(data.to_vec()),
+        Transaction::transaction_end(),
+    ]
+}
+
+/// Returns the expectations of an instruction that reads a response.
+fn read(instruction: &[u8], response: &[u8]) -> Vec<Transaction<u8>> {
+    vec![
+        Transaction::transaction_start(),
+        Transaction::write_vec(instruction.to_vec()),
+        Transaction::read_vec(response.to_vec()),
+        Transaction::transaction_end(),
+    ]
+}
+
+fn standard(id: u16) -> Id {
+    Id::Standard(StandardId::new(id).unwrap())
+}
+
+fn extended(id: u32) -> Id {
+    Id::Extended(ExtendedId::new(id).unwrap())
+}
+
+/// Runs a test against the given expectations.
+fn with_device(expectations: &[Vec<Transaction<u8>>], f: impl FnOnce(&mut Mcp2515<SpiMock<u8>>)) {
+    let expectations = expectations.concat();
+    let mut device = Mcp2515::new(SpiMock::new(&expectations));
+    f(&mut device);
+    device.into_inner().done();
+}
+
+#[test]
+fn test_bit_timing_16mhz_500k() {
+    let timing = BitTiming::new(16_000_000, 500_000).unwrap();
+    assert_eq!(
+        timing,
+        BitTiming {
+            prescaler: 1,
+            sjw: 1,
+            prop_seg: 6,
+            phase_seg1: 7,
+            phase_seg2: 2,
+            triple_sample: false,
+        }
+    );
+    assert_eq!(timing.quanta(), 16);
+    assert!(timing.is_valid());
+}
+
+#[test]
+fn test_bit_timing_8mhz_500k() {
+    let timing = BitTiming::new(8_000_000, 500_000).unwrap();
+    assert_eq!(timing.prescaler, 1);
+    assert_eq!(timing.quanta(), 8);
+    assert_eq!(timing.phase_seg2, 2);
+    assert!(timing.is_valid());
+}
+
+#[test]
+fn test_bit_timing_uses_prescaler() {
+    // 64 and 32 quanta are too many, 21.3 aren't whole.
+    let timing = BitTiming::new(16_000_000, 125_000).unwrap();
+    assert_eq!(timing.prescaler, 4);
+    assert_eq!(timing.quanta(), 16);
+
+    let timing = BitTiming::new(16_000_000, 100_000).unwrap();
+    assert_eq!(timing.prescaler, 4);
+    assert_eq!(timing.quanta(), 20);
+    assert!(timing.is_valid());
+}
+
+#[test]
+fn test_bit_timing_all_common_rates_valid() {
+    for oscillator in [8_000_000, 16_000_000, 20_000_000] {
+        for bitrate in [50_000, 100_000, 125_000, 250_000, 500_000] {
+            let timing = BitTiming::new(oscillator, bitrate).unwrap();
+            assert!(timing.is_valid(), "{oscillator} Hz, {bitrate} bit/s");
+            let quanta = u32::from(timing.quanta());
+            assert_eq!(
+                2 * u32::from(timing.prescaler) * quanta * bitrate,
+                oscillator
+            );
+            // Samples between 75 % and 90 % of the bit.
+            let sample = 1000 * (quanta - u32::from(timing.phase_seg2)) / quanta;
+            assert!((750..=900).contains(&sample), "sample point {sample}");
+        }
+    }
+}
+
+#[test]
+fn test_bit_timing_impossible() {
+    assert_eq!(BitTiming::new(12_000_000, 1_000_000), None);
+    assert_eq!(BitTiming::new(16_000_000, 0), None);
+    assert_eq!(BitTiming::new(16_000_000, 3_000_000), None);
+}
+
+#[test]
+fn test_bit_timing_invalid() {
+    let timing = BitTiming::new(16_000_000, 500_000).unwrap();
+    assert!(
+        !BitTiming {
+            phase_seg2: 1,
+            ..timing
+        }
+        .is_valid()
+    );
+    assert!(!BitTiming { sjw: 3, ..timing }.is_valid());
+    assert!(
+        !BitTiming {
+            prop_seg: 9,
+            ..timing
+        }
+        .is_valid()
+    );
+}
+
+#[test]
+fn test_reset() {
+    with_device(&[write(&[0xC0])], |device| device.reset().unwrap());
+}
+
+#[test]
+fn test_configure() {
+    let timing = BitTiming::new(16_000_000, 500_000).unwrap();
+    let config = Config::new(timing);
+    with_device(
+        &[
+            // CNF3, CNF2, CNF1 and CANINTE.
+            write_registers(0x28, &[0x01, 0xB5, 0x00, 0x23]),
+            write(&[0x05, 0x60, 0x04, 0x04]),
+            write(&[0x05, 0x0F, 0x08, 0x00]),
+        ],
+        |device| device.configure(&config).unwrap(),
+    );
+}
+
+#[test]
+fn test_configure_one_shot_triple_sample() {
+    let timing = BitTiming {
+        sjw: 2,
+        triple_sample: true,
+        ..BitTiming::new(16_000_000, 250_000).unwrap()
+    };
+    let config = Config {
+        interrupts: Interrupts::NONE,
+        rollover: false,
+        one_shot: true,
+        ..Config::new(timing)
+    };
+    with_device(
+        &[
+            write_registers(0x28, &[0x01, 0xF5, 0x41, 0x00]),
+            write(&[0x05, 0x60, 0x04, 0x00]),
+            write(&[0x05, 0x0F, 0x08, 0x08]),
+        ],
+        |device| device.configure(&config).unwrap(),
+    );
+}
+
+#[test]
+fn test_set_mode() {
+    with_device(
+        &[
+            write(&[0x05, 0x0F, 0xE0, 0x00]),
+            write(&[0x05, 0x0F, 0xE0, 0x40]),
+            write(&[0x05, 0x0F, 0xE0, 0x60]),
+            write(&[0x05, 0x0F, 0xE0, 0x80]),
+        ],
+        |device| {
+            device.set_mode(Mode::Normal).unwrap();
+            device.set_mode(Mode::Loopback).unwrap();
+            device.set_mode(Mode::ListenOnly).unwrap();
+            device.set_mode(Mode::Configuration).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_read_mode() {
+    with_device(
+        &[
+            read(&[0x03, 0x0E], &[0x80]),
+            // Interrupt code bits are ignored.
+            read(&[0x03, 0x0E], &[0x4E]),
+            read(&[0x03, 0x0E], &[0x20]),
+            read(&[0x03, 0x0E], &[0xE0]),
+        ],
+        |device| {
+            assert_eq!(device.read_mode().unwrap(), Some(Mode::Configuration));
+            assert_eq!(device.read_mode().unwrap(), Some(Mode::Loopback));
+            assert_eq!(device.read_mode().unwrap(), Some(Mode::Sleep));
+            assert_eq!(device.read_mode().unwrap(), None);
+        },
+    );
+}
+
+#[test]
+fn test_set_filter() {
+    with_device(
+        &[
+            write_registers(0x00, &[0x6A, 0x20, 0x00, 0x00]),
+            write_registers(0x18, &[0xC7, 0xEB, 0x50, 0xE5]),
+            write_registers(0x10, &[0xFF, 0xE0, 0x00, 0x00]),
+        ],
+        |device| {
+            device.set_filter(Filter::Rxf0, standard(0x351)).unwrap();
+            device
+                .set_filter(Filter::Rxf5, extended(0x18FF_50E5))
+                .unwrap();
+            device.set_filter(Filter::Rxf3, standard(0x7FF)).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_set_mask() {
+    with_device(
+        &[
+            write_registers(0x20, &[0xFF, 0xE0, 0x00, 0x00]),
+            // The mask has no extended identifier enable bit.
+            write_registers(0x24, &[0xFF, 0xE3, 0xFF, 0xFF]),
+        ],
+        |device| {
+            device.set_mask(Mask::Rxm0, standard(0x7FF)).unwrap();
+            device.set_mask(Mask::Rxm1, extended(0x1FFF_FFFF)).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_transmit_first_free_buffer() {
+    let frame = Frame::new(standard(0x351), &[0x12, 0x34, 0x56]).unwrap();
+    with_device(
+        &[
+            read(&[0xA0], &[0x00]),
+            write(&[
+                0x02, 0x30, 0x02, 0x6A, 0x20, 0x00, 0x00, 0x03, 0x12, 0x34, 0x56,
+            ]),
+            write(&[0x81]),
+            // Buffer 0 is pending.
+            read(&[0xA0], &[0x04]),
+            write(&[
+                0x02, 0x40, 0x00, 0x6A, 0x20, 0x00, 0x00, 0x03, 0x12, 0x34, 0x56,
+            ]),
+            write(&[0x82]),
+            // Buffers 0 and 1 are pending.
+            read(&[0xA0], &[0x14]),
+            write(&[
+                0x02, 0x50, 0x03, 0x6A, 0x20, 0x00, 0x00, 0x03, 0x12, 0x34, 0x56,
+            ]),
+            write(&[0x84]),
+        ],
+        |device| {
+            let buffer = device.transmit(&frame, Priority::High).unwrap();
+            assert_eq!(buffer, Some(TxBuffer::Txb0));
+            let buffer = device.transmit(&frame, Priority::Lowest).unwrap();
+            assert_eq!(buffer, Some(TxBuffer::Txb1));
+            let buffer = device.transmit(&frame, Priority::Highest).unwrap();
+            assert_eq!(buffer, Some(TxBuffer::Txb2));
+        },
+    );
+}
+
+#[test]
+fn test_transmit_all_pending() {
+    let frame = Frame::new(standard(0x351), &[]).unwrap();
+    with_device(&[read(&[0xA0], &[0x54])], |device| {
+        assert_eq!(device.transmit(&frame, Priority::Low).unwrap(), None);
+    });
+}
+
+#[test]
+fn test_transmit_extended_and_remote() {
+    let data = Frame::new(extended(0x18FF_50E5), &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    let remote = Frame::new_remote(standard(0x305), 8).unwrap();
+    with_device(
+        &[
+            read(&[0xA0], &[0x00]),
+            write(&[
+                0x02, 0x30, 0x01, 0xC7, 0xEB, 0x50, 0xE5, 0x08, 1, 2, 3, 4, 5, 6, 7, 8,
+            ]),
+            write(&[0x81]),
+            read(&[0xA0], &[0x00]),
+            // Remote frames carry the length but no data.
+            write(&[0x02, 0x30, 0x00, 0x60, 0xA0, 0x00, 0x00, 0x48]),
+            write(&[0x81]),
+        ],
+        |device| {
+            device.transmit(&data, Priority::Low).unwrap();
+            device.transmit(&remote, Priority::Lowest).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_read_tx_status() {
+    with_device(&[read(&[0x03, 0x40], &[0x7A])], |device| {
+        let status = device.read_tx_status(TxBuffer::Txb1).unwrap();
+        assert!(status.is_pending());
+        assert!(status.is_aborted());
+        assert!(status.lost_arbitration());
+        assert!(status.is_error());
+        assert_eq!(status.priority(), Priority::High);
+    });
+}
+
+#[test]
+fn test_abort() {
+    with_device(
+        &[
+            write(&[0x05, 0x50, 0x08, 0x00]),
+            write(&[0x05, 0x0F, 0x10, 0x10]),
+            write(&[0x05, 0x0F, 0x10, 0x00]),
+        ],
+        |device| {
+            device.abort(TxBuffer::Txb2).unwrap();
+            device.set_abort_all(true).unwrap();
+            device.set_abort_all(false).unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_receive_empty() {
+    with_device(&[read(&[0xB0], &[0x00])], |device| {
+        assert_eq!(device.receive().unwrap(), None);
+    });
+}
+
+#[test]
+fn test_receive_standard() {
+    with_device(
+        &[
+            read(&[0xB0], &[0x40]),
+            read(
+                &[0x90],
+                &[0x60, 0xA0, 0x00, 0x00, 0x02, 0xAB, 0xCD, 0, 0, 0, 0, 0, 0],
+            ),
+        ],
+        |device| {
+            let frame = device.receive().unwrap().unwrap();
+            assert_eq!(frame.id(), standard(0x305));
+            assert!(frame.is_data_frame());
+            assert_eq!(frame.data(), &[0xAB, 0xCD]);
+        },
+    );
+}
+
+#[test]
+fn test_receive_buffer_0_first() {
+    with_device(
+        &[
+            read(&[0xB0], &[0xC0]),
+            read(&[0x90], &[0x6A, 0x20, 0, 0, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]),
+            read(&[0xB0], &[0x80]),
+            read(
+                &[0x94],
+                &[0xC7, 0xEB, 0x50, 0xE5, 0x01, 0x99, 0, 0, 0, 0, 0, 0, 0],
+            ),
+        ],
+        |device| {
+            let frame = device.receive().unwrap().unwrap();
+            assert_eq!(frame.id(), standard(0x351));
+            assert_eq!(frame.dlc(), 0);
+
+            let frame = device.receive().unwrap().unwrap();
+            assert!(frame.is_extended());
+            assert_eq!(frame.id(), extended(0x18FF_50E5));
+            assert_eq!(frame.data(), &[0x99]);
+        },
+    );
+}
+
+#[test]
+fn test_receive_remote() {
+    with_device(
+        &[
+            // A standard remote frame sets SRR, an extended one RTR.
+            read(&[0xB0], &[0x40]),
+            read(&[0x90], &[0x60, 0xB0, 0, 0, 0x08, 0, 0, 0, 0, 0, 0, 0, 0]),
+            read(&[0xB0], &[0x40]),
+            read(
+                &[0x90],
+                &[0xC7, 0xEB, 0x50, 0xE5, 0x44, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+        ],
+        |device| {
+            let frame = device.receive().unwrap().unwrap();
+            assert!(frame.is_remote_frame());
+            assert_eq!(frame.dlc(), 8);
+            assert!(frame.data().is_empty());
+
+            let frame = device.receive().unwrap().unwrap();
+            assert!(frame.is_remote_frame());
+            assert_eq!(frame.dlc(), 4);
+        },
+    );
+}
+
+#[test]
+fn test_receive_long_dlc() {
+    with_device(
+        &[
+            read(&[0xB0], &[0x40]),
+            read(&[0x90], &[0x6A, 0x20, 0, 0, 0x0F, 1, 2, 3, 4, 5, 6, 7, 8]),
+        ],
+        |device| {
+            let frame = device.receive().unwrap().unwrap();
+            assert_eq!(frame.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        },
+    );
+}
+
+#[test]
+fn test_loopback() {
+    let frame = Frame::new(standard(0x35C), &[0xC0, 0x00]).unwrap();
+    with_device(
+        &[
+            write(&[0x05, 0x0F, 0xE0, 0x40]),
+            read(&[0x03, 0x0E], &[0x40]),
+            read(&[0xA0], &[0x00]),
+            write(&[0x02, 0x30, 0x00, 0x6B, 0x80, 0x00, 0x00, 0x02, 0xC0, 0x00]),
+            write(&[0x81]),
+            // The controller received its own frame.
+            read(&[0xB0], &[0x40]),
+            read(
+                &[0x90],
+                &[0x6B, 0x80, 0, 0, 0x02, 0xC0, 0x00, 0, 0, 0, 0, 0, 0],
+            ),
+        ],
+        |device| {
+            device.set_mode(Mode::Loopback).unwrap();
+            assert_eq!(device.read_mode().unwrap(), Some(Mode::Loopback));
+            device.transmit(&frame, Priority::Lowest).unwrap();
+            assert_eq!(device.receive().unwrap(), Some(frame));
+        },
+    );
+}
+
+#[test]
+fn test_interrupts() {
+    with_device(
+        &[
+            read(&[0x03, 0x2C], &[0x25]),
+            write_registers(0x2B, &[0x03]),
+            write(&[0x05, 0x2C, 0x21, 0x00]),
+        ],
+        |device| {
+            let flags = device.read_interrupts().unwrap();
+            assert!(flags.contains(Interrupts::RX0 | Interrupts::ERROR));
+            assert!(flags.contains(TxBuffer::Txb0.interrupt()));
+            assert!(!flags.contains(Interrupts::RX1));
+            device
+                .write_interrupt_enable(Interrupts::RX0 | Interrupts::RX1)
+                .unwrap();
+            device
+                .clear_interrupts(Interrupts::RX0 | Interrupts::ERROR)
+                .unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_error_state() {
+    with_device(
+        &[
+            read(&[0x03, 0x2D], &[0x00]),
+            read(&[0x03, 0x2D], &[0x05]),
+            read(&[0x03, 0x2D], &[0x19]),
+            read(&[0x03, 0x2D], &[0x3D]),
+            read(&[0x03, 0x1C], &[200, 7]),
+            write(&[0x05, 0x2D, 0xC0, 0x00]),
+        ],
+        |device| {
+            let flags = device.read_error_flags().unwrap();
+            assert_eq!(flags, ErrorFlags::NONE);
+            assert_eq!(flags.state(), ErrorState::Active);
+
+            let flags = device.read_error_flags().unwrap();
+            assert!(flags.contains(ErrorFlags::WARNING | ErrorFlags::TX_WARNING));
+            assert_eq!(flags.state(), ErrorState::Active);
+
+            let flags = device.read_error_flags().unwrap();
+            assert_eq!(flags.state(), ErrorState::Passive);
+
+            let flags = device.read_error_flags().unwrap();
+            assert_eq!(flags.state(), ErrorState::BusOff);
+
+            let counters = device.read_error_counters().unwrap();
+            assert_eq!(counters, ErrorCounters { tx: 200, rx: 7 });
+
+            device.clear_overflow().unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_nb_can() {
+    use embedded_can::nb::Can;
+
+    let frame = Frame::new(standard(0x351), &[0x01]).unwrap();
+    with_device(
+        &[
+            read(&[0xA0], &[0x54]),
+            read(&[0xA0], &[0x10]),
+            write(&[0x02, 0x30, 0x00, 0x6A, 0x20, 0x00, 0x00, 0x01, 0x01]),
+            write(&[0x81]),
+            read(&[0xB0], &[0x00]),
+        ],
+        |device| {
+            assert!(matches!(
+                Can::transmit(device, &frame),
+                Err(nb::Error::WouldBlock)
+            ));
+            assert_eq!(Can::transmit(device, &frame), Ok(None));
+            assert!(matches!(Can::receive(device), Err(nb::Error::WouldBlock)));
+        },
+    );
+}
+
+#[test]
+fn test_frame_too_long() {
+    assert!(Frame::new(standard(0x351), &[0; 9]).is_none());
+    assert!(Frame::new_remote(standard(0x351), 9).is_none());
+}
@@ -0,0 +1,35 @@
+//! Frame check sequence.
+
+/// Computes the CRC-16/MODBUS of `data`.
+///
+/// Polynomial 0x8005 reflected, initial value 0xFFFF. Sent low byte first.
+#[must_use]
+pub const fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    let mut i = 0;
+    while i < data.len() {
+        crc ^= data[i] as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x0001 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+}
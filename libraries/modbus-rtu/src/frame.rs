@@ -0,0 +1,127 @@
+use crate::{MAX_ADU, MIN_ADU, crc16};
+
+/// Inter-frame timing of a baud rate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timing {
+    frame_gap_us: u32,
+}
+
+impl Timing {
+    /// Bits per character: start, eight data, parity or second stop and stop
+    /// bit.
+    const CHARACTER_BITS: u32 = 11;
+    /// Fixed gap above 19200 baud.
+    const FAST_GAP_US: u32 = 1750;
+
+    /// Creates the timing of a baud rate.
+    ///
+    /// Frames are separated by 3.5 character times, but at least 1.75 ms as
+    /// the specification recommends above 19200 baud.
+    #[must_use]
+    pub const fn new(baud: u32) -> Self {
+        let frame_gap_us = if baud > 19_200 || baud == 0 {
+            Self::FAST_GAP_US
+        } else {
+            // 3.5 characters in microseconds, rounded up.
+            (7 * Self::CHARACTER_BITS * 1_000_000).div_ceil(2 * baud)
+        };
+        Self { frame_gap_us }
+    }
+
+    /// Returns the silence that ends a frame in microseconds.
+    #[must_use]
+    pub const fn frame_gap_us(&self) -> u32 {
+        self.frame_gap_us
+    }
+}
+
+/// Reason a received frame was discarded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameError {
+    /// Shorter than an address, function code and CRC.
+    TooShort,
+    /// Longer than [`MAX_ADU`].
+    TooLong,
+    /// The CRC doesn't match.
+    Crc,
+}
+
+/// Collects bytes into frames delimited by silence on the line.
+///
+/// The gap of 1.5 characters within a frame isn't checked since polling
+/// can't measure it reliably. The CRC still rejects frames whose parts were
+/// merged or split.
+#[derive(Clone, Debug)]
+pub struct Receiver {
+    timing: Timing,
+    buf: [u8; MAX_ADU],
+    len: usize,
+    overrun: bool,
+    silence_us: u32,
+}
+
+impl Receiver {
+    /// Creates a receiver waiting for a frame.
+    #[must_use]
+    pub const fn new(timing: Timing) -> Self {
+        Self {
+            timing,
+            buf: [0; MAX_ADU],
+            len: 0,
+            overrun: false,
+            silence_us: 0,
+        }
+    }
+
+    /// Discards a partially received frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.overrun = false;
+        self.silence_us = 0;
+    }
+
+    /// Returns true while a frame is partially received.
+    #[must_use]
+    pub const fn is_receiving(&self) -> bool {
+        self.len > 0 || self.overrun
+    }
+
+    /// Processes a received byte.
+    pub fn push(&mut self, byte: u8) {
+        self.silence_us = 0;
+        if self.len < MAX_ADU {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overrun = true;
+        }
+    }
+
+    /// Processes `dt_us` of silence on the line.
+    ///
+    /// Returns the frame without its CRC once the silence ends it, or an
+    /// error if it is invalid.
+    pub fn idle(&mut self, dt_us: u32) -> Option<Result<&[u8], FrameError>> {
+        if !self.is_receiving() {
+            return None;
+        }
+        self.silence_us = self.silence_us.saturating_add(dt_us);
+        if self.silence_us < self.timing.frame_gap_us() {
+            return None;
+        }
+
+        let (len, overrun) = (self.len, self.overrun);
+        self.reset();
+        if overrun {
+            return Some(Err(FrameError::TooLong));
+        }
+        if len < MIN_ADU {
+            return Some(Err(FrameError::TooShort));
+        }
+        let (frame, crc) = self.buf[..len].split_at(len - 2);
+        if crc16(frame).to_le_bytes() != [crc[0], crc[1]] {
+            return Some(Err(FrameError::Crc));
+        }
+        Some(Ok(frame))
+    }
+}
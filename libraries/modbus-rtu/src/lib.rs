@@ -0,0 +1,45 @@
+//! Modbus RTU server for energy management systems polling the pack over
+//! RS-485.
+//!
+//! Every frame is delimited by at least 3.5 characters of silence on the
+//! line:
+//!
+//! | Offset | Size | Content                  |
+//! |--------|------|--------------------------|
+//! | 0      | 1    | Unit address             |
+//! | 1      | 1    | Function code            |
+//! | 2      | n    | Data, big endian         |
+//! | 2 + n  | 2    | CRC-16, little endian    |
+//!
+//! The [`Server`] supports the functions [`READ_HOLDING_REGISTERS`],
+//! [`READ_INPUT_REGISTERS`], [`WRITE_SINGLE_REGISTER`] and
+//! [`WRITE_MULTIPLE_REGISTERS`] on a [`Registers`] map and answers failed
+//! requests with an [`Exception`]. Requests to [`BROADCAST`] are executed
+//! without a response. [`pack::PackRegisters`] maps the telemetry and
+//! protection settings of the cellcore.
+//!
+//! The server reads from and transmits on a [`Port`]. [`Rs485`] implements
+//! it for a UART and the driver enable pin of a transceiver.
+
+#![no_std]
+
+pub use self::crc::crc16;
+pub use self::frame::{FrameError, Receiver, Timing};
+pub use self::port::{Port, Rs485, Rs485Error};
+pub use self::server::{
+    Counters, Exception, READ_HOLDING_REGISTERS, READ_INPUT_REGISTERS, Registers, Server,
+    WRITE_MULTIPLE_REGISTERS, WRITE_SINGLE_REGISTER,
+};
+
+mod crc;
+mod frame;
+pub mod pack;
+mod port;
+mod server;
+
+/// Unit address of requests to all servers.
+pub const BROADCAST: u8 = 0;
+/// Maximum length of a frame.
+pub const MAX_ADU: usize = 256;
+/// Minimum length of a frame: address, function code and CRC.
+pub const MIN_ADU: usize = 4;
@@ -0,0 +1,301 @@
+//! Register map of the pack.
+//!
+//! Input registers hold the telemetry:
+//!
+//! | Address                  | Content                                        |
+//! |--------------------------|------------------------------------------------|
+//! | [`SOC`]                  | State of charge in 0.01 %                      |
+//! | [`VOLTAGE`]              | Pack voltage in 10 mV                          |
+//! | [`CURRENT`]              | Pack current in 0.1 A, positive while charging |
+//! | [`MAX_CELL`]             | Highest cell voltage in mV                     |
+//! | [`MIN_CELL`]             | Lowest cell voltage in mV                      |
+//! | [`MAX_TEMPERATURE`]      | Highest temperature in 0.1 °C                  |
+//! | [`MIN_TEMPERATURE`]      | Lowest temperature in 0.1 °C                   |
+//! | [`WARNINGS`]             | Quantities at least at [`Level::Warning`]      |
+//! | [`ALARMS`]               | Quantities at least at [`Level::Alarm`]        |
+//! | [`TRIPS`]                | Quantities at [`Level::Trip`]                  |
+//! | [`CHARGE_CURRENT`]       | Charge current limit in 0.1 A                  |
+//! | [`DISCHARGE_CURRENT`]    | Discharge current limit in 0.1 A               |
+//! | [`CHARGE_VOLTAGE`]       | Charge voltage limit in 10 mV                  |
+//! | [`DISCHARGE_VOLTAGE`]    | Discharge voltage limit in 10 mV               |
+//! | [`CELL_COUNT`]           | Number of cells                                |
+//! | [`TEMPERATURE_COUNT`]    | Number of temperatures                         |
+//! | [`CELLS`] + n            | Voltage of cell n in mV                        |
+//! | [`TEMPERATURES`] + n     | Temperature n in 0.1 °C                        |
+//!
+//! Bit n of the flag registers is quantity n of [`Quantity::ALL`]. Missing
+//! measurements read as [`MISSING`] or [`MISSING_SIGNED`].
+//!
+//! Holding registers hold the protection thresholds. The thresholds of a
+//! quantity start at its index in [`Quantity::ALL`] times
+//! [`QUANTITY_REGISTERS`] and consist of the value, hysteresis and delay in
+//! milliseconds of [`Level::Warning`], [`Level::Alarm`] and [`Level::Trip`].
+//! Voltages are in mV, temperatures in 0.1 °C and currents in 0.1 A.
+//! Writes that leave the thresholds of a quantity invalid by
+//! [`Limit::is_valid`] are refused as a whole with
+//! [`Exception::IllegalDataValue`].
+
+use cellcore::limits::Limits;
+use cellcore::protection::{self, Level, Limit, Protection, Quantity, Threshold};
+use p3t1755::Temperature;
+
+use crate::{Exception, Registers};
+
+/// State of charge.
+pub const SOC: u16 = 0;
+/// Pack voltage.
+pub const VOLTAGE: u16 = 1;
+/// Pack current.
+pub const CURRENT: u16 = 2;
+/// Highest cell voltage.
+pub const MAX_CELL: u16 = 3;
+/// Lowest cell voltage.
+pub const MIN_CELL: u16 = 4;
+/// Highest temperature.
+pub const MAX_TEMPERATURE: u16 = 5;
+/// Lowest temperature.
+pub const MIN_TEMPERATURE: u16 = 6;
+/// Quantities with an active warning.
+pub const WARNINGS: u16 = 7;
+/// Quantities with an active alarm.
+pub const ALARMS: u16 = 8;
+/// Quantities that tripped.
+pub const TRIPS: u16 = 9;
+/// Charge current limit.
+pub const CHARGE_CURRENT: u16 = 10;
+/// Discharge current limit.
+pub const DISCHARGE_CURRENT: u16 = 11;
+/// Charge voltage limit.
+pub const CHARGE_VOLTAGE: u16 = 12;
+/// Discharge voltage limit.
+pub const DISCHARGE_VOLTAGE: u16 = 13;
+/// Number of cells.
+pub const CELL_COUNT: u16 = 14;
+/// Number of temperatures.
+pub const TEMPERATURE_COUNT: u16 = 15;
+/// First cell voltage.
+pub const CELLS: u16 = 0x100;
+/// First temperature.
+pub const TEMPERATURES: u16 = 0x200;
+
+/// Holding registers per quantity.
+pub const QUANTITY_REGISTERS: u16 = 9;
+
+/// Unsigned value of a missing measurement.
+pub const MISSING: u16 = 0xFFFF;
+/// Signed value of a missing measurement.
+pub const MISSING_SIGNED: i16 = i16::MIN;
+
+/// Telemetry of the pack.
+///
+/// `None` marks a measurement that is missing or invalid.
+#[derive(Clone, Copy)]
+pub struct Telemetry<'a> {
+    /// State of charge in 0.01 %.
+    pub soc: Option<u16>,
+    /// Pack voltage in millivolts.
+    pub voltage_mv: Option<u32>,
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: Option<i32>,
+    /// Cell voltages in millivolts.
+    pub cells: &'a [Option<u16>],
+    /// Temperatures of the pack.
+    pub temperatures: &'a [Option<Temperature>],
+    /// Protection state.
+    pub protection: &'a Protection,
+    /// Current and voltage limits.
+    pub limits: Limits,
+}
+
+impl Telemetry<'_> {
+    /// Returns the flags of the quantities at or above `level`.
+    fn flags(&self, level: Level) -> u16 {
+        let mut flags = 0;
+        for (bit, quantity) in Quantity::ALL.into_iter().enumerate() {
+            if self.protection.level(quantity) >= Some(level) {
+                flags |= 1 << bit;
+            }
+        }
+        flags
+    }
+
+    fn temperatures(&self) -> impl Iterator<Item = i16> + '_ {
+        self.temperatures.iter().flatten().map(|&t| decicelsius(t))
+    }
+}
+
+/// Converts to an unsigned register, truncating and saturating.
+fn unsigned(value: Option<u32>, divisor: u32) -> u16 {
+    value.map_or(MISSING, |value| {
+        (value / divisor).try_into().unwrap_or(MISSING - 1)
+    })
+}
+
+/// Converts to a signed register, truncating towards zero and saturating.
+fn signed(value: Option<i32>, divisor: i32) -> u16 {
+    let value = value.map_or(MISSING_SIGNED, |value| {
+        (value / divisor).clamp(i32::from(MISSING_SIGNED) + 1, i16::MAX.into()) as i16
+    });
+    value as u16
+}
+
+/// Converts a temperature to 0.1 °C.
+fn decicelsius(temperature: Temperature) -> i16 {
+    // The raw temperature is in 1/16 °C.
+    (i32::from(temperature.raw()) * 10 / 16) as i16
+}
+
+/// Returns the quantity, threshold and field of a holding register.
+fn threshold(
+    config: &mut protection::Config,
+    address: u16,
+) -> Option<(Quantity, &mut Threshold, u16)> {
+    let quantity = *Quantity::ALL.get(usize::from(address / QUANTITY_REGISTERS))?;
+    let index = address % QUANTITY_REGISTERS;
+    let level = Level::ALL[usize::from(index / 3)];
+    let threshold = config.limit_mut(quantity).threshold_mut(level);
+    Some((quantity, threshold, index % 3))
+}
+
+/// Registers of the pack backed by telemetry and protection settings.
+///
+/// Created for every poll of the server. Written settings only change
+/// `config`; the application applies them after [`PackRegisters::is_changed`].
+pub struct PackRegisters<'a> {
+    telemetry: Telemetry<'a>,
+    config: &'a mut protection::Config,
+    changed: bool,
+}
+
+impl<'a> PackRegisters<'a> {
+    /// Creates registers from the telemetry and the settings.
+    pub const fn new(telemetry: Telemetry<'a>, config: &'a mut protection::Config) -> Self {
+        Self {
+            telemetry,
+            config,
+            changed: false,
+        }
+    }
+
+    /// Returns true if a holding register was written.
+    #[must_use]
+    pub const fn is_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+/// Unit of the values of a quantity.
+#[derive(Clone, Copy)]
+enum Unit {
+    Millivolt,
+    Decicelsius,
+    Deciampere,
+}
+
+impl Unit {
+    const fn of(quantity: Quantity) -> Self {
+        match quantity {
+            Quantity::OverVoltage | Quantity::UnderVoltage => Self::Millivolt,
+            Quantity::OverTemperature | Quantity::UnderTemperature => Self::Decicelsius,
+            Quantity::ChargeOverCurrent | Quantity::DischargeOverCurrent => Self::Deciampere,
+        }
+    }
+
+    /// Converts an internal value to a register.
+    fn encode(self, value: i32) -> u16 {
+        match self {
+            Self::Millivolt => value.clamp(0, u16::MAX.into()) as u16,
+            // Temperatures are in 1/16 °C.
+            Self::Decicelsius => {
+                (value * 10 / 16).clamp(i16::MIN.into(), i16::MAX.into()) as i16 as u16
+            }
+            Self::Deciampere => (value / 100).clamp(0, u16::MAX.into()) as u16,
+        }
+    }
+
+    /// Converts a register to an internal value.
+    fn decode(self, value: u16) -> i32 {
+        match self {
+            Self::Millivolt => value.into(),
+            Self::Decicelsius => i32::from(value as i16) * 16 / 10,
+            Self::Deciampere => i32::from(value) * 100,
+        }
+    }
+}
+
+impl Registers for PackRegisters<'_> {
+    fn read_input(&mut self, address: u16) -> Result<u16, Exception> {
+        let t = &self.telemetry;
+        let value = match address {
+            SOC => t.soc.unwrap_or(MISSING),
+            VOLTAGE => unsigned(t.voltage_mv, 10),
+            CURRENT => signed(t.current_ma, 100),
+            MAX_CELL => t.cells.iter().flatten().max().copied().unwrap_or(MISSING),
+            MIN_CELL => t.cells.iter().flatten().min().copied().unwrap_or(MISSING),
+            MAX_TEMPERATURE => t.temperatures().max().unwrap_or(MISSING_SIGNED) as u16,
+            MIN_TEMPERATURE => t.temperatures().min().unwrap_or(MISSING_SIGNED) as u16,
+            WARNINGS => t.flags(Level::Warning),
+            ALARMS => t.flags(Level::Alarm),
+            TRIPS => t.flags(Level::Trip),
+            CHARGE_CURRENT => unsigned(Some(t.limits.charge_ma), 100),
+            DISCHARGE_CURRENT => unsigned(Some(t.limits.discharge_ma), 100),
+            CHARGE_VOLTAGE => unsigned(Some(t.limits.charge_voltage_mv), 10),
+            DISCHARGE_VOLTAGE => unsigned(Some(t.limits.discharge_voltage_mv), 10),
+            CELL_COUNT => t.cells.len() as u16,
+            TEMPERATURE_COUNT => t.temperatures.len() as u16,
+            CELLS..TEMPERATURES => {
+                let cell = t.cells.get(usize::from(address - CELLS));
+                cell.ok_or(Exception::IllegalDataAddress)?
+                    .unwrap_or(MISSING)
+            }
+            TEMPERATURES.. => {
+                let temperature = t.temperatures.get(usize::from(address - TEMPERATURES));
+                temperature
+                    .ok_or(Exception::IllegalDataAddress)?
+                    .map_or(MISSING_SIGNED, decicelsius) as u16
+            }
+            _ => return Err(Exception::IllegalDataAddress),
+        };
+        Ok(value)
+    }
+
+    fn read_holding(&mut self, address: u16) -> Result<u16, Exception> {
+        let (quantity, threshold, field) =
+            threshold(self.config, address).ok_or(Exception::IllegalDataAddress)?;
+        let unit = Unit::of(quantity);
+        Ok(match field {
+            0 => unit.encode(threshold.value),
+            1 => unit.encode(threshold.hysteresis),
+            _ => threshold.delay_ms.try_into().unwrap_or(u16::MAX),
+        })
+    }
+
+    fn write_holding(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+        self.write_holdings(address, &[value])
+    }
+
+    fn write_holdings(&mut self, start: u16, values: &[u16]) -> Result<(), Exception> {
+        // Changed on a copy, so a refused write leaves everything unchanged.
+        let mut config = *self.config;
+        for (address, &value) in (start..).zip(values) {
+            let (quantity, threshold, field) =
+                threshold(&mut config, address).ok_or(Exception::IllegalDataAddress)?;
+            let unit = Unit::of(quantity);
+            match field {
+                0 => threshold.value = unit.decode(value),
+                1 => threshold.hysteresis = unit.decode(value),
+                _ => threshold.delay_ms = value.into(),
+            }
+        }
+        let valid = |quantity: Quantity| {
+            let limit: &Limit = config.limit(quantity);
+            limit == self.config.limit(quantity) || limit.is_valid(quantity)
+        };
+        if !Quantity::ALL.into_iter().all(valid) {
+            return Err(Exception::IllegalDataValue);
+        }
+        *self.config = config;
+        self.changed = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use embedded_hal::digital::OutputPin;
+use embedded_io::{Read, ReadReady, Write};
+
+/// Serial link to the Modbus client.
+pub trait Port {
+    /// Error of the link.
+    type Error;
+
+    /// Returns the next received byte, or `None` if there is none.
+    fn read(&mut self) -> Result<Option<u8>, Self::Error>;
+
+    /// Sends a frame and returns once its last bit has left the line.
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Error of an [`Rs485`] port.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rs485Error<U, P> {
+    /// The UART failed.
+    Uart(U),
+    /// The driver enable pin failed.
+    DriverEnable(P),
+}
+
+/// Half-duplex RS-485 port whose transceiver driver is enabled while
+/// transmitting.
+///
+/// The receiver enable of the transceiver is expected to be tied to the
+/// inverted driver enable, so the port doesn't receive its own frames.
+pub struct Rs485<U, P> {
+    uart: U,
+    driver_enable: P,
+}
+
+impl<U, P: OutputPin> Rs485<U, P> {
+    /// Creates a port and disables the driver.
+    pub fn new(uart: U, mut driver_enable: P) -> Result<Self, P::Error> {
+        driver_enable.set_low()?;
+        Ok(Self {
+            uart,
+            driver_enable,
+        })
+    }
+
+    /// Releases the UART and the driver enable pin.
+    pub fn release(self) -> (U, P) {
+        (self.uart, self.driver_enable)
+    }
+}
+
+impl<U, P> Port for Rs485<U, P>
+where
+    U: Read + ReadReady + Write,
+    P: OutputPin,
+{
+    type Error = Rs485Error<U::Error, P::Error>;
+
+    fn read(&mut self) -> Result<Option<u8>, Self::Error> {
+        if !self.uart.read_ready().map_err(Rs485Error::Uart)? {
+            return Ok(None);
+        }
+        let mut byte = [0];
+        let n = self.uart.read(&mut byte).map_err(Rs485Error::Uart)?;
+        Ok((n == 1).then_some(byte[0]))
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        self.driver_enable
+            .set_high()
+            .map_err(Rs485Error::DriverEnable)?;
+        // The flush waits for the last stop bit, so the driver isn't disabled
+        // in the middle of a character.
+        let result = self
+            .uart
+            .write_all(frame)
+            .and_then(|()| self.uart.flush())
+            .map_err(Rs485Error::Uart);
+        // Release the bus even if the UART failed.
+        self.driver_enable
+            .set_low()
+            .map_err(Rs485Error::DriverEnable)?;
+        result
+    }
+}
@@ -0,0 +1,274 @@
+use crate::frame::{Receiver, Timing};
+use crate::port::Port;
+use crate::{BROADCAST, MAX_ADU, crc16};
+
+/// Reads holding registers.
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+/// Reads input registers.
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+/// Writes a single holding register.
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+/// Writes multiple holding registers.
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Set in the function code of an exception response.
+const EXCEPTION_FLAG: u8 = 0x80;
+/// Most registers read by one request.
+const MAX_READ: u16 = 125;
+/// Most registers written by one request.
+const MAX_WRITE: u16 = 123;
+
+/// Exception code of an error response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Exception {
+    /// The function code isn't supported.
+    IllegalFunction = 0x01,
+    /// A register of the request doesn't exist.
+    IllegalDataAddress = 0x02,
+    /// A value or the length of the request is invalid.
+    IllegalDataValue = 0x03,
+    /// The request failed while it was executed.
+    ServerDeviceFailure = 0x04,
+}
+
+/// Register map of a server.
+pub trait Registers {
+    /// Reads an input register.
+    fn read_input(&mut self, address: u16) -> Result<u16, Exception>;
+
+    /// Reads a holding register.
+    fn read_holding(&mut self, address: u16) -> Result<u16, Exception>;
+
+    /// Writes a holding register.
+    fn write_holding(&mut self, address: u16, value: u16) -> Result<(), Exception>;
+
+    /// Writes consecutive holding registers starting at `start`.
+    ///
+    /// The default implementation checks all addresses and then writes one
+    /// register after the other. Maps whose values depend on each other
+    /// override it to check all values before changing anything.
+    fn write_holdings(&mut self, start: u16, values: &[u16]) -> Result<(), Exception> {
+        for address in (start..).take(values.len()) {
+            self.read_holding(address)?;
+        }
+        for (address, &value) in (start..).zip(values) {
+            self.write_holding(address, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of events since the server was created.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Counters {
+    /// Valid frames addressed to the server, including broadcasts.
+    pub requests: u16,
+    /// Frames discarded because of their length or CRC.
+    pub frame_errors: u16,
+    /// Exception responses.
+    pub exceptions: u16,
+}
+
+/// Modbus RTU server.
+#[derive(Clone, Debug)]
+pub struct Server {
+    unit: u8,
+    receiver: Receiver,
+    counters: Counters,
+}
+
+impl Server {
+    /// Creates a server answering to a unit address between 1 and 247.
+    #[must_use]
+    pub const fn new(unit: u8, timing: Timing) -> Self {
+        Self {
+            unit,
+            receiver: Receiver::new(timing),
+            counters: Counters {
+                requests: 0,
+                frame_errors: 0,
+                exceptions: 0,
+            },
+        }
+    }
+
+    /// Returns the unit address.
+    #[must_use]
+    pub const fn unit(&self) -> u8 {
+        self.unit
+    }
+
+    /// Changes the unit address.
+    pub fn set_unit(&mut self, unit: u8) {
+        self.unit = unit;
+    }
+
+    /// Returns the event counters.
+    #[must_use]
+    pub const fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Reads the received bytes and answers a request once `dt_us` of
+    /// silence has ended it.
+    ///
+    /// Has to be called more often than the frame gap of the baud rate.
+    pub fn poll<P: Port>(
+        &mut self,
+        port: &mut P,
+        dt_us: u32,
+        registers: &mut impl Registers,
+    ) -> Result<(), P::Error> {
+        let mut received = false;
+        while let Some(byte) = port.read()? {
+            self.receiver.push(byte);
+            received = true;
+        }
+        if received {
+            return Ok(());
+        }
+
+        let mut response = [0; MAX_ADU];
+        let len = match self.receiver.idle(dt_us) {
+            None => return Ok(()),
+            Some(Err(_)) => {
+                self.counters.frame_errors = self.counters.frame_errors.saturating_add(1);
+                return Ok(());
+            }
+            Some(Ok(frame)) => handle(
+                self.unit,
+                &mut self.counters,
+                frame,
+                registers,
+                &mut response,
+            ),
+        };
+        match len {
+            Some(len) => port.transmit(&response[..len]),
+            None => Ok(()),
+        }
+    }
+
+    /// Answers a frame whose CRC was checked and removed.
+    ///
+    /// Returns the length of the response including its CRC, or `None` if
+    /// the frame is addressed to another unit or is a broadcast.
+    pub fn handle(
+        &mut self,
+        frame: &[u8],
+        registers: &mut impl Registers,
+        response: &mut [u8; MAX_ADU],
+    ) -> Option<usize> {
+        handle(self.unit, &mut self.counters, frame, registers, response)
+    }
+}
+
+fn handle(
+    unit: u8,
+    counters: &mut Counters,
+    frame: &[u8],
+    registers: &mut impl Registers,
+    response: &mut [u8; MAX_ADU],
+) -> Option<usize> {
+    let (&address, pdu) = frame.split_first()?;
+    if address != unit && address != BROADCAST {
+        return None;
+    }
+    counters.requests = counters.requests.saturating_add(1);
+    let (&function, data) = pdu.split_first()?;
+    // Leave room for the address, function code and CRC.
+    let result = execute(function, data, registers, &mut response[2..MAX_ADU - 2]);
+    if address == BROADCAST {
+        return None;
+    }
+
+    response[0] = unit;
+    let len = match result {
+        Ok(len) => {
+            response[1] = function;
+            2 + len
+        }
+        Err(exception) => {
+            counters.exceptions = counters.exceptions.saturating_add(1);
+            response[1] = function | EXCEPTION_FLAG;
+            response[2] = exception as u8;
+            3
+        }
+    };
+    let crc = crc16(&response[..len]);
+    response[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+    Some(len + 2)
+}
+
+/// Executes a request and writes the data of the response to `out`.
+///
+/// Returns the length of the response data.
+fn execute(
+    function: u8,
+    data: &[u8],
+    registers: &mut impl Registers,
+    out: &mut [u8],
+) -> Result<usize, Exception> {
+    match function {
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            let &[a0, a1, q0, q1] = data else {
+                return Err(Exception::IllegalDataValue);
+            };
+            let start = u16::from_be_bytes([a0, a1]);
+            let count = u16::from_be_bytes([q0, q1]);
+            check_range(start, count, MAX_READ)?;
+            out[0] = (2 * count) as u8;
+            for i in 0..count {
+                let value = if function == READ_HOLDING_REGISTERS {
+                    registers.read_holding(start + i)?
+                } else {
+                    registers.read_input(start + i)?
+                };
+                let offset = 1 + 2 * usize::from(i);
+                out[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            Ok(1 + 2 * usize::from(count))
+        }
+        WRITE_SINGLE_REGISTER => {
+            let &[a0, a1, v0, v1] = data else {
+                return Err(Exception::IllegalDataValue);
+            };
+            let address = u16::from_be_bytes([a0, a1]);
+            registers.write_holding(address, u16::from_be_bytes([v0, v1]))?;
+            out[..4].copy_from_slice(data);
+            Ok(4)
+        }
+        WRITE_MULTIPLE_REGISTERS => {
+            let &[a0, a1, q0, q1, len, ref values @ ..] = data else {
+                return Err(Exception::IllegalDataValue);
+            };
+            let start = u16::from_be_bytes([a0, a1]);
+            let count = u16::from_be_bytes([q0, q1]);
+            check_range(start, count, MAX_WRITE)?;
+            if usize::from(len) != 2 * usize::from(count) || values.len() != usize::from(len) {
+                return Err(Exception::IllegalDataValue);
+            }
+            let mut words = [0; MAX_WRITE as usize];
+            for (word, value) in words.iter_mut().zip(values.chunks_exact(2)) {
+                *word = u16::from_be_bytes([value[0], value[1]]);
+            }
+            registers.write_holdings(start, &words[..usize::from(count)])?;
+            out[..4].copy_from_slice(&data[..4]);
+            Ok(4)
+        }
+        _ => Err(Exception::IllegalFunction),
+    }
+}
+
+/// Checks the number of registers of a request and that they don't wrap
+/// around.
+fn check_range(start: u16, count: u16, max: u16) -> Result<(), Exception> {
+    if !(1..=max).contains(&count) {
+        return Err(Exception::IllegalDataValue);
+    }
+    if start.checked_add(count - 1).is_none() {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
@@ -0,0 +1,798 @@
+//! Integration tests for the Modbus RTU server.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use cellcore::limits::Limits;
+use cellcore::protection::{self, Measurements, Protection, Quantity};
+use modbus_rtu::pack::{self, PackRegisters, Telemetry};
+use modbus_rtu::{
+    Exception, FrameError, MAX_ADU, Port, Receiver, Registers, Rs485, Rs485Error, Server, Timing,
+    crc16,
+};
+use p3t1755::Temperature;
+
+const UNIT: u8 = 17;
+/// Frame gap at 19200 baud.
+const GAP_US: u32 = 2006;
+/// Poll interval of the tests.
+const POLL_US: u32 = 500;
+
+/// Appends the CRC to a frame.
+fn adu(frame: &[u8]) -> Vec<u8> {
+    let mut adu = frame.to_vec();
+    adu.extend_from_slice(&crc16(frame).to_le_bytes());
+    adu
+}
+
+/// Both ends of a byte stream between a client and the server.
+#[derive(Default)]
+struct Loopback {
+    /// Bytes from the client to the server.
+    to_server: VecDeque<u8>,
+    /// Frames sent by the server.
+    to_client: Vec<Vec<u8>>,
+}
+
+impl Port for Loopback {
+    type Error = Infallible;
+
+    fn read(&mut self) -> Result<Option<u8>, Infallible> {
+        Ok(self.to_server.pop_front())
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Infallible> {
+        self.to_client.push(frame.to_vec());
+        Ok(())
+    }
+}
+
+/// Registers backed by arrays.
+struct Map {
+    input: [u16; 8],
+    holding: [u16; 8],
+}
+
+impl Map {
+    fn new() -> Self {
+        Self {
+            input: [100, 101, 102, 103, 104, 105, 106, 107],
+            holding: [0; 8],
+        }
+    }
+}
+
+impl Registers for Map {
+    fn read_input(&mut self, address: u16) -> Result<u16, Exception> {
+        self.input
+            .get(usize::from(address))
+            .copied()
+            .ok_or(Exception::IllegalDataAddress)
+    }
+
+    fn read_holding(&mut self, address: u16) -> Result<u16, Exception> {
+        self.holding
+            .get(usize::from(address))
+            .copied()
+            .ok_or(Exception::IllegalDataAddress)
+    }
+
+    fn write_holding(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+        if value == 0xDEAD {
+            return Err(Exception::IllegalDataValue);
+        }
+        *self
+            .holding
+            .get_mut(usize::from(address))
+            .ok_or(Exception::IllegalDataAddress)? = value;
+        Ok(())
+    }
+}
+
+/// Polls the server until the line was silent for `silence_us`.
+fn run<P: Port>(
+    server: &mut Server,
+    port: &mut P,
+    registers: &mut impl Registers,
+    silence_us: u32,
+) -> Result<(), P::Error> {
+    for _ in 0..silence_us.div_ceil(POLL_US) {
+        server.poll(port, POLL_US, registers)?;
+    }
+    Ok(())
+}
+
+/// Sends a request and returns the responses.
+fn exchange(server: &mut Server, registers: &mut impl Registers, request: &[u8]) -> Vec<Vec<u8>> {
+    let mut port = Loopback::default();
+    port.to_server.extend(request);
+    let Ok(()) = run(server, &mut port, registers, 2 * GAP_US);
+    port.to_client
+}
+
+fn server() -> Server {
+    Server::new(UNIT, Timing::new(19_200))
+}
+
+#[test]
+fn test_crc_reference_frame() {
+    // Example request from the Modbus specification.
+    assert_eq!(
+        adu(&[0x01, 0x03, 0x00, 0x6B, 0x00, 0x03]),
+        [0x01, 0x03, 0x00, 0x6B, 0x00, 0x03, 0x74, 0x17]
+    );
+}
+
+#[test]
+fn test_timing() {
+    assert_eq!(Timing::new(9600).frame_gap_us(), 4011);
+    assert_eq!(Timing::new(19_200).frame_gap_us(), GAP_US);
+    assert_eq!(Timing::new(38_400).frame_gap_us(), 1750);
+    assert_eq!(Timing::new(115_200).frame_gap_us(), 1750);
+}
+
+#[test]
+fn test_receiver_waits_for_gap() {
+    let mut receiver = Receiver::new(Timing::new(19_200));
+    assert!(receiver.idle(GAP_US).is_none());
+    for byte in adu(&[UNIT, 0x03, 0x00, 0x00, 0x00, 0x01]) {
+        receiver.push(byte);
+    }
+    assert!(receiver.is_receiving());
+    assert!(receiver.idle(GAP_US - 1).is_none());
+    // Another byte restarts the gap.
+    receiver.push(0x55);
+    assert!(receiver.idle(GAP_US - 1).is_none());
+    assert_eq!(receiver.idle(1), Some(Err(FrameError::Crc)));
+    assert!(!receiver.is_receiving());
+}
+
+#[test]
+fn test_receiver_frame_errors() {
+    let mut receiver = Receiver::new(Timing::new(19_200));
+    for byte in [UNIT, 0x03, 0x00] {
+        receiver.push(byte);
+    }
+    assert_eq!(receiver.idle(GAP_US), Some(Err(FrameError::TooShort)));
+
+    for _ in 0..=MAX_ADU {
+        receiver.push(0x55);
+    }
+    assert_eq!(receiver.idle(GAP_US), Some(Err(FrameError::TooLong)));
+
+    let frame = [UNIT, 0x03, 0x00, 0x00, 0x00, 0x01];
+    for byte in adu(&frame) {
+        receiver.push(byte);
+    }
+    assert_eq!(receiver.idle(GAP_US), Some(Ok(&frame[..])));
+}
+
+#[test]
+fn test_read_holding_registers() {
+    let mut server = server();
+    let mut map = Map::new();
+    map.holding[2] = 0x1234;
+    map.holding[3] = 0xABCD;
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[UNIT, 0x03, 0x00, 0x02, 0x00, 0x02]),
+    );
+    assert_eq!(
+        responses,
+        [adu(&[UNIT, 0x03, 0x04, 0x12, 0x34, 0xAB, 0xCD])]
+    );
+}
+
+#[test]
+fn test_read_input_registers() {
+    let mut server = server();
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[UNIT, 0x04, 0x00, 0x06, 0x00, 0x02]),
+    );
+    assert_eq!(responses, [adu(&[UNIT, 0x04, 0x04, 0, 106, 0, 107])]);
+}
+
+#[test]
+fn test_write_single_register() {
+    let mut server = server();
+    let mut map = Map::new();
+    let request = adu(&[UNIT, 0x06, 0x00, 0x05, 0x02, 0x01]);
+    let responses = exchange(&mut server, &mut map, &request);
+    // The response echoes the request.
+    assert_eq!(responses, [request]);
+    assert_eq!(map.holding[5], 0x0201);
+}
+
+#[test]
+fn test_write_multiple_registers() {
+    let mut server = server();
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[
+            UNIT, 0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x00, 0x0A, 0x01, 0x02,
+        ]),
+    );
+    assert_eq!(responses, [adu(&[UNIT, 0x10, 0x00, 0x01, 0x00, 0x02])]);
+    assert_eq!(map.holding[1..3], [0x000A, 0x0102]);
+}
+
+#[test]
+fn test_write_multiple_checks_addresses_first() {
+    let mut server = server();
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[
+            UNIT, 0x10, 0x00, 0x07, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02,
+        ]),
+    );
+    assert_eq!(responses, [adu(&[UNIT, 0x90, 0x02])]);
+    assert_eq!(map.holding[7], 0);
+}
+
+#[test]
+fn test_exceptions() {
+    let cases: &[(&[u8], [u8; 2])] = &[
+        // Unsupported function.
+        (&[UNIT, 0x2B, 0x0E, 0x01, 0x00], [0xAB, 0x01]),
+        // Register out of range.
+        (&[UNIT, 0x03, 0x00, 0x07, 0x00, 0x02], [0x83, 0x02]),
+        (&[UNIT, 0x04, 0x01, 0x00, 0x00, 0x01], [0x84, 0x02]),
+        // Quantity out of range.
+        (&[UNIT, 0x03, 0x00, 0x00, 0x00, 0x00], [0x83, 0x03]),
+        (&[UNIT, 0x04, 0x00, 0x00, 0x00, 0x7E], [0x84, 0x03]),
+        // Wrapping around the address space.
+        (&[UNIT, 0x03, 0xFF, 0xFF, 0x00, 0x02], [0x83, 0x02]),
+        // Truncated request.
+        (&[UNIT, 0x06, 0x00, 0x01, 0x00], [0x86, 0x03]),
+        // Byte count doesn't match the quantity.
+        (
+            &[UNIT, 0x10, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00, 0x01],
+            [0x90, 0x03],
+        ),
+        // Rejected value.
+        (&[UNIT, 0x06, 0x00, 0x01, 0xDE, 0xAD], [0x86, 0x03]),
+    ];
+    let mut server = server();
+    let mut map = Map::new();
+    for (request, [function, code]) in cases {
+        let responses = exchange(&mut server, &mut map, &adu(request));
+        assert_eq!(
+            responses,
+            [adu(&[UNIT, *function, *code])],
+            "request {request:02X?}"
+        );
+    }
+    assert_eq!(server.counters().exceptions, cases.len() as u16);
+}
+
+#[test]
+fn test_broadcast_write_without_response() {
+    let mut server = server();
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[0x00, 0x06, 0x00, 0x04, 0x00, 0x2A]),
+    );
+    assert!(responses.is_empty());
+    assert_eq!(map.holding[4], 42);
+    assert_eq!(server.counters().requests, 1);
+}
+
+#[test]
+fn test_other_unit_ignored() {
+    let mut server = server();
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[UNIT + 1, 0x06, 0x00, 0x04, 0x00, 0x2A]),
+    );
+    assert!(responses.is_empty());
+    assert_eq!(map.holding[4], 0);
+    assert_eq!(server.counters().requests, 0);
+}
+
+#[test]
+fn test_corrupted_frame_ignored() {
+    let mut server = server();
+    let mut map = Map::new();
+    let mut request = adu(&[UNIT, 0x06, 0x00, 0x04, 0x00, 0x2A]);
+    request[5] ^= 0x01;
+    let responses = exchange(&mut server, &mut map, &request);
+    assert!(responses.is_empty());
+    assert_eq!(map.holding[4], 0);
+    assert_eq!(server.counters().frame_errors, 1);
+}
+
+#[test]
+fn test_response_after_frame_gap() {
+    let mut server = server();
+    let mut map = Map::new();
+    let mut port = Loopback::default();
+    port.to_server
+        .extend(adu(&[UNIT, 0x04, 0x00, 0x00, 0x00, 0x01]));
+
+    let Ok(()) = server.poll(&mut port, POLL_US, &mut map);
+    let Ok(()) = run(&mut server, &mut port, &mut map, GAP_US - POLL_US);
+    assert!(port.to_client.is_empty());
+    let Ok(()) = server.poll(&mut port, POLL_US, &mut map);
+    assert_eq!(port.to_client, [adu(&[UNIT, 0x04, 0x02, 0, 100])]);
+}
+
+#[test]
+fn test_back_to_back_requests() {
+    let mut server = server();
+    let mut map = Map::new();
+    let mut port = Loopback::default();
+    port.to_server
+        .extend(adu(&[UNIT, 0x06, 0x00, 0x00, 0x00, 0x07]));
+    let Ok(()) = run(&mut server, &mut port, &mut map, GAP_US + POLL_US);
+    port.to_server
+        .extend(adu(&[UNIT, 0x03, 0x00, 0x00, 0x00, 0x01]));
+    let Ok(()) = run(&mut server, &mut port, &mut map, GAP_US + POLL_US);
+    assert_eq!(
+        port.to_client,
+        [
+            adu(&[UNIT, 0x06, 0x00, 0x00, 0x00, 0x07]),
+            adu(&[UNIT, 0x03, 0x02, 0x00, 0x07]),
+        ]
+    );
+}
+
+#[test]
+fn test_set_unit() {
+    let mut server = server();
+    server.set_unit(3);
+    assert_eq!(server.unit(), 3);
+    let mut map = Map::new();
+    let responses = exchange(
+        &mut server,
+        &mut map,
+        &adu(&[3, 0x04, 0x00, 0x01, 0x00, 0x01]),
+    );
+    assert_eq!(responses, [adu(&[3, 0x04, 0x02, 0, 101])]);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Event {
+    DriverEnable(bool),
+    Write(Vec<u8>),
+    Flush,
+}
+
+/// Shared state of the fake UART and driver enable pin.
+#[derive(Default)]
+struct Line {
+    rx: VecDeque<u8>,
+    events: Vec<Event>,
+    fail_write: bool,
+}
+
+struct FakeUart(Rc<RefCell<Line>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct UartError;
+
+impl embedded_io::Error for UartError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+impl embedded_io::ErrorType for FakeUart {
+    type Error = UartError;
+}
+
+impl embedded_io::Read for FakeUart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, UartError> {
+        let mut line = self.0.borrow_mut();
+        let n = buf.len().min(line.rx.len());
+        for (slot, byte) in buf.iter_mut().zip(line.rx.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl embedded_io::ReadReady for FakeUart {
+    fn read_ready(&mut self) -> Result<bool, UartError> {
+        Ok(!self.0.borrow().rx.is_empty())
+    }
+}
+
+impl embedded_io::Write for FakeUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, UartError> {
+        let mut line = self.0.borrow_mut();
+        if line.fail_write {
+            return Err(UartError);
+        }
+        line.events.push(Event::Write(buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), UartError> {
+        self.0.borrow_mut().events.push(Event::Flush);
+        Ok(())
+    }
+}
+
+struct FakePin(Rc<RefCell<Line>>);
+
+impl embedded_hal::digital::ErrorType for FakePin {
+    type Error = Infallible;
+}
+
+impl embedded_hal::digital::OutputPin for FakePin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.borrow_mut().events.push(Event::DriverEnable(false));
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.borrow_mut().events.push(Event::DriverEnable(true));
+        Ok(())
+    }
+}
+
+fn rs485() -> (Rs485<FakeUart, FakePin>, Rc<RefCell<Line>>) {
+    let line = Rc::new(RefCell::new(Line::default()));
+    let Ok(port) = Rs485::new(FakeUart(line.clone()), FakePin(line.clone()));
+    (port, line)
+}
+
+#[test]
+fn test_rs485_driver_enable() {
+    let (mut port, line) = rs485();
+    let mut server = server();
+    let mut map = Map::new();
+    line.borrow_mut()
+        .rx
+        .extend(adu(&[UNIT, 0x04, 0x00, 0x02, 0x00, 0x01]));
+    run(&mut server, &mut port, &mut map, 2 * GAP_US).unwrap();
+
+    // Enabled only around the complete frame.
+    assert_eq!(
+        line.borrow().events,
+        [
+            Event::DriverEnable(false),
+            Event::DriverEnable(true),
+            Event::Write(adu(&[UNIT, 0x04, 0x02, 0, 102])),
+            Event::Flush,
+            Event::DriverEnable(false),
+        ]
+    );
+}
+
+#[test]
+fn test_rs485_disables_driver_on_error() {
+    let (mut port, line) = rs485();
+    line.borrow_mut().fail_write = true;
+    assert_eq!(port.transmit(&[1, 2, 3]), Err(Rs485Error::Uart(UartError)));
+    assert_eq!(
+        line.borrow().events,
+        [
+            Event::DriverEnable(false),
+            Event::DriverEnable(true),
+            Event::DriverEnable(false),
+        ]
+    );
+    let (_uart, _pin) = port.release();
+}
+
+#[test]
+fn test_rs485_read() {
+    let (mut port, line) = rs485();
+    assert_eq!(port.read(), Ok(None));
+    line.borrow_mut().rx.extend([0x12, 0x34]);
+    assert_eq!(port.read(), Ok(Some(0x12)));
+    assert_eq!(port.read(), Ok(Some(0x34)));
+    assert_eq!(port.read(), Ok(None));
+}
+
+fn celsius(deg_c: i8) -> Temperature {
+    Temperature::from_degrees_celsius(deg_c)
+}
+
+fn nominal() -> Measurements {
+    Measurements {
+        max_cell_mv: Some(3350),
+        min_cell_mv: Some(3300),
+        max_temperature: Some(celsius(30)),
+        min_temperature: Some(celsius(20)),
+        current_ma: Some(0),
+    }
+}
+
+fn telemetry<'a>(
+    cells: &'a [Option<u16>],
+    temperatures: &'a [Option<Temperature>],
+    protection: &'a Protection,
+) -> Telemetry<'a> {
+    Telemetry {
+        soc: Some(5123),
+        voltage_mv: Some(53_210),
+        current_ma: Some(-12_345),
+        cells,
+        temperatures,
+        protection,
+        limits: Limits {
+            charge_ma: 50_000,
+            discharge_ma: 100_000,
+            charge_voltage_mv: 55_200,
+            discharge_voltage_mv: 46_400,
+        },
+    }
+}
+
+/// Reads all given input registers.
+fn read_inputs(registers: &mut impl Registers, addresses: &[u16]) -> Vec<u16> {
+    addresses
+        .iter()
+        .map(|&address| registers.read_input(address).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_pack_telemetry() {
+    let protection = Protection::new(protection::Config::default());
+    let cells = [Some(3301), None, Some(3345)];
+    let temperatures = [Some(celsius(25)), Some(Temperature::from_raw(-40).unwrap())];
+    let mut config = protection::Config::default();
+    let mut registers =
+        PackRegisters::new(telemetry(&cells, &temperatures, &protection), &mut config);
+
+    let values = read_inputs(&mut registers, &(0..16).collect::<Vec<_>>());
+    assert_eq!(
+        values,
+        [
+            5123,
+            5321,
+            -123i16 as u16,
+            3345,
+            3301,
+            250,
+            -25i16 as u16,
+            0,
+            0,
+            0,
+            500,
+            1000,
+            5520,
+            4640,
+            3,
+            2,
+        ]
+    );
+    assert_eq!(
+        read_inputs(&mut registers, &[0x100, 0x101, 0x102, 0x200, 0x201]),
+        [3301, pack::MISSING, 3345, 250, -25i16 as u16]
+    );
+    assert_eq!(
+        registers.read_input(0x103),
+        Err(Exception::IllegalDataAddress)
+    );
+    assert_eq!(
+        registers.read_input(0x202),
+        Err(Exception::IllegalDataAddress)
+    );
+    assert_eq!(registers.read_input(16), Err(Exception::IllegalDataAddress));
+    assert!(!registers.is_changed());
+}
+
+#[test]
+fn test_pack_missing_measurements() {
+    let protection = Protection::new(protection::Config::default());
+    let mut config = protection::Config::default();
+    let telemetry = Telemetry {
+        soc: None,
+        voltage_mv: None,
+        current_ma: None,
+        ..telemetry(&[None], &[None], &protection)
+    };
+    let mut registers = PackRegisters::new(telemetry, &mut config);
+    let missing_signed = pack::MISSING_SIGNED as u16;
+    assert_eq!(
+        read_inputs(&mut registers, &[0, 1, 2, 3, 4, 5, 6, 0x100, 0x200]),
+        [
+            pack::MISSING,
+            pack::MISSING,
+            missing_signed,
+            pack::MISSING,
+            pack::MISSING,
+            missing_signed,
+            missing_signed,
+            pack::MISSING,
+            missing_signed,
+        ]
+    );
+}
+
+#[test]
+fn test_pack_protection_flags() {
+    let mut protection = Protection::new(protection::Config::default());
+    let measurements = Measurements {
+        max_cell_mv: Some(3700),
+        max_temperature: Some(celsius(52)),
+        ..nominal()
+    };
+    for _ in 0..60 {
+        protection.update(100, &measurements);
+    }
+    assert_eq!(
+        protection.level(Quantity::OverTemperature),
+        Some(protection::Level::Warning)
+    );
+
+    let mut config = protection::Config::default();
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    // Over voltage is bit 0, over temperature bit 2.
+    assert_eq!(
+        read_inputs(&mut registers, &[pack::WARNINGS, pack::ALARMS, pack::TRIPS]),
+        [0b101, 0b001, 0b001]
+    );
+}
+
+#[test]
+fn test_pack_read_thresholds() {
+    let protection = Protection::new(protection::Config::default());
+    let mut config = protection::Config::default();
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    let read = |registers: &mut PackRegisters<'_>, quantity: u16, index: u16| {
+        registers
+            .read_holding(quantity * pack::QUANTITY_REGISTERS + index)
+            .unwrap()
+    };
+    // Over voltage warning.
+    assert_eq!(read(&mut registers, 0, 0), 3550);
+    assert_eq!(read(&mut registers, 0, 1), 50);
+    assert_eq!(read(&mut registers, 0, 2), 5000);
+    // Over temperature trip.
+    assert_eq!(read(&mut registers, 2, 6), 600);
+    assert_eq!(read(&mut registers, 2, 7), 100);
+    // Under temperature trip.
+    assert_eq!(read(&mut registers, 3, 6), -200i16 as u16);
+    // Discharge over current alarm.
+    assert_eq!(read(&mut registers, 5, 3), 1200);
+    assert_eq!(read(&mut registers, 5, 5), 2000);
+    assert_eq!(
+        registers.read_holding(6 * pack::QUANTITY_REGISTERS),
+        Err(Exception::IllegalDataAddress)
+    );
+}
+
+#[test]
+fn test_pack_write_thresholds() {
+    let protection = Protection::new(protection::Config::default());
+    let mut config = protection::Config::default();
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    registers.write_holding(1, 40).unwrap();
+    registers.write_holding(3 * 9 + 6, -150i16 as u16).unwrap();
+    registers.write_holding(4 * 9 + 6, 700).unwrap();
+    registers.write_holding(4 * 9 + 8, 100).unwrap();
+    assert_eq!(
+        registers.write_holding(2 * 9 + 1, -10i16 as u16),
+        Err(Exception::IllegalDataValue)
+    );
+    assert_eq!(
+        registers.write_holding(54, 1),
+        Err(Exception::IllegalDataAddress)
+    );
+    assert!(registers.is_changed());
+
+    assert_eq!(config.over_voltage.warning.hysteresis, 40);
+    assert_eq!(
+        config.under_temperature.trip.value,
+        i32::from(celsius(-15).raw())
+    );
+    assert_eq!(config.charge_over_current.trip.value, 70_000);
+    assert_eq!(config.charge_over_current.trip.delay_ms, 100);
+    assert_eq!(
+        config.over_temperature.warning,
+        protection::Config::default().over_temperature.warning
+    );
+}
+
+#[test]
+fn test_pack_write_validates_thresholds() {
+    let protection = Protection::new(protection::Config::default());
+    let mut config = protection::Config::default();
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    // Over voltage alarm above the trip, under voltage trip above the alarm
+    // and a trip above the cell ratings.
+    for (address, value) in [(3, 3700), (9 + 6, 2850), (6, 4600)] {
+        assert_eq!(
+            registers.write_holding(address, value),
+            Err(Exception::IllegalDataValue)
+        );
+    }
+    assert!(!registers.is_changed());
+    assert_eq!(config, protection::Config::default());
+}
+
+#[test]
+fn test_pack_write_multiple_thresholds() {
+    let protection = Protection::new(protection::Config::default());
+    let mut config = protection::Config::default();
+    let mut server = server();
+
+    // The alarm above the trip refuses the valid delay before it, too.
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    let responses = exchange(
+        &mut server,
+        &mut registers,
+        &adu(&[
+            UNIT, 0x10, 0x00, 0x02, 0x00, 0x02, 0x04, 0x00, 0x64, 0x0E, 0x74,
+        ]),
+    );
+    assert_eq!(responses, [adu(&[UNIT, 0x90, 0x03])]);
+    assert!(!registers.is_changed());
+    assert_eq!(config, protection::Config::default());
+
+    // Raising all over voltage thresholds above the old trip is only valid
+    // as a whole.
+    let mut registers = PackRegisters::new(telemetry(&[], &[], &protection), &mut config);
+    let responses = exchange(
+        &mut server,
+        &mut registers,
+        &adu(&[
+            UNIT, 0x10, 0x00, 0x00, 0x00, 0x07, 0x0E, 0x0E, 0x74, 0x00, 0x32, 0x13, 0x88, 0x0E,
+            0x9C, 0x00, 0x64, 0x03, 0xE8, 0x0E, 0xC4,
+        ]),
+    );
+    assert_eq!(responses, [adu(&[UNIT, 0x10, 0x00, 0x00, 0x00, 0x07])]);
+    assert!(registers.is_changed());
+    assert_eq!(config.over_voltage.warning.value, 3700);
+    assert_eq!(config.over_voltage.alarm.value, 3740);
+    assert_eq!(config.over_voltage.trip.value, 3780);
+}
+
+#[test]
+fn test_pack_over_rs485() {
+    let protection = Protection::new(protection::Config::default());
+    let cells = [Some(3300); 16];
+    let mut config = protection::Config::default();
+    let (mut port, line) = rs485();
+    let mut server = server();
+
+    // Reads the state of charge and pack voltage, then lowers the over
+    // voltage trip threshold.
+    line.borrow_mut()
+        .rx
+        .extend(adu(&[UNIT, 0x04, 0x00, 0x00, 0x00, 0x02]));
+    let mut registers = PackRegisters::new(telemetry(&cells, &[], &protection), &mut config);
+    run(&mut server, &mut port, &mut registers, 2 * GAP_US).unwrap();
+    line.borrow_mut()
+        .rx
+        .extend(adu(&[UNIT, 0x06, 0x00, 0x06, 0x0E, 0x10]));
+    run(&mut server, &mut port, &mut registers, 2 * GAP_US).unwrap();
+    assert!(registers.is_changed());
+    assert_eq!(config.over_voltage.trip.value, 3600);
+
+    let writes: Vec<_> = line
+        .borrow()
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Write(bytes) => Some(bytes.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        writes,
+        [
+            adu(&[UNIT, 0x04, 0x04, 0x14, 0x03, 0x14, 0xC9]),
+            adu(&[UNIT, 0x06, 0x00, 0x06, 0x0E, 0x10]),
+        ]
+    );
+}
@@ -0,0 +1,45 @@
+//! Pylontech compatible low voltage BMS messages for hybrid inverters.
+//!
+//! Most hybrid inverters talk to low voltage batteries with the CAN messages
+//! of Pylontech packs at 500 kbit/s with standard identifiers. The battery
+//! sends all of its messages about once a second:
+//!
+//! | Identifier         | Content                                         |
+//! |--------------------|-------------------------------------------------|
+//! | [`LIMITS_ID`]      | Charge and discharge voltage and current limits |
+//! | [`SOC_ID`]         | State of charge and health                      |
+//! | [`MEASUREMENT_ID`] | Pack voltage, current and temperature           |
+//! | [`ALARMS_ID`]      | Protection and alarm flags, module count        |
+//! | [`REQUEST_ID`]     | Charge and discharge enable, charge requests    |
+//! | [`NAME_ID`]        | Manufacturer name                               |
+//!
+//! The inverter answers with a [`HEARTBEAT_ID`] message, which [`Link`]
+//! watches to tell whether an inverter is connected. Values are little
+//! endian.
+
+#![no_std]
+
+pub use self::link::Link;
+pub use self::message::{Alarms, Flags, Message, Requests, Status};
+
+mod link;
+mod message;
+
+/// Charge voltage limit, charge and discharge current limits and discharge
+/// voltage limit.
+pub const LIMITS_ID: u16 = 0x351;
+/// State of charge and state of health.
+pub const SOC_ID: u16 = 0x355;
+/// Pack voltage, current and temperature.
+pub const MEASUREMENT_ID: u16 = 0x356;
+/// Protection and alarm flags.
+pub const ALARMS_ID: u16 = 0x359;
+/// Charge and discharge enable and charge requests.
+pub const REQUEST_ID: u16 = 0x35C;
+/// Manufacturer name.
+pub const NAME_ID: u16 = 0x35E;
+/// Heartbeat of the inverter.
+pub const HEARTBEAT_ID: u16 = 0x305;
+
+/// Manufacturer name most inverters expect.
+pub const PYLON: [u8; 8] = *b"PYLON   ";
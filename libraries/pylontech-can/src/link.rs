@@ -0,0 +1,73 @@
+use embedded_can::{Frame, Id, StandardId};
+
+use crate::HEARTBEAT_ID;
+
+/// Supervision of the inverter heartbeat.
+///
+/// The inverter counts as connected from its first heartbeat until no
+/// heartbeat arrived within the timeout.
+#[derive(Clone, Debug)]
+pub struct Link {
+    timeout_ms: u32,
+    since_heartbeat_ms: u32,
+    connected: bool,
+}
+
+impl Link {
+    /// Timeout used by most batteries.
+    pub const DEFAULT_TIMEOUT_MS: u32 = 10_000;
+
+    /// Creates a supervision without a connected inverter.
+    #[must_use]
+    pub const fn new(timeout_ms: u32) -> Self {
+        Self {
+            timeout_ms,
+            since_heartbeat_ms: 0,
+            connected: false,
+        }
+    }
+
+    /// Returns true if a frame is a heartbeat of the inverter.
+    ///
+    /// The payload differs between inverters and is ignored.
+    #[must_use]
+    pub fn is_heartbeat<F: Frame>(frame: &F) -> bool {
+        frame.is_data_frame()
+            && StandardId::new(HEARTBEAT_ID).is_some_and(|id| frame.id() == Id::Standard(id))
+    }
+
+    /// Processes a received frame.
+    ///
+    /// Returns true if it was a heartbeat.
+    pub fn receive<F: Frame>(&mut self, frame: &F) -> bool {
+        let heartbeat = Self::is_heartbeat(frame);
+        if heartbeat {
+            self.since_heartbeat_ms = 0;
+            self.connected = true;
+        }
+        heartbeat
+    }
+
+    /// Advances the timeout by `dt_ms`.
+    ///
+    /// Returns true while the inverter is connected.
+    pub fn update(&mut self, dt_ms: u32) -> bool {
+        self.since_heartbeat_ms = self.since_heartbeat_ms.saturating_add(dt_ms);
+        if self.since_heartbeat_ms >= self.timeout_ms {
+            self.connected = false;
+        }
+        self.connected
+    }
+
+    /// Returns true while the inverter is connected.
+    #[must_use]
+    pub const fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMEOUT_MS)
+    }
+}
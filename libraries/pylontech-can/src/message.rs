@@ -0,0 +1,271 @@
+use cellcore::limits::Limits;
+use cellcore::protection::{Level, Protection, Quantity};
+use embedded_can::{Frame, StandardId};
+use p3t1755::Temperature;
+
+use crate::{ALARMS_ID, LIMITS_ID, MEASUREMENT_ID, NAME_ID, REQUEST_ID, SOC_ID};
+
+/// Manufacturer code sent with the alarms.
+const MANUFACTURER: [u8; 2] = *b"PN";
+
+/// Converts to an unsigned field, truncating and saturating.
+fn unsigned(value: u32, divisor: u32) -> u16 {
+    (value / divisor).try_into().unwrap_or(u16::MAX)
+}
+
+/// Converts to a signed field, truncating towards zero and saturating.
+fn signed(value: i32, divisor: i32) -> i16 {
+    (value / divisor).clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+/// A message to send, independent of the CAN controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Message {
+    id: u16,
+    data: [u8; 8],
+    len: u8,
+}
+
+impl Message {
+    fn new(id: u16, payload: &[u8]) -> Self {
+        let mut data = [0; 8];
+        data[..payload.len()].copy_from_slice(payload);
+        Self {
+            id,
+            data,
+            len: payload.len() as u8,
+        }
+    }
+
+    /// Returns the standard identifier.
+    #[must_use]
+    pub const fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns the payload.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+
+    /// Converts the message to a frame of a CAN controller.
+    #[must_use]
+    pub fn to_frame<F: Frame>(&self) -> Option<F> {
+        F::new(StandardId::new(self.id)?, self.data())
+    }
+}
+
+/// Alarm flags of one severity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Flags(pub u16);
+
+impl Flags {
+    /// A cell voltage is too high.
+    pub const OVER_VOLTAGE: Self = Self(1 << 1);
+    /// A cell voltage is too low.
+    pub const UNDER_VOLTAGE: Self = Self(1 << 2);
+    /// A temperature is too high.
+    pub const OVER_TEMPERATURE: Self = Self(1 << 3);
+    /// A temperature is too low.
+    pub const UNDER_TEMPERATURE: Self = Self(1 << 4);
+    /// The discharge current is too high.
+    pub const DISCHARGE_OVER_CURRENT: Self = Self(1 << 7);
+    /// The charge current is too high.
+    pub const CHARGE_OVER_CURRENT: Self = Self(1 << 8);
+    /// The battery failed internally, for example a cellagent is lost.
+    pub const SYSTEM_ERROR: Self = Self(1 << 11);
+
+    /// No flag is set.
+    pub const NONE: Self = Self(0);
+
+    /// Returns true if all flags of `other` are set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears the flags of `other`.
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    /// Returns the flag of a protected quantity.
+    const fn of(quantity: Quantity) -> Self {
+        match quantity {
+            Quantity::OverVoltage => Self::OVER_VOLTAGE,
+            Quantity::UnderVoltage => Self::UNDER_VOLTAGE,
+            Quantity::OverTemperature => Self::OVER_TEMPERATURE,
+            Quantity::UnderTemperature => Self::UNDER_TEMPERATURE,
+            Quantity::ChargeOverCurrent => Self::CHARGE_OVER_CURRENT,
+            Quantity::DischargeOverCurrent => Self::DISCHARGE_OVER_CURRENT,
+        }
+    }
+}
+
+impl core::ops::BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Flags reported in the alarm message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Alarms {
+    /// Conditions the battery has shut down for.
+    pub protection: Flags,
+    /// Conditions the battery warns about.
+    pub alarm: Flags,
+}
+
+impl Alarms {
+    /// Maps the state of the protection: trips are reported as protection,
+    /// warnings and alarms as alarm.
+    #[must_use]
+    pub fn from_protection(protection: &Protection) -> Self {
+        let mut alarms = Self::default();
+        for quantity in Quantity::ALL {
+            let flag = Flags::of(quantity);
+            match protection.level(quantity) {
+                None => {}
+                Some(Level::Warning | Level::Alarm) => alarms.alarm.set(flag, true),
+                Some(Level::Trip) => alarms.protection.set(flag, true),
+            }
+        }
+        alarms
+    }
+}
+
+/// Flags of the request message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Requests {
+    /// The inverter may charge.
+    pub charge_enable: bool,
+    /// The inverter may discharge.
+    pub discharge_enable: bool,
+    /// The inverter should charge from the grid, for example because the
+    /// state of charge is very low.
+    pub force_charge: bool,
+    /// The inverter should charge to full, for example to balance the cells.
+    pub full_charge: bool,
+}
+
+impl Requests {
+    /// Enables the directions whose current limit isn't zero.
+    #[must_use]
+    pub const fn from_limits(limits: &Limits) -> Self {
+        Self {
+            charge_enable: limits.charge_ma > 0,
+            discharge_enable: limits.discharge_ma > 0,
+            force_charge: false,
+            full_charge: false,
+        }
+    }
+
+    const fn bits(self) -> u8 {
+        (self.charge_enable as u8) << 7
+            | (self.discharge_enable as u8) << 6
+            | (self.force_charge as u8) << 5
+            | (self.full_charge as u8) << 3
+    }
+}
+
+/// State of the battery sent to the inverter.
+#[derive(Clone, Copy)]
+pub struct Status {
+    /// Current and voltage limits.
+    pub limits: Limits,
+    /// State of charge in 0.01 %.
+    pub soc: u16,
+    /// State of health in 0.01 %.
+    pub soh: u16,
+    /// Pack voltage in millivolts.
+    pub voltage_mv: u32,
+    /// Pack current in milliamperes, positive while charging.
+    pub current_ma: i32,
+    /// Representative temperature of the pack, usually the highest one.
+    pub temperature: Temperature,
+    /// Protection and alarm flags.
+    pub alarms: Alarms,
+    /// Charge and discharge requests.
+    pub requests: Requests,
+    /// Number of battery modules.
+    pub modules: u8,
+    /// Manufacturer name, [`crate::PYLON`] for most inverters.
+    pub name: [u8; 8],
+}
+
+impl Status {
+    /// Returns all messages in the order they are sent.
+    #[must_use]
+    pub fn messages(&self) -> [Message; 6] {
+        [
+            self.limits_message(),
+            self.soc_message(),
+            self.measurement_message(),
+            self.alarms_message(),
+            self.request_message(),
+            self.name_message(),
+        ]
+    }
+
+    /// Returns the limits in 0.1 V and 0.1 A.
+    #[must_use]
+    pub fn limits_message(&self) -> Message {
+        let limits = &self.limits;
+        let current = |ma: u32| signed(ma.try_into().unwrap_or(i32::MAX), 100);
+        let [a, b] = unsigned(limits.charge_voltage_mv, 100).to_le_bytes();
+        let [c, d] = current(limits.charge_ma).to_le_bytes();
+        let [e, f] = current(limits.discharge_ma).to_le_bytes();
+        let [g, h] = unsigned(limits.discharge_voltage_mv, 100).to_le_bytes();
+        Message::new(LIMITS_ID, &[a, b, c, d, e, f, g, h])
+    }
+
+    /// Returns the state of charge and health in percent.
+    #[must_use]
+    pub fn soc_message(&self) -> Message {
+        let [a, b] = (self.soc.min(10_000) / 100).to_le_bytes();
+        let [c, d] = (self.soh.min(10_000) / 100).to_le_bytes();
+        Message::new(SOC_ID, &[a, b, c, d])
+    }
+
+    /// Returns the voltage in 0.01 V, the current in 0.1 A and the
+    /// temperature in 0.1 °C.
+    #[must_use]
+    pub fn measurement_message(&self) -> Message {
+        let voltage = signed(self.voltage_mv.try_into().unwrap_or(i32::MAX), 10);
+        // The raw temperature is in 1/16 °C.
+        let temperature = signed(i32::from(self.temperature.raw()) * 10, 16);
+        let [a, b] = voltage.to_le_bytes();
+        let [c, d] = signed(self.current_ma, 100).to_le_bytes();
+        let [e, f] = temperature.to_le_bytes();
+        Message::new(MEASUREMENT_ID, &[a, b, c, d, e, f])
+    }
+
+    /// Returns the protection and alarm flags and the module count.
+    #[must_use]
+    pub fn alarms_message(&self) -> Message {
+        let [a, b] = self.alarms.protection.0.to_le_bytes();
+        let [c, d] = self.alarms.alarm.0.to_le_bytes();
+        let [m, n] = MANUFACTURER;
+        Message::new(ALARMS_ID, &[a, b, c, d, self.modules, m, n])
+    }
+
+    /// Returns the request flags.
+    #[must_use]
+    pub fn request_message(&self) -> Message {
+        Message::new(REQUEST_ID, &[self.requests.bits(), 0])
+    }
+
+    /// Returns the manufacturer name.
+    #[must_use]
+    pub fn name_message(&self) -> Message {
+        Message::new(NAME_ID, &self.name)
+    }
+}
@@ -0,0 +1,228 @@
+//! Integration tests for the Pylontech messages.
+
+use cellcore::limits::Limits;
+use cellcore::protection::{self, Measurements, Protection};
+use embedded_can::{ExtendedId, Frame, Id, StandardId};
+use p3t1755::Temperature;
+use pylontech_can::{
+    ALARMS_ID, Alarms, Flags, HEARTBEAT_ID, LIMITS_ID, Link, MEASUREMENT_ID, Message, NAME_ID,
+    PYLON, REQUEST_ID, Requests, SOC_ID, Status,
+};
+
+/// Frame of a CAN controller.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct TestFrame {
+    id: Id,
+    remote: bool,
+    data: Vec<u8>,
+}
+
+impl Frame for TestFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        (data.len() <= 8).then(|| Self {
+            id: id.into(),
+            remote: false,
+            data: data.to_vec(),
+        })
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        (dlc <= 8).then(|| Self {
+            id: id.into(),
+            remote: true,
+            data: Vec::new(),
+        })
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+fn status() -> Status {
+    Status {
+        limits: Limits {
+            charge_ma: 50_000,
+            discharge_ma: 100_000,
+            charge_voltage_mv: 55_200,
+            discharge_voltage_mv: 46_400,
+        },
+        soc: 8765,
+        soh: 9950,
+        voltage_mv: 53_120,
+        current_ma: -12_340,
+        temperature: Temperature::from_raw(408).unwrap(),
+        alarms: Alarms {
+            protection: Flags::OVER_VOLTAGE | Flags::UNDER_TEMPERATURE,
+            alarm: Flags::CHARGE_OVER_CURRENT | Flags::SYSTEM_ERROR,
+        },
+        requests: Requests {
+            charge_enable: true,
+            discharge_enable: true,
+            force_charge: false,
+            full_charge: false,
+        },
+        modules: 2,
+        name: PYLON,
+    }
+}
+
+fn golden(message: &Message) -> (u16, &[u8]) {
+    (message.id(), message.data())
+}
+
+#[test]
+fn test_golden_frames() {
+    let messages = status().messages();
+    let expected: [(u16, &[u8]); 6] = [
+        (LIMITS_ID, &[0x28, 0x02, 0xF4, 0x01, 0xE8, 0x03, 0xD0, 0x01]),
+        (SOC_ID, &[0x57, 0x00, 0x63, 0x00]),
+        (MEASUREMENT_ID, &[0xC0, 0x14, 0x85, 0xFF, 0xFF, 0x00]),
+        (ALARMS_ID, &[0x12, 0x00, 0x00, 0x09, 0x02, b'P', b'N']),
+        (REQUEST_ID, &[0xC0, 0x00]),
+        (NAME_ID, b"PYLON   "),
+    ];
+    for (message, expected) in messages.iter().zip(expected) {
+        assert_eq!(golden(message), expected);
+    }
+}
+
+#[test]
+fn test_negative_temperature_and_requests() {
+    let status = Status {
+        temperature: Temperature::from_raw(-168).unwrap(),
+        current_ma: 20_050,
+        requests: Requests {
+            force_charge: true,
+            full_charge: true,
+            ..Requests::from_limits(&Limits {
+                discharge_ma: 0,
+                ..status().limits
+            })
+        },
+        ..status()
+    };
+    assert_eq!(
+        golden(&status.measurement_message()),
+        (MEASUREMENT_ID, &[0xC0, 0x14, 0xC8, 0x00, 0x97, 0xFF][..])
+    );
+    assert_eq!(
+        golden(&status.request_message()),
+        (REQUEST_ID, &[0xA8, 0x00][..])
+    );
+}
+
+#[test]
+fn test_fields_saturate() {
+    let status = Status {
+        limits: Limits {
+            charge_ma: 5_000_000,
+            discharge_ma: u32::MAX,
+            charge_voltage_mv: 10_000_000,
+            discharge_voltage_mv: 0,
+        },
+        soc: u16::MAX,
+        voltage_mv: 400_000,
+        current_ma: i32::MIN,
+        ..status()
+    };
+    assert_eq!(
+        status.limits_message().data(),
+        [0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x7F, 0x00, 0x00]
+    );
+    assert_eq!(status.soc_message().data()[..2], [100, 0]);
+    assert_eq!(
+        status.measurement_message().data()[..4],
+        [0xFF, 0x7F, 0x00, 0x80]
+    );
+}
+
+#[test]
+fn test_requests_from_limits() {
+    let limits = Limits {
+        charge_ma: 0,
+        ..status().limits
+    };
+    assert_eq!(
+        Requests::from_limits(&limits),
+        Requests {
+            charge_enable: false,
+            discharge_enable: true,
+            force_charge: false,
+            full_charge: false,
+        }
+    );
+}
+
+#[test]
+fn test_alarms_from_protection() {
+    let mut protection = Protection::new(protection::Config::default());
+    let nominal = Measurements {
+        max_cell_mv: Some(3300),
+        min_cell_mv: Some(3300),
+        max_temperature: Some(Temperature::from_degrees_celsius(25)),
+        min_temperature: Some(Temperature::from_degrees_celsius(25)),
+        current_ma: Some(0),
+    };
+    protection.update(10_000, &nominal);
+    assert_eq!(Alarms::from_protection(&protection), Alarms::default());
+
+    let faulty = Measurements {
+        max_cell_mv: Some(3700),
+        max_temperature: Some(Temperature::from_degrees_celsius(52)),
+        ..nominal
+    };
+    protection.update(10_000, &faulty);
+    let alarms = Alarms::from_protection(&protection);
+    assert_eq!(alarms.protection, Flags::OVER_VOLTAGE);
+    assert_eq!(alarms.alarm, Flags::OVER_TEMPERATURE);
+    assert!(!alarms.alarm.contains(Flags::OVER_VOLTAGE));
+}
+
+#[test]
+fn test_message_to_frame() {
+    let message = status().soc_message();
+    let frame: TestFrame = message.to_frame().unwrap();
+    assert_eq!(frame.id(), Id::Standard(StandardId::new(SOC_ID).unwrap()));
+    assert_eq!(frame.data(), message.data());
+}
+
+#[test]
+fn test_link_heartbeat() {
+    let heartbeat = TestFrame::new(StandardId::new(HEARTBEAT_ID).unwrap(), &[0; 8]).unwrap();
+    let mut link = Link::default();
+    assert!(!link.is_connected());
+    assert!(!link.update(100));
+
+    assert!(link.receive(&heartbeat));
+    assert!(link.update(9900));
+    assert!(!link.update(100));
+
+    // Only data frames with the standard identifier count.
+    let other = TestFrame::new(StandardId::new(0x306).unwrap(), &[]).unwrap();
+    let extended = TestFrame::new(ExtendedId::new(u32::from(HEARTBEAT_ID)).unwrap(), &[]).unwrap();
+    let remote = TestFrame::new_remote(StandardId::new(HEARTBEAT_ID).unwrap(), 0).unwrap();
+    for frame in [other, extended, remote] {
+        assert!(!link.receive(&frame));
+    }
+    assert!(!link.is_connected());
+
+    assert!(link.receive(&heartbeat));
+    assert!(link.is_connected());
+}
@@ -0,0 +1,344 @@
+//! Multiple TCA9535 devices on one bus acting as a single wide port.
+
+use embedded_hal::i2c::I2c;
+
+use crate::{Address, Configuration, Input, Output, PinIndex, Tca9535};
+
+/// Output register value after power-on reset.
+const OUTPUT_RESET: Output = Output(0xFFFF);
+/// Configuration register value after power-on reset (all pins are inputs).
+const CONFIGURATION_RESET: Configuration = Configuration(0xFFFF);
+
+/// Global index of a pin within a [`Tca9535Array`].
+///
+/// Pins are numbered consecutively across the devices of the array in the
+/// order the addresses were passed to [`Tca9535Array::new`]. Pin 0 is `P0` of
+/// the first device, pin 16 is `P0` of the second device and so on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ArrayPin(u8);
+
+impl ArrayPin {
+    /// Number of pins on a single device.
+    const PINS_PER_DEVICE: u8 = 16;
+    /// Highest possible number of pins (eight devices).
+    const MAX: u8 = 8 * Self::PINS_PER_DEVICE;
+
+    /// Creates a pin from its global index.
+    ///
+    /// Returns `None` if the index is beyond the 128 pins of eight devices.
+    #[must_use]
+    pub const fn new(index: u8) -> Option<Self> {
+        if index < Self::MAX {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    /// Creates a pin from the position of its device in the array and the
+    /// pin on that device.
+    ///
+    /// Returns `None` if the device position is beyond eight devices.
+    #[must_use]
+    pub const fn from_parts(device: u8, pin: PinIndex) -> Option<Self> {
+        if device < Self::MAX / Self::PINS_PER_DEVICE {
+            Some(Self(device * Self::PINS_PER_DEVICE + pin.bit()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the global index of this pin.
+    #[inline]
+    #[must_use]
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the position of the device this pin belongs to.
+    #[inline]
+    #[must_use]
+    pub const fn device(self) -> usize {
+        (self.0 / Self::PINS_PER_DEVICE) as usize
+    }
+
+    /// Returns the pin on the device this pin belongs to.
+    #[inline]
+    #[must_use]
+    pub const fn pin(self) -> PinIndex {
+        PIN_INDICES[(self.0 % Self::PINS_PER_DEVICE) as usize]
+    }
+}
+
+const PIN_INDICES: [PinIndex; 16] = [
+    PinIndex::P0,
+    PinIndex::P1,
+    PinIndex::P2,
+    PinIndex::P3,
+    PinIndex::P4,
+    PinIndex::P5,
+    PinIndex::P6,
+    PinIndex::P7,
+    PinIndex::P8,
+    PinIndex::P9,
+    PinIndex::P10,
+    PinIndex::P11,
+    PinIndex::P12,
+    PinIndex::P13,
+    PinIndex::P14,
+    PinIndex::P15,
+];
+
+/// Error raised by a single device of a [`Tca9535Array`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ArrayError<E> {
+    /// Address of the device that failed.
+    pub address: Address,
+    /// Error reported by the I2C bus.
+    pub error: E,
+}
+
+/// Shadowed state of a single device.
+#[derive(Clone, Copy)]
+struct Shadow {
+    addr: Address,
+    output: Output,
+    configuration: Configuration,
+    /// Set when a write to the output register failed. The device may or may
+    /// not have latched the new value, so the next write must not be skipped.
+    output_stale: bool,
+    /// Same as `output_stale` but for the configuration register.
+    configuration_stale: bool,
+}
+
+/// Up to eight TCA9535 devices sharing one I2C bus, addressed as one port.
+///
+/// The array keeps a shadow copy of the output and configuration registers of
+/// every device. Modifications are applied to the shadow copy and only the
+/// devices whose registers actually changed are written, one I2C transfer per
+/// device.
+///
+/// The shadow copy always reflects the last value that was successfully
+/// written to a device. If a write fails the device keeps its previous shadow
+/// value and is marked stale, causing it to be rewritten on the next
+/// modification even if the requested value matches the shadow.
+pub struct Tca9535Array<I, const N: usize> {
+    i2c: I,
+    devices: [Shadow; N],
+}
+
+impl<I: I2c, const N: usize> Tca9535Array<I, N> {
+    /// Creates a new array from the addresses of its devices.
+    ///
+    /// The shadow registers are initialised to the power-on reset values. Use
+    /// [`Self::sync`] if the devices may have been configured before.
+    ///
+    /// Returns `None` if an address appears more than once.
+    pub fn new(i2c: I, addrs: [Address; N]) -> Option<Self> {
+        for (i, addr) in addrs.iter().enumerate() {
+            if addrs[..i].contains(addr) {
+                return None;
+            }
+        }
+        Some(Self {
+            i2c,
+            devices: addrs.map(|addr| Shadow {
+                addr,
+                output: OUTPUT_RESET,
+                configuration: CONFIGURATION_RESET,
+                output_stale: false,
+                configuration_stale: false,
+            }),
+        })
+    }
+
+    /// Releases the I2C bus from the array.
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    /// Returns the addresses of the devices in array order.
+    pub fn addresses(&self) -> [Address; N] {
+        self.devices.map(|shadow| shadow.addr)
+    }
+
+    /// Returns the shadowed output registers.
+    pub fn outputs(&self) -> [Output; N] {
+        self.devices.map(|shadow| shadow.output)
+    }
+
+    /// Returns the shadowed configuration registers.
+    pub fn configurations(&self) -> [Configuration; N] {
+        self.devices.map(|shadow| shadow.configuration)
+    }
+
+    /// Reads the output and configuration registers of all devices into the
+    /// shadow registers.
+    ///
+    /// Stops at the first device that fails. Devices that were read before
+    /// the failure are updated, the others keep their previous shadow values.
+    pub fn sync(&mut self) -> Result<(), ArrayError<I::Error>> {
+        for shadow in &mut self.devices {
+            let address = shadow.addr;
+            let mut device = Tca9535::new(&mut self.i2c, address);
+            let output = device
+                .read_output()
+                .map_err(|error| ArrayError { address, error })?;
+            let configuration = device
+                .read_configuration()
+                .map_err(|error| ArrayError { address, error })?;
+            shadow.output = output;
+            shadow.configuration = configuration;
+            shadow.output_stale = false;
+            shadow.configuration_stale = false;
+        }
+        Ok(())
+    }
+
+    /// Reads the input registers of all devices.
+    pub fn read_inputs(&mut self) -> Result<[Input; N], ArrayError<I::Error>> {
+        let mut inputs = [Input(0); N];
+        for (input, shadow) in inputs.iter_mut().zip(&self.devices) {
+            *input = Tca9535::new(&mut self.i2c, shadow.addr)
+                .read_input()
+                .map_err(|error| ArrayError {
+                    address: shadow.addr,
+                    error,
+                })?;
+        }
+        Ok(inputs)
+    }
+
+    /// Reads the level of a single pin.
+    ///
+    /// Only the device the pin belongs to is accessed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin belongs to a device beyond the size of the array.
+    pub fn is_high(&mut self, pin: ArrayPin) -> Result<bool, ArrayError<I::Error>> {
+        let addr = self.devices[pin.device()].addr;
+        let input = Tca9535::new(&mut self.i2c, addr)
+            .read_input()
+            .map_err(|error| ArrayError {
+                address: addr,
+                error,
+            })?;
+        Ok(input.is_high(pin.pin()))
+    }
+
+    /// Drives a single output pin high.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin belongs to a device beyond the size of the array.
+    pub fn set_high(&mut self, pin: ArrayPin) -> Result<(), ArrayError<I::Error>> {
+        self.set_level(pin, true)
+    }
+
+    /// Drives a single output pin low.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin belongs to a device beyond the size of the array.
+    pub fn set_low(&mut self, pin: ArrayPin) -> Result<(), ArrayError<I::Error>> {
+        self.set_level(pin, false)
+    }
+
+    /// Drives a single output pin to the given level.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pin belongs to a device beyond the size of the array.
+    pub fn set_level(&mut self, pin: ArrayPin, high: bool) -> Result<(), ArrayError<I::Error>> {
+        let mut outputs = self.outputs();
+        let output = &mut outputs[pin.device()];
+        *output = if high {
+            output.with_high(pin.pin())
+        } else {
+            output.with_low(pin.pin())
+        };
+        self.write_outputs(outputs)
+    }
+
+    /// Writes the output registers of all devices.
+    ///
+    /// Only devices whose value differs from the shadow register (or whose
+    /// previous write failed) are accessed. Stops at the first device that
+    /// fails.
+    pub fn write_outputs(&mut self, outputs: [Output; N]) -> Result<(), ArrayError<I::Error>> {
+        for (shadow, output) in self.devices.iter_mut().zip(outputs) {
+            if shadow.output == output && !shadow.output_stale {
+                continue;
+            }
+            match Tca9535::new(&mut self.i2c, shadow.addr).write_output(output) {
+                Ok(()) => {
+                    shadow.output = output;
+                    shadow.output_stale = false;
+                }
+                Err(error) => {
+                    shadow.output_stale = true;
+                    return Err(ArrayError {
+                        address: shadow.addr,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Modifies the output registers of all devices.
+    ///
+    /// See [`Self::write_outputs`] for which devices are accessed.
+    pub fn modify_outputs(
+        &mut self,
+        f: impl FnOnce(&mut [Output; N]),
+    ) -> Result<(), ArrayError<I::Error>> {
+        let mut outputs = self.outputs();
+        f(&mut outputs);
+        self.write_outputs(outputs)
+    }
+
+    /// Writes the configuration registers of all devices.
+    ///
+    /// Only devices whose value differs from the shadow register (or whose
+    /// previous write failed) are accessed. Stops at the first device that
+    /// fails.
+    pub fn write_configurations(
+        &mut self,
+        configurations: [Configuration; N],
+    ) -> Result<(), ArrayError<I::Error>> {
+        for (shadow, configuration) in self.devices.iter_mut().zip(configurations) {
+            if shadow.configuration == configuration && !shadow.configuration_stale {
+                continue;
+            }
+            match Tca9535::new(&mut self.i2c, shadow.addr).write_configuration(configuration) {
+                Ok(()) => {
+                    shadow.configuration = configuration;
+                    shadow.configuration_stale = false;
+                }
+                Err(error) => {
+                    shadow.configuration_stale = true;
+                    return Err(ArrayError {
+                        address: shadow.addr,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Modifies the configuration registers of all devices.
+    ///
+    /// See [`Self::write_configurations`] for which devices are accessed.
+    pub fn modify_configurations(
+        &mut self,
+        f: impl FnOnce(&mut [Configuration; N]),
+    ) -> Result<(), ArrayError<I::Error>> {
+        let mut configurations = self.configurations();
+        f(&mut configurations);
+        self.write_configurations(configurations)
+    }
+}
@@ -3,13 +3,20 @@
 //! This library currently opts to provide only a low level interface to the
 //! TCA9535 device. Higher level abstractions like abstracting individual pins
 //! as types implementing the `embedded-hal` traits are not zero-cost.
+//!
+//! [`Tca9535Array`] combines several devices on the same bus into one wide
+//! port with shadowed output and configuration registers.
 
 #![no_std]
 
 use core::mem;
 use core::ops::Range;
 
-use embedded_hal::i2c::I2c;
+use embedded_hal::i2c::{I2c, Operation};
+
+pub use self::array::{ArrayError, ArrayPin, Tca9535Array};
+
+mod array;
 
 const INPUT_PORT0: u8 = 0x00;
 const OUTPUT_PORT0: u8 = 0x02;
@@ -48,6 +55,27 @@ impl<I: I2c> Tca9535<I> {
         self.write_register_pair(OUTPUT_PORT0, value.0)
     }
 
+    /// Writes a sequence of values to the output registers in a single
+    /// transfer.
+    ///
+    /// After the register pointer the device alternates between the two
+    /// output registers for every data byte and updates its pins as soon as a
+    /// byte is acknowledged. This allows generating strobes on the outputs
+    /// without the overhead of a separate transfer for every value.
+    pub fn write_output_sequence<const K: usize>(
+        &mut self,
+        values: [Output; K],
+    ) -> Result<(), I::Error> {
+        let bytes = values.map(|value| value.0.to_le_bytes());
+        self.i2c.transaction(
+            self.addr.get(),
+            &mut [
+                Operation::Write(&[OUTPUT_PORT0]),
+                Operation::Write(bytes.as_flattened()),
+            ],
+        )
+    }
+
     /// Reads the polarity inversion registers.
     pub fn read_polarity_inversion(&mut self) -> Result<PolarityInversion, I::Error> {
         self.read_register_pair(POLARITY_INVERSION_PORT0)
@@ -1,5 +1,9 @@
+use embedded_hal::i2c::ErrorKind;
 use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction};
-use tca9535::{Address, Configuration, Input, Output, PinIndex, PolarityInversion, Tca9535};
+use tca9535::{
+    Address, ArrayError, ArrayPin, Configuration, Input, Output, PinIndex, PolarityInversion,
+    Tca9535, Tca9535Array,
+};
 
 #[test]
 fn test_address_new_valid() {
@@ -345,6 +349,24 @@ fn test_write_output() {
     device.into_inner().done();
 }
 
+#[test]
+fn test_write_output_sequence() {
+    let expectations = [
+        Transaction::transaction_start(0x20),
+        Transaction::write(0x20, vec![0x02]),
+        Transaction::write(0x20, vec![0x01, 0x80, 0x00, 0x80, 0x01, 0x00]),
+        Transaction::transaction_end(0x20),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut device = Tca9535::new(mock, Address::Lll);
+    device
+        .write_output_sequence([Output(0x8001), Output(0x8000), Output(0x0001)])
+        .unwrap();
+
+    device.into_inner().done();
+}
+
 #[test]
 fn test_read_polarity_inversion() {
     let expectations = [Transaction::write_read(0x20, vec![0x04], vec![0xEF, 0xBE])];
@@ -484,3 +506,180 @@ fn test_all_pins_manipulation() {
     output = output.with_low(PinIndex::P15);
     assert_eq!(output.0, 0x0000);
 }
+
+#[test]
+fn test_array_pin() {
+    let pin = ArrayPin::new(0).unwrap();
+    assert_eq!(pin.device(), 0);
+    assert_eq!(pin.pin(), PinIndex::P0);
+
+    let pin = ArrayPin::new(37).unwrap();
+    assert_eq!(pin.device(), 2);
+    assert_eq!(pin.pin(), PinIndex::P5);
+    assert_eq!(ArrayPin::from_parts(2, PinIndex::P5), Some(pin));
+
+    let pin = ArrayPin::new(127).unwrap();
+    assert_eq!(pin.device(), 7);
+    assert_eq!(pin.pin(), PinIndex::P15);
+
+    assert_eq!(ArrayPin::new(128), None);
+    assert_eq!(ArrayPin::from_parts(8, PinIndex::P0), None);
+}
+
+#[test]
+fn test_array_rejects_duplicate_addresses() {
+    let mock = I2cMock::new(&[]);
+    assert!(Tca9535Array::new(mock.clone(), [Address::Lll, Address::Hhh, Address::Lll]).is_none());
+    assert!(Tca9535Array::new(mock.clone(), [Address::Lll, Address::Hhh]).is_some());
+    mock.clone().done();
+}
+
+#[test]
+fn test_array_read_inputs() {
+    let expectations = [
+        Transaction::write_read(0x20, vec![0x00], vec![0x34, 0x12]),
+        Transaction::write_read(0x27, vec![0x00], vec![0x78, 0x56]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Hhh]).unwrap();
+    let inputs = array.read_inputs().unwrap();
+    assert_eq!(inputs, [Input(0x1234), Input(0x5678)]);
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_read_inputs_reports_failing_address() {
+    let expectations = [
+        Transaction::write_read(0x20, vec![0x00], vec![0x34, 0x12]),
+        Transaction::write_read(0x23, vec![0x00], vec![0x00, 0x00]).with_error(ErrorKind::Other),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Lhh, Address::Hhh]).unwrap();
+    let err = array.read_inputs().unwrap_err();
+    assert_eq!(err.address, Address::Lhh);
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_is_high_accesses_single_device() {
+    let expectations = [Transaction::write_read(0x21, vec![0x00], vec![0x00, 0x80])];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Llh]).unwrap();
+    assert!(array.is_high(ArrayPin::new(31).unwrap()).unwrap());
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_sync() {
+    let expectations = [
+        Transaction::write_read(0x20, vec![0x02], vec![0x00, 0x00]),
+        Transaction::write_read(0x20, vec![0x06], vec![0xFF, 0x00]),
+        Transaction::write_read(0x21, vec![0x02], vec![0x0F, 0x00]),
+        Transaction::write_read(0x21, vec![0x06], vec![0x00, 0xFF]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Llh]).unwrap();
+    array.sync().unwrap();
+    assert_eq!(array.outputs(), [Output(0x0000), Output(0x000F)]);
+    assert_eq!(
+        array.configurations(),
+        [Configuration(0x00FF), Configuration(0xFF00)]
+    );
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_set_level_writes_only_changed_device() {
+    let expectations = [
+        Transaction::write(0x24, vec![0x02, 0xFF, 0x7F]),
+        Transaction::write(0x24, vec![0x02, 0xFF, 0xFF]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Hll]).unwrap();
+    let pin = ArrayPin::from_parts(1, PinIndex::P15).unwrap();
+    array.set_low(pin).unwrap();
+    assert_eq!(array.outputs(), [Output(0xFFFF), Output(0x7FFF)]);
+    // Already low, nothing to write.
+    array.set_low(pin).unwrap();
+    array.set_high(pin).unwrap();
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_modify_outputs_batches_per_device() {
+    let expectations = [
+        Transaction::write(0x20, vec![0x02, 0xFE, 0xFF]),
+        Transaction::write(0x22, vec![0x02, 0x00, 0x00]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Llh, Address::Lhl]).unwrap();
+    array
+        .modify_outputs(|outputs| {
+            outputs[0] = outputs[0].with_low(PinIndex::P0);
+            outputs[2] = Output(0x0000);
+        })
+        .unwrap();
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_partial_failure_keeps_shadow_consistent() {
+    let expectations = [
+        // First device succeeds, second fails.
+        Transaction::write(0x20, vec![0x06, 0x00, 0x00]),
+        Transaction::write(0x21, vec![0x06, 0x00, 0x00]).with_error(ErrorKind::Other),
+        // Retrying with the same value rewrites the failed device only.
+        Transaction::write(0x21, vec![0x06, 0x00, 0x00]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll, Address::Llh]).unwrap();
+    let configurations = [Configuration(0x0000), Configuration(0x0000)];
+    let err = array.write_configurations(configurations).unwrap_err();
+    assert_eq!(
+        err,
+        ArrayError {
+            address: Address::Llh,
+            error: ErrorKind::Other,
+        }
+    );
+    assert_eq!(
+        array.configurations(),
+        [Configuration(0x0000), Configuration(0xFFFF)]
+    );
+
+    array.write_configurations(configurations).unwrap();
+    assert_eq!(array.configurations(), configurations);
+
+    array.into_inner().done();
+}
+
+#[test]
+fn test_array_failed_write_is_retried_even_if_unchanged() {
+    let expectations = [
+        Transaction::write(0x20, vec![0x02, 0x00, 0x00]).with_error(ErrorKind::Other),
+        // The device may have latched part of the write, so restoring the
+        // shadowed value must still reach the bus.
+        Transaction::write(0x20, vec![0x02, 0xFF, 0xFF]),
+    ];
+    let mock = I2cMock::new(&expectations);
+
+    let mut array = Tca9535Array::new(mock, [Address::Lll]).unwrap();
+    assert!(array.write_outputs([Output(0x0000)]).is_err());
+    assert_eq!(array.outputs(), [Output(0xFFFF)]);
+    array.write_outputs([Output(0xFFFF)]).unwrap();
+
+    array.into_inner().done();
+}